@@ -1,12 +1,12 @@
-use futures::StreamExt as _;
 use tokio::io::{split, AsyncReadExt, AsyncWriteExt};
 
-use tokio_ipc::{Endpoint, SecurityAttributes, ServerId};
+use tokio_ipc::{Connection, Endpoint, SecurityAttributes, ServerId};
 
 async fn run_server(path: String) {
     #[cfg(not(windows))]
     let options = Some(tokio_ipc::EndpointOptions {
         on_conflict: tokio_ipc::OnConflict::Overwrite,
+        ..Default::default()
     });
     #[cfg(windows)]
     let options = None;
@@ -15,34 +15,28 @@ async fn run_server(path: String) {
         .unwrap()
         .security_attributes(SecurityAttributes::allow_everyone_create().unwrap());
 
-    let incoming = endpoint.incoming().expect("failed to open new socket");
-    futures::pin_mut!(incoming);
-
-    while let Some(result) = incoming.next().await {
-        match result {
-            Ok(stream) => {
-                let (mut reader, mut writer) = split(stream);
+    endpoint
+        .serve(handle_connection, std::future::pending())
+        .await
+        .expect("failed to open new socket");
+}
 
-                tokio::spawn(async move {
-                    loop {
-                        let mut buf = [0u8; 4];
+async fn handle_connection(stream: Connection) {
+    let (mut reader, mut writer) = split(stream);
+    loop {
+        let mut buf = [0u8; 4];
 
-                        if reader.read_exact(&mut buf).await.is_err() {
-                            println!("Closing socket");
-                            break;
-                        }
-                        if let Ok("ping") = std::str::from_utf8(&buf[..]) {
-                            println!("RECEIVED: PING");
-                            writer
-                                .write_all(b"pong")
-                                .await
-                                .expect("unable to write to socket");
-                            println!("SEND: PONG");
-                        }
-                    }
-                });
-            }
-            _ => unreachable!("ideally"),
+        if reader.read_exact(&mut buf).await.is_err() {
+            println!("Closing socket");
+            break;
+        }
+        if let Ok("ping") = std::str::from_utf8(&buf[..]) {
+            println!("RECEIVED: PING");
+            writer
+                .write_all(b"pong")
+                .await
+                .expect("unable to write to socket");
+            println!("SEND: PONG");
         }
     }
 }
@@ -0,0 +1,257 @@
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+use std::{io, mem, ptr};
+
+use tokio::io::Interest;
+use tokio::net::UnixStream;
+
+// Most descriptors Linux accepts in a single message
+const SCM_MAX_FD: usize = 253;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const RECV_FLAGS: libc::c_int = libc::MSG_CMSG_CLOEXEC;
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+const RECV_FLAGS: libc::c_int = 0;
+
+/// Returns a buffer with room for a full `SCM_RIGHTS` message and, on Linux, the credentials
+/// the kernel may attach next to it. It's made of u64 for cmsghdr alignment.
+fn control_buffer() -> Vec<u64> {
+    let fds = mem::size_of::<RawFd>() * SCM_MAX_FD;
+    #[allow(unused_mut)]
+    let mut len = unsafe { libc::CMSG_SPACE(fds as u32) } as usize;
+    #[cfg(target_os = "linux")]
+    {
+        len += unsafe { libc::CMSG_SPACE(mem::size_of::<libc::ucred>() as u32) } as usize;
+    }
+    vec![0; len.div_ceil(mem::size_of::<u64>())]
+}
+
+/// Sends `buf` with a single control message of the given level and type carrying `data`.
+fn send_msg(
+    stream: &UnixStream,
+    buf: &[u8],
+    cmsg_level: libc::c_int,
+    cmsg_type: libc::c_int,
+    data: &[u8],
+) -> io::Result<usize> {
+    let mut control = control_buffer();
+    let control_len = unsafe { libc::CMSG_SPACE(data.len() as u32) } as usize;
+    if control_len > mem::size_of_val(control.as_slice()) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "too much ancillary data for a single message",
+        ));
+    }
+    let mut iov = libc::iovec {
+        iov_base: buf.as_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut msg = unsafe { mem::zeroed::<libc::msghdr>() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    if !data.is_empty() {
+        msg.msg_control = control.as_mut_ptr().cast();
+        msg.msg_controllen = control_len as _;
+        unsafe {
+            let cmsg = libc::CMSG_FIRSTHDR(&msg);
+            (*cmsg).cmsg_level = cmsg_level;
+            (*cmsg).cmsg_type = cmsg_type;
+            (*cmsg).cmsg_len = libc::CMSG_LEN(data.len() as u32) as _;
+            ptr::copy_nonoverlapping(data.as_ptr(), libc::CMSG_DATA(cmsg), data.len());
+        }
+    }
+    match unsafe { libc::sendmsg(stream.as_raw_fd(), &msg, 0) } {
+        -1 => Err(io::Error::last_os_error()),
+        sent => Ok(sent as usize),
+    }
+}
+
+/// Receives into `buf`, handing the level, type and data of every control message to
+/// `on_message`.
+///
+/// Returns the number of bytes received and whether the kernel truncated the control messages
+/// (`MSG_CTRUNC`), which drops descriptors that didn't fit or couldn't be allocated.
+fn recv_msg(
+    stream: &UnixStream,
+    buf: &mut [u8],
+    mut on_message: impl FnMut(libc::c_int, libc::c_int, &[u8]),
+) -> io::Result<(usize, bool)> {
+    let mut control = control_buffer();
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr().cast(),
+        iov_len: buf.len(),
+    };
+    let mut msg = unsafe { mem::zeroed::<libc::msghdr>() };
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr().cast();
+    msg.msg_controllen = mem::size_of_val(control.as_slice()) as _;
+
+    let received = unsafe { libc::recvmsg(stream.as_raw_fd(), &mut msg, RECV_FLAGS) };
+    if received == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            let data_len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+            let data = std::slice::from_raw_parts(libc::CMSG_DATA(cmsg), data_len);
+            on_message((*cmsg).cmsg_level, (*cmsg).cmsg_type, data);
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+    // The bytes are consumed either way, so they're returned even if descriptors were lost
+    Ok((received as usize, msg.msg_flags & libc::MSG_CTRUNC != 0))
+}
+
+/// Takes ownership of the descriptors in an `SCM_RIGHTS` message right away, so they're closed
+/// if anything goes wrong.
+fn owned_fds(data: &[u8]) -> impl Iterator<Item = OwnedFd> + '_ {
+    data.chunks_exact(mem::size_of::<RawFd>()).map(|fd| {
+        let fd = RawFd::from_ne_bytes(fd.try_into().expect("chunk has the size of a fd"));
+        unsafe { OwnedFd::from_raw_fd(fd) }
+    })
+}
+
+pub(crate) async fn send_with_fds(
+    stream: &UnixStream,
+    buf: &[u8],
+    fds: &[RawFd],
+) -> io::Result<usize> {
+    if buf.is_empty() && !fds.is_empty() {
+        // Ancillary data travels with the first byte of the message
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "file descriptors must be sent along with at least one byte",
+        ));
+    }
+    let data: Vec<u8> = fds.iter().flat_map(|fd| fd.to_ne_bytes()).collect();
+    stream
+        .async_io(Interest::WRITABLE, || {
+            send_msg(stream, buf, libc::SOL_SOCKET, libc::SCM_RIGHTS, &data)
+        })
+        .await
+}
+
+pub(crate) async fn recv_with_fds(
+    stream: &UnixStream,
+    buf: &mut [u8],
+) -> io::Result<(usize, Vec<OwnedFd>, bool)> {
+    stream
+        .async_io(Interest::READABLE, || {
+            let mut fds = Vec::new();
+            let (received, truncated) = recv_msg(stream, buf, |level, kind, data| {
+                if (level, kind) == (libc::SOL_SOCKET, libc::SCM_RIGHTS) {
+                    fds.extend(owned_fds(data));
+                }
+            })?;
+            Ok((received, fds, truncated))
+        })
+        .await
+}
+
+/// Process credentials received through `SCM_CREDENTIALS`, created by
+/// [`Connection::recv_with_credentials`](crate::Connection::recv_with_credentials).
+///
+/// The kernel checks the credentials a process sends: unprivileged processes can only send their
+/// own, so these can be trusted as much as [`PeerCredentials`](crate::PeerCredentials). Unlike
+/// those, they're captured per message rather than when the connection was established.
+#[cfg(target_os = "linux")]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReceivedCredentials {
+    pid: u32,
+    uid: u32,
+    gid: u32,
+}
+
+#[cfg(target_os = "linux")]
+impl ReceivedCredentials {
+    /// Returns the process ID of the sender.
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    /// Returns the user ID of the sender.
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    /// Returns the group ID of the sender.
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) fn set_pass_credentials(stream: &UnixStream, enabled: bool) -> io::Result<()> {
+    let enabled = libc::c_int::from(enabled);
+    let result = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PASSCRED,
+            ptr::addr_of!(enabled).cast(),
+            mem::size_of_val(&enabled) as libc::socklen_t,
+        )
+    };
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) async fn send_with_credentials(stream: &UnixStream, buf: &[u8]) -> io::Result<usize> {
+    if buf.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "credentials must be sent along with at least one byte",
+        ));
+    }
+    let credentials = unsafe {
+        libc::ucred {
+            pid: libc::getpid(),
+            uid: libc::geteuid(),
+            gid: libc::getegid(),
+        }
+    };
+    let data = unsafe {
+        std::slice::from_raw_parts(
+            ptr::addr_of!(credentials).cast::<u8>(),
+            mem::size_of_val(&credentials),
+        )
+    };
+    stream
+        .async_io(Interest::WRITABLE, || {
+            send_msg(stream, buf, libc::SOL_SOCKET, libc::SCM_CREDENTIALS, data)
+        })
+        .await
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) async fn recv_with_credentials(
+    stream: &UnixStream,
+    buf: &mut [u8],
+) -> io::Result<(usize, Option<ReceivedCredentials>)> {
+    stream
+        .async_io(Interest::READABLE, || {
+            let mut credentials = None;
+            let (received, _) = recv_msg(stream, buf, |level, kind, data| match (level, kind) {
+                (libc::SOL_SOCKET, libc::SCM_CREDENTIALS)
+                    if data.len() >= mem::size_of::<libc::ucred>() =>
+                {
+                    let ucred = unsafe { ptr::read_unaligned(data.as_ptr().cast::<libc::ucred>()) };
+                    credentials = Some(ReceivedCredentials {
+                        pid: ucred.pid as u32,
+                        uid: ucred.uid,
+                        gid: ucred.gid,
+                    });
+                }
+                // Close descriptors nobody asked for
+                (libc::SOL_SOCKET, libc::SCM_RIGHTS) => owned_fds(data).for_each(drop),
+                _ => {}
+            })?;
+            Ok((received, credentials))
+        })
+        .await
+}
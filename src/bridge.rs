@@ -0,0 +1,67 @@
+//! Forwarding local IPC connections to a TCP or IPC target.
+//!
+//! [`serve`] accepts connections on an [`Endpoint`] and relays each of them to its own
+//! connection to the target, for example to expose a local daemon to containers or remote
+//! debuggers. The number of connections bridged at a time is limited through the endpoint's
+//! [`max_connections`](crate::EndpointOptions::max_connections).
+//!
+//! Requires the `bridge` feature.
+
+use std::future::Future;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use tokio::net::TcpStream;
+
+use crate::{proxy, Connection, Endpoint};
+
+/// Where [`serve`] forwards accepted connections to.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum BridgeTarget {
+    /// A TCP address in `host:port` form, resolved for every connection.
+    Tcp(String),
+    /// The path of another IPC endpoint.
+    Ipc(PathBuf),
+}
+
+/// Forwards every connection accepted on `endpoint` to `target` until `shutdown` resolves.
+///
+/// Each accepted connection is relayed with [`proxy::bidirectional`] on its own task. When the
+/// target can't be reached, the accepted connection is closed. Connections that end, and the
+/// errors that end them, are logged at the trace level. Like [`Endpoint::serve`], this waits
+/// for the bridged connections that are still open after `shutdown` resolved before returning.
+pub async fn serve(
+    endpoint: Endpoint,
+    target: BridgeTarget,
+    shutdown: impl Future<Output = ()>,
+) -> io::Result<()> {
+    let target = Arc::new(target);
+    endpoint
+        .serve(move |conn| forward(conn, target.clone()), shutdown)
+        .await
+}
+
+async fn forward(mut conn: Connection, target: Arc<BridgeTarget>) {
+    let result = match &*target {
+        BridgeTarget::Tcp(addr) => match TcpStream::connect(addr.as_str()).await {
+            Ok(mut stream) => {
+                // Relayed IPC traffic tends to be small request/response messages
+                let _ = stream.set_nodelay(true);
+                proxy::bidirectional(&mut conn, &mut stream).await
+            }
+            Err(e) => Err(e),
+        },
+        BridgeTarget::Ipc(path) => match Endpoint::connect(path.clone(), None).await {
+            Ok(mut upstream) => proxy::bidirectional(&mut conn, &mut upstream).await,
+            Err(e) => Err(e),
+        },
+    };
+    match result {
+        Ok((sent, received)) => tracing::trace!(
+            "Bridged connection to {target:?} closed after sending {sent} and receiving \
+             {received} bytes"
+        ),
+        Err(e) => tracing::trace!("Bridged connection to {target:?} failed: {e:?}"),
+    }
+}
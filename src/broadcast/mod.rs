@@ -0,0 +1,59 @@
+//! One-to-many datagram delivery to every running instance that joined a group.
+//!
+//! Useful for "announce to everyone" scenarios like update notifications, where the sender
+//! neither knows nor cares how many instances are listening. Each [`BroadcastReceiver`] gets its
+//! own copy of every message sent with [`broadcast`] while it's alive.
+//!
+//! On Unix, every receiver binds a datagram socket inside a per-group folder next to the
+//! [`ServerId`](crate::ServerId) sockets, and [`broadcast`] sends to each socket in that folder.
+//! On Windows, every receiver creates a mailslot and registers it in a per-group folder in the
+//! temp dir, and [`broadcast`] writes to each registered mailslot.
+//!
+//! Delivery is best-effort: messages to receivers whose queue is full are dropped, and stale
+//! entries left behind by crashed instances are cleaned up as they're found.
+
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
+mod win;
+
+use std::io;
+
+#[cfg(unix)]
+use self::unix as platform;
+#[cfg(windows)]
+use self::win as platform;
+
+/// Receiving end of a broadcast group.
+///
+/// Leaves the group when dropped.
+#[derive(Debug)]
+pub struct BroadcastReceiver(platform::BroadcastReceiver);
+
+impl BroadcastReceiver {
+    /// Joins the broadcast group with the given name.
+    ///
+    /// Fails with [`io::ErrorKind::InvalidInput`] if the name is empty or contains path
+    /// separators or `..`.
+    pub fn join(group: &str) -> io::Result<Self> {
+        crate::check_file_name("broadcast group", group)?;
+        Ok(Self(platform::BroadcastReceiver::join(group)?))
+    }
+
+    /// Receives the next message into `buf`, returning its length.
+    ///
+    /// Messages that don't fit into `buf` are truncated on Unix and fail with an error on Windows,
+    /// so size it for the largest message the group uses.
+    pub async fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.recv(buf).await
+    }
+}
+
+/// Sends `message` to every receiver in the broadcast group with the given name.
+///
+/// Returns how many receivers the message was delivered to. Group names are checked like in
+/// [`BroadcastReceiver::join`].
+pub fn broadcast(group: &str, message: &[u8]) -> io::Result<usize> {
+    crate::check_file_name("broadcast group", group)?;
+    platform::broadcast(group, message)
+}
@@ -0,0 +1,83 @@
+use std::fs::{self, DirBuilder};
+use std::os::unix::fs::DirBuilderExt;
+use std::os::unix::net::UnixDatagram as StdUnixDatagram;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{io, process};
+
+use tokio::net::UnixDatagram;
+
+use crate::unix::default_folder;
+
+static RECEIVER_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn group_folder(group: &str) -> io::Result<PathBuf> {
+    let folder = default_folder()?.join(format!("{group}.broadcast"));
+    DirBuilder::new()
+        .recursive(true)
+        .mode(0o700)
+        .create(&folder)?;
+    Ok(folder)
+}
+
+#[derive(Debug)]
+pub(crate) struct BroadcastReceiver {
+    path: PathBuf,
+    socket: UnixDatagram,
+}
+
+impl BroadcastReceiver {
+    pub(crate) fn join(group: &str) -> io::Result<Self> {
+        let id = RECEIVER_COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = group_folder(group)?.join(format!("{}-{id}.sock", process::id()));
+        // A previous process with the same pid may have crashed without cleaning up
+        let _ = fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path)?;
+        Ok(Self { path, socket })
+    }
+
+    pub(crate) async fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.socket.recv(buf).await
+    }
+}
+
+impl Drop for BroadcastReceiver {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+pub(crate) fn broadcast(group: &str, message: &[u8]) -> io::Result<usize> {
+    let socket = StdUnixDatagram::unbound()?;
+    // A slow receiver must not hold up everyone else
+    socket.set_nonblocking(true)?;
+
+    let mut delivered = 0;
+    for entry in fs::read_dir(group_folder(group)?)? {
+        let path = match entry {
+            Ok(entry) => entry.path(),
+            // Removed by a receiver that left while the folder was being listed
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if path.extension().map_or(true, |ext| ext != "sock") {
+            continue;
+        }
+        match socket.send_to(message, &path) {
+            Ok(_) => delivered += 1,
+            // Left behind by a receiver that didn't shut down cleanly, or by one that is leaving
+            // right now and may not have removed the socket yet
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::ConnectionRefused | io::ErrorKind::NotFound
+                ) =>
+            {
+                let _ = fs::remove_file(&path);
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(delivered)
+}
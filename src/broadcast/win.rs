@@ -0,0 +1,132 @@
+use std::ffi::OsStr;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::{FromRawHandle, RawHandle};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::{process, ptr};
+
+use windows_sys::Win32::Foundation::{ERROR_SEM_TIMEOUT, INVALID_HANDLE_VALUE};
+use windows_sys::Win32::System::Mailslots::CreateMailslotW;
+
+use crate::win::default_folder;
+
+// How often a blocked read checks whether its future was dropped
+const READ_POLL_INTERVAL_MS: u32 = 100;
+
+static RECEIVER_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn group_folder(group: &str) -> io::Result<PathBuf> {
+    let folder = default_folder()?.join(format!("{group}.broadcast"));
+    fs::create_dir_all(&folder)?;
+    Ok(folder)
+}
+
+fn mailslot_path(group: &str, id: &OsStr) -> PathBuf {
+    let mut path = PathBuf::from(format!(r"\\.\mailslot\tokio-ipc\{group}"));
+    path.push(id);
+    path
+}
+
+#[derive(Debug)]
+pub(crate) struct BroadcastReceiver {
+    marker: PathBuf,
+    mailslot: Arc<File>,
+}
+
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+impl BroadcastReceiver {
+    pub(crate) fn join(group: &str) -> io::Result<Self> {
+        let id = format!(
+            "{}-{}",
+            process::id(),
+            RECEIVER_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let name: Vec<u16> = mailslot_path(group, id.as_ref())
+            .as_os_str()
+            .encode_wide()
+            .chain(Some(0))
+            .collect();
+
+        let handle =
+            unsafe { CreateMailslotW(name.as_ptr(), 0, READ_POLL_INTERVAL_MS, ptr::null()) };
+        if handle == INVALID_HANDLE_VALUE {
+            return Err(io::Error::last_os_error());
+        }
+        let mailslot = unsafe { File::from_raw_handle(handle as RawHandle) };
+
+        // Mailslots can't be enumerated, so senders find receivers through these marker files
+        let marker = group_folder(group)?.join(&id);
+        File::create(&marker)?;
+
+        Ok(Self {
+            marker,
+            mailslot: Arc::new(mailslot),
+        })
+    }
+
+    pub(crate) async fn recv(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mailslot = self.mailslot.clone();
+        let mut message = vec![0; buf.len()];
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let _guard = CancelOnDrop(cancelled.clone());
+
+        let (message, len) = tokio::task::spawn_blocking(move || loop {
+            match (&*mailslot).read(&mut message) {
+                Ok(len) => return Ok((message, len)),
+                Err(e)
+                    if e.raw_os_error() == Some(ERROR_SEM_TIMEOUT as i32)
+                        && !cancelled.load(Ordering::Relaxed) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(e),
+            }
+        })
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))??;
+
+        buf[..len].copy_from_slice(&message[..len]);
+        Ok(len)
+    }
+}
+
+impl Drop for BroadcastReceiver {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.marker);
+    }
+}
+
+pub(crate) fn broadcast(group: &str, message: &[u8]) -> io::Result<usize> {
+    let mut delivered = 0;
+    for entry in fs::read_dir(group_folder(group)?)? {
+        let entry = match entry {
+            Ok(entry) => entry,
+            // Removed by a receiver that left while the folder was being listed
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        let path = mailslot_path(group, &entry.file_name());
+        match fs::OpenOptions::new().write(true).open(path) {
+            Ok(mut mailslot) => {
+                mailslot.write_all(message)?;
+                delivered += 1;
+            }
+            // Left behind by a receiver that didn't shut down cleanly
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                let _ = fs::remove_file(entry.path());
+            }
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(delivered)
+}
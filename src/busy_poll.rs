@@ -0,0 +1,49 @@
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+
+/// Spin state for a single direction (read or write) of a connection.
+///
+/// While the spin window is open, an operation that would block reschedules the task right away
+/// instead of letting it park until the reactor reports readiness.
+pub(crate) struct BusyPoll {
+    duration: Option<Duration>,
+    // Deliberately uses the wall clock: while spinning the runtime is never idle, so a paused
+    // Tokio clock would never advance and the window would never close.
+    spin_start: Option<Instant>,
+}
+
+impl BusyPoll {
+    pub(crate) fn new() -> Self {
+        Self {
+            duration: None,
+            spin_start: None,
+        }
+    }
+
+    pub(crate) fn get(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    pub(crate) fn set(&mut self, duration: Option<Duration>) {
+        self.duration = duration;
+        self.spin_start = None;
+    }
+
+    pub(crate) fn poll_op<T>(&mut self, cx: &mut Context<'_>, result: Poll<T>) -> Poll<T> {
+        match result {
+            Poll::Ready(result) => {
+                self.spin_start = None;
+                Poll::Ready(result)
+            }
+            Poll::Pending => {
+                if let Some(duration) = self.duration {
+                    let spin_start = *self.spin_start.get_or_insert_with(Instant::now);
+                    if spin_start.elapsed() < duration {
+                        cx.waker().wake_by_ref();
+                    }
+                }
+                Poll::Pending
+            }
+        }
+    }
+}
@@ -0,0 +1,321 @@
+//! Recording the frames of a connection and replaying them later.
+//!
+//! [`TappedConnection`] wraps a [`FramedConnection`] and writes every frame it sends or receives
+//! to a capture. [`CaptureReader`] reads a capture back, and [`replay`] feeds the frames received
+//! in it to a handler, which is useful for reproducing protocol bugs reported from the field.
+//!
+//! # Format
+//!
+//! A capture starts with the 8 bytes `TIPCCAP1`, followed by one record per frame:
+//!
+//! | Field     | Size     | Content                                                  |
+//! |-----------|----------|----------------------------------------------------------|
+//! | direction | 1 byte   | 0 for a frame that was sent, 1 for one that was received |
+//! | timestamp | 8 bytes  | microseconds since the Unix epoch, big-endian            |
+//! | length    | 4 bytes  | length of the frame, big-endian                          |
+//! | frame     | `length` | the frame itself                                         |
+//!
+//! Requires the `capture` feature.
+
+use std::fmt;
+use std::fs::File;
+use std::future::Future;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use bytes::{Bytes, BytesMut};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+
+use crate::framing::FramedConnection;
+use crate::Endpoint;
+
+const MAGIC: &[u8; 8] = b"TIPCCAP1";
+
+/// Whether a captured frame was sent or received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    /// The frame was sent to the peer.
+    Sent,
+    /// The frame was received from the peer.
+    Received,
+}
+
+/// A single captured frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// Whether the frame was sent or received.
+    pub direction: Direction,
+    /// When the frame was sent or received, with microsecond precision.
+    pub timestamp: SystemTime,
+    /// The frame itself.
+    pub frame: Bytes,
+}
+
+fn write_record(
+    capture: &mut impl Write,
+    direction: Direction,
+    timestamp: SystemTime,
+    frame: &[u8],
+) -> io::Result<()> {
+    let micros = timestamp
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_micros() as u64);
+    let len = u32::try_from(frame.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "frame is too long"))?;
+    let direction = match direction {
+        Direction::Sent => 0u8,
+        Direction::Received => 1,
+    };
+    capture.write_all(&[direction])?;
+    capture.write_all(&micros.to_be_bytes())?;
+    capture.write_all(&len.to_be_bytes())?;
+    capture.write_all(frame)
+}
+
+/// A [`FramedConnection`] that records every frame to a capture.
+///
+/// Frames are sent and received like on the underlying connection. Records are written to the
+/// capture synchronously, which is meant for files or in-memory buffers, and flushed along with
+/// the connection. If writing the capture fails, the failure is logged at the trace level and
+/// capturing stops, but the connection keeps working.
+pub struct TappedConnection<W: Write = BufWriter<File>> {
+    inner: FramedConnection,
+    capture: Option<W>,
+}
+
+impl TappedConnection {
+    /// Wraps a framed connection, capturing to a new file at `path`.
+    ///
+    /// An existing file at `path` is truncated.
+    pub fn create(conn: FramedConnection, path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(conn, BufWriter::new(File::create(path)?))
+    }
+}
+
+impl<W: Write> TappedConnection<W> {
+    /// Wraps a framed connection, capturing to `capture`.
+    pub fn new(conn: FramedConnection, mut capture: W) -> io::Result<Self> {
+        capture.write_all(MAGIC)?;
+        Ok(Self {
+            inner: conn,
+            capture: Some(capture),
+        })
+    }
+
+    fn record(&mut self, direction: Direction, frame: &[u8]) {
+        let Some(capture) = &mut self.capture else {
+            return;
+        };
+        if let Err(e) = write_record(capture, direction, SystemTime::now(), frame) {
+            tracing::trace!("Stopped capturing after failing to write a record: {e:?}");
+            self.capture = None;
+        }
+    }
+
+    fn flush_capture(&mut self) {
+        if let Some(Err(e)) = self.capture.as_mut().map(Write::flush) {
+            tracing::trace!("Stopped capturing after failing to flush: {e:?}");
+            self.capture = None;
+        }
+    }
+
+    /// Sends a single frame and flushes it.
+    pub async fn send(&mut self, frame: Bytes) -> io::Result<()>
+    where
+        W: Unpin,
+    {
+        SinkExt::send(self, frame).await
+    }
+
+    /// Receives the next frame, or `None` once the peer closed the connection.
+    pub async fn recv(&mut self) -> Option<io::Result<Bytes>>
+    where
+        W: Unpin,
+    {
+        StreamExt::next(self).await
+    }
+
+    /// Returns a reference to the underlying connection.
+    pub fn get_ref(&self) -> &FramedConnection {
+        &self.inner
+    }
+
+    /// Stops capturing, returning the underlying connection and the capture.
+    ///
+    /// The capture is `None` if writing to it failed.
+    pub fn into_parts(mut self) -> (FramedConnection, Option<W>) {
+        self.flush_capture();
+        (self.inner, self.capture)
+    }
+}
+
+impl<W: Write> fmt::Debug for TappedConnection<W> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TappedConnection")
+            .field("inner", &self.inner)
+            .field("capturing", &self.capture.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<W: Write + Unpin> Stream for TappedConnection<W> {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = Pin::into_inner(self);
+        let frame = ready!(Pin::new(&mut this.inner).poll_next(cx));
+        if let Some(Ok(frame)) = &frame {
+            this.record(Direction::Received, frame);
+        }
+        Poll::Ready(frame)
+    }
+}
+
+impl<W: Write + Unpin> Sink<Bytes> for TappedConnection<W> {
+    type Error = io::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_ready(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, frame: Bytes) -> io::Result<()> {
+        let this = Pin::into_inner(self);
+        Pin::new(&mut this.inner).start_send(frame.clone())?;
+        this.record(Direction::Sent, &frame);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = Pin::into_inner(self);
+        ready!(Pin::new(&mut this.inner).poll_flush(cx))?;
+        this.flush_capture();
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = Pin::into_inner(self);
+        ready!(Pin::new(&mut this.inner).poll_close(cx))?;
+        this.flush_capture();
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Reads the records of a capture written by a [`TappedConnection`].
+#[derive(Debug)]
+pub struct CaptureReader<R: Read = BufReader<File>> {
+    reader: R,
+}
+
+impl CaptureReader {
+    /// Opens the capture file at `path`.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> CaptureReader<R> {
+    /// Reads a capture from `reader`, failing if it doesn't start like one.
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a tokio-ipc capture",
+            ));
+        }
+        Ok(Self { reader })
+    }
+
+    fn read_record(&mut self) -> io::Result<Option<Record>> {
+        let mut direction = [0; 1];
+        loop {
+            match self.reader.read(&mut direction) {
+                Ok(0) => return Ok(None),
+                Ok(_) => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+        let direction = match direction[0] {
+            0 => Direction::Sent,
+            1 => Direction::Received,
+            other => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("invalid direction {other} in capture"),
+                ))
+            }
+        };
+        let mut header = [0; 12];
+        self.reader.read_exact(&mut header)?;
+        let micros = u64::from_be_bytes(header[..8].try_into().expect("header has a u64"));
+        let len = u32::from_be_bytes(header[8..].try_into().expect("header has a u32"));
+        let mut frame = BytesMut::zeroed(len as usize);
+        self.reader.read_exact(&mut frame)?;
+        Ok(Some(Record {
+            direction,
+            timestamp: UNIX_EPOCH + Duration::from_micros(micros),
+            frame: frame.freeze(),
+        }))
+    }
+}
+
+impl<R: Read> Iterator for CaptureReader<R> {
+    type Item = io::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_record().transpose()
+    }
+}
+
+/// Feeds the frames received in the capture at `path` to `handler`, returning the frames it
+/// sends back.
+///
+/// The handler gets one end of a fresh [`Endpoint::pair`] and sees the received frames in their
+/// original order, without the original delays. Frames the capture recorded as sent are skipped.
+/// After the last frame, the write half of the other end is shut down, so on Unix the handler
+/// sees the end of the stream. Named pipes can't be half-closed, so on Windows the handler has to
+/// return on its own. This returns once the handler dropped its connection.
+pub async fn replay<F, Fut>(path: impl AsRef<Path>, handler: F) -> io::Result<Vec<Bytes>>
+where
+    F: FnOnce(FramedConnection) -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let frames = CaptureReader::open(path)?
+        .filter_map(|record| match record {
+            Ok(record) if record.direction == Direction::Received => Some(Ok(record.frame)),
+            Ok(_) => None,
+            Err(e) => Some(Err(e)),
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+
+    let (conn, peer) = Endpoint::pair().await?;
+    let handler = tokio::spawn(handler(FramedConnection::new(conn)));
+    let (mut sink, mut stream) = FramedConnection::new(peer).split();
+    let feed = async move {
+        for frame in frames {
+            sink.feed(frame).await?;
+        }
+        sink.close().await
+    };
+    let collect = async move {
+        let mut sent = Vec::new();
+        while let Some(frame) = stream.next().await {
+            sent.push(frame?);
+        }
+        Ok::<_, io::Error>(sent)
+    };
+    let (fed, sent) = futures::join!(feed, collect);
+    if let Err(e) = fed {
+        // The handler is free to hang up before reading everything
+        tracing::trace!("Failed to feed the whole capture to the handler: {e:?}");
+    }
+    handler
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    sent
+}
@@ -0,0 +1,99 @@
+//! Connectionless Unix datagram sockets.
+//!
+//! Unlike connections, datagram sockets don't need a handshake: any number of
+//! [`unbound`](DatagramSocket::unbound) senders can fire messages at a socket that was
+//! [`bind`](DatagramSocket::bind)ed to a path, which makes them a good fit for many-to-one
+//! messaging like logging sinks. Message boundaries are preserved, but there's no way to tell
+//! whether a message was processed by the receiver.
+//!
+//! Only available on Unix.
+
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+
+use tokio::net::UnixDatagram;
+
+use crate::unix::{ensure_secure_parent, resolve_conflict};
+use crate::{EndpointOptions, IntoIpcPath};
+
+/// A Unix datagram socket.
+///
+/// A bound socket removes its socket file when dropped.
+pub struct DatagramSocket {
+    socket: UnixDatagram,
+    path: Option<PathBuf>,
+}
+
+impl DatagramSocket {
+    /// Binds a socket to `path` to receive datagrams on.
+    ///
+    /// Honors the `on_conflict` and `allow_insecure_folder` options like
+    /// [`Endpoint::new`](crate::Endpoint::new) and
+    /// [`Endpoint::incoming`](crate::Endpoint::incoming) do.
+    pub fn bind(path: impl IntoIpcPath, options: Option<EndpointOptions>) -> io::Result<Self> {
+        let path = path.into_ipc_path()?;
+        let options = options.unwrap_or_default();
+        if !options.allow_insecure_folder {
+            ensure_secure_parent(&path)?;
+        }
+        resolve_conflict(&path, options.on_conflict, |path| {
+            std::os::unix::net::UnixDatagram::unbound()?.connect(path)
+        })?;
+        Ok(Self {
+            socket: UnixDatagram::bind(&path)?,
+            path: Some(path),
+        })
+    }
+
+    /// Creates a socket that isn't bound to any path, for sending only.
+    ///
+    /// Receivers see datagrams from unbound sockets without a sender path, so they can't reply.
+    pub fn unbound() -> io::Result<Self> {
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            path: None,
+        })
+    }
+
+    /// Returns the path the socket is bound to.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Sends `buf` as a single datagram to the socket bound to `target`.
+    pub async fn send_to(&self, buf: &[u8], target: impl IntoIpcPath) -> io::Result<usize> {
+        self.socket.send_to(buf, target.into_ipc_path()?).await
+    }
+
+    /// Receives a single datagram into `buf`, returning its length.
+    ///
+    /// Datagrams that don't fit into `buf` are truncated.
+    pub async fn recv(&self, buf: &mut [u8]) -> io::Result<usize> {
+        self.socket.recv(buf).await
+    }
+
+    /// Receives a single datagram into `buf`, returning its length and the path of the sender,
+    /// if it's bound to one.
+    ///
+    /// Datagrams that don't fit into `buf` are truncated.
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, Option<PathBuf>)> {
+        let (len, addr) = self.socket.recv_from(buf).await?;
+        Ok((len, addr.as_pathname().map(Path::to_path_buf)))
+    }
+}
+
+impl fmt::Debug for DatagramSocket {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DatagramSocket")
+            .field("path", &self.path)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for DatagramSocket {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
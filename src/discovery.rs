@@ -0,0 +1,91 @@
+//! Publishing endpoint paths under a name only the current user can look up.
+//!
+//! This pairs with [`ServerId::randomized`](crate::ServerId::randomized): the server binds to an
+//! unpredictable path and advertises it under a well-known name, and clients running as the same
+//! user discover the path by that name. Advertisements are stored in the same private per-user
+//! folder that holds sockets by default, so other users can neither read nor replace them.
+
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{fmt, process};
+
+use crate::{platform, IntoIpcPath};
+
+/// An endpoint path published with [`advertise`].
+///
+/// The advertisement is withdrawn when this is dropped.
+pub struct Advertisement {
+    file: PathBuf,
+    endpoint: PathBuf,
+}
+
+impl Advertisement {
+    /// Returns the advertised endpoint path.
+    pub fn endpoint(&self) -> &Path {
+        &self.endpoint
+    }
+}
+
+impl fmt::Debug for Advertisement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Advertisement")
+            .field("file", &self.file)
+            .field("endpoint", &self.endpoint)
+            .finish()
+    }
+}
+
+impl Drop for Advertisement {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.file);
+    }
+}
+
+static TEMP_FILE_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+fn advertisement_file(name: &str) -> io::Result<PathBuf> {
+    crate::check_file_name("advertisement", name)?;
+    Ok(platform::default_folder()?.join(format!("{name}.endpoint")))
+}
+
+/// Publishes the path of `endpoint` under `name`, replacing any previous advertisement.
+///
+/// Fails with [`io::ErrorKind::InvalidInput`] if the name is empty or contains path separators
+/// or `..`.
+pub fn advertise(name: &str, endpoint: impl IntoIpcPath) -> io::Result<Advertisement> {
+    let endpoint = endpoint.into_ipc_path()?;
+    let contents = endpoint.to_str().ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Endpoint path {endpoint:?} is not valid UTF-8"),
+        )
+    })?;
+
+    let file = advertisement_file(name)?;
+    // Write to a temporary file first so clients never observe a partially written path
+    // Tasks of the same process may advertise under the same name concurrently
+    let temp_file = file.with_extension(format!(
+        "endpoint.{}-{}",
+        process::id(),
+        TEMP_FILE_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let mut options = OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    options.open(&temp_file)?.write_all(contents.as_bytes())?;
+    fs::rename(&temp_file, &file)?;
+
+    Ok(Advertisement { file, endpoint })
+}
+
+/// Looks up the endpoint path advertised under `name`.
+///
+/// Fails with [`io::ErrorKind::NotFound`] if nothing is advertised under that name, and with
+/// [`io::ErrorKind::InvalidInput`] for names [`advertise`] rejects.
+pub fn discover(name: &str) -> io::Result<PathBuf> {
+    let file = advertisement_file(name)?;
+    Ok(PathBuf::from(fs::read_to_string(file)?))
+}
@@ -0,0 +1,577 @@
+//! Length-delimited message framing on top of a [`Connection`].
+//!
+//! Byte-stream connections have no message boundaries. [`FramedConnection`] prefixes every
+//! message with its length as a 32-bit big-endian integer, so each frame sent by one side is
+//! received as exactly one frame by the other.
+//!
+//! When the [maximum lifetime](crate::Connection::set_max_lifetime) or
+//! [idle timeout](crate::Connection::set_idle_timeout) of the underlying connection runs out,
+//! a close notice is sent before the connection is shut down. It's a length prefix of
+//! `0xFFFFFFFF` followed by a byte with the reason, which the other side reports through
+//! [`FramedConnection::peer_expiry`] so clients can tell a planned close from a failure and
+//! reconnect right away.
+//!
+//! Either side can ask to [upgrade](FramedConnection::upgrade) the connection to another
+//! protocol, like a different codec or an encrypted or compressed stream, without reconnecting.
+//! The request is a length prefix of `0xFFFFFFFE`, a byte `1`, the protocol name as a 16-bit
+//! big-endian length followed by UTF-8, and is answered by the same prefix followed by `2` to
+//! accept or `3` to reject. Once accepted, both sides stop framing and speak the new protocol
+//! over the [`Upgraded`] connection.
+//!
+//! The [cap on buffered data](crate::Connection::set_max_buffered) of the connection also caps the
+//! frame length, and layers that queue received frames for later, like multiplexed streams and
+//! pub/sub subscriptions, draw from it until the data is read. They also draw from the memory
+//! budget shared with other connections, if one is attached to the connection.
+//!
+//! Requires the `framing` feature.
+
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::{fmt, io, mem};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_util::codec::{Decoder, Encoder, Framed, LengthDelimitedCodec};
+
+use crate::{Connection, ExpiryReason, IpcStream};
+
+/// The default maximum frame length of 8 MiB.
+pub const DEFAULT_MAX_FRAME_LENGTH: usize = 8 * 1024 * 1024;
+
+// Length prefix that announces a close notice instead of a frame
+const CLOSE_NOTICE: [u8; 4] = u32::MAX.to_be_bytes();
+// Length prefix that announces an upgrade request or reply instead of a frame
+const UPGRADE: [u8; 4] = (u32::MAX - 1).to_be_bytes();
+const UPGRADE_REQUEST: u8 = 1;
+const UPGRADE_ACCEPT: u8 = 2;
+const UPGRADE_REJECT: u8 = 3;
+
+fn encode_reason(reason: ExpiryReason) -> u8 {
+    match reason {
+        ExpiryReason::MaxLifetime => 1,
+        ExpiryReason::Idle => 2,
+    }
+}
+
+fn decode_reason(reason: u8) -> io::Result<ExpiryReason> {
+    match reason {
+        1 => Ok(ExpiryReason::MaxLifetime),
+        2 => Ok(ExpiryReason::Idle),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown close notice reason {reason}"),
+        )),
+    }
+}
+
+fn invalid_data(error: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+enum Item {
+    Frame(BytesMut),
+    CloseNotice(ExpiryReason),
+    UpgradeRequest(String),
+    UpgradeReply(bool),
+}
+
+/// Length-delimited frames, interleaved with close notices and upgrade messages.
+struct FrameCodec {
+    inner: LengthDelimitedCodec,
+    // whether the length of the next frame was read already, so the buffer starts with its data
+    in_frame: bool,
+}
+
+impl Decoder for FrameCodec {
+    type Item = Item;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> io::Result<Option<Item>> {
+        if !self.in_frame && src.starts_with(&CLOSE_NOTICE) {
+            let Some(&reason) = src.get(CLOSE_NOTICE.len()) else {
+                return Ok(None);
+            };
+            src.advance(CLOSE_NOTICE.len() + 1);
+            return decode_reason(reason).map(|reason| Some(Item::CloseNotice(reason)));
+        }
+        if !self.in_frame && src.starts_with(&UPGRADE) {
+            return decode_upgrade(src);
+        }
+        let buffered = src.len();
+        let frame = self.inner.decode(src)?;
+        self.in_frame = frame.is_none() && (self.in_frame || src.len() < buffered);
+        Ok(frame.map(Item::Frame))
+    }
+}
+
+fn decode_upgrade(src: &mut BytesMut) -> io::Result<Option<Item>> {
+    let Some(&kind) = src.get(UPGRADE.len()) else {
+        return Ok(None);
+    };
+    let header_len = UPGRADE.len() + 1;
+    match kind {
+        UPGRADE_REQUEST => {
+            let Some(mut len) = src.get(header_len..header_len + 2) else {
+                return Ok(None);
+            };
+            let len = usize::from(len.get_u16());
+            if src.len() < header_len + 2 + len {
+                return Ok(None);
+            }
+            src.advance(header_len + 2);
+            let protocol = String::from_utf8(src.split_to(len).to_vec())
+                .map_err(|_| invalid_data("upgrade protocol isn't UTF-8"))?;
+            Ok(Some(Item::UpgradeRequest(protocol)))
+        }
+        UPGRADE_ACCEPT | UPGRADE_REJECT => {
+            src.advance(header_len);
+            Ok(Some(Item::UpgradeReply(kind == UPGRADE_ACCEPT)))
+        }
+        _ => Err(invalid_data(&format!("unknown upgrade message {kind}"))),
+    }
+}
+
+impl Encoder<Bytes> for FrameCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, frame: Bytes, dst: &mut BytesMut) -> io::Result<()> {
+        if frame.len() >= u32::from_be_bytes(UPGRADE) as usize {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame is too long to be sent",
+            ));
+        }
+        self.inner.encode(frame, dst)
+    }
+}
+
+/// A [`Connection`] that sends and receives whole frames instead of bytes.
+///
+/// Received frames are yielded through the [`Stream`] implementation, frames are sent through
+/// the [`Sink`] implementation. Sending a frame that exceeds the maximum frame length fails without
+/// writing anything, receiving one fails with [`InvalidData`](io::ErrorKind::InvalidData).
+pub struct FramedConnection {
+    inner: Framed<Connection, FrameCodec>,
+    // unsent frames followed by the close notice, while it's being written
+    notice: Option<BytesMut>,
+    notice_sent: bool,
+    peer_expiry: Option<ExpiryReason>,
+    peer_upgrade: Option<String>,
+}
+
+impl FramedConnection {
+    /// Wraps a connection, using [`DEFAULT_MAX_FRAME_LENGTH`].
+    pub fn new(conn: Connection) -> Self {
+        Self::with_max_frame_length(conn, DEFAULT_MAX_FRAME_LENGTH)
+    }
+
+    /// Wraps a connection, rejecting frames longer than `max_frame_length` bytes.
+    ///
+    /// Both sides should agree on the limit; frames rejected by the receiver close the stream.
+    /// The [cap on buffered data](Connection::set_max_buffered) of `conn` lowers the limit.
+    pub fn with_max_frame_length(conn: Connection, max_frame_length: usize) -> Self {
+        let max_frame_length = max_frame_length.min(conn.max_buffered().unwrap_or(usize::MAX));
+        let codec = LengthDelimitedCodec::builder()
+            .max_frame_length(max_frame_length)
+            .new_codec();
+        let codec = FrameCodec {
+            inner: codec,
+            in_frame: false,
+        };
+        Self {
+            inner: Framed::new(conn, codec),
+            notice: None,
+            notice_sent: false,
+            peer_expiry: None,
+            peer_upgrade: None,
+        }
+    }
+
+    /// Returns the maximum frame length.
+    pub fn max_frame_length(&self) -> usize {
+        self.inner.codec().inner.max_frame_length()
+    }
+
+    /// Returns why the peer closed the connection, if it sent a close notice because the maximum
+    /// lifetime or idle timeout of its connection ran out.
+    ///
+    /// The stream ends right after the notice.
+    pub fn peer_expiry(&self) -> Option<ExpiryReason> {
+        self.peer_expiry
+    }
+
+    /// Asks the peer to switch the connection over to `protocol`.
+    ///
+    /// Returns the [`Upgraded`] connection if the peer accepts, or this connection, still framed,
+    /// if it rejects. Frames received while waiting for the answer fail the upgrade with
+    /// [`InvalidData`](io::ErrorKind::InvalidData), so only request upgrades while the peer isn't
+    /// sending on its own, like between the requests of a request-response protocol, and not from
+    /// both sides at once.
+    pub async fn upgrade(mut self, protocol: &str) -> io::Result<Result<Upgraded, Self>> {
+        let len = u16::try_from(protocol.len()).map_err(|_| {
+            io::Error::new(io::ErrorKind::InvalidInput, "upgrade protocol is too long")
+        })?;
+        // Frames queued before are complete, so the request starts at a frame boundary
+        let request = self.inner.write_buffer_mut();
+        request.extend_from_slice(&UPGRADE);
+        request.put_u8(UPGRADE_REQUEST);
+        request.put_u16(len);
+        request.extend_from_slice(protocol.as_bytes());
+        SinkExt::flush(&mut self.inner).await?;
+
+        match self.inner.next().await {
+            Some(Ok(Item::UpgradeReply(true))) => Ok(Ok(Upgraded::new(self.inner))),
+            Some(Ok(Item::UpgradeReply(false))) => Ok(Err(self)),
+            Some(Ok(Item::Frame(_) | Item::UpgradeRequest(_))) => Err(invalid_data(
+                "received a frame while waiting for the upgrade to be answered",
+            )),
+            Some(Ok(Item::CloseNotice(reason))) => {
+                self.peer_expiry = Some(reason);
+                Err(io::ErrorKind::UnexpectedEof.into())
+            }
+            Some(Err(e)) => Err(e),
+            None => Err(io::ErrorKind::UnexpectedEof.into()),
+        }
+    }
+
+    /// Returns the protocol the peer asked to upgrade the connection to, if it did.
+    ///
+    /// The stream ends right after the request, until it's rejected with
+    /// [`reject_upgrade`](Self::reject_upgrade). Accept it with
+    /// [`accept_upgrade`](Self::accept_upgrade).
+    pub fn peer_upgrade(&self) -> Option<&str> {
+        self.peer_upgrade.as_deref()
+    }
+
+    /// Accepts the upgrade the peer asked for, handing the connection over to the new protocol.
+    ///
+    /// Fails with [`InvalidInput`](io::ErrorKind::InvalidInput) if the peer didn't ask for one.
+    pub async fn accept_upgrade(mut self) -> io::Result<Upgraded> {
+        if self.peer_upgrade.is_none() {
+            return Err(no_upgrade());
+        }
+        self.answer_upgrade(UPGRADE_ACCEPT).await?;
+        Ok(Upgraded::new(self.inner))
+    }
+
+    /// Rejects the upgrade the peer asked for, so both sides go on exchanging frames.
+    ///
+    /// Fails with [`InvalidInput`](io::ErrorKind::InvalidInput) if the peer didn't ask for one.
+    pub async fn reject_upgrade(&mut self) -> io::Result<()> {
+        if self.peer_upgrade.take().is_none() {
+            return Err(no_upgrade());
+        }
+        self.answer_upgrade(UPGRADE_REJECT).await
+    }
+
+    async fn answer_upgrade(&mut self, answer: u8) -> io::Result<()> {
+        let reply = self.inner.write_buffer_mut();
+        reply.extend_from_slice(&UPGRADE);
+        reply.put_u8(answer);
+        SinkExt::flush(&mut self.inner).await
+    }
+
+    /// Sends the close notice once the lifetime of the connection is over, before the
+    /// connection shuts itself down.
+    fn poll_notice(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.notice_sent {
+            return Poll::Ready(());
+        }
+        let Some(reason) = self.inner.get_mut().poll_expiry(cx) else {
+            return Poll::Ready(());
+        };
+        // Frames that were only partly written go first, so the notice starts at a frame boundary
+        let notice = self.notice.get_or_insert_with(|| {
+            let mut notice = mem::take(self.inner.write_buffer_mut());
+            notice.extend_from_slice(&CLOSE_NOTICE);
+            notice.put_u8(encode_reason(reason));
+            notice
+        });
+        while !notice.is_empty() {
+            match ready!(self.inner.get_mut().poll_write_expired(cx, notice)) {
+                Ok(written) if written > 0 => notice.advance(written),
+                // The peer is gone, there's nobody left to tell
+                _ => break,
+            }
+        }
+        self.notice = None;
+        self.notice_sent = true;
+        Poll::Ready(())
+    }
+
+    /// Sends a single frame and flushes it.
+    pub async fn send(&mut self, frame: Bytes) -> io::Result<()> {
+        SinkExt::send(&mut self.inner, frame).await
+    }
+
+    /// Receives the next frame, or `None` once the peer closed the connection.
+    pub async fn recv(&mut self) -> Option<io::Result<Bytes>> {
+        StreamExt::next(self).await
+    }
+
+    /// Returns a reference to the underlying connection.
+    pub fn get_ref(&self) -> &Connection {
+        self.inner.get_ref()
+    }
+
+    /// Returns a mutable reference to the underlying connection.
+    ///
+    /// Reading from or writing to it directly corrupts the framing.
+    pub fn get_mut(&mut self) -> &mut Connection {
+        self.inner.get_mut()
+    }
+
+    /// Consumes the framing layer, returning the underlying connection.
+    ///
+    /// Bytes that were already read but not yet yielded as a frame are lost.
+    pub fn into_inner(self) -> Connection {
+        self.inner.into_inner()
+    }
+}
+
+fn no_upgrade() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        "the peer didn't ask to upgrade the connection",
+    )
+}
+
+impl fmt::Debug for FramedConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FramedConnection")
+            .field("max_frame_length", &self.max_frame_length())
+            .finish_non_exhaustive()
+    }
+}
+
+impl Stream for FramedConnection {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.peer_expiry.is_some() || this.peer_upgrade.is_some() {
+            return Poll::Ready(None);
+        }
+        ready!(this.poll_notice(cx));
+        match ready!(Pin::new(&mut this.inner).poll_next(cx)) {
+            Some(Ok(Item::Frame(frame))) => Poll::Ready(Some(Ok(frame.freeze()))),
+            Some(Ok(Item::CloseNotice(reason))) => {
+                this.peer_expiry = Some(reason);
+                Poll::Ready(None)
+            }
+            Some(Ok(Item::UpgradeRequest(protocol))) => {
+                this.peer_upgrade = Some(protocol);
+                Poll::Ready(None)
+            }
+            Some(Ok(Item::UpgradeReply(_))) => Poll::Ready(Some(Err(invalid_data(
+                "received an answer to an upgrade that wasn't asked for",
+            )))),
+            Some(Err(e)) => Poll::Ready(Some(Err(e))),
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+impl Sink<Bytes> for FramedConnection {
+    type Error = io::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_notice(cx));
+        Pin::new(&mut this.inner).poll_ready(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, frame: Bytes) -> io::Result<()> {
+        Pin::new(&mut self.inner).start_send(frame)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_notice(cx));
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_notice(cx));
+        Pin::new(&mut this.inner).poll_close(cx)
+    }
+}
+
+/// A connection handed over to another protocol by an upgrade.
+///
+/// Created by [`FramedConnection::upgrade`] and [`FramedConnection::accept_upgrade`]. Reads
+/// start with whatever the peer sent after the upgrade, including bytes that already arrived
+/// together with the upgrade messages.
+pub struct Upgraded {
+    conn: Connection,
+    read_buf: BytesMut,
+}
+
+impl Upgraded {
+    fn new(framed: Framed<Connection, FrameCodec>) -> Self {
+        let parts = framed.into_parts();
+        Self {
+            conn: parts.io,
+            read_buf: parts.read_buf,
+        }
+    }
+
+    /// Returns a reference to the underlying connection.
+    pub fn get_ref(&self) -> &Connection {
+        &self.conn
+    }
+
+    /// Returns a mutable reference to the underlying connection.
+    ///
+    /// Reading from it directly skips bytes that were already received.
+    pub fn get_mut(&mut self) -> &mut Connection {
+        &mut self.conn
+    }
+
+    /// Consumes the upgraded connection, returning the underlying connection and the bytes that
+    /// were already received from it.
+    pub fn into_parts(self) -> (Connection, Bytes) {
+        (self.conn, self.read_buf.freeze())
+    }
+}
+
+impl fmt::Debug for Upgraded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Upgraded")
+            .field("buffered", &self.read_buf.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl AsyncRead for Upgraded {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if this.read_buf.is_empty() {
+            return Pin::new(&mut this.conn).poll_read(cx, buf);
+        }
+        let len = this.read_buf.len().min(buf.remaining());
+        buf.put_slice(&this.read_buf.split_to(len));
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for Upgraded {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().conn).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().conn).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().conn).poll_shutdown(cx)
+    }
+}
+
+/// Stream of incoming connections, wrapped in [`FramedConnection`]s as they're accepted.
+///
+/// Created by [`Endpoint::incoming_framed`](crate::Endpoint::incoming_framed) or
+/// [`IpcStream::framed`]. Accept errors are yielded as items, the stream only ends once it was
+/// shut down through its [`ShutdownHandle`](crate::ShutdownHandle).
+pub struct IncomingFramed {
+    inner: IpcStream,
+    max_frame_length: usize,
+}
+
+impl IncomingFramed {
+    pub(crate) fn new(inner: IpcStream) -> Self {
+        Self {
+            inner,
+            max_frame_length: DEFAULT_MAX_FRAME_LENGTH,
+        }
+    }
+
+    /// Returns the maximum frame length of accepted connections.
+    pub fn max_frame_length(&self) -> usize {
+        self.max_frame_length
+    }
+
+    /// Rejects frames longer than `max_frame_length` bytes on connections accepted from now on.
+    pub fn set_max_frame_length(&mut self, max_frame_length: usize) {
+        self.max_frame_length = max_frame_length;
+    }
+
+    /// Waits for the next incoming connection.
+    ///
+    /// Fails with [`io::ErrorKind::NotConnected`] once the stream was shut down.
+    pub async fn accept(&mut self) -> io::Result<FramedConnection> {
+        futures::future::poll_fn(|cx| self.poll_accept(cx)).await
+    }
+
+    /// Polls for the next incoming connection, see [`IpcStream::poll_accept`].
+    pub fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<FramedConnection>> {
+        let conn = ready!(self.inner.poll_accept(cx))?;
+        Poll::Ready(Ok(FramedConnection::with_max_frame_length(
+            conn,
+            self.max_frame_length,
+        )))
+    }
+
+    /// Returns a reference to the underlying stream of connections.
+    pub fn get_ref(&self) -> &IpcStream {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying stream of connections.
+    pub fn get_mut(&mut self) -> &mut IpcStream {
+        &mut self.inner
+    }
+
+    /// Consumes the framing layer, returning the underlying stream of connections.
+    pub fn into_inner(self) -> IpcStream {
+        self.inner
+    }
+
+    /// Wraps accepted connections in [`TypedConnection`](crate::typed::TypedConnection)s that
+    /// encode messages with `codec`.
+    ///
+    /// Requires the `typed` feature.
+    #[cfg(feature = "typed")]
+    pub fn typed<T, C>(self, codec: C) -> crate::typed::IncomingTyped<T, C>
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+        C: crate::typed::Codec + Clone,
+    {
+        crate::typed::IncomingTyped::new(self, codec)
+    }
+}
+
+impl fmt::Debug for IncomingFramed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IncomingFramed")
+            .field("max_frame_length", &self.max_frame_length)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Stream for IncomingFramed {
+    type Item = io::Result<FramedConnection>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let conn = ready!(Pin::new(&mut this.inner).poll_next(cx));
+        Poll::Ready(conn.map(|conn| {
+            let conn = conn?;
+            Ok(FramedConnection::with_max_frame_length(
+                conn,
+                this.max_frame_length,
+            ))
+        }))
+    }
+}
@@ -0,0 +1,327 @@
+//! Liveness checking through periodic ping/pong frames.
+//!
+//! A peer that hangs without closing its end of the connection looks exactly like one that has
+//! nothing to say. [`HeartbeatConnection`] sends a ping whenever nothing was received for a
+//! while and answers the peer's pings, so a peer that stops responding surfaces as a
+//! [`TimedOut`](io::ErrorKind::TimedOut) error on the stream. Both sides of the connection must
+//! use it.
+//!
+//! [`HeartbeatConnection::ping`] sends a ping on demand and measures how long the peer takes to
+//! answer it, for tooling that needs to know whether the peer is responsive rather than just
+//! connected. The round-trip times of all pings are summarized in [`RoundTripTime`] and, for
+//! accepted connections, added to the [endpoint statistics](crate::EndpointStatsSnapshot).
+//!
+//! Requires the `heartbeat` feature.
+
+use std::collections::VecDeque;
+use std::future::{poll_fn, Future};
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use std::{fmt, io};
+
+use bytes::{BufMut, Bytes, BytesMut};
+use futures::{Sink, SinkExt, Stream, StreamExt};
+use tokio::time::{sleep, Instant, Sleep};
+
+use crate::framing::FramedConnection;
+
+const DATA: u8 = 0;
+const PING: u8 = 1;
+const PONG: u8 = 2;
+
+/// Options for a [`HeartbeatConnection`].
+#[derive(Debug, Clone)]
+pub struct HeartbeatOptions {
+    /// How long to wait for a frame from the peer before sending a ping.
+    pub interval: Duration,
+    /// How long the peer may stay silent, pings included, before it's considered dead.
+    ///
+    /// Should be a few times the `interval` of the peer.
+    pub timeout: Duration,
+}
+
+impl Default for HeartbeatOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(5),
+            timeout: Duration::from_secs(15),
+        }
+    }
+}
+
+/// Round-trip times measured by the pings of a [`HeartbeatConnection`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoundTripTime {
+    /// The most recent round-trip time.
+    pub last: Duration,
+    /// The shortest round-trip time.
+    pub min: Duration,
+    /// The longest round-trip time.
+    pub max: Duration,
+    /// Moving average that weights recent round trips more, computed like TCP's smoothed RTT.
+    pub smoothed: Duration,
+    /// Number of round trips measured.
+    pub samples: u64,
+}
+
+impl RoundTripTime {
+    fn new(rtt: Duration) -> Self {
+        Self {
+            last: rtt,
+            min: rtt,
+            max: rtt,
+            smoothed: rtt,
+            samples: 1,
+        }
+    }
+
+    fn record(&mut self, rtt: Duration) {
+        self.last = rtt;
+        self.min = self.min.min(rtt);
+        self.max = self.max.max(rtt);
+        self.smoothed = self.smoothed * 7 / 8 + rtt / 8;
+        self.samples += 1;
+    }
+}
+
+/// A [`FramedConnection`] that checks the liveness of the peer in the background of reads.
+///
+/// Application frames are sent and received like on the underlying connection, heartbeat
+/// frames are handled internally. Pings are only sent and answered while the stream is polled,
+/// so the connection should be read from continuously, for example from a dedicated task after
+/// [`split`](StreamExt::split)ting it.
+pub struct HeartbeatConnection {
+    inner: FramedConnection,
+    options: HeartbeatOptions,
+    ping_timer: Pin<Box<Sleep>>,
+    deadline: Pin<Box<Sleep>>,
+    send_ping: bool,
+    // the pong answering the last ping of the peer, until it's sent
+    pong: Option<Bytes>,
+    next_ping_id: u64,
+    // pings that weren't answered yet, oldest first
+    sent_pings: VecDeque<(u64, Instant)>,
+    // the id of the last answered ping, with its round-trip time
+    last_pong: Option<(u64, Duration)>,
+    round_trip_time: Option<RoundTripTime>,
+    // application frames received while waiting for a pong
+    received: VecDeque<Bytes>,
+}
+
+impl HeartbeatConnection {
+    /// Wraps a framed connection.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn new(conn: FramedConnection, options: Option<HeartbeatOptions>) -> Self {
+        let options = options.unwrap_or_default();
+        Self {
+            inner: conn,
+            ping_timer: Box::pin(sleep(options.interval)),
+            deadline: Box::pin(sleep(options.timeout)),
+            options,
+            send_ping: false,
+            pong: None,
+            next_ping_id: 0,
+            sent_pings: VecDeque::new(),
+            last_pong: None,
+            round_trip_time: None,
+            received: VecDeque::new(),
+        }
+    }
+
+    /// Sends a single frame and flushes it.
+    pub async fn send(&mut self, frame: Bytes) -> io::Result<()> {
+        SinkExt::send(self, frame).await
+    }
+
+    /// Receives the next frame, or `None` once the peer closed the connection.
+    pub async fn recv(&mut self) -> Option<io::Result<Bytes>> {
+        StreamExt::next(self).await
+    }
+
+    /// Sends a ping right away and waits for the peer to answer it, returning the round-trip time.
+    ///
+    /// Application frames received in the meantime are kept for the stream. Fails with
+    /// [`TimedOut`](io::ErrorKind::TimedOut) if the peer stays silent for the configured timeout,
+    /// and with [`UnexpectedEof`](io::ErrorKind::UnexpectedEof) if it closes the connection first.
+    pub async fn ping(&mut self) -> io::Result<Duration> {
+        // A ping that's already due is sent with this id as well
+        let id = self.next_ping_id;
+        self.send_ping = true;
+        poll_fn(|cx| self.poll_pong(cx, id)).await
+    }
+
+    /// Returns the round-trip times measured so far, by [`ping`](Self::ping) as well as by the
+    /// pings sent while the connection is idle.
+    ///
+    /// `None` until the first ping was answered.
+    pub fn round_trip_time(&self) -> Option<RoundTripTime> {
+        self.round_trip_time
+    }
+
+    /// Returns a reference to the underlying connection.
+    pub fn get_ref(&self) -> &FramedConnection {
+        &self.inner
+    }
+
+    /// Consumes the heartbeat layer, returning the underlying connection.
+    pub fn into_inner(self) -> FramedConnection {
+        self.inner
+    }
+
+    fn poll_send_control(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.pong.is_some() || self.send_ping {
+            ready!(self.inner.poll_ready_unpin(cx))?;
+            if let Some(pong) = self.pong.take() {
+                self.inner.start_send_unpin(pong)?;
+                continue;
+            }
+            let id = self.next_ping_id;
+            let mut ping = BytesMut::with_capacity(9);
+            ping.put_u8(PING);
+            ping.put_u64(id);
+            self.inner.start_send_unpin(ping.freeze())?;
+            self.next_ping_id += 1;
+            self.sent_pings.push_back((id, Instant::now()));
+            self.send_ping = false;
+        }
+        self.inner.poll_flush_unpin(cx)
+    }
+
+    /// Answers a ping of the peer by echoing its id.
+    fn queue_pong(&mut self, id: &[u8]) {
+        let mut pong = BytesMut::with_capacity(id.len() + 1);
+        pong.put_u8(PONG);
+        pong.put_slice(id);
+        self.pong = Some(pong.freeze());
+    }
+
+    /// Records the answer to one of our pings. Pongs arrive in order, so earlier pings that
+    /// weren't answered separately are settled as well.
+    fn record_pong(&mut self, id: &[u8]) {
+        let Ok(id) = <[u8; 8]>::try_from(id).map(u64::from_be_bytes) else {
+            return;
+        };
+        while let Some(&(sent_id, sent)) = self.sent_pings.front() {
+            if sent_id > id {
+                break;
+            }
+            self.sent_pings.pop_front();
+            if sent_id == id {
+                let rtt = sent.elapsed();
+                self.last_pong = Some((id, rtt));
+                match &mut self.round_trip_time {
+                    Some(round_trip_time) => round_trip_time.record(rtt),
+                    None => self.round_trip_time = Some(RoundTripTime::new(rtt)),
+                }
+                self.inner.get_ref().record_round_trip(rtt);
+            }
+        }
+    }
+
+    /// Polls for the answer to the ping with `id`, keeping application frames for the stream.
+    fn poll_pong(&mut self, cx: &mut Context<'_>, id: u64) -> Poll<io::Result<Duration>> {
+        loop {
+            if let Some((answered, rtt)) = self.last_pong {
+                if answered >= id {
+                    return Poll::Ready(Ok(rtt));
+                }
+            }
+            match ready!(self.poll_frame(cx)) {
+                Some(Ok(frame)) => self.received.push_back(frame),
+                Some(Err(e)) => return Poll::Ready(Err(e)),
+                None => {
+                    return Poll::Ready(Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "peer closed the connection before answering the ping",
+                    )))
+                }
+            }
+        }
+    }
+
+    /// Polls for the next application frame, handling heartbeat frames along the way.
+    fn poll_frame(&mut self, cx: &mut Context<'_>) -> Poll<Option<io::Result<Bytes>>> {
+        loop {
+            // Pending only means the control frames are still being written
+            if let Poll::Ready(Err(e)) = self.poll_send_control(cx) {
+                return Poll::Ready(Some(Err(e)));
+            }
+
+            match self.inner.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(mut frame))) => {
+                    let now = Instant::now();
+                    self.deadline.as_mut().reset(now + self.options.timeout);
+                    self.ping_timer.as_mut().reset(now + self.options.interval);
+                    match frame.first() {
+                        Some(&DATA) => return Poll::Ready(Some(Ok(frame.split_off(1)))),
+                        Some(&PING) => self.queue_pong(&frame[1..]),
+                        Some(&PONG) => self.record_pong(&frame[1..]),
+                        _ => tracing::trace!("Dropping invalid heartbeat frame"),
+                    }
+                    continue;
+                }
+                Poll::Ready(frame) => return Poll::Ready(frame),
+                Poll::Pending => {}
+            }
+
+            if self.deadline.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Some(Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "peer stopped responding to heartbeats",
+                ))));
+            }
+            if self.ping_timer.as_mut().poll(cx).is_pending() {
+                return Poll::Pending;
+            }
+            self.ping_timer
+                .as_mut()
+                .reset(Instant::now() + self.options.interval);
+            self.send_ping = true;
+        }
+    }
+}
+
+impl fmt::Debug for HeartbeatConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HeartbeatConnection")
+            .field("inner", &self.inner)
+            .field("options", &self.options)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Stream for HeartbeatConnection {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if let Some(frame) = self.received.pop_front() {
+            return Poll::Ready(Some(Ok(frame)));
+        }
+        self.poll_frame(cx)
+    }
+}
+
+impl Sink<Bytes> for HeartbeatConnection {
+    type Error = io::Error;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.inner.poll_ready_unpin(cx)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, frame: Bytes) -> io::Result<()> {
+        let mut tagged = BytesMut::with_capacity(frame.len() + 1);
+        tagged.put_u8(DATA);
+        tagged.put_slice(&frame);
+        self.inner.start_send_unpin(tagged.freeze())
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.inner.poll_flush_unpin(cx)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.inner.poll_close_unpin(cx)
+    }
+}
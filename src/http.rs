@@ -0,0 +1,192 @@
+//! HTTP/1 over IPC with `hyper`.
+//!
+//! [`Connection`] implements hyper's `Read` and `Write`, so it can be handed to hyper directly.
+//! [`Connector`] picks the endpoint to dial from the authority of a request URI, the way clients of
+//! the Docker daemon talk to `http://localhost/...` over a Unix socket, and [`Acceptor`] serves a
+//! hyper service on the connections of an [`IpcStream`].
+//!
+//! Requires the `hyper` feature.
+
+use std::collections::HashMap;
+use std::error::Error as StdError;
+use std::future::Future;
+use std::path::Path;
+use std::pin::pin;
+use std::sync::Arc;
+use std::{fmt, io};
+
+use futures::future::{self, Either};
+use hyper::body::{Body, Incoming};
+use hyper::client::conn::http1::{self as client, SendRequest};
+use hyper::server::conn::http1::Builder;
+use hyper::service::Service;
+use hyper::{Request, Response, Uri};
+use tokio::sync::watch;
+
+use crate::{Connection, Endpoint, EndpointOptions, IntoIpcPath, IpcStream};
+
+fn hyper_error(e: hyper::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+/// Dials the endpoint that serves the authority of a request URI.
+///
+/// Authorities are looked up as a whole first, then by their host alone, so a route for
+/// `localhost` also serves `localhost:8080`. URIs no route matches go to the fallback endpoint.
+#[derive(Clone, Debug)]
+pub struct Connector {
+    routes: HashMap<String, Arc<Path>>,
+    fallback: Option<Arc<Path>>,
+    options: Option<EndpointOptions>,
+}
+
+impl Connector {
+    /// Creates a connector without any routes, dialing endpoints with `options`.
+    pub fn new(options: Option<EndpointOptions>) -> Self {
+        Self {
+            routes: HashMap::new(),
+            fallback: None,
+            options,
+        }
+    }
+
+    /// Dials the endpoint at `path` for URIs with the given authority.
+    pub fn route(
+        mut self,
+        authority: impl Into<String>,
+        path: impl IntoIpcPath,
+    ) -> io::Result<Self> {
+        self.routes.insert(
+            authority.into().to_ascii_lowercase(),
+            path.into_ipc_path()?.into(),
+        );
+        Ok(self)
+    }
+
+    /// Dials the endpoint at `path` for URIs that no route matches.
+    pub fn fallback(mut self, path: impl IntoIpcPath) -> io::Result<Self> {
+        self.fallback = Some(path.into_ipc_path()?.into());
+        Ok(self)
+    }
+
+    fn resolve(&self, uri: &Uri) -> Option<&Arc<Path>> {
+        let lookup = |key: &str| self.routes.get(&key.to_ascii_lowercase());
+        uri.authority()
+            .and_then(|authority| lookup(authority.as_str()).or_else(|| lookup(authority.host())))
+            .or(self.fallback.as_ref())
+    }
+
+    /// Connects to the endpoint for `uri`.
+    ///
+    /// Fails with [`NotFound`](io::ErrorKind::NotFound) if no route matches and there's no
+    /// fallback.
+    pub async fn connect(&self, uri: &Uri) -> io::Result<Connection> {
+        let Some(path) = self.resolve(uri) else {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("No IPC endpoint for {uri}"),
+            ));
+        };
+        Endpoint::connect_shared(path.clone(), self.options).await
+    }
+
+    /// Connects to the endpoint for `uri` and performs an HTTP/1 handshake over the connection.
+    ///
+    /// The connection is driven by a background task until the returned sender and all of its
+    /// responses are dropped. Must be called from within a Tokio runtime.
+    pub async fn handshake<B>(&self, uri: &Uri) -> io::Result<SendRequest<B>>
+    where
+        B: Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        let conn = self.connect(uri).await?;
+        let (sender, connection) = client::handshake(conn).await.map_err(hyper_error)?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                tracing::trace!("HTTP connection failed: {e:?}");
+            }
+        });
+        Ok(sender)
+    }
+
+    /// Sends a single request to the endpoint for its URI and waits for the response head.
+    pub async fn send<B>(&self, req: Request<B>) -> io::Result<Response<Incoming>>
+    where
+        B: Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        let mut sender = self.handshake(req.uri()).await?;
+        sender.send_request(req).await.map_err(hyper_error)
+    }
+}
+
+/// Serves a hyper service over HTTP/1 on the connections of an [`IpcStream`].
+pub struct Acceptor {
+    incoming: IpcStream,
+    builder: Builder,
+}
+
+impl fmt::Debug for Acceptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Acceptor")
+            .field("builder", &self.builder)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Acceptor {
+    /// Serves the connections of `incoming` with hyper's default HTTP/1 settings.
+    pub fn new(incoming: IpcStream) -> Self {
+        Self {
+            incoming,
+            builder: Builder::new(),
+        }
+    }
+
+    /// Returns the HTTP/1 settings connections are served with, for tuning them.
+    pub fn builder_mut(&mut self) -> &mut Builder {
+        &mut self.builder
+    }
+
+    /// Serves every accepted connection with a clone of `service` until `shutdown` resolves.
+    ///
+    /// Once `shutdown` resolves, no more connections are accepted and open connections are shut
+    /// down gracefully, finishing the requests in flight. Like [`Endpoint::serve`], this returns
+    /// once all of them are closed. Connections that fail are logged at the trace level.
+    pub async fn serve<S, B>(self, service: S, shutdown: impl Future<Output = ()>) -> io::Result<()>
+    where
+        S: Service<Request<Incoming>, Response = Response<B>> + Clone + Send + 'static,
+        S::Future: Send + 'static,
+        S::Error: Into<Box<dyn StdError + Send + Sync>>,
+        B: Body + Send + 'static,
+        B::Data: Send,
+        B::Error: Into<Box<dyn StdError + Send + Sync>>,
+    {
+        let (stop, stopped) = watch::channel(());
+        let shutdown = async move {
+            shutdown.await;
+            let _ = stop.send(());
+        };
+        let builder = self.builder;
+        let handler = move |conn: Connection| {
+            let connection = builder.serve_connection(conn, service.clone());
+            let mut stopped = stopped.clone();
+            async move {
+                let connection = pin!(connection);
+                let result = match future::select(connection, pin!(stopped.changed())).await {
+                    Either::Left((result, _)) => result,
+                    Either::Right((_, mut connection)) => {
+                        connection.as_mut().graceful_shutdown();
+                        connection.await
+                    }
+                };
+                if let Err(e) = result {
+                    tracing::trace!("HTTP connection failed: {e:?}");
+                }
+            }
+        };
+        self.incoming.serve(handler, shutdown).await
+    }
+}
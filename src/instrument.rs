@@ -0,0 +1,92 @@
+//! Tracing spans and events of connections, emitted with the `instrument` feature.
+//!
+//! Every connection that's accepted or connected gets an `ipc_connection` span with an id, its
+//! role, the endpoint path and the peer's credentials where available. Reads, writes and
+//! shutdowns are recorded as events inside of it, and the span closes when the connection is
+//! dropped. Without the feature, connections carry a disabled span and nothing is recorded.
+
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use tracing::Span;
+
+use crate::platform;
+
+const ENABLED: bool = cfg!(feature = "instrument");
+
+static NEXT_CONNECTION_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Creates the span the events of a connection are recorded in.
+pub(crate) fn connection_span(
+    role: &'static str,
+    path: Option<&Path>,
+    conn: &platform::Connection,
+) -> Span {
+    if !ENABLED {
+        return Span::none();
+    }
+    let id = NEXT_CONNECTION_ID.fetch_add(1, Ordering::Relaxed);
+    let span = tracing::debug_span!(
+        "ipc_connection",
+        id,
+        role,
+        path = ?path,
+        peer_pid = tracing::field::Empty,
+        peer_uid = tracing::field::Empty,
+        peer_sid = tracing::field::Empty,
+    );
+    if let Ok(credentials) = platform::peer_credentials(conn) {
+        if let Some(pid) = credentials.pid() {
+            span.record("peer_pid", pid);
+        }
+        #[cfg(unix)]
+        span.record("peer_uid", credentials.uid());
+        #[cfg(windows)]
+        span.record("peer_sid", credentials.sid());
+    }
+    span
+}
+
+pub(crate) fn record_open(span: &Span) {
+    if ENABLED {
+        span.in_scope(|| tracing::debug!("Connection opened"));
+    }
+}
+
+pub(crate) fn record_connect_error(path: &Path, error: &io::Error) {
+    if ENABLED {
+        tracing::debug!(path = ?path, error = %error, "Connect failed");
+    }
+}
+
+pub(crate) fn record_read(result: &io::Result<()>, bytes: usize) {
+    if !ENABLED {
+        return;
+    }
+    match result {
+        Ok(()) if bytes == 0 => tracing::trace!("Read reached the end of the stream"),
+        Ok(()) => tracing::trace!(bytes, "Read"),
+        Err(e) => tracing::debug!(error = %e, "Read failed"),
+    }
+}
+
+pub(crate) fn record_write(result: &io::Result<usize>) {
+    if !ENABLED {
+        return;
+    }
+    match result {
+        Ok(bytes) => tracing::trace!(bytes, "Wrote"),
+        Err(e) => tracing::debug!(error = %e, "Write failed"),
+    }
+}
+
+pub(crate) fn record_shutdown(result: &io::Result<()>) {
+    if !ENABLED {
+        return;
+    }
+    match result {
+        Ok(()) => tracing::debug!("Shut down"),
+        Err(e) => tracing::debug!(error = %e, "Shutdown failed"),
+    }
+}
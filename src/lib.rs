@@ -19,34 +19,131 @@ mod unix;
 #[cfg(windows)]
 mod win;
 
+#[cfg(unix)]
+mod ancillary;
+#[cfg(feature = "bridge")]
+pub mod bridge;
+pub mod broadcast;
+mod busy_poll;
+#[cfg(feature = "capture")]
+pub mod capture;
+#[cfg(unix)]
+pub mod datagram;
+pub mod discovery;
+mod extensions;
+#[cfg(feature = "framing")]
+pub mod framing;
+#[cfg(feature = "futures-io")]
+mod futures_compat;
+#[cfg(feature = "heartbeat")]
+pub mod heartbeat;
+#[cfg(feature = "hyper")]
+pub mod http;
+#[cfg(feature = "hyper")]
+mod hyper_rt;
+mod instrument;
+mod lifetime;
+#[cfg(any(feature = "mux", feature = "pubsub"))]
+pub mod memory;
+mod metrics;
+pub mod middleware;
+#[cfg(feature = "mux")]
+pub mod mux;
+mod peer;
+#[cfg(all(target_os = "linux", feature = "polkit"))]
+mod polkit;
+mod pool;
+#[cfg(feature = "process")]
+pub mod process;
+#[cfg(feature = "proxy")]
+pub mod proxy;
+#[cfg(feature = "pubsub")]
+pub mod pubsub;
+mod reconnect;
+#[cfg(feature = "rpc")]
+pub mod rpc;
+#[cfg(feature = "shm")]
+pub mod shm;
+mod shutdown;
+mod split;
+mod stats;
+pub mod sync;
+#[cfg(feature = "test-util")]
+pub mod test;
+#[cfg(feature = "test-util")]
+pub use self::test as testing;
+mod timeout;
+#[cfg(feature = "tower")]
+pub mod tower;
+#[cfg(feature = "typed")]
+pub mod typed;
+
+use std::future::{poll_fn, Future};
 use std::io;
+use std::num::NonZeroUsize;
+#[cfg(unix)]
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+#[cfg(windows)]
+use std::os::windows::io::{AsHandle, AsRawHandle, BorrowedHandle, OwnedHandle, RawHandle};
 use std::path::{Path, PathBuf};
-use std::pin::Pin;
-use std::task::{Context, Poll};
+use std::pin::{pin, Pin};
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, Instant};
 
+#[cfg(feature = "futures")]
 use futures::Stream;
-use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::io::{AsyncRead, AsyncWrite, Interest, ReadBuf, Ready};
+use tokio::sync::{oneshot, Semaphore};
+use tokio::task::JoinSet;
+use tracing::Span;
+
+use crate::busy_poll::BusyPoll;
+use crate::lifetime::Lifetime;
+use crate::stats::{ConnectionStats, EndpointStats};
+use crate::timeout::Timeout;
 
 mod platform {
     #[cfg(unix)]
     pub use crate::unix::EndpointOptions;
     #[cfg(unix)]
     pub(crate) use crate::unix::{
-        from_std_stream, Connection, Endpoint, IpcStream, SecurityAttributes,
+        default_folder, from_std_stream, group_id, is_idle, local_path, pair, peek,
+        peer_credentials, peer_path, recv_buffer_size, send_buffer_size, set_recv_buffer_size, set_send_buffer_size,
+        wait_for_path, Connection, Endpoint, IpcStream, OwnedReadHalf, OwnedWriteHalf,
+        SecurityAttributes,
     };
+    #[cfg(target_os = "linux")]
+    pub(crate) use crate::unix::peer_security_context;
     #[cfg(windows)]
     pub(crate) use crate::win::{
-        Connection, Endpoint, IpcStream, SecurityAttributes,
+        default_folder, from_owned_handle, is_idle, local_path, pair, peek, peer_credentials,
+        peer_path, wait_for_pipe, Connection, Endpoint, IpcStream, OwnedReadHalf, OwnedWriteHalf,
+        SecurityAttributes,
     };
     #[cfg(windows)]
     pub use crate::win::EndpointOptions;
     #[cfg(windows)]
+    pub use crate::win::{Impersonation, NamedPipe, PipeInfo};
+    #[cfg(windows)]
     pub use tokio::net::windows::named_pipe::PipeMode;
 }
 
+#[cfg(target_os = "linux")]
+pub use ancillary::ReceivedCredentials;
+pub use extensions::Extensions;
+pub use lifetime::ExpiryReason;
+pub use peer::PeerCredentials;
 pub use platform::EndpointOptions;
 #[cfg(windows)]
 pub use platform::PipeMode;
+#[cfg(windows)]
+pub use platform::{Impersonation, NamedPipe, PipeInfo};
+pub use pool::{Pool, PooledConnection};
+pub use reconnect::{Backoff, ConnectionState, ReconnectingConnection};
+pub use shutdown::ShutdownHandle;
+pub use split::{OwnedReadHalf, OwnedWriteHalf};
+pub use stats::{EndpointStatsHandle, EndpointStatsSnapshot};
 
 /// Path used for an IPC client or server.
 pub trait IntoIpcPath: Send {
@@ -61,12 +158,117 @@ impl IntoIpcPath for PathBuf {
 }
 
 /// How to proceed when the socket path already exists
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
 pub enum OnConflict {
     /// Throw an error when attempting to bind to the path
+    #[default]
     Error,
     /// Overwrite the existing socket
     Overwrite,
+    /// Overwrite the existing socket only if no server is listening on it anymore
+    ///
+    /// The socket is probed with a connection attempt first. If a server accepts it, binding
+    /// fails with [`io::ErrorKind::AddrInUse`] instead of taking the path over from the running
+    /// server. Paths that aren't sockets are never removed.
+    OverwriteIfStale,
+}
+
+/// How a server reacts when its socket file is deleted or replaced while it's running
+///
+/// Temp cleaners or other processes removing the socket file leave the listener bound to an
+/// unreachable socket, so new clients can't connect anymore. The socket's folder is watched with
+/// filesystem notifications, or checked every 100 ms without the `notify` feature.
+#[cfg(unix)]
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum OnSocketRemoved {
+    /// Don't watch the socket file
+    #[default]
+    Ignore,
+    /// Bind a new socket at the same path, replacing any file that took its place
+    Rebind,
+    /// Fail every further accept with a [`io::ErrorKind::NotFound`] error wrapping
+    /// [`SocketRemoved`]
+    Error,
+}
+
+/// Error the accept stream fails with once the socket file was deleted or replaced and
+/// [`OnSocketRemoved::Error`] is set
+///
+/// It's wrapped in an [`io::Error`] of kind [`io::ErrorKind::NotFound`], from which it can be
+/// recovered with [`io::Error::get_ref`] and `downcast_ref`.
+#[cfg(unix)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocketRemoved {
+    path: PathBuf,
+}
+
+#[cfg(unix)]
+impl SocketRemoved {
+    /// Path of the socket file that is gone
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(unix)]
+impl std::fmt::Display for SocketRemoved {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Socket file {:?} was deleted or replaced", self.path)
+    }
+}
+
+#[cfg(unix)]
+impl std::error::Error for SocketRemoved {}
+
+#[cfg(unix)]
+impl From<SocketRemoved> for io::Error {
+    fn from(removed: SocketRemoved) -> Self {
+        Self::new(io::ErrorKind::NotFound, removed)
+    }
+}
+
+/// Scheduling class of a [`Connection`], for prioritizing latency-sensitive clients when a
+/// server is saturated
+///
+/// Accepted connections are classified with [`IpcStream::set_qos_classifier`]. [`IpcStream::serve`]
+/// caps how many batch connections are handled at once, and the pub/sub `Publisher` gives batch
+/// clients less room for undelivered messages.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum QosClass {
+    /// Latency-sensitive traffic, like a user waiting for an answer
+    #[default]
+    Interactive,
+    /// Bulk traffic that can wait while interactive connections are served
+    Batch,
+}
+
+/// Timeout and retry behavior for [`Endpoint::connect_with`]
+///
+/// The default matches [`Endpoint::connect`]: no timeout and no retries.
+#[derive(Debug, Default, Clone)]
+pub struct ConnectOptions {
+    /// Upper bound for the whole connect, including retries and the wait for a busy pipe
+    ///
+    /// Fails with [`io::ErrorKind::TimedOut`] once it elapses.
+    pub timeout: Option<Duration>,
+    /// Retry failed attempts, spaced out according to the backoff
+    ///
+    /// Only errors that may go away on their own are retried: a missing socket or pipe, a refused
+    /// connection, or a busy pipe that didn't free up in time. [`Backoff::max_attempts`] bounds
+    /// the total number of attempts, including the first one.
+    pub retry: Option<Backoff>,
+}
+
+/// Identity a client expects the server of a connection to run as
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ServerIdentity {
+    /// The server runs as the same user as the client
+    CurrentUser,
+    /// The server runs as a privileged system account (root on Unix, `LocalSystem` on Windows)
+    System,
+    /// The server runs as the user with the given UID
+    #[cfg(unix)]
+    Uid(u32),
 }
 
 /// Cross-platform representation of an IPC connection path
@@ -74,11 +276,16 @@ pub enum OnConflict {
 /// Calling [`IntoIpcPath::into_ipc_path`] on this struct will generate a platform-specific IPC
 /// path.
 ///
-/// Windows: `\\.\pipe\{serverId}`
+/// Windows: `\\.\pipe\{serverId}` (or `\\{host}\pipe\{serverId}` with a
+/// [`remote_host`](Self::remote_host))
 ///
-/// Mac: `$TMPDIR/{serverId}.sock`
+/// Mac: `$TMPDIR/tokio-ipc-{uid}/{serverId}.sock`
 ///
-/// Linux: `$XDG_RUNTIME_DIR/{serverId}.sock` (defaults to `$TMPDIR` if it doesn't exist)
+/// Linux: `$XDG_RUNTIME_DIR/{serverId}.sock` (defaults to `$TMPDIR/tokio-ipc-{uid}` if it doesn't
+/// exist)
+///
+/// The `tokio-ipc-{uid}` folder is created with `0700` permissions. Generating the path fails if
+/// the folder or the temp dir can be tampered with by other users.
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct ServerId<T>
 where
@@ -86,6 +293,7 @@ where
 {
     id: T,
     parent_folder: Option<PathBuf>,
+    remote_host: Option<String>,
 }
 
 impl<T> ServerId<T>
@@ -97,6 +305,7 @@ where
         Self {
             id,
             parent_folder: None,
+            remote_host: None,
         }
     }
 
@@ -106,6 +315,30 @@ where
         self.parent_folder = Some(folder.into());
         self
     }
+
+    /// Targets a named pipe on another machine, reached over SMB. This only has an effect on
+    /// Windows systems.
+    ///
+    /// Connecting to the resulting path requires
+    /// [`EndpointOptions::allow_remote`](crate::EndpointOptions) to be set.
+    pub fn remote_host(mut self, host: impl Into<String>) -> Self {
+        self.remote_host = Some(host.into());
+        self
+    }
+}
+
+impl ServerId<String> {
+    /// Creates a [`ServerId`] with an unpredictable name starting with `prefix`.
+    ///
+    /// Well-known names let other processes probe for the server or squat on its name before it
+    /// starts. A randomized name avoids both, as long as it's only shared with trusted clients,
+    /// for example through [`discovery::advertise`] or by handing it to child processes.
+    pub fn randomized(prefix: &str) -> io::Result<Self> {
+        let mut bytes = [0; 16];
+        getrandom::getrandom(&mut bytes)?;
+        let suffix: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+        Ok(Self::new(format!("{prefix}-{suffix}")))
+    }
 }
 
 impl<T> IntoIpcPath for ServerId<T>
@@ -117,6 +350,27 @@ where
     }
 }
 
+/// Waits until a server socket exists at the given path.
+///
+/// Instead of polling, this subscribes to filesystem notifications for the parent folder (inotify
+/// on Linux, kqueue on macOS and BSD), so the future resolves as soon as the server binds. Without
+/// the `notify` feature, which is enabled by default, the path is checked every 100 ms instead.
+/// Wrap it in [`tokio::time::timeout`] to bound the wait.
+#[cfg(unix)]
+pub async fn wait_for_path(path: impl IntoIpcPath) -> io::Result<()> {
+    platform::wait_for_path(&path.into_ipc_path()?).await
+}
+
+/// Waits until an instance of the named pipe at the given path is available for connecting.
+///
+/// Unlike the busy-retry in [`Endpoint::connect`], this also covers the case where the server
+/// hasn't created the pipe yet. Fails with [`io::ErrorKind::TimedOut`] if no instance became
+/// available within `timeout`.
+#[cfg(windows)]
+pub async fn wait_for_pipe(path: impl IntoIpcPath, timeout: Duration) -> io::Result<()> {
+    platform::wait_for_pipe(&path.into_ipc_path()?, timeout).await
+}
+
 /// Permissions and ownership for the IPC connection
 pub struct SecurityAttributes(platform::SecurityAttributes);
 
@@ -140,6 +394,85 @@ impl SecurityAttributes {
     pub fn allow_everyone_create() -> io::Result<Self> {
         Ok(Self(platform::SecurityAttributes::allow_everyone_create()?))
     }
+
+    /// New security attributes that only allow peers running as the same user as the server.
+    ///
+    /// File permissions alone aren't enough in every location, so the user of each peer is also
+    /// checked when accepting: via the peer credentials on Unix and via the client process token
+    /// on Windows. Connections from other users are dropped before they're yielded.
+    pub fn same_user_only() -> io::Result<Self> {
+        Ok(Self(platform::SecurityAttributes::same_user_only()?))
+    }
+
+    /// Hand the socket file over to the user `uid` once it's bound.
+    ///
+    /// Changing the owner usually requires root privileges, binding fails otherwise.
+    #[cfg(unix)]
+    pub fn set_owner(self, uid: u32) -> io::Result<Self> {
+        Ok(Self(self.0.set_owner(uid)?))
+    }
+
+    /// Hand the socket file over to the group `gid` once it's bound.
+    ///
+    /// Combined with a mode like `0o660`, this gives the members of a group access to a socket
+    /// without opening it up to everyone. Unprivileged servers can only pick groups they are a
+    /// member of.
+    #[cfg(unix)]
+    pub fn set_group(self, gid: u32) -> io::Result<Self> {
+        Ok(Self(self.0.set_group(gid)?))
+    }
+
+    /// Like [`set_group`](Self::set_group), looking up the group by name.
+    ///
+    /// Fails with [`NotFound`](io::ErrorKind::NotFound) if there is no such group.
+    #[cfg(unix)]
+    pub fn set_group_name(self, name: &str) -> io::Result<Self> {
+        self.set_group(platform::group_id(name)?)
+    }
+
+    /// New security attributes with the security descriptor given in SDDL form.
+    ///
+    /// For example, `D:P(A;;GA;;;BA)(A;;GA;;;SY)` only gives Administrators and `LocalSystem`
+    /// access to the pipe. Keep in mind that the server needs `FILE_CREATE_PIPE_INSTANCE` access
+    /// to create further instances of its own pipe.
+    #[cfg(windows)]
+    pub fn from_sddl(sddl: &str) -> io::Result<Self> {
+        Ok(Self(platform::SecurityAttributes::from_sddl(sddl)?))
+    }
+
+    /// New security attributes that only give the listed SIDs access to the pipe.
+    ///
+    /// Each entry pairs a SID, either in string form like `S-1-5-32-544` or as an SDDL alias like
+    /// `BA`, with the access mask to grant it, for example `GENERIC_READ | GENERIC_WRITE`. Access
+    /// isn't inherited from anywhere else, so the user the server runs as must be included.
+    #[cfg(windows)]
+    pub fn allow_sids<'a>(entries: impl IntoIterator<Item = (&'a str, u32)>) -> io::Result<Self> {
+        Ok(Self(platform::SecurityAttributes::allow_sids(entries)?))
+    }
+}
+
+/// Makes sure `name` can be used as a single file name inside one of the crate's folders.
+pub(crate) fn check_file_name(kind: &str, name: &str) -> io::Result<()> {
+    if name.is_empty() || name.contains(['/', '\\', '\0']) || name.contains("..") {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "Invalid {kind} name {name:?}, it must not be empty or contain separators or `..`"
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn is_would_block<T>(result: &io::Result<T>) -> bool {
+    matches!(result, Err(e) if e.kind() == io::ErrorKind::WouldBlock)
+}
+
+fn connect_timeout(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("Timed out connecting to {path:?}"),
+    )
 }
 
 /// IPC endpoint.
@@ -148,7 +481,59 @@ pub struct Endpoint(platform::Endpoint);
 impl Endpoint {
     /// Stream of incoming connections
     pub fn incoming(self) -> io::Result<IpcStream> {
-        Ok(IpcStream(self.0.incoming()?))
+        let max_connections = self.0.max_connections();
+        let mut incoming = IpcStream::wrap(self.0.incoming()?);
+        incoming.max_connections = max_connections;
+        Ok(incoming)
+    }
+    /// Stream of incoming connections from peers that pass `filter`.
+    ///
+    /// The filter receives the credentials of each peer before the connection is yielded and
+    /// returns whether to accept it. Rejected connections, and connections whose credentials can't
+    /// be determined, are closed without reading anything from them.
+    pub fn incoming_filtered(
+        self,
+        filter: impl Fn(&PeerCredentials) -> bool + Send + 'static,
+    ) -> io::Result<IpcStream> {
+        let mut incoming = self.incoming()?;
+        incoming.filter = Some(Box::new(filter));
+        Ok(incoming)
+    }
+    /// Serves connections until `shutdown` resolves.
+    ///
+    /// Every accepted connection is handed to `handler`, whose future runs on its own task. Accept
+    /// errors are logged and don't stop the server. Once `shutdown` resolves, the listener is
+    /// closed and this waits for the handlers that are still running before returning, so
+    /// handlers of long-lived connections should watch for the same signal.
+    pub async fn serve<F, Fut>(
+        self,
+        handler: F,
+        shutdown: impl Future<Output = ()>,
+    ) -> io::Result<()>
+    where
+        F: FnMut(Connection) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.incoming()?.serve(handler, shutdown).await
+    }
+    /// Stream of incoming connections, wrapped in [`FramedConnection`](framing::FramedConnection)s.
+    ///
+    /// Requires the `framing` feature.
+    #[cfg(feature = "framing")]
+    pub fn incoming_framed(self) -> io::Result<framing::IncomingFramed> {
+        Ok(self.incoming()?.framed())
+    }
+    /// Stream of incoming connections, wrapped in
+    /// [`TypedConnection`](typed::TypedConnection)s that encode messages with `codec`.
+    ///
+    /// Requires the `typed` feature.
+    #[cfg(feature = "typed")]
+    pub fn incoming_typed<T, C>(self, codec: C) -> io::Result<typed::IncomingTyped<T, C>>
+    where
+        T: serde::Serialize + serde::de::DeserializeOwned,
+        C: typed::Codec + Clone,
+    {
+        Ok(self.incoming_framed()?.typed(codec))
     }
     /// Set security attributes for the connection
     pub fn security_attributes(mut self, security_attributes: SecurityAttributes) -> Self {
@@ -161,23 +546,720 @@ impl Endpoint {
     }
     /// Make new connection using the provided path and running event pool.
     pub async fn connect(path: impl IntoIpcPath, options: Option<EndpointOptions>) -> io::Result<Connection> {
-        Ok(Connection(platform::Endpoint::connect(path, options).await?))
+        Self::connect_shared(path.into_ipc_path()?.into(), options).await
+    }
+    /// Connects to a path that's shared with the connection, so retries don't copy it.
+    pub(crate) async fn connect_shared(
+        path: Arc<Path>,
+        options: Option<EndpointOptions>,
+    ) -> io::Result<Connection> {
+        let started = Instant::now();
+        match platform::Endpoint::connect(&path, options).await {
+            Ok(conn) => {
+                metrics::record_connect(&path, started.elapsed());
+                Ok(Connection::wrap(conn)
+                    .with_endpoint_path(Some(path))
+                    .instrumented("client"))
+            }
+            Err(e) => {
+                metrics::record_connect_error(&path);
+                instrument::record_connect_error(&path, &e);
+                Err(e)
+            }
+        }
+    }
+    /// Make new connection, with a timeout and retries as configured in `connect_options`.
+    pub async fn connect_with(
+        path: impl IntoIpcPath,
+        options: Option<EndpointOptions>,
+        connect_options: ConnectOptions,
+    ) -> io::Result<Connection> {
+        let path: Arc<Path> = path.into_ipc_path()?.into();
+        let connect = async {
+            let mut attempt = 0;
+            loop {
+                attempt += 1;
+                let error = match Self::connect_shared(path.clone(), options).await {
+                    Ok(conn) => return Ok(conn),
+                    Err(e) => e,
+                };
+                let Some(backoff) = &connect_options.retry else {
+                    return Err(error);
+                };
+                let transient = matches!(
+                    error.kind(),
+                    io::ErrorKind::NotFound
+                        | io::ErrorKind::ConnectionRefused
+                        | io::ErrorKind::WouldBlock
+                        | io::ErrorKind::TimedOut
+                );
+                if !transient || backoff.max_attempts.is_some_and(|max| attempt >= max.get()) {
+                    return Err(error);
+                }
+                tracing::trace!("Connect attempt {attempt} to {path:?} failed: {error:?}");
+                tokio::time::sleep(backoff.delay(attempt)).await;
+            }
+        };
+        match connect_options.timeout {
+            Some(timeout) => tokio::time::timeout(timeout, connect)
+                .await
+                .map_err(|_| connect_timeout(&path))?,
+            None => connect.await,
+        }
+    }
+    /// Make new connection, first waiting up to `timeout` for the server to bind if the socket or
+    /// pipe doesn't exist yet.
+    ///
+    /// This is meant for clients that may start before the server. On Unix the wait is driven by
+    /// filesystem notifications like in `wait_for_path`, on Windows by `WaitNamedPipe` like in
+    /// `wait_for_pipe`.
+    pub async fn connect_wait(
+        path: impl IntoIpcPath,
+        options: Option<EndpointOptions>,
+        timeout: Duration,
+    ) -> io::Result<Connection> {
+        let path: Arc<Path> = path.into_ipc_path()?.into();
+        let connect = async {
+            #[cfg(unix)]
+            platform::wait_for_path(&path).await?;
+            #[cfg(windows)]
+            platform::wait_for_pipe(&path, timeout).await?;
+            Self::connect_shared(path.clone(), options).await
+        };
+        tokio::time::timeout(timeout, connect)
+            .await
+            .map_err(|_| connect_timeout(&path))?
     }
 
     /// New IPC endpoint at the given path
     pub fn new(path: impl IntoIpcPath, options: Option<EndpointOptions>) -> io::Result<Self> {
         Ok(Self(platform::Endpoint::new(path, options)?))
     }
+
+    /// Creates two connections that are connected to each other, without a listener.
+    ///
+    /// On Unix, this uses `socketpair(2)`, so nothing is created on disk. On Windows, there are no
+    /// anonymous pipes with overlapped IO, so this creates a single-instance named pipe with an
+    /// unpredictable name that doesn't accept remote clients, and connects to it right away.
+    ///
+    /// For IPC with a child process, hand one end to the child through
+    /// [`Connection::into_inner`] and keep the other.
+    pub async fn pair() -> io::Result<(Connection, Connection)> {
+        let (first, second) = platform::pair().await?;
+        Ok((Connection::wrap(first), Connection::wrap(second)))
+    }
 }
 
 /// IPC connection.
-pub struct Connection(platform::Connection);
+pub struct Connection {
+    inner: platform::Connection,
+    read_timeout: Timeout,
+    write_timeout: Timeout,
+    read_spin: BusyPoll,
+    write_spin: BusyPoll,
+    lifetime: Lifetime,
+    stats: Option<ConnectionStats>,
+    extensions: Extensions,
+    qos_class: QosClass,
+    max_buffered: Option<usize>,
+    #[cfg(any(feature = "mux", feature = "pubsub"))]
+    memory_budget: Option<memory::MemoryBudget>,
+    endpoint_path: Option<Arc<Path>>,
+    span: Span,
+}
 
 impl Connection {
+    fn wrap(inner: platform::Connection) -> Self {
+        Self {
+            inner,
+            read_timeout: Timeout::new("read"),
+            write_timeout: Timeout::new("write"),
+            read_spin: BusyPoll::new(),
+            write_spin: BusyPoll::new(),
+            lifetime: Lifetime::new(),
+            stats: None,
+            extensions: Extensions::new(),
+            qos_class: QosClass::default(),
+            max_buffered: None,
+            #[cfg(any(feature = "mux", feature = "pubsub"))]
+            memory_budget: None,
+            endpoint_path: None,
+            span: Span::none(),
+        }
+    }
+
+    /// Records the events of this connection in a new span, with the `instrument` feature.
+    fn instrumented(mut self, role: &'static str) -> Self {
+        self.span = instrument::connection_span(role, self.endpoint_path(), &self.inner);
+        instrument::record_open(&self.span);
+        self
+    }
+
+    fn with_endpoint_path(mut self, path: Option<Arc<Path>>) -> Self {
+        self.endpoint_path = path;
+        self
+    }
+
+    fn with_stats(mut self, stats: ConnectionStats) -> Self {
+        self.stats = Some(stats);
+        self
+    }
+
+    /// Reports a round-trip time measured on this connection to the endpoint statistics.
+    #[cfg(feature = "heartbeat")]
+    pub(crate) fn record_round_trip(&self, rtt: Duration) {
+        if let Some(stats) = &self.stats {
+            stats.record_round_trip(rtt);
+        }
+    }
+
+    /// Shuts the connection down once its maximum lifetime is over.
+    ///
+    /// Resolves to `true` if the lifetime ended, in which case reads and writes must not reach
+    /// the underlying connection anymore.
+    fn poll_lifetime(&mut self, ctx: &mut Context<'_>) -> Poll<bool> {
+        if !self.lifetime.poll_expired(ctx) {
+            return Poll::Ready(false);
+        }
+        if self.lifetime.needs_shutdown() {
+            // The peer may be gone already, the connection is over either way
+            let _ = ready!(Pin::new(&mut self.inner).poll_shutdown(ctx));
+            self.lifetime.mark_shut_down();
+        }
+        Poll::Ready(true)
+    }
+
+    /// Notices the end of the lifetime without shutting the connection down yet, so a protocol
+    /// layer can say goodbye first.
+    #[cfg(feature = "framing")]
+    pub(crate) fn poll_expiry(&mut self, ctx: &mut Context<'_>) -> Option<ExpiryReason> {
+        self.lifetime.poll_expired(ctx);
+        self.lifetime.expiry()
+    }
+
+    /// Writes to the connection after its lifetime ended, as long as it wasn't shut down yet.
+    #[cfg(feature = "framing")]
+    pub(crate) fn poll_write_expired(
+        &mut self,
+        ctx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        if !self.lifetime.needs_shutdown() {
+            return Poll::Ready(Err(self.lifetime.expired_error()));
+        }
+        Pin::new(&mut self.inner).poll_write(ctx, buf)
+    }
+
+    /// Runs a write on the underlying connection, applying the lifetime, busy polling, the write
+    /// timeout and statistics.
+    fn poll_write_with<F>(&mut self, ctx: &mut Context<'_>, write: F) -> Poll<io::Result<usize>>
+    where
+        F: FnOnce(Pin<&mut platform::Connection>, &mut Context<'_>) -> Poll<io::Result<usize>>,
+    {
+        let _entered = self.span.enter();
+        if ready!(self.poll_lifetime(ctx)) {
+            return Poll::Ready(Err(self.lifetime.expired_error()));
+        }
+        let result = self.write_timeout.poll_op(ctx, |ctx| {
+            let result = write(Pin::new(&mut self.inner), ctx);
+            self.write_spin.poll_op(ctx, result)
+        });
+        if let Poll::Ready(Ok(1..)) = result {
+            self.lifetime.record_activity();
+        }
+        if let Poll::Ready(result) = &result {
+            instrument::record_write(result);
+            if let Some(stats) = &self.stats {
+                stats.record_write(result, *result.as_ref().unwrap_or(&0));
+            }
+        }
+        result
+    }
+
     /// Create a stream from an existing [`UnixStream`](std::os::unix::net::UnixStream).
     #[cfg(unix)]
     pub async fn from_std_stream(stream: std::os::unix::net::UnixStream) -> io::Result<Self> {
-        Ok(Self(platform::from_std_stream(stream).await?))
+        Ok(Self::wrap(platform::from_std_stream(stream).await?))
+    }
+
+    /// Create a stream from the file descriptor of a connected Unix socket, for example one
+    /// received through [`recv_with_fds`](Self::recv_with_fds) or set up by another library.
+    #[cfg(unix)]
+    pub async fn from_fd(fd: OwnedFd) -> io::Result<Self> {
+        Self::from_std_stream(fd.into()).await
+    }
+
+    /// Create a stream from a handle to either end of a connected named pipe, for example one
+    /// inherited from a parent process or set up by another library.
+    ///
+    /// Whether it's the server or client end is detected from the pipe. The handle must have
+    /// been opened for overlapped IO (`FILE_FLAG_OVERLAPPED`), and this must be called from
+    /// within a Tokio runtime.
+    #[cfg(windows)]
+    pub fn from_owned_handle(handle: OwnedHandle) -> io::Result<Self> {
+        Ok(Self::wrap(platform::from_owned_handle(handle)?))
+    }
+
+    /// Consumes the connection, returning the file descriptor of the underlying socket.
+    ///
+    /// The socket stays in non-blocking mode. Timeouts, busy polling and statistics configured on
+    /// this connection no longer apply to it.
+    #[cfg(unix)]
+    pub fn into_fd(self) -> io::Result<OwnedFd> {
+        Ok(self.inner.into_std()?.into())
+    }
+
+    /// Returns the read timeout of this connection.
+    pub fn read_timeout(&self) -> Option<Duration> {
+        self.read_timeout.get()
+    }
+
+    /// Sets the read timeout of this connection.
+    ///
+    /// Every subsequent read that doesn't make progress within `timeout` fails with
+    /// [`io::ErrorKind::TimedOut`]. Passing `None` disables the timeout.
+    ///
+    /// A read that is dropped while pending doesn't count against the next one, which starts
+    /// with the full timeout.
+    pub fn set_read_timeout(&mut self, timeout: Option<Duration>) {
+        self.read_timeout.set(timeout);
+    }
+
+    /// Returns the write timeout of this connection.
+    pub fn write_timeout(&self) -> Option<Duration> {
+        self.write_timeout.get()
+    }
+
+    /// Sets the write timeout of this connection.
+    ///
+    /// Every subsequent write, flush or shutdown that doesn't make progress within `timeout`
+    /// fails with [`io::ErrorKind::TimedOut`]. Passing `None` disables the timeout.
+    ///
+    /// As with [`set_read_timeout`](Self::set_read_timeout), an operation that is dropped while
+    /// pending doesn't count against the next one.
+    pub fn set_write_timeout(&mut self, timeout: Option<Duration>) {
+        self.write_timeout.set(timeout);
+    }
+
+    /// Returns the busy-poll window of this connection.
+    pub fn busy_poll(&self) -> Option<Duration> {
+        self.read_spin.get()
+    }
+
+    /// Enables busy polling for latency-critical connections.
+    ///
+    /// When an operation would block, the task is rescheduled immediately instead of parking for
+    /// up to `duration`, trading CPU time for lower wake-up latency. This is useful for things
+    /// like audio or game engine IPC where microseconds matter. Kernel-level busy polling
+    /// (`SO_BUSY_POLL`) only applies to network devices, so it isn't used for local sockets.
+    /// Passing `None` disables busy polling.
+    pub fn set_busy_poll(&mut self, duration: Option<Duration>) {
+        self.read_spin.set(duration);
+        self.write_spin.set(duration);
+    }
+
+    /// Splits the connection into owned read and write halves that can be moved into separate
+    /// tasks.
+    ///
+    /// Unlike [`tokio::io::split`], this doesn't put the connection behind a lock. Each half keeps
+    /// the timeouts and busy polling configured for its direction, and the maximum lifetime keeps
+    /// applying to both. The connection's [`Extensions`] are dropped.
+    pub fn into_split(self) -> (OwnedReadHalf, OwnedWriteHalf) {
+        let (read, write) = self.inner.into_split();
+        let stats = self.stats.map(Arc::new);
+        let read = OwnedReadHalf {
+            inner: read,
+            timeout: self.read_timeout,
+            spin: self.read_spin,
+            lifetime: self.lifetime.duplicate(),
+            stats: stats.clone(),
+            span: self.span.clone(),
+        };
+        let write = OwnedWriteHalf {
+            inner: write,
+            timeout: self.write_timeout,
+            spin: self.write_spin,
+            lifetime: self.lifetime,
+            stats,
+            span: self.span,
+        };
+        (read, write)
+    }
+
+    /// Waits for the connection to become ready for any of the operations in `interest`.
+    ///
+    /// Meant to be combined with [`try_read`](Self::try_read) and [`try_write`](Self::try_write)
+    /// in manual readiness loops. Readiness can be spurious, so those may still fail with
+    /// [`WouldBlock`](io::ErrorKind::WouldBlock) afterwards.
+    pub async fn ready(&self, interest: Interest) -> io::Result<Ready> {
+        self.inner.ready(interest).await
+    }
+
+    /// Receives bytes into `buf` without removing them from the connection.
+    ///
+    /// Waits until at least one byte is available, the next read then returns the same bytes.
+    /// This lets protocol dispatchers look at the start of a connection, like a magic number,
+    /// before handing it on. Resolves to 0 once the peer closed the connection.
+    ///
+    /// On Unix, this uses `MSG_PEEK`. Named pipes can't be peeked reliably, so on Windows the
+    /// bytes are read ahead into a buffer of the connection instead, and repeated peeks return
+    /// the same bytes rather than anything that arrived in the meantime.
+    ///
+    /// Timeouts, busy polling and statistics don't apply to this call.
+    pub async fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        platform::peek(&mut self.inner, buf).await
+    }
+
+    /// Reads whatever is available into `buf` without waiting.
+    ///
+    /// Fails with [`WouldBlock`](io::ErrorKind::WouldBlock) if nothing can be read right now.
+    /// Statistics and the idle timeout account for the read, while timeouts and busy polling
+    /// don't apply to it.
+    pub fn try_read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let result = self.inner.try_read(buf);
+        if !is_would_block(&result) {
+            if let Some(stats) = &self.stats {
+                stats.record_read(&result, *result.as_ref().unwrap_or(&0));
+            }
+        }
+        if let Ok(1..) = result {
+            self.lifetime.record_activity();
+        }
+        result
+    }
+
+    /// Writes as much of `buf` as fits right now without waiting.
+    ///
+    /// Fails with [`WouldBlock`](io::ErrorKind::WouldBlock) if nothing can be written right now.
+    /// Statistics and the idle timeout account for the write, while timeouts and busy polling
+    /// don't apply to it.
+    pub fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        let result = self.inner.try_write(buf);
+        if !is_would_block(&result) {
+            if let Some(stats) = &self.stats {
+                stats.record_write(&result, *result.as_ref().unwrap_or(&0));
+            }
+        }
+        if let Ok(1..) = result {
+            self.lifetime.record_activity();
+        }
+        result
+    }
+
+    /// Returns the path of the endpoint this connection was accepted from or connected to.
+    ///
+    /// Unlike [`local_addr`](Self::local_addr) and [`peer_addr`](Self::peer_addr), this doesn't
+    /// ask the operating system, the connection shares the path with its endpoint. `None` for
+    /// pairs and connections created from OS handles.
+    pub fn endpoint_path(&self) -> Option<&Path> {
+        self.endpoint_path.as_deref()
+    }
+
+    /// Returns the path of the socket or named pipe on this end of the connection.
+    ///
+    /// On Unix, that's the socket path for connections accepted by a server and `None` for
+    /// clients and pairs, whose sockets are unnamed. Both ends of a named pipe share the name of
+    /// the pipe.
+    pub fn local_addr(&self) -> io::Result<Option<PathBuf>> {
+        platform::local_path(&self.inner)
+    }
+
+    /// Returns the path of the socket or named pipe on the other end of the connection.
+    ///
+    /// On Unix, that's the socket path for clients and `None` for connections accepted by a
+    /// server. Both ends of a named pipe share the name of the pipe.
+    pub fn peer_addr(&self) -> io::Result<Option<PathBuf>> {
+        platform::peer_path(&self.inner)
+    }
+
+    /// Returns the credentials of the process on the other end of the connection.
+    ///
+    /// Servers can use this to decide per client what it's allowed to do.
+    pub fn peer_credentials(&self) -> io::Result<PeerCredentials> {
+        platform::peer_credentials(&self.inner)
+    }
+
+    /// Returns the size of the kernel send buffer of this connection (`SO_SNDBUF`), in bytes.
+    ///
+    /// Linux reports twice the size that was set, since it reserves half of the buffer for
+    /// bookkeeping. Only available on Unix.
+    #[cfg(unix)]
+    pub fn send_buffer_size(&self) -> io::Result<usize> {
+        platform::send_buffer_size(&self.inner)
+    }
+
+    /// Sets the size of the kernel send buffer of this connection (`SO_SNDBUF`), in bytes.
+    ///
+    /// See [`EndpointOptions::send_buffer_size`] to apply it to all connections of an endpoint.
+    /// Only available on Unix, the buffers of named pipes are set when the server creates them.
+    #[cfg(unix)]
+    pub fn set_send_buffer_size(&self, size: usize) -> io::Result<()> {
+        platform::set_send_buffer_size(&self.inner, size)
+    }
+
+    /// Returns the size of the kernel receive buffer of this connection (`SO_RCVBUF`), in bytes.
+    ///
+    /// Linux reports twice the size that was set, since it reserves half of the buffer for
+    /// bookkeeping. Only available on Unix.
+    #[cfg(unix)]
+    pub fn recv_buffer_size(&self) -> io::Result<usize> {
+        platform::recv_buffer_size(&self.inner)
+    }
+
+    /// Sets the size of the kernel receive buffer of this connection (`SO_RCVBUF`), in bytes.
+    ///
+    /// See [`EndpointOptions::recv_buffer_size`] to apply it to all connections of an endpoint.
+    /// Only available on Unix, the buffers of named pipes are set when the server creates them.
+    #[cfg(unix)]
+    pub fn set_recv_buffer_size(&self, size: usize) -> io::Result<()> {
+        platform::set_recv_buffer_size(&self.inner, size)
+    }
+
+    /// Returns the security label of the process on the other end of the connection
+    /// (`SO_PEERSEC`), like its SELinux context or AppArmor profile.
+    ///
+    /// The label is captured when the connection is established. Fails with the error reported
+    /// by the kernel, usually `ENOPROTOOPT`, if no security module that labels sockets is active.
+    /// Only available on Linux.
+    #[cfg(target_os = "linux")]
+    pub fn peer_security_context(&self) -> io::Result<String> {
+        platform::peer_security_context(&self.inner)
+    }
+
+    /// Returns the metadata attached to this connection.
+    pub fn extensions(&self) -> &Extensions {
+        &self.extensions
+    }
+
+    /// Returns the metadata attached to this connection for modification.
+    ///
+    /// Typically used to attach routing or authorization state right after accepting the
+    /// connection, so handlers don't need a separate map keyed by connection.
+    pub fn extensions_mut(&mut self) -> &mut Extensions {
+        &mut self.extensions
+    }
+
+    /// Returns the scheduling class of this connection.
+    pub fn qos_class(&self) -> QosClass {
+        self.qos_class
+    }
+
+    /// Sets the scheduling class of this connection.
+    ///
+    /// Accepted connections are classified by the [classifier](IpcStream::set_qos_classifier) of
+    /// their stream, all others are [`QosClass::Interactive`].
+    pub fn set_qos_class(&mut self, qos_class: QosClass) {
+        self.qos_class = qos_class;
+    }
+
+    /// Returns the maximum lifetime of this connection.
+    pub fn max_lifetime(&self) -> Option<Duration> {
+        self.lifetime.get()
+    }
+
+    /// Limits how long this connection stays open, measured from when it was established.
+    ///
+    /// Once `max_lifetime` has passed, the connection is shut down gracefully: the peer reads the
+    /// end of the stream, local reads do the same and local writes fail with
+    /// [`io::ErrorKind::BrokenPipe`]. This is useful for forcing clients to periodically
+    /// reconnect, for example to re-authenticate or to rebalance across servers. Passing `None`
+    /// removes the limit.
+    pub fn set_max_lifetime(&mut self, max_lifetime: Option<Duration>) {
+        self.lifetime.set(max_lifetime);
+    }
+
+    /// Returns the idle timeout of this connection.
+    pub fn idle_timeout(&self) -> Option<Duration> {
+        self.lifetime.idle_timeout()
+    }
+
+    /// Closes this connection once no data was read or written for `idle_timeout`.
+    ///
+    /// The timeout starts over with every read or write that transfers data. When it runs out,
+    /// the connection is shut down the same way as after its
+    /// [maximum lifetime](Self::set_max_lifetime), so a server waiting on a read sees the end of
+    /// the stream. This is useful for reaping abandoned clients in long-running daemons. Passing
+    /// `None` disables the timeout.
+    pub fn set_idle_timeout(&mut self, idle_timeout: Option<Duration>) {
+        self.lifetime.set_idle_timeout(idle_timeout);
+    }
+
+    /// Returns how many bytes of received data the layers on top of this connection may buffer.
+    pub fn max_buffered(&self) -> Option<usize> {
+        self.max_buffered
+    }
+
+    /// Caps how many bytes of received data the framing, multiplexing and pub/sub layers buffer
+    /// for this connection, so one peer can't balloon the memory of the process.
+    ///
+    /// Longer frames are rejected like frames above the maximum frame length. Once received data
+    /// that wasn't read yet reaches the cap, the layers stop reading from the connection until
+    /// some of it is read, which also holds up streams and subscriptions that are read. Applies to
+    /// layers created after this is set. Passing `None` removes the cap, which is the default.
+    pub fn set_max_buffered(&mut self, max_buffered: Option<usize>) {
+        self.max_buffered = max_buffered;
+    }
+
+    /// Returns the memory budget the layers on top of this connection draw from.
+    #[cfg(any(feature = "mux", feature = "pubsub"))]
+    pub fn memory_budget(&self) -> Option<&memory::MemoryBudget> {
+        self.memory_budget.as_ref()
+    }
+
+    /// Makes the multiplexing and pub/sub layers on top of this connection buffer received data
+    /// within `budget`, along with every other connection attached to it.
+    ///
+    /// This applies on top of the [cap on buffered data](Self::set_max_buffered) of the
+    /// connection, and to layers created after this is set. Passing `None` detaches the
+    /// connection, which is the default.
+    #[cfg(any(feature = "mux", feature = "pubsub"))]
+    pub fn set_memory_budget(&mut self, budget: Option<memory::MemoryBudget>) {
+        self.memory_budget = budget;
+    }
+
+    /// Returns why the connection was closed by its maximum lifetime or idle timeout, if it was.
+    pub fn expiry(&self) -> Option<ExpiryReason> {
+        self.lifetime.expiry()
+    }
+
+    #[cfg(feature = "test-util")]
+    pub(crate) fn expire_now(&self, reason: ExpiryReason) {
+        self.lifetime.expire_now(reason);
+    }
+
+    /// Resolves once the maximum lifetime or idle timeout ran out.
+    ///
+    /// A timer task notices the expiry even while nothing reads from or writes to the connection,
+    /// so a server can wait for this next to other work and drop connections that expired.
+    /// The connection itself is shut down the next time it's read from or written to. Never
+    /// resolves if neither limit is set.
+    pub async fn expired(&self) -> ExpiryReason {
+        self.lifetime.expired().await
+    }
+
+    /// Sends `buf` along with duplicates of `fds` to the peer.
+    ///
+    /// The descriptors are attached to the first byte of `buf` as `SCM_RIGHTS` ancillary data, so
+    /// `buf` must not be empty when sending descriptors. The peer has to receive the bytes with
+    /// [`recv_with_fds`](Self::recv_with_fds), a plain read discards the descriptors. Returns the
+    /// number of bytes written, which can be less than `buf.len()` like with any write.
+    ///
+    /// Timeouts, busy polling and statistics don't apply to this call.
+    #[cfg(unix)]
+    pub async fn send_with_fds(&self, buf: &[u8], fds: &[impl AsFd]) -> io::Result<usize> {
+        let fds: Vec<_> = fds.iter().map(|fd| fd.as_fd().as_raw_fd()).collect();
+        ancillary::send_with_fds(&self.inner, buf, &fds).await
+    }
+
+    /// Receives bytes into `buf` along with any file descriptors sent with them through
+    /// [`send_with_fds`](Self::send_with_fds).
+    ///
+    /// Returns the number of bytes read, 0 meaning the end of the stream, the received
+    /// descriptors, which are opened with close-on-exec where supported, and whether descriptors
+    /// were lost. The kernel drops descriptors beyond the 253 Linux allows per message and those
+    /// this process has no room for under its descriptor limit (`MSG_CTRUNC`), but the bytes
+    /// are still read. Bytes that were already buffered by a wrapper such as `BufReader` are not
+    /// seen by this call.
+    ///
+    /// Timeouts, busy polling and statistics don't apply to this call.
+    #[cfg(unix)]
+    pub async fn recv_with_fds(&self, buf: &mut [u8]) -> io::Result<(usize, Vec<OwnedFd>, bool)> {
+        ancillary::recv_with_fds(&self.inner, buf).await
+    }
+
+    /// Enables or disables receiving the credentials of the sender with every message
+    /// (`SO_PASSCRED`).
+    ///
+    /// This has to be enabled for [`recv_with_credentials`](Self::recv_with_credentials) to
+    /// report anything. The kernel then attaches the credentials of the sender to all bytes, even
+    /// if the peer didn't explicitly send any. Only available on Linux.
+    #[cfg(target_os = "linux")]
+    pub fn set_pass_credentials(&self, enabled: bool) -> io::Result<()> {
+        ancillary::set_pass_credentials(&self.inner, enabled)
+    }
+
+    /// Sends `buf` with the credentials of this process attached as `SCM_CREDENTIALS` ancillary
+    /// data.
+    ///
+    /// The peer receives them through [`recv_with_credentials`](Self::recv_with_credentials),
+    /// provided it enabled [`set_pass_credentials`](Self::set_pass_credentials). `buf` must not be
+    /// empty. Only available on Linux.
+    ///
+    /// Timeouts, busy polling and statistics don't apply to this call.
+    #[cfg(target_os = "linux")]
+    pub async fn send_with_credentials(&self, buf: &[u8]) -> io::Result<usize> {
+        ancillary::send_with_credentials(&self.inner, buf).await
+    }
+
+    /// Receives bytes into `buf` along with the credentials the peer sent with them.
+    ///
+    /// The credentials are always `None` unless
+    /// [`set_pass_credentials`](Self::set_pass_credentials) was enabled before the bytes were
+    /// sent. Only available on Linux.
+    ///
+    /// Timeouts, busy polling and statistics don't apply to this call.
+    #[cfg(target_os = "linux")]
+    pub async fn recv_with_credentials(
+        &self,
+        buf: &mut [u8],
+    ) -> io::Result<(usize, Option<ReceivedCredentials>)> {
+        ancillary::recv_with_credentials(&self.inner, buf).await
+    }
+
+    /// Returns buffer sizes, instance counts and modes of the underlying named pipe.
+    ///
+    /// Useful for diagnostics and for sizing application buffers to match the pipe.
+    #[cfg(windows)]
+    pub fn pipe_info(&self) -> io::Result<PipeInfo> {
+        self.inner.pipe_info()
+    }
+
+    /// Makes the current thread act with the token of the client until the returned guard is
+    /// dropped or [`revert`](Impersonation::revert)ed (`ImpersonateNamedPipeClient`).
+    ///
+    /// This lets a privileged server access files and other resources with the permissions of
+    /// the caller rather than its own. Only the server end of a connection can impersonate, and
+    /// only after reading something from the pipe. Clients have to opt in with
+    /// [`EndpointOptions::allow_impersonation`] for the token to be usable beyond identifying
+    /// them. Only available on Windows.
+    #[cfg(windows)]
+    pub fn impersonate_client(&self) -> io::Result<Impersonation<'_>> {
+        self.inner.impersonate_client()
+    }
+
+    /// Asks polkit whether the peer process is authorized for `action_id`.
+    ///
+    /// The peer is identified by the PID and UID of the socket credentials, so this is the
+    /// standard way for a privileged daemon to gate requests coming from an unprivileged client.
+    /// With `allow_interaction`, polkit may prompt the user for authentication before answering,
+    /// in which case this can take a while to resolve.
+    ///
+    /// Requires the `polkit` feature.
+    #[cfg(all(target_os = "linux", feature = "polkit"))]
+    pub async fn check_polkit_authorization(
+        &self,
+        action_id: &str,
+        allow_interaction: bool,
+    ) -> io::Result<bool> {
+        polkit::check_authorization(&self.inner, action_id, allow_interaction).await
+    }
+
+    /// Consumes the connection, returning the underlying [`UnixStream`](tokio::net::UnixStream).
+    ///
+    /// Timeouts, busy polling and statistics configured on this connection no longer apply to
+    /// the returned stream.
+    #[cfg(unix)]
+    pub fn into_inner(self) -> tokio::net::UnixStream {
+        self.inner
+    }
+
+    /// Consumes the connection, returning the underlying [`NamedPipe`].
+    ///
+    /// Timeouts, busy polling and statistics configured on this connection no longer apply to
+    /// the returned pipe. Bytes that were [`peek`](Self::peek)ed but not read yet are lost.
+    #[cfg(windows)]
+    pub fn into_inner(self) -> NamedPipe {
+        self.inner.into_inner()
     }
 }
 
@@ -188,7 +1270,25 @@ impl AsyncRead for Connection {
         buf: &mut ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
         let this = Pin::into_inner(self);
-        Pin::new(&mut this.0).poll_read(ctx, buf)
+        let _entered = this.span.enter();
+        if ready!(this.poll_lifetime(ctx)) {
+            return Poll::Ready(Ok(()));
+        }
+        let filled = buf.filled().len();
+        let result = this.read_timeout.poll_op(ctx, |ctx| {
+            let result = Pin::new(&mut this.inner).poll_read(ctx, buf);
+            this.read_spin.poll_op(ctx, result)
+        });
+        if buf.filled().len() > filled {
+            this.lifetime.record_activity();
+        }
+        if let Poll::Ready(result) = &result {
+            instrument::record_read(result, buf.filled().len() - filled);
+            if let Some(stats) = &this.stats {
+                stats.record_read(result, buf.filled().len() - filled);
+            }
+        }
+        result
     }
 }
 
@@ -198,37 +1298,445 @@ impl AsyncWrite for Connection {
         ctx: &mut Context<'_>,
         buf: &[u8],
     ) -> Poll<Result<usize, io::Error>> {
-        let this = Pin::into_inner(self);
-        Pin::new(&mut this.0).poll_write(ctx, buf)
+        Pin::into_inner(self).poll_write_with(ctx, |inner, ctx| inner.poll_write(ctx, buf))
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        Pin::into_inner(self)
+            .poll_write_with(ctx, |inner, ctx| inner.poll_write_vectored(ctx, bufs))
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.inner.is_write_vectored()
     }
 
     fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
         let this = Pin::into_inner(self);
-        Pin::new(&mut this.0).poll_flush(ctx)
+        this.write_timeout.poll_op(ctx, |ctx| {
+            let result = Pin::new(&mut this.inner).poll_flush(ctx);
+            this.write_spin.poll_op(ctx, result)
+        })
     }
 
     fn poll_shutdown(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
         let this = Pin::into_inner(self);
-        Pin::new(&mut this.0).poll_shutdown(ctx)
+        let _entered = this.span.enter();
+        let result = this.write_timeout.poll_op(ctx, |ctx| {
+            let result = Pin::new(&mut this.inner).poll_shutdown(ctx);
+            this.write_spin.poll_op(ctx, result)
+        });
+        if let Poll::Ready(result) = &result {
+            instrument::record_shutdown(result);
+        }
+        result
     }
 }
 
 /// Stream of incoming connections.
-pub struct IpcStream(platform::IpcStream);
+pub struct IpcStream {
+    inner: platform::IpcStream,
+    // Shared with every accepted connection
+    path: Option<Arc<Path>>,
+    stats: Arc<EndpointStats>,
+    max_connection_lifetime: Option<Duration>,
+    connection_idle_timeout: Option<Duration>,
+    connection_max_buffered: Option<usize>,
+    #[cfg(any(feature = "mux", feature = "pubsub"))]
+    connection_memory_budget: Option<memory::MemoryBudget>,
+    filter: Option<AcceptFilter>,
+    classifier: Option<QosClassifier>,
+    max_batch_handlers: Option<NonZeroUsize>,
+    max_connections: Option<NonZeroUsize>,
+    shutdown: ShutdownHandle,
+    // `None` once shutdown was requested
+    shutdown_signal: Option<oneshot::Receiver<()>>,
+}
+
+type AcceptFilter = Box<dyn Fn(&PeerCredentials) -> bool + Send>;
+type QosClassifier = Box<dyn Fn(&PeerCredentials) -> QosClass + Send>;
 
 impl IpcStream {
+    fn wrap(inner: platform::IpcStream) -> Self {
+        let path = inner.local_path();
+        let stats = Arc::new(EndpointStats::new(path.as_deref()));
+        let (shutdown, shutdown_signal) = ShutdownHandle::new(stats.clone());
+        Self {
+            inner,
+            path,
+            stats,
+            max_connection_lifetime: None,
+            connection_idle_timeout: None,
+            connection_max_buffered: None,
+            #[cfg(any(feature = "mux", feature = "pubsub"))]
+            connection_memory_budget: None,
+            filter: None,
+            classifier: None,
+            max_batch_handlers: None,
+            max_connections: None,
+            shutdown,
+            shutdown_signal: Some(shutdown_signal),
+        }
+    }
+
     /// Create a listener from an existing [`UnixListener`](std::os::unix::net::UnixListener).
     #[cfg(unix)]
     pub fn from_std_listener(listener: std::os::unix::net::UnixListener) -> io::Result<Self> {
-        Ok(Self(platform::IpcStream::from_std_listener(listener)?))
+        Ok(Self::wrap(platform::IpcStream::from_std_listener(
+            listener,
+        )?))
+    }
+
+    /// Returns a handle to the statistics aggregated across all connections accepted by this
+    /// stream.
+    pub fn stats(&self) -> EndpointStatsHandle {
+        EndpointStatsHandle(self.stats.clone())
+    }
+
+    /// Returns the maximum lifetime applied to accepted connections.
+    pub fn max_connection_lifetime(&self) -> Option<Duration> {
+        self.max_connection_lifetime
+    }
+
+    /// Applies [`Connection::set_max_lifetime`] to every connection accepted from now on.
+    pub fn set_max_connection_lifetime(&mut self, max_lifetime: Option<Duration>) {
+        self.max_connection_lifetime = max_lifetime;
+    }
+
+    /// Returns the idle timeout applied to accepted connections.
+    pub fn connection_idle_timeout(&self) -> Option<Duration> {
+        self.connection_idle_timeout
+    }
+
+    /// Applies [`Connection::set_idle_timeout`] to every connection accepted from now on.
+    pub fn set_connection_idle_timeout(&mut self, idle_timeout: Option<Duration>) {
+        self.connection_idle_timeout = idle_timeout;
+    }
+
+    /// Returns the cap on buffered received data applied to accepted connections.
+    pub fn connection_max_buffered(&self) -> Option<usize> {
+        self.connection_max_buffered
+    }
+
+    /// Applies [`Connection::set_max_buffered`] to every connection accepted from now on.
+    pub fn set_connection_max_buffered(&mut self, max_buffered: Option<usize>) {
+        self.connection_max_buffered = max_buffered;
+    }
+
+    /// Returns the memory budget attached to accepted connections.
+    #[cfg(any(feature = "mux", feature = "pubsub"))]
+    pub fn connection_memory_budget(&self) -> Option<&memory::MemoryBudget> {
+        self.connection_memory_budget.as_ref()
+    }
+
+    /// Applies [`Connection::set_memory_budget`] to every connection accepted from now on.
+    #[cfg(any(feature = "mux", feature = "pubsub"))]
+    pub fn set_connection_memory_budget(&mut self, budget: Option<memory::MemoryBudget>) {
+        self.connection_memory_budget = budget;
+    }
+
+    /// Classifies every connection accepted from now on with `classifier`.
+    ///
+    /// The classifier receives the credentials of each peer. Connections whose credentials can't
+    /// be determined are [`QosClass::Batch`].
+    pub fn set_qos_classifier(
+        &mut self,
+        classifier: impl Fn(&PeerCredentials) -> QosClass + Send + 'static,
+    ) {
+        self.classifier = Some(Box::new(classifier));
+    }
+
+    /// Returns how many [`QosClass::Batch`] connections [`serve`](Self::serve) handles at once.
+    pub fn max_batch_handlers(&self) -> Option<NonZeroUsize> {
+        self.max_batch_handlers
+    }
+
+    /// Limits how many [`QosClass::Batch`] connections [`serve`](Self::serve) handles at once.
+    ///
+    /// Batch connections accepted beyond the limit wait for a running batch handler to finish
+    /// before their own handler starts, while interactive connections are handled right away.
+    /// Passing `None` removes the limit, which is the default.
+    pub fn set_max_batch_handlers(&mut self, max: Option<NonZeroUsize>) {
+        self.max_batch_handlers = max;
+    }
+
+    /// Consumes the stream, returning the underlying [`UnixListener`](tokio::net::UnixListener).
+    ///
+    /// The socket file is no longer removed automatically, since the returned listener is still
+    /// bound to it. Connections that were accepted as part of a batch but not handed out yet are
+    /// closed.
+    #[cfg(unix)]
+    pub fn into_inner(self) -> tokio::net::UnixListener {
+        self.inner.into_inner()
+    }
+
+    /// Detaches the bound listener for handing it over to another process, for example to restart
+    /// a service without clients ever seeing the endpoint disappear.
+    ///
+    /// Returns the listening socket along with the path of the socket file, which is no longer
+    /// removed by this process. The socket is left open across `exec`, so a re-exec'd process
+    /// only needs to know its number, typically through an argument or environment variable, and
+    /// can resume accepting with [`from_raw_parts`](Self::from_raw_parts). Clients connecting in
+    /// between wait in the listen backlog. Connections that were accepted as part of a batch but
+    /// not handed out yet are closed.
+    #[cfg(unix)]
+    pub fn into_raw_parts(self) -> io::Result<(OwnedFd, Option<PathBuf>)> {
+        self.inner.into_raw_parts()
+    }
+
+    /// Resumes accepting on a listener detached with [`into_raw_parts`](Self::into_raw_parts).
+    ///
+    /// The socket file at `path` is removed when the returned stream is dropped, as it would have
+    /// been by the original stream. Statistics and other settings of the original stream aren't
+    /// carried over.
+    #[cfg(unix)]
+    pub fn from_raw_parts(listener: OwnedFd, path: Option<PathBuf>) -> io::Result<Self> {
+        let inner = platform::IpcStream::from_raw_parts(listener, path)?;
+        Ok(Self::wrap(inner))
+    }
+
+    /// Returns the path of the socket or named pipe this stream accepts connections on.
+    ///
+    /// `None` for Unix listeners that aren't bound to a path.
+    pub fn local_path(&self) -> Option<PathBuf> {
+        self.path.as_deref().map(Path::to_path_buf)
+    }
+
+    /// Returns a handle for shutting the stream down gracefully.
+    pub fn shutdown_handle(&self) -> ShutdownHandle {
+        self.shutdown.clone()
+    }
+
+    /// Wraps accepted connections in [`FramedConnection`](framing::FramedConnection)s, using
+    /// the [default maximum frame length](framing::DEFAULT_MAX_FRAME_LENGTH).
+    ///
+    /// Requires the `framing` feature.
+    #[cfg(feature = "framing")]
+    pub fn framed(self) -> framing::IncomingFramed {
+        framing::IncomingFramed::new(self)
+    }
+
+    /// Waits for the next incoming connection.
+    ///
+    /// Fails with [`io::ErrorKind::NotConnected`] once the stream was shut down through its
+    /// [`ShutdownHandle`].
+    pub async fn accept(&mut self) -> io::Result<Connection> {
+        poll_fn(|cx| self.poll_accept(cx)).await
+    }
+
+    /// Polls for the next incoming connection.
+    ///
+    /// This is the building block for [`accept`](Self::accept) and the `Stream` implementation,
+    /// for use in hand-written futures. While the endpoint's `max_connections` are open, this
+    /// returns `Pending` without polling the listener.
+    pub fn poll_accept(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<Connection>> {
+        if self.poll_shutdown_requested(cx) {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "The listener was shut down",
+            )));
+        }
+        // Connections the platform accepts ahead are held open, so it mustn't accept more than
+        // there's room for
+        let headroom = match self.max_connections {
+            Some(max) => {
+                let headroom = ready!(self.stats.poll_below(max.get() as u64, cx));
+                usize::try_from(headroom).unwrap_or(usize::MAX)
+            }
+            None => usize::MAX,
+        };
+        loop {
+            let conn = match ready!(self.inner.poll_accept(cx, headroom)) {
+                Ok(conn) => conn,
+                Err(e) => {
+                    self.stats.record_accept_error();
+                    return Poll::Ready(Err(e));
+                }
+            };
+            if !self.is_allowed(&conn) {
+                self.stats.record_rejected();
+                continue;
+            }
+            let stats = ConnectionStats::new(self.stats.clone());
+            let mut conn = Connection::wrap(conn)
+                .with_stats(stats)
+                .with_endpoint_path(self.path.clone())
+                .instrumented("server");
+            conn.set_max_lifetime(self.max_connection_lifetime);
+            conn.set_idle_timeout(self.connection_idle_timeout);
+            conn.set_max_buffered(self.connection_max_buffered);
+            #[cfg(any(feature = "mux", feature = "pubsub"))]
+            conn.set_memory_budget(self.connection_memory_budget.clone());
+            conn.set_qos_class(self.classify(&conn.inner));
+            return Poll::Ready(Ok(conn));
+        }
+    }
+
+    /// Serves connections until `shutdown` resolves, like [`Endpoint::serve`].
+    ///
+    /// Settings of the stream apply to the served connections, including the
+    /// [classifier](Self::set_qos_classifier) and the
+    /// [limit on batch handlers](Self::set_max_batch_handlers) that [`Endpoint::serve`] leaves
+    /// unset.
+    pub async fn serve<F, Fut>(
+        mut self,
+        mut handler: F,
+        shutdown: impl Future<Output = ()>,
+    ) -> io::Result<()>
+    where
+        F: FnMut(Connection) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let mut shutdown = pin!(shutdown);
+        let mut handlers = JoinSet::new();
+        let batch_slots = self
+            .max_batch_handlers
+            .map(|max| Arc::new(Semaphore::new(max.get())));
+        loop {
+            let accepted = poll_fn(|cx| {
+                while let Poll::Ready(Some(result)) = handlers.poll_join_next(cx) {
+                    if let Err(e) = result {
+                        tracing::trace!("Connection handler failed: {e:?}");
+                    }
+                }
+                if shutdown.as_mut().poll(cx).is_ready() {
+                    return Poll::Ready(None);
+                }
+                self.poll_accept(cx).map(Some)
+            })
+            .await;
+            match accepted {
+                Some(Ok(conn)) => {
+                    let slots = batch_slots
+                        .clone()
+                        .filter(|_| conn.qos_class() == QosClass::Batch);
+                    let handling = handler(conn);
+                    handlers.spawn(async move {
+                        let _slot = match slots {
+                            Some(slots) => slots.acquire_owned().await.ok(),
+                            None => None,
+                        };
+                        handling.await;
+                    });
+                }
+                // The stream's shutdown handle was used
+                Some(Err(_)) if self.shutdown_signal.is_none() => break,
+                Some(Err(e)) => {
+                    tracing::trace!("Failed to accept connection: {e:?}");
+                    // Errors like running out of file descriptors tend to repeat
+                    tokio::time::sleep(Duration::from_millis(10)).await;
+                }
+                None => break,
+            }
+        }
+
+        drop(self);
+        while let Some(result) = handlers.join_next().await {
+            if let Err(e) = result {
+                tracing::trace!("Connection handler failed: {e:?}");
+            }
+        }
+        Ok(())
+    }
+
+    fn poll_shutdown_requested(&mut self, cx: &mut Context<'_>) -> bool {
+        let Some(signal) = &mut self.shutdown_signal else {
+            return true;
+        };
+        if Pin::new(signal).poll(cx).is_pending() {
+            return false;
+        }
+        self.shutdown_signal = None;
+        true
+    }
+
+    fn classify(&self, conn: &platform::Connection) -> QosClass {
+        let Some(classifier) = &self.classifier else {
+            return QosClass::default();
+        };
+        match platform::peer_credentials(conn) {
+            Ok(credentials) => classifier(&credentials),
+            Err(e) => {
+                tracing::trace!("Classified peer with unknown credentials as batch: {e:?}");
+                QosClass::Batch
+            }
+        }
+    }
+
+    fn is_allowed(&self, conn: &platform::Connection) -> bool {
+        let Some(filter) = &self.filter else {
+            return true;
+        };
+        match platform::peer_credentials(conn) {
+            Ok(credentials) => filter(&credentials),
+            Err(e) => {
+                tracing::trace!("Rejected connection with unknown peer credentials: {e:?}");
+                false
+            }
+        }
     }
 }
 
+#[cfg(unix)]
+impl AsFd for Connection {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inner.as_fd()
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for Connection {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+#[cfg(windows)]
+impl AsHandle for Connection {
+    fn as_handle(&self) -> BorrowedHandle<'_> {
+        self.inner.as_handle()
+    }
+}
+
+#[cfg(windows)]
+impl AsRawHandle for Connection {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.inner.as_raw_handle()
+    }
+}
+
+/// The listening socket. Named pipes don't have a single listener on Windows, each client is
+/// accepted on a pipe instance of its own.
+#[cfg(unix)]
+impl AsFd for IpcStream {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.inner.as_fd()
+    }
+}
+
+#[cfg(unix)]
+impl AsRawFd for IpcStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.inner.as_raw_fd()
+    }
+}
+
+/// Accept errors are yielded as items, the stream only ends once it was shut down through its
+/// [`ShutdownHandle`].
+///
+/// Requires the `futures` feature, which is enabled by default.
+#[cfg(feature = "futures")]
 impl Stream for IpcStream {
     type Item = io::Result<Connection>;
 
     fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
         let this = Pin::into_inner(self);
-        Pin::new(&mut this.0).poll_next(cx).map_ok(Connection)
+        match this.poll_accept(cx) {
+            Poll::Ready(Err(_)) if this.shutdown_signal.is_none() => Poll::Ready(None),
+            poll => poll.map(Some),
+        }
     }
 }
@@ -0,0 +1,252 @@
+use std::future::{self, Future};
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::Context;
+use std::time::Duration;
+
+use tokio::runtime::Handle;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep_until, Instant, Sleep};
+
+/// Why the lifetime of a [`Connection`](crate::Connection) ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ExpiryReason {
+    /// The [maximum lifetime](crate::Connection::set_max_lifetime) has passed
+    MaxLifetime,
+    /// No data was read or written for the [idle timeout](crate::Connection::set_idle_timeout)
+    Idle,
+}
+
+/// When a connection was established and how long it may stay open.
+#[derive(Clone, Copy)]
+struct Limits {
+    established: Instant,
+    max: Option<Duration>,
+    idle_timeout: Option<Duration>,
+}
+
+/// State shared by the halves of a split connection and its timer task.
+struct Shared {
+    // milliseconds between `established` and the last activity
+    last_active: AtomicU64,
+    expired: watch::Sender<Option<ExpiryReason>>,
+}
+
+impl Shared {
+    fn expire(&self, reason: ExpiryReason) {
+        self.expired.send_if_modified(|expired| {
+            let changed = expired.is_none();
+            expired.get_or_insert(reason);
+            changed
+        });
+    }
+}
+
+impl Limits {
+    /// Returns why the lifetime ended by `now`, or when to check again.
+    fn check(&self, shared: &Shared, now: Instant) -> Result<ExpiryReason, Option<Instant>> {
+        let max_deadline = self.max.map(|max| self.established + max);
+        let last_active = Duration::from_millis(shared.last_active.load(Ordering::Relaxed));
+        let idle_deadline = self
+            .idle_timeout
+            .map(|idle_timeout| self.established + last_active + idle_timeout);
+        if max_deadline.is_some_and(|deadline| deadline <= now) {
+            return Ok(ExpiryReason::MaxLifetime);
+        }
+        if idle_deadline.is_some_and(|deadline| deadline <= now) {
+            return Ok(ExpiryReason::Idle);
+        }
+        Err(match (max_deadline, idle_deadline) {
+            (Some(max), Some(idle)) => Some(max.min(idle)),
+            (max, idle) => max.or(idle),
+        })
+    }
+}
+
+/// Aborts the timer task once the last half of the connection is dropped.
+struct Timer(JoinHandle<()>);
+
+impl Drop for Timer {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// Ends the lifetime when it's over, even if the connection isn't polled in the meantime.
+async fn run_timer(limits: Limits, shared: Arc<Shared>) {
+    loop {
+        match limits.check(&shared, Instant::now()) {
+            Ok(reason) => {
+                shared.expire(reason);
+                return;
+            }
+            Err(Some(deadline)) => sleep_until(deadline).await,
+            Err(None) => return,
+        }
+    }
+}
+
+/// Upper bound on how long a connection stays open, in total and without any activity.
+///
+/// The lifetime is measured from when the connection was established, the idle timeout from the
+/// last read or write that transferred data. Once either is over, the connection is shut down
+/// gracefully: the peer sees the end of the stream and local reads report it as well. A timer
+/// task records the expiry as soon as it happens, so tasks waiting for it are woken even while
+/// the connection itself isn't polled.
+pub(crate) struct Lifetime {
+    limits: Limits,
+    shared: Arc<Shared>,
+    sleep: Option<Pin<Box<Sleep>>>,
+    timer: Option<Arc<Timer>>,
+    shut_down: bool,
+}
+
+impl Lifetime {
+    pub(crate) fn new() -> Self {
+        Self {
+            limits: Limits {
+                established: Instant::now(),
+                max: None,
+                idle_timeout: None,
+            },
+            shared: Arc::new(Shared {
+                last_active: AtomicU64::new(0),
+                expired: watch::Sender::new(None),
+            }),
+            sleep: None,
+            timer: None,
+            shut_down: false,
+        }
+    }
+
+    /// Creates an independent copy ending at the same time, for the halves of a split connection.
+    pub(crate) fn duplicate(&self) -> Self {
+        let mut lifetime = Self {
+            limits: self.limits,
+            shared: self.shared.clone(),
+            sleep: None,
+            timer: self.timer.clone(),
+            shut_down: self.shut_down,
+        };
+        lifetime.reset_sleep();
+        lifetime
+    }
+
+    pub(crate) fn get(&self) -> Option<Duration> {
+        self.limits.max
+    }
+
+    pub(crate) fn set(&mut self, max: Option<Duration>) {
+        self.limits.max = max;
+        self.reset_timer();
+    }
+
+    pub(crate) fn idle_timeout(&self) -> Option<Duration> {
+        self.limits.idle_timeout
+    }
+
+    pub(crate) fn set_idle_timeout(&mut self, idle_timeout: Option<Duration>) {
+        self.limits.idle_timeout = idle_timeout;
+        self.record_activity();
+        self.reset_timer();
+    }
+
+    /// Restarts the idle timeout, called whenever data was read or written.
+    pub(crate) fn record_activity(&self) {
+        if self.limits.idle_timeout.is_some() {
+            let elapsed = self.limits.established.elapsed().as_millis();
+            let elapsed = u64::try_from(elapsed).unwrap_or(u64::MAX);
+            self.shared.last_active.store(elapsed, Ordering::Relaxed);
+        }
+    }
+
+    fn reset_sleep(&mut self) {
+        let next_check = self.limits.check(&self.shared, Instant::now());
+        self.sleep = match next_check {
+            Ok(_) => Some(Box::pin(sleep_until(Instant::now()))),
+            Err(deadline) => deadline.map(|deadline| Box::pin(sleep_until(deadline))),
+        };
+    }
+
+    /// Restarts the timer task and the sleep for the current limits.
+    fn reset_timer(&mut self) {
+        self.reset_sleep();
+        // Without a runtime, the expiry is only noticed when the connection is polled
+        self.timer = match (&self.sleep, Handle::try_current()) {
+            (Some(_), Ok(handle)) => {
+                let task = handle.spawn(run_timer(self.limits, self.shared.clone()));
+                Some(Arc::new(Timer(task)))
+            }
+            _ => None,
+        };
+    }
+
+    /// Returns whether the lifetime is over, scheduling a wake-up for when it ends otherwise.
+    pub(crate) fn poll_expired(&mut self, cx: &mut Context<'_>) -> bool {
+        while let Some(timer) = &mut self.sleep {
+            if self.shared.expired.borrow().is_some() {
+                self.sleep = None;
+                break;
+            }
+            if timer.as_mut().poll(cx).is_pending() {
+                break;
+            }
+            match self.limits.check(&self.shared, Instant::now()) {
+                Ok(reason) => {
+                    self.shared.expire(reason);
+                    self.sleep = None;
+                }
+                // There was activity since the timer was set
+                Err(_) => self.reset_sleep(),
+            }
+        }
+        self.expiry().is_some()
+    }
+
+    /// Why the lifetime ended, if it did.
+    pub(crate) fn expiry(&self) -> Option<ExpiryReason> {
+        *self.shared.expired.borrow()
+    }
+
+    /// Resolves once the lifetime ended, which is never if no limit is set.
+    pub(crate) async fn expired(&self) -> ExpiryReason {
+        let mut expired = self.shared.expired.subscribe();
+        // The sender is kept alive by `self`, so waiting can't fail
+        let reason = expired
+            .wait_for(Option::is_some)
+            .await
+            .map(|reason| *reason);
+        match reason {
+            Ok(Some(reason)) => reason,
+            _ => future::pending().await,
+        }
+    }
+
+    /// Ends the lifetime right away, as if the limit for `reason` ran out.
+    #[cfg(feature = "test-util")]
+    pub(crate) fn expire_now(&self, reason: ExpiryReason) {
+        self.shared.expire(reason);
+    }
+
+    /// Whether the connection still needs to be shut down after the lifetime ended.
+    pub(crate) fn needs_shutdown(&self) -> bool {
+        self.expiry().is_some() && !self.shut_down
+    }
+
+    pub(crate) fn mark_shut_down(&mut self) {
+        self.shut_down = true;
+    }
+
+    /// The error reported by writes once the lifetime ended.
+    pub(crate) fn expired_error(&self) -> io::Error {
+        let reason = match self.expiry() {
+            Some(ExpiryReason::Idle) => "connection exceeded its idle timeout",
+            _ => "connection exceeded its maximum lifetime",
+        };
+        io::Error::new(io::ErrorKind::BrokenPipe, reason)
+    }
+}
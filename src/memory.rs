@@ -0,0 +1,208 @@
+//! A memory budget shared by many connections.
+//!
+//! Multiplexed streams and pub/sub subscriptions queue received data until it's read.
+//! [`Connection::set_max_buffered`] caps that per connection. A [`MemoryBudget`] caps what all
+//! connections it's attached to buffer together, giving a single knob for the worst case memory
+//! of a process serving many peers.
+//!
+//! Requires the `mux` or `pubsub` feature.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::pin::pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use futures::future::{self, Either, Future};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::CancellationToken;
+
+use crate::Connection;
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn permits(len: usize) -> u32 {
+    u32::try_from(len.min(Semaphore::MAX_PERMITS)).unwrap_or(u32::MAX)
+}
+
+/// What happens when received data doesn't fit in a [`MemoryBudget`] anymore.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum BudgetPolicy {
+    /// Connections stop reading until data buffered by any of them is read.
+    ///
+    /// Nothing is dropped, but a peer that's never read from holds up every other connection.
+    #[default]
+    Backpressure,
+    /// The connection that joined the budget last is shed to make room.
+    ///
+    /// It stops reading like after losing the connection, so its streams read EOF and its
+    /// subscriptions end. The data it already buffered is given back as it's read or its streams
+    /// and subscriptions are dropped, and the connection that ran out waits for that like with
+    /// [`Backpressure`](Self::Backpressure).
+    ShedNewest,
+}
+
+/// Bytes of received data that all connections attached to it may buffer together.
+///
+/// Clones share the same budget. Attach it to connections with
+/// [`Connection::set_memory_budget`] or to everything a server accepts with
+/// [`IpcStream::set_connection_memory_budget`](crate::IpcStream::set_connection_memory_budget).
+/// Connections join the budget when a multiplexer or subscriber is created on them and leave it
+/// when that's dropped.
+#[derive(Clone)]
+pub struct MemoryBudget(Arc<Shared>);
+
+struct Shared {
+    limit: usize,
+    policy: BudgetPolicy,
+    semaphore: Arc<Semaphore>,
+    next_id: AtomicU64,
+    // Connections that joined, oldest first
+    members: Mutex<BTreeMap<u64, CancellationToken>>,
+}
+
+impl MemoryBudget {
+    /// Creates a budget of `limit` bytes that applies `policy` once it's exhausted.
+    ///
+    /// Single frames longer than the budget are admitted once nothing else is buffered.
+    pub fn new(limit: usize, policy: BudgetPolicy) -> Self {
+        let limit = limit.clamp(1, Semaphore::MAX_PERMITS);
+        Self(Arc::new(Shared {
+            limit,
+            policy,
+            semaphore: Arc::new(Semaphore::new(limit)),
+            next_id: AtomicU64::new(0),
+            members: Mutex::new(BTreeMap::new()),
+        }))
+    }
+
+    /// Returns the number of bytes connections may buffer together.
+    pub fn limit(&self) -> usize {
+        self.0.limit
+    }
+
+    /// Returns the number of bytes that are not buffered right now.
+    pub fn available(&self) -> usize {
+        self.0.semaphore.available_permits()
+    }
+
+    /// Returns what happens when the budget is exhausted.
+    pub fn policy(&self) -> BudgetPolicy {
+        self.0.policy
+    }
+
+    fn join(&self) -> Member {
+        let id = self.0.next_id.fetch_add(1, Ordering::Relaxed);
+        let shed = CancellationToken::new();
+        lock(&self.0.members).insert(id, shed.clone());
+        Member {
+            budget: self.clone(),
+            id,
+            shed,
+        }
+    }
+
+    fn shed_newest(&self) {
+        if let Some((id, shed)) = lock(&self.0.members).pop_last() {
+            tracing::debug!("Shedding connection {id} to stay within the memory budget");
+            shed.cancel();
+        }
+    }
+}
+
+impl fmt::Debug for MemoryBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MemoryBudget")
+            .field("limit", &self.0.limit)
+            .field("available", &self.available())
+            .field("policy", &self.0.policy)
+            .finish_non_exhaustive()
+    }
+}
+
+struct Member {
+    budget: MemoryBudget,
+    id: u64,
+    shed: CancellationToken,
+}
+
+impl Member {
+    // Returns `None` once the connection is shed
+    async fn reserve(&self, len: usize) -> Option<OwnedSemaphorePermit> {
+        let shared = &self.budget.0;
+        let len = permits(len.min(shared.limit));
+        if shared.policy == BudgetPolicy::ShedNewest {
+            if let Ok(permit) = shared.semaphore.clone().try_acquire_many_owned(len) {
+                return Some(permit);
+            }
+            self.budget.shed_newest();
+        }
+        let acquire = shared.semaphore.clone().acquire_many_owned(len);
+        match future::select(pin!(acquire), pin!(self.shed.cancelled())).await {
+            Either::Left((permit, _)) => permit.ok(),
+            Either::Right(_) => None,
+        }
+    }
+}
+
+impl Drop for Member {
+    fn drop(&mut self) {
+        lock(&self.budget.0.members).remove(&self.id);
+    }
+}
+
+/// Room for received data that's queued until it's read, shared by everything buffering data of
+/// one connection.
+pub(crate) struct BufferBudget {
+    connection: Option<Arc<Semaphore>>,
+    shared: Option<Member>,
+}
+
+/// Room held on to while received data is buffered.
+pub(crate) struct Room {
+    _connection: Option<OwnedSemaphorePermit>,
+    _shared: Option<OwnedSemaphorePermit>,
+}
+
+impl BufferBudget {
+    pub(crate) fn new(conn: &Connection) -> Self {
+        Self {
+            connection: conn
+                .max_buffered()
+                .map(|max| Arc::new(Semaphore::new(max.min(Semaphore::MAX_PERMITS)))),
+            shared: conn.memory_budget().map(MemoryBudget::join),
+        }
+    }
+
+    /// Waits until `len` more bytes fit, returning the room to hold on to while they're buffered.
+    ///
+    /// Frames are never longer than the connection's cap, so they always fit eventually. Returns
+    /// `None` once the connection is shed from its memory budget.
+    pub(crate) async fn reserve(&self, len: usize) -> Option<Room> {
+        let connection = match self.connection.clone() {
+            Some(semaphore) => semaphore.acquire_many_owned(permits(len)).await.ok(),
+            None => None,
+        };
+        let shared = match &self.shared {
+            Some(member) => Some(member.reserve(len).await?),
+            None => None,
+        };
+        Some(Room {
+            _connection: connection,
+            _shared: shared,
+        })
+    }
+
+    /// Runs `fut` to completion, unless the connection is shed from its memory budget first.
+    pub(crate) async fn unless_shed<F: Future>(&self, fut: F) -> Option<F::Output> {
+        let Some(member) = &self.shared else {
+            return Some(fut.await);
+        };
+        match future::select(pin!(fut), pin!(member.shed.cancelled())).await {
+            Either::Left((output, _)) => Some(output),
+            Either::Right(_) => None,
+        }
+    }
+}
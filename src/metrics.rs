@@ -0,0 +1,189 @@
+//! Metrics of endpoints, emitted through the `metrics` facade with the `metrics` feature.
+//!
+//! Every metric carries an `endpoint` label with the path of the endpoint. Endpoints report the
+//! number of active connections, how many were accepted, turned away by their accept filter or
+//! failed to be accepted, the bytes read from and written to their connections and the round-trip
+//! times measured by heartbeat pings on them. Clients report how long connecting to an endpoint
+//! takes and how often it fails. Without the feature, nothing is recorded.
+//!
+//! | Metric                                 | Kind      |
+//! |----------------------------------------|-----------|
+//! | `tokio_ipc_active_connections`         | gauge     |
+//! | `tokio_ipc_accepted_connections_total` | counter   |
+//! | `tokio_ipc_rejected_connections_total` | counter   |
+//! | `tokio_ipc_accept_errors_total`        | counter   |
+//! | `tokio_ipc_io_errors_total`            | counter   |
+//! | `tokio_ipc_bytes_read_total`           | counter   |
+//! | `tokio_ipc_bytes_written_total`        | counter   |
+//! | `tokio_ipc_round_trip_seconds`         | histogram |
+//! | `tokio_ipc_connect_duration_seconds`   | histogram |
+//! | `tokio_ipc_connect_errors_total`       | counter   |
+
+#[cfg(not(feature = "metrics"))]
+pub(crate) use self::disabled::*;
+#[cfg(feature = "metrics")]
+pub(crate) use self::enabled::*;
+
+#[cfg(feature = "metrics")]
+mod enabled {
+    use std::fmt;
+    use std::path::Path;
+    use std::time::Duration;
+
+    use metrics::{counter, gauge, histogram, Counter, Gauge, Histogram};
+
+    fn label(path: Option<&Path>) -> String {
+        path.map(|path| path.display().to_string())
+            .unwrap_or_default()
+    }
+
+    /// Handles to the metrics of one endpoint, registered once so recording stays cheap.
+    pub(crate) struct EndpointMetrics {
+        active_connections: Gauge,
+        accepted_connections: Counter,
+        rejected_connections: Counter,
+        accept_errors: Counter,
+        io_errors: Counter,
+        bytes_read: Counter,
+        bytes_written: Counter,
+        round_trip: Histogram,
+    }
+
+    impl EndpointMetrics {
+        pub(crate) fn new(path: Option<&Path>) -> Self {
+            let endpoint = label(path);
+            Self {
+                active_connections: gauge!(
+                    "tokio_ipc_active_connections",
+                    "endpoint" => endpoint.clone()
+                ),
+                accepted_connections: counter!(
+                    "tokio_ipc_accepted_connections_total",
+                    "endpoint" => endpoint.clone()
+                ),
+                rejected_connections: counter!(
+                    "tokio_ipc_rejected_connections_total",
+                    "endpoint" => endpoint.clone()
+                ),
+                accept_errors: counter!(
+                    "tokio_ipc_accept_errors_total",
+                    "endpoint" => endpoint.clone()
+                ),
+                io_errors: counter!(
+                    "tokio_ipc_io_errors_total",
+                    "endpoint" => endpoint.clone()
+                ),
+                bytes_read: counter!(
+                    "tokio_ipc_bytes_read_total",
+                    "endpoint" => endpoint.clone()
+                ),
+                bytes_written: counter!(
+                    "tokio_ipc_bytes_written_total",
+                    "endpoint" => endpoint.clone()
+                ),
+                round_trip: histogram!(
+                    "tokio_ipc_round_trip_seconds",
+                    "endpoint" => endpoint
+                ),
+            }
+        }
+
+        pub(crate) fn record_accepted(&self) {
+            self.accepted_connections.increment(1);
+            self.active_connections.increment(1.0);
+        }
+
+        pub(crate) fn record_closed(&self) {
+            self.active_connections.decrement(1.0);
+        }
+
+        pub(crate) fn record_rejected(&self) {
+            self.rejected_connections.increment(1);
+        }
+
+        pub(crate) fn record_accept_error(&self) {
+            self.accept_errors.increment(1);
+        }
+
+        pub(crate) fn record_io_error(&self) {
+            self.io_errors.increment(1);
+        }
+
+        pub(crate) fn record_read(&self, bytes: usize) {
+            self.bytes_read.increment(bytes as u64);
+        }
+
+        pub(crate) fn record_written(&self, bytes: usize) {
+            self.bytes_written.increment(bytes as u64);
+        }
+
+        pub(crate) fn record_round_trip(&self, rtt: Duration) {
+            self.round_trip.record(rtt.as_secs_f64());
+        }
+    }
+
+    impl Default for EndpointMetrics {
+        fn default() -> Self {
+            Self {
+                active_connections: Gauge::noop(),
+                accepted_connections: Counter::noop(),
+                rejected_connections: Counter::noop(),
+                accept_errors: Counter::noop(),
+                io_errors: Counter::noop(),
+                bytes_read: Counter::noop(),
+                bytes_written: Counter::noop(),
+                round_trip: Histogram::noop(),
+            }
+        }
+    }
+
+    impl fmt::Debug for EndpointMetrics {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("EndpointMetrics").finish_non_exhaustive()
+        }
+    }
+
+    pub(crate) fn record_connect(path: &Path, elapsed: Duration) {
+        histogram!("tokio_ipc_connect_duration_seconds", "endpoint" => label(Some(path)))
+            .record(elapsed.as_secs_f64());
+    }
+
+    pub(crate) fn record_connect_error(path: &Path) {
+        counter!("tokio_ipc_connect_errors_total", "endpoint" => label(Some(path))).increment(1);
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+mod disabled {
+    use std::path::Path;
+    use std::time::Duration;
+
+    #[derive(Debug, Default)]
+    pub(crate) struct EndpointMetrics;
+
+    impl EndpointMetrics {
+        pub(crate) fn new(_path: Option<&Path>) -> Self {
+            Self
+        }
+
+        pub(crate) fn record_accepted(&self) {}
+
+        pub(crate) fn record_closed(&self) {}
+
+        pub(crate) fn record_rejected(&self) {}
+
+        pub(crate) fn record_accept_error(&self) {}
+
+        pub(crate) fn record_io_error(&self) {}
+
+        pub(crate) fn record_read(&self, _bytes: usize) {}
+
+        pub(crate) fn record_written(&self, _bytes: usize) {}
+
+        pub(crate) fn record_round_trip(&self, _rtt: Duration) {}
+    }
+
+    pub(crate) fn record_connect(_path: &Path, _elapsed: Duration) {}
+
+    pub(crate) fn record_connect_error(_path: &Path) {}
+}
@@ -0,0 +1,161 @@
+//! Composable wrappers around the handling of accepted connections.
+//!
+//! A [`Chain`] runs every accepted connection through a list of [`Middleware`]s before it reaches
+//! the handler. Each middleware receives the [`Connection`] along with a [`Next`] that runs the
+//! rest of the chain, so it can inspect the connection first (for example its
+//! [`peer_credentials`](Connection::peer_credentials)), attach state to its
+//! [`extensions`](Connection::extensions_mut), do work once the handler is done, or drop the
+//! connection without calling [`Next::run`] at all.
+//!
+//! ```no_run
+//! use tokio_ipc::middleware::{from_fn, Chain};
+//! use tokio_ipc::{Endpoint, ServerId};
+//!
+//! # async fn run() -> std::io::Result<()> {
+//! let handler = Chain::new()
+//!     .layer(from_fn(|conn, next| async move {
+//!         match conn.peer_credentials() {
+//!             Ok(credentials) => {
+//!                 tracing::info!("Accepted connection from {:?}", credentials.pid());
+//!                 next.run(conn).await;
+//!             }
+//!             Err(e) => tracing::warn!("Rejected connection: {e}"),
+//!         }
+//!     }))
+//!     .handler(|_conn| async move {
+//!         // talk to the client
+//!     });
+//!
+//! Endpoint::new(ServerId::new("my-server"), None)?
+//!     .serve(handler, std::future::pending())
+//!     .await
+//! # }
+//! ```
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+use crate::Connection;
+
+/// The future returned by [`Middleware::call`] and [`Next::run`].
+pub type BoxFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+type Handler = dyn Fn(Connection) -> BoxFuture + Send + Sync;
+
+/// A step in a [`Chain`] that wraps the handling of each connection.
+///
+/// Closures can be turned into middleware with [`from_fn`].
+pub trait Middleware: Send + Sync + 'static {
+    /// Handles a connection, calling [`next.run`](Next::run) to pass it on to the rest of the
+    /// chain.
+    fn call(&self, conn: Connection, next: Next) -> BoxFuture;
+}
+
+/// The rest of a [`Chain`], from the point of view of a [`Middleware`].
+pub struct Next {
+    middleware: Arc<[Arc<dyn Middleware>]>,
+    handler: Arc<Handler>,
+    index: usize,
+}
+
+impl Next {
+    /// Runs the remaining middleware and the handler on `conn`.
+    pub fn run(mut self, conn: Connection) -> BoxFuture {
+        match self.middleware.get(self.index).cloned() {
+            Some(middleware) => {
+                self.index += 1;
+                middleware.call(conn, self)
+            }
+            None => (self.handler)(conn),
+        }
+    }
+}
+
+impl fmt::Debug for Next {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Next")
+            .field("remaining", &(self.middleware.len() - self.index))
+            .finish_non_exhaustive()
+    }
+}
+
+/// An ordered list of [`Middleware`]s in front of a connection handler.
+#[derive(Default)]
+pub struct Chain {
+    middleware: Vec<Arc<dyn Middleware>>,
+}
+
+impl Chain {
+    /// Creates an empty chain.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a middleware. Middleware added first sees each connection first.
+    pub fn layer(mut self, middleware: impl Middleware) -> Self {
+        self.middleware.push(Arc::new(middleware));
+        self
+    }
+
+    /// Puts `handler` at the end of the chain, returning a handler for
+    /// [`Endpoint::serve`](crate::Endpoint::serve).
+    pub fn handler<F, Fut>(
+        self,
+        handler: F,
+    ) -> impl Fn(Connection) -> BoxFuture + Clone + Send + Sync + 'static
+    where
+        F: Fn(Connection) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let middleware: Arc<[Arc<dyn Middleware>]> = self.middleware.into();
+        let handler: Arc<Handler> =
+            Arc::new(move |conn: Connection| Box::pin(handler(conn)) as BoxFuture);
+        move |conn: Connection| {
+            let next = Next {
+                middleware: middleware.clone(),
+                handler: handler.clone(),
+                index: 0,
+            };
+            next.run(conn)
+        }
+    }
+}
+
+impl fmt::Debug for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Chain")
+            .field("middleware", &self.middleware.len())
+            .finish()
+    }
+}
+
+/// Middleware created from a closure with [`from_fn`].
+#[derive(Clone)]
+pub struct FromFn<F>(F);
+
+impl<F> fmt::Debug for FromFn<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FromFn").finish_non_exhaustive()
+    }
+}
+
+/// Creates a [`Middleware`] from an async closure taking the connection and the [`Next`] step.
+pub fn from_fn<F, Fut>(f: F) -> FromFn<F>
+where
+    F: Fn(Connection, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    FromFn(f)
+}
+
+impl<F, Fut> Middleware for FromFn<F>
+where
+    F: Fn(Connection, Next) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    fn call(&self, conn: Connection, next: Next) -> BoxFuture {
+        Box::pin((self.0)(conn, next))
+    }
+}
@@ -0,0 +1,588 @@
+//! Many logical streams over a single connection.
+//!
+//! A [`Multiplexer`] splits one [`Connection`] into any number of independent bidirectional
+//! [`MuxStream`]s, so concurrent operations don't each need their own socket or pipe. Either side
+//! can [`open`](Multiplexer::open) streams, the other side picks them up with
+//! [`accept`](Multiplexer::accept).
+//!
+//! Every stream has a receive window of 256 KiB per direction, like yamux. A side may only send
+//! as much data as the other side's window allows, and the window is replenished with window
+//! updates as the data is read. Writing to a stream whose reader falls behind therefore waits
+//! instead of buffering without limit, and so does writing while the connection itself is
+//! backed up. With many streams open, the windows add up; the
+//! [cap on buffered data](crate::Connection::set_max_buffered) of the connection bounds what all
+//! streams buffer together, at the cost of streams that aren't read holding up the others. Data
+//! frames carry up to 64 KiB, so the cap has to be larger than that.
+//!
+//! Frames wait for the connection in two lanes. Window updates, stream openings and the frames of
+//! streams set to [`Priority::High`] are written before anything in the normal lane, so they
+//! don't sit behind megabytes of bulk data queued by other streams. A frame that's already being
+//! written isn't interrupted, which bounds the delay to one frame of at most 64 KiB.
+//!
+//! Requires the `mux` feature.
+
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{ready, Context, Poll, Waker};
+use std::{fmt, io};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::stream::{SplitSink, SplitStream};
+use futures::{SinkExt, StreamExt};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::{mpsc, OwnedSemaphorePermit, Semaphore};
+use tokio_util::sync::PollSemaphore;
+
+use crate::framing::FramedConnection;
+use crate::memory::{BufferBudget, Room};
+use crate::Connection;
+
+const OPEN: u8 = 0;
+const DATA: u8 = 1;
+const CLOSE: u8 = 2;
+const WINDOW_UPDATE: u8 = 3;
+
+const HEADER_LEN: usize = 5;
+const MAX_PAYLOAD_LEN: usize = 64 * 1024;
+const INITIAL_WINDOW: u32 = 256 * 1024;
+// Number of data frames that may wait for the connection across all streams
+const OUTBOUND_CAPACITY: usize = 64;
+
+type Streams = Arc<Mutex<HashMap<u32, StreamEntry>>>;
+
+struct StreamEntry {
+    // `None` once the other side shut down its write side
+    inbound: Option<mpsc::UnboundedSender<Received>>,
+    state: Arc<Mutex<StreamState>>,
+}
+
+/// Flow control and write progress of a stream, shared with the reader and writer tasks.
+struct StreamState {
+    // Bytes the other side is still willing to receive
+    send_window: u32,
+    // Bytes the other side may still send before it has to wait for a window update
+    recv_window: u32,
+    // Frames of the stream written to the connection so far
+    written: u64,
+    // Set once the connection can't carry any more frames
+    closed: bool,
+    // Task waiting for window or for queued frames to be written
+    waker: Option<Waker>,
+}
+
+impl StreamState {
+    fn shared() -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
+            send_window: INITIAL_WINDOW,
+            recv_window: INITIAL_WINDOW,
+            written: 0,
+            closed: false,
+            waker: None,
+        }))
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// The lane a [`MuxStream`] queues its frames in.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Priority {
+    /// Written in order with the frames of other normal streams.
+    #[default]
+    Normal,
+    /// Written ahead of frames waiting in the normal lane, for control messages like heartbeats
+    /// or cancellations.
+    High,
+}
+
+/// Data received for a stream, holding on to its share of the connection's buffer budget.
+struct Received {
+    data: Bytes,
+    room: Room,
+}
+
+/// A frame waiting for the writer task.
+struct Outbound {
+    frame: Bytes,
+    priority: Priority,
+    // Set for frames that count towards flushing the stream
+    stream: Option<Arc<Mutex<StreamState>>>,
+    // Set for data frames, released once the frame is written
+    permit: Option<OwnedSemaphorePermit>,
+}
+
+impl Outbound {
+    fn control(frame: Bytes, priority: Priority) -> Self {
+        Self {
+            frame,
+            priority,
+            stream: None,
+            permit: None,
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn frame(id: u32, kind: u8, payload: &[u8]) -> Bytes {
+    let mut frame = BytesMut::with_capacity(HEADER_LEN + payload.len());
+    frame.put_u32(id);
+    frame.put_u8(kind);
+    frame.put_slice(payload);
+    frame.freeze()
+}
+
+fn connection_closed() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "multiplexed connection closed")
+}
+
+/// Opens and accepts [`MuxStream`]s over a single connection.
+///
+/// Both ends of the connection need a multiplexer, one created with [`client`](Self::client)
+/// and the other with [`server`](Self::server), so that the stream IDs they assign never collide.
+/// The connection stays open until the multiplexer and all of its streams are dropped.
+pub struct Multiplexer {
+    streams: Streams,
+    outbound: mpsc::UnboundedSender<Outbound>,
+    permits: Arc<Semaphore>,
+    incoming: mpsc::UnboundedReceiver<MuxStream>,
+    next_id: AtomicU32,
+}
+
+impl Multiplexer {
+    /// Multiplexes the client end of a connection.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn client(conn: Connection) -> Self {
+        Self::new(conn, 1)
+    }
+
+    /// Multiplexes the server end of a connection.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn server(conn: Connection) -> Self {
+        Self::new(conn, 2)
+    }
+
+    fn new(conn: Connection, first_id: u32) -> Self {
+        let budget = BufferBudget::new(&conn);
+        let (sink, stream) = FramedConnection::new(conn).split();
+        let streams = Streams::default();
+        let permits = Arc::new(Semaphore::new(OUTBOUND_CAPACITY));
+        let (outbound, outbound_rx) = mpsc::unbounded_channel();
+        let (incoming_tx, incoming) = mpsc::unbounded_channel();
+
+        tokio::spawn(write_frames(sink, outbound_rx, permits.clone()));
+        tokio::spawn(read_frames(
+            stream,
+            streams.clone(),
+            outbound.downgrade(),
+            permits.clone(),
+            incoming_tx,
+            budget,
+        ));
+
+        Self {
+            streams,
+            outbound,
+            permits,
+            incoming,
+            next_id: AtomicU32::new(first_id),
+        }
+    }
+
+    /// Opens a new stream to the other side.
+    ///
+    /// Fails once all stream IDs of this side are used up, since they are never reused.
+    pub fn open(&self) -> io::Result<MuxStream> {
+        let id = self
+            .next_id
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |id| id.checked_add(2))
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "out of multiplexed stream IDs"))?;
+        let stream = MuxStream::register(id, &self.streams, &self.outbound, &self.permits);
+        self.outbound
+            .send(Outbound::control(frame(id, OPEN, &[]), Priority::High))
+            .map_err(|_| connection_closed())?;
+        Ok(stream)
+    }
+
+    /// Waits for the next stream opened by the other side, or `None` once the connection closed.
+    pub async fn accept(&mut self) -> Option<MuxStream> {
+        self.incoming.recv().await
+    }
+}
+
+impl fmt::Debug for Multiplexer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Multiplexer")
+            .field("streams", &lock(&self.streams).len())
+            .finish_non_exhaustive()
+    }
+}
+
+async fn write_frames(
+    mut sink: SplitSink<FramedConnection, Bytes>,
+    mut outbound: mpsc::UnboundedReceiver<Outbound>,
+    permits: Arc<Semaphore>,
+) {
+    let mut high = VecDeque::new();
+    let mut normal = VecDeque::new();
+    loop {
+        // Sort everything that's waiting, so high priority frames can overtake the rest
+        while let Ok(next) = outbound.try_recv() {
+            match next.priority {
+                Priority::High => high.push_back(next),
+                Priority::Normal => normal.push_back(next),
+            }
+        }
+        let next = match high.pop_front().or_else(|| normal.pop_front()) {
+            Some(next) => next,
+            None => match outbound.recv().await {
+                Some(next) => next,
+                None => break,
+            },
+        };
+        let Outbound {
+            frame,
+            stream,
+            permit,
+            ..
+        } = next;
+        if let Err(e) = sink.send(frame).await {
+            tracing::trace!("Failed to write multiplexed frame: {e:?}");
+            break;
+        }
+        drop(permit);
+        if let Some(state) = stream {
+            let mut state = lock(&state);
+            state.written += 1;
+            state.wake();
+        }
+    }
+    let _ = sink.close().await;
+    // Fail the writes and flushes still waiting for the connection.
+    permits.close();
+    outbound.close();
+    while let Ok(next) = outbound.try_recv() {
+        normal.push_back(next);
+    }
+    for Outbound { stream, .. } in high.into_iter().chain(normal) {
+        if let Some(state) = stream {
+            let mut state = lock(&state);
+            state.closed = true;
+            state.wake();
+        }
+    }
+}
+
+async fn read_frames(
+    mut stream: SplitStream<FramedConnection>,
+    streams: Streams,
+    outbound: mpsc::WeakUnboundedSender<Outbound>,
+    permits: Arc<Semaphore>,
+    incoming: mpsc::UnboundedSender<MuxStream>,
+    budget: BufferBudget,
+) {
+    // Being shed from the memory budget ends reading like losing the connection.
+    while let Some(Some(Ok(mut frame))) = budget.unless_shed(stream.next()).await {
+        if frame.len() < HEADER_LEN {
+            tracing::trace!("Dropping truncated multiplexed frame");
+            continue;
+        }
+        let id = frame.get_u32();
+        match frame.get_u8() {
+            OPEN => {
+                if lock(&streams).contains_key(&id) {
+                    tracing::trace!("Ignoring open of multiplexed stream {id}, which is in use");
+                    continue;
+                }
+                // Holding on to a strong sender here would keep the connection open forever.
+                if let Some(outbound) = outbound.upgrade() {
+                    let stream = MuxStream::register(id, &streams, &outbound, &permits);
+                    // Dropping a stream nobody accepts closes it again.
+                    let _ = incoming.send(stream);
+                }
+            }
+            DATA => {
+                // Stops reading until the streams read some of what they buffered
+                let Some(room) = budget.reserve(frame.len()).await else {
+                    break;
+                };
+                let mut streams = lock(&streams);
+                let Some(entry) = streams.get(&id) else {
+                    continue;
+                };
+                let mut state = lock(&entry.state);
+                match state.recv_window.checked_sub(frame.len() as u32) {
+                    Some(window) => {
+                        state.recv_window = window;
+                        drop(state);
+                        if let Some(inbound) = &entry.inbound {
+                            let _ = inbound.send(Received { data: frame, room });
+                        }
+                    }
+                    None => {
+                        tracing::trace!(
+                            "Closing multiplexed stream {id}, which overran its window"
+                        );
+                        drop(state);
+                        streams.remove(&id);
+                    }
+                }
+            }
+            WINDOW_UPDATE if frame.len() == 4 => {
+                let increment = frame.get_u32();
+                if let Some(entry) = lock(&streams).get(&id) {
+                    let mut state = lock(&entry.state);
+                    state.send_window = state.send_window.saturating_add(increment);
+                    state.wake();
+                }
+            }
+            // Only the other side's write side is done, the entry stays until the local stream
+            // is dropped so it still gets window updates and learns about connection loss.
+            CLOSE => {
+                if let Some(entry) = lock(&streams).get_mut(&id) {
+                    entry.inbound = None;
+                }
+            }
+            kind => tracing::trace!("Dropping multiplexed frame of unknown kind {kind}"),
+        }
+    }
+    // Every stream reads EOF once the connection is gone, and writes fail.
+    for (_, entry) in lock(&streams).drain() {
+        let mut state = lock(&entry.state);
+        state.closed = true;
+        state.wake();
+    }
+}
+
+/// A logical bidirectional stream carried by a [`Multiplexer`].
+///
+/// Shutting down the write side lets the other side read EOF, while reading keeps working until
+/// the other side does the same. Dropping the stream closes both directions, anything the other
+/// side still sends is discarded.
+///
+/// Writes wait while the other side's window is used up or the connection is backed up, and
+/// flushing waits until everything written so far was handed to the connection.
+pub struct MuxStream {
+    id: u32,
+    inbound: mpsc::UnboundedReceiver<Received>,
+    buffered: Bytes,
+    // Share of the connection's buffer budget held by `buffered`
+    room: Option<Room>,
+    // Bytes read since the last window update
+    consumed: u32,
+    state: Arc<Mutex<StreamState>>,
+    outbound: mpsc::UnboundedSender<Outbound>,
+    permits: PollSemaphore,
+    // Frames of the stream handed to the writer task so far
+    queued: u64,
+    priority: Priority,
+    // The lane frames are queued in, which follows `priority` once the queued frames are written
+    lane: Priority,
+    streams: Streams,
+    write_closed: bool,
+}
+
+impl MuxStream {
+    fn register(
+        id: u32,
+        streams: &Streams,
+        outbound: &mpsc::UnboundedSender<Outbound>,
+        permits: &Arc<Semaphore>,
+    ) -> Self {
+        let (tx, inbound) = mpsc::unbounded_channel();
+        let state = StreamState::shared();
+        lock(streams).insert(
+            id,
+            StreamEntry {
+                inbound: Some(tx),
+                state: state.clone(),
+            },
+        );
+        Self {
+            id,
+            inbound,
+            buffered: Bytes::new(),
+            room: None,
+            consumed: 0,
+            state,
+            outbound: outbound.clone(),
+            permits: PollSemaphore::new(permits.clone()),
+            queued: 0,
+            priority: Priority::Normal,
+            lane: Priority::Normal,
+            streams: streams.clone(),
+            write_closed: false,
+        }
+    }
+
+    /// Returns the ID of the stream, which is the same on both sides.
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Returns the priority of frames written to the stream.
+    pub fn priority(&self) -> Priority {
+        self.priority
+    }
+
+    /// Sets the priority of frames written to the stream from now on.
+    ///
+    /// Frames of the stream are never reordered, so if some are still waiting in the other lane,
+    /// the next write waits for them to be written before switching lanes.
+    pub fn set_priority(&mut self, priority: Priority) {
+        self.priority = priority;
+    }
+
+    /// Switches to the lane of the current priority once the other lane holds no more frames of
+    /// the stream.
+    fn poll_lane(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if self.lane != self.priority {
+            ready!(Pin::new(&mut *self).poll_flush(cx))?;
+            self.lane = self.priority;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn release_window(&mut self, len: usize) {
+        self.consumed += len as u32;
+        // Batch updates so reading byte by byte doesn't send a frame per byte
+        if self.consumed < INITIAL_WINDOW / 2 {
+            return;
+        }
+        let increment = std::mem::take(&mut self.consumed);
+        {
+            let mut state = lock(&self.state);
+            state.recv_window = state.recv_window.saturating_add(increment);
+        }
+        let update = frame(self.id, WINDOW_UPDATE, &increment.to_be_bytes());
+        let _ = self
+            .outbound
+            .send(Outbound::control(update, Priority::High));
+    }
+
+    fn queue(&mut self, frame: Bytes, permit: OwnedSemaphorePermit) -> io::Result<()> {
+        self.outbound
+            .send(Outbound {
+                frame,
+                priority: self.lane,
+                stream: Some(self.state.clone()),
+                permit: Some(permit),
+            })
+            .map_err(|_| connection_closed())?;
+        self.queued += 1;
+        Ok(())
+    }
+}
+
+impl fmt::Debug for MuxStream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MuxStream")
+            .field("id", &self.id)
+            .field("priority", &self.priority)
+            .field("write_closed", &self.write_closed)
+            .finish_non_exhaustive()
+    }
+}
+
+impl AsyncRead for MuxStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = Pin::into_inner(self);
+        while this.buffered.is_empty() {
+            this.room = None;
+            match ready!(this.inbound.poll_recv(cx)) {
+                Some(Received { data, room }) => {
+                    this.buffered = data;
+                    this.room = Some(room);
+                }
+                None => return Poll::Ready(Ok(())),
+            }
+        }
+        let len = this.buffered.len().min(buf.remaining());
+        buf.put_slice(&this.buffered.split_to(len));
+        if this.buffered.is_empty() {
+            this.room = None;
+        }
+        this.release_window(len);
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for MuxStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = Pin::into_inner(self);
+        if this.write_closed {
+            return Poll::Ready(Err(io::ErrorKind::BrokenPipe.into()));
+        }
+        ready!(this.poll_lane(cx))?;
+        let permit = ready!(this.permits.poll_acquire(cx)).ok_or_else(connection_closed)?;
+        let len = {
+            let mut state = lock(&this.state);
+            if state.closed {
+                return Poll::Ready(Err(connection_closed()));
+            }
+            if state.send_window == 0 {
+                state.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            }
+            let len = buf
+                .len()
+                .min(MAX_PAYLOAD_LEN)
+                .min(state.send_window as usize);
+            state.send_window -= len as u32;
+            len
+        };
+        this.queue(frame(this.id, DATA, &buf[..len]), permit)?;
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let mut state = lock(&self.state);
+        if state.written >= self.queued {
+            return Poll::Ready(Ok(()));
+        }
+        if state.closed {
+            return Poll::Ready(Err(connection_closed()));
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        if !this.write_closed {
+            ready!(this.poll_lane(cx))?;
+            let permit = ready!(this.permits.poll_acquire(cx)).ok_or_else(connection_closed)?;
+            this.queue(frame(this.id, CLOSE, &[]), permit)?;
+            this.write_closed = true;
+        }
+        self.poll_flush(cx)
+    }
+}
+
+impl Drop for MuxStream {
+    fn drop(&mut self) {
+        lock(&self.streams).remove(&self.id);
+        if !self.write_closed {
+            let close = frame(self.id, CLOSE, &[]);
+            let _ = self.outbound.send(Outbound::control(close, self.lane));
+        }
+    }
+}
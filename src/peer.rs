@@ -0,0 +1,66 @@
+/// Identity of the process on the other end of a connection
+///
+/// Captured from the operating system when the connection is queried, so it can't be spoofed by
+/// the peer. On Unix, this uses `SO_PEERCRED` on Linux and `getpeereid`/`LOCAL_PEERCRED` on macOS
+/// and the BSDs. On Windows, a server reads its client's user from the token the pipe
+/// authenticated the client with. For clients on other machines, that's the Negotiate (Kerberos or
+/// NTLM) logon of their SMB session, so authorization filters see the same identity Windows RPC
+/// would give them. A client looks up the server process from the pipe and reads its user from
+/// the process token.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PeerCredentials {
+    #[cfg(unix)]
+    pub(crate) uid: u32,
+    #[cfg(unix)]
+    pub(crate) gid: u32,
+    #[cfg(windows)]
+    pub(crate) session_id: u32,
+    #[cfg(windows)]
+    pub(crate) sid: String,
+    #[cfg(windows)]
+    pub(crate) remote_computer: Option<String>,
+    pub(crate) pid: Option<u32>,
+}
+
+impl PeerCredentials {
+    /// Returns the effective user ID of the peer.
+    #[cfg(unix)]
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    /// Returns the effective group ID of the peer.
+    #[cfg(unix)]
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    /// Returns the Remote Desktop Services session ID of the peer.
+    #[cfg(windows)]
+    pub fn session_id(&self) -> u32 {
+        self.session_id
+    }
+
+    /// Returns the SID of the user the peer runs as, in its string form like `S-1-5-18`.
+    #[cfg(windows)]
+    pub fn sid(&self) -> &str {
+        &self.sid
+    }
+
+    /// Returns the name of the computer a remote pipe client connected from, `None` if the peer is
+    /// on this machine.
+    ///
+    /// Clients can only connect from other machines to endpoints created with
+    /// [`allow_remote`](crate::EndpointOptions::allow_remote).
+    #[cfg(windows)]
+    pub fn remote_computer(&self) -> Option<&str> {
+        self.remote_computer.as_deref()
+    }
+
+    /// Returns the process ID of the peer, if the platform reports it.
+    ///
+    /// It's not reported for clients on other machines.
+    pub fn pid(&self) -> Option<u32> {
+        self.pid
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+use std::{fs, io};
+
+use tokio::net::UnixStream;
+use zbus::zvariant::Value;
+
+const ALLOW_USER_INTERACTION: u32 = 0x1;
+
+pub(crate) async fn check_authorization(
+    stream: &UnixStream,
+    action_id: &str,
+    allow_interaction: bool,
+) -> io::Result<bool> {
+    let cred = stream.peer_cred()?;
+    let pid = cred
+        .pid()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::Other, "peer PID is not available"))?;
+
+    // polkit identifies the process by its PID together with its start time, so a recycled PID
+    // can't inherit the authorization
+    let mut subject_details = HashMap::new();
+    subject_details.insert("pid", Value::U32(pid as u32));
+    subject_details.insert("start-time", Value::U64(process_start_time(pid)?));
+    subject_details.insert("uid", Value::I32(cred.uid() as i32));
+    let subject = ("unix-process", subject_details);
+
+    let flags = if allow_interaction {
+        ALLOW_USER_INTERACTION
+    } else {
+        0
+    };
+
+    let connection = zbus::Connection::system().await.map_err(dbus_error)?;
+    let proxy = zbus::Proxy::new(
+        &connection,
+        "org.freedesktop.PolicyKit1",
+        "/org/freedesktop/PolicyKit1/Authority",
+        "org.freedesktop.PolicyKit1.Authority",
+    )
+    .await
+    .map_err(dbus_error)?;
+
+    let (authorized, _challenge, _details): (bool, bool, HashMap<String, String>) = proxy
+        .call(
+            "CheckAuthorization",
+            &(subject, action_id, HashMap::<&str, &str>::new(), flags, ""),
+        )
+        .await
+        .map_err(dbus_error)?;
+    Ok(authorized)
+}
+
+fn process_start_time(pid: i32) -> io::Result<u64> {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat"))?;
+    // The command name may contain spaces and parentheses, so skip past its closing parenthesis.
+    // The start time is the 22nd field overall, which is the 20th after the command name.
+    stat.rsplit_once(')')
+        .and_then(|(_, rest)| rest.split_whitespace().nth(19))
+        .and_then(|start_time| start_time.parse().ok())
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("failed to parse start time of process {pid}"),
+            )
+        })
+}
+
+fn dbus_error(e: zbus::Error) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
@@ -0,0 +1,161 @@
+use std::ops::{Deref, DerefMut};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::{fmt, io};
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use crate::{Connection, Endpoint, EndpointOptions, IntoIpcPath};
+
+struct PoolShared {
+    path: Arc<Path>,
+    options: Option<EndpointOptions>,
+    idle: Mutex<Vec<Connection>>,
+    permits: Arc<Semaphore>,
+}
+
+impl PoolShared {
+    fn idle(&self) -> MutexGuard<'_, Vec<Connection>> {
+        self.idle.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A pool of client connections to a single endpoint.
+///
+/// At most `max_size` connections are checked out at once, [`get`](Self::get) waits for one to be
+/// returned beyond that. Connections are only opened when needed and are reused after being
+/// returned, idle connections that the server closed in the meantime are discarded on checkout.
+///
+/// Cloning the pool is cheap, all clones share the same connections.
+#[derive(Clone)]
+pub struct Pool(Arc<PoolShared>);
+
+impl Pool {
+    /// Creates an empty pool of connections to `path`.
+    ///
+    /// Fails with [`InvalidInput`](io::ErrorKind::InvalidInput) if `max_size` is zero, since no
+    /// connection could ever be checked out.
+    pub fn new(
+        path: impl IntoIpcPath,
+        options: Option<EndpointOptions>,
+        max_size: usize,
+    ) -> io::Result<Self> {
+        if max_size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "connection pool needs room for at least one connection",
+            ));
+        }
+        Ok(Self(Arc::new(PoolShared {
+            path: path.into_ipc_path()?.into(),
+            options,
+            idle: Mutex::default(),
+            permits: Arc::new(Semaphore::new(max_size.min(Semaphore::MAX_PERMITS))),
+        })))
+    }
+
+    /// Checks out a connection, reusing an idle one if possible.
+    pub async fn get(&self) -> io::Result<PooledConnection> {
+        let permit = self
+            .0
+            .permits
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|_| io::Error::new(io::ErrorKind::Other, "connection pool closed"))?;
+
+        let idle = loop {
+            let Some(conn) = self.0.idle().pop() else {
+                break None;
+            };
+            if is_alive(&conn) {
+                break Some(conn);
+            }
+            tracing::trace!("Discarding dead pooled connection");
+        };
+        let conn = match idle {
+            Some(conn) => conn,
+            None => Endpoint::connect_shared(self.0.path.clone(), self.0.options).await?,
+        };
+
+        Ok(PooledConnection {
+            conn: Some(conn),
+            pool: Arc::downgrade(&self.0),
+            _permit: permit,
+        })
+    }
+
+    /// Returns the number of idle connections.
+    pub fn idle_count(&self) -> usize {
+        self.0.idle().len()
+    }
+}
+
+impl fmt::Debug for Pool {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pool")
+            .field("path", &self.0.path)
+            .field("idle", &self.idle_count())
+            .field("available", &self.0.permits.available_permits())
+            .finish()
+    }
+}
+
+/// An idle connection should have nothing to read. EOF or an error means the server closed it,
+/// and unexpected data means it's out of sync with the protocol, so it's not reused either way.
+/// The socket or pipe is probed directly, so this doesn't register for wakeups or consume
+/// anything.
+fn is_alive(conn: &Connection) -> bool {
+    crate::platform::is_idle(&conn.inner)
+}
+
+/// A connection checked out of a [`Pool`].
+///
+/// The connection is returned to the pool when this is dropped. Connections that failed or were
+/// left in the middle of an exchange should be [`detach`](Self::detach)ed instead.
+pub struct PooledConnection {
+    conn: Option<Connection>,
+    pool: Weak<PoolShared>,
+    _permit: OwnedSemaphorePermit,
+}
+
+impl PooledConnection {
+    /// Takes the connection out of the pool for good.
+    pub fn detach(mut self) -> Connection {
+        self.conn
+            .take()
+            .expect("connection is only taken when dropping or detaching")
+    }
+}
+
+impl Deref for PooledConnection {
+    type Target = Connection;
+
+    fn deref(&self) -> &Connection {
+        self.conn
+            .as_ref()
+            .expect("connection is only taken when dropping or detaching")
+    }
+}
+
+impl DerefMut for PooledConnection {
+    fn deref_mut(&mut self) -> &mut Connection {
+        self.conn
+            .as_mut()
+            .expect("connection is only taken when dropping or detaching")
+    }
+}
+
+impl fmt::Debug for PooledConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PooledConnection").finish_non_exhaustive()
+    }
+}
+
+impl Drop for PooledConnection {
+    fn drop(&mut self) {
+        if let (Some(conn), Some(pool)) = (self.conn.take(), self.pool.upgrade()) {
+            pool.idle().push(conn);
+        }
+    }
+}
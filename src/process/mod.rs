@@ -0,0 +1,68 @@
+//! Connections to child processes.
+//!
+//! [`spawn_with_connection`] spawns a child with one end of an anonymous connection already
+//! open, so parent and child can talk without agreeing on a path first. The child picks its end
+//! up with [`connection_from_parent`].
+//!
+//! On Unix, the child end is a socket from `socketpair(2)` that is inherited as a file
+//! descriptor. On Windows, it's the client end of a single-instance named pipe with an
+//! unpredictable name, inherited as a handle. Either way, the number of the descriptor or handle
+//! is passed in the [`CONNECTION_ENV_VAR`] environment variable.
+//!
+//! Requires the `process` feature.
+
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
+mod win;
+
+use std::io;
+
+use tokio::process::{Child, Command};
+
+#[cfg(unix)]
+use self::unix as platform;
+#[cfg(windows)]
+use self::win as platform;
+use crate::Connection;
+
+/// The environment variable through which the child learns about its end of the connection.
+pub const CONNECTION_ENV_VAR: &str = "TOKIO_IPC_CONNECTION";
+
+/// Spawns `command` as a child process that inherits one end of a new connection, returning the
+/// child and the other end.
+///
+/// The command is modified in the process and shouldn't be spawned again. On Windows, handles
+/// are inherited by every child spawned at the same time, so other children spawned concurrently
+/// from this process may hold on to the child end as well, keeping the connection from closing
+/// when the child exits. Must be called from within a Tokio runtime.
+pub async fn spawn_with_connection(command: &mut Command) -> io::Result<(Child, Connection)> {
+    let (child, conn) = platform::spawn_with_connection(command).await?;
+    Ok((child, Connection::wrap(conn)))
+}
+
+/// Takes over the connection to the parent that spawned this process with
+/// [`spawn_with_connection`].
+///
+/// Fails with [`io::ErrorKind::NotFound`] if the process wasn't spawned that way, and with
+/// [`io::ErrorKind::InvalidInput`] if [`CONNECTION_ENV_VAR`] doesn't name a socket or pipe. The
+/// variable is removed from the environment, so processes spawned afterwards don't inherit it
+/// and a second call fails with `NotFound`. Processes spawned before the call do inherit it, so
+/// call this early or clear it with [`Command::env_remove`]. Must be called from within a Tokio
+/// runtime.
+pub fn connection_from_parent() -> io::Result<Connection> {
+    let value = std::env::var(CONNECTION_ENV_VAR).map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{CONNECTION_ENV_VAR} is not set, the process has no connection to its parent"),
+        )
+    })?;
+    std::env::remove_var(CONNECTION_ENV_VAR);
+    let raw = value.parse().map_err(|_| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{CONNECTION_ENV_VAR} is not a valid descriptor: {value:?}"),
+        )
+    })?;
+    Ok(Connection::wrap(platform::connection_from_parent(raw)?))
+}
@@ -0,0 +1,65 @@
+use std::os::fd::{AsRawFd, FromRawFd, RawFd};
+use std::os::unix::net::UnixStream as StdUnixStream;
+use std::{io, mem};
+
+use tokio::net::UnixStream;
+use tokio::process::{Child, Command};
+
+use super::CONNECTION_ENV_VAR;
+use crate::unix::set_cloexec;
+
+pub(crate) async fn spawn_with_connection(
+    command: &mut Command,
+) -> io::Result<(Child, UnixStream)> {
+    let (parent_end, child_end) = StdUnixStream::pair()?;
+    let fd = child_end.as_raw_fd();
+    command.env(CONNECTION_ENV_VAR, fd.to_string());
+    // Only clear close-on-exec in the forked child, so children spawned concurrently from other
+    // threads don't inherit the socket as well
+    unsafe {
+        command.pre_exec(move || set_cloexec(fd, false));
+    }
+    let child = command.spawn()?;
+    drop(child_end);
+
+    parent_end.set_nonblocking(true)?;
+    Ok((child, UnixStream::from_std(parent_end)?))
+}
+
+/// Fails unless `fd` is an open stream socket, so an unrelated descriptor that happens to have
+/// the number in the environment variable is never taken over.
+fn check_stream_socket(fd: RawFd) -> io::Result<()> {
+    let mut stat = unsafe { mem::zeroed::<libc::stat>() };
+    if unsafe { libc::fstat(fd, &mut stat) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut kind: libc::c_int = 0;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+    let is_stream_socket = stat.st_mode & libc::S_IFMT == libc::S_IFSOCK
+        && unsafe {
+            libc::getsockopt(
+                fd,
+                libc::SOL_SOCKET,
+                libc::SO_TYPE,
+                (&mut kind as *mut libc::c_int).cast(),
+                &mut len,
+            )
+        } == 0
+        && kind == libc::SOCK_STREAM;
+    if !is_stream_socket {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{CONNECTION_ENV_VAR} doesn't refer to a stream socket"),
+        ));
+    }
+    Ok(())
+}
+
+pub(crate) fn connection_from_parent(fd: RawFd) -> io::Result<UnixStream> {
+    check_stream_socket(fd)?;
+    let stream = unsafe { StdUnixStream::from_raw_fd(fd) };
+    // Don't leak the connection into processes spawned by the child
+    set_cloexec(fd, true)?;
+    stream.set_nonblocking(true)?;
+    UnixStream::from_std(stream)
+}
@@ -0,0 +1,62 @@
+use std::fs::OpenOptions;
+use std::io;
+use std::os::windows::fs::OpenOptionsExt;
+use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle, RawHandle};
+
+use tokio::net::windows::named_pipe;
+use tokio::process::{Child, Command};
+use windows_sys::Win32::Foundation::{SetHandleInformation, HANDLE, HANDLE_FLAG_INHERIT};
+use windows_sys::Win32::Storage::FileSystem::{GetFileType, FILE_FLAG_OVERLAPPED, FILE_TYPE_PIPE};
+
+use super::CONNECTION_ENV_VAR;
+use crate::win::{from_owned_handle, Connection, NamedPipe};
+use crate::{IntoIpcPath, ServerId};
+
+pub(crate) async fn spawn_with_connection(
+    command: &mut Command,
+) -> io::Result<(Child, Connection)> {
+    // Nobody else can connect first without knowing the name
+    let path = ServerId::randomized("tokio-ipc-child")?.into_ipc_path()?;
+    let server = named_pipe::ServerOptions::new()
+        .first_pipe_instance(true)
+        .max_instances(1)
+        .reject_remote_clients(true)
+        .create(&path)?;
+    // Tokio requires pipe handles to be opened for overlapped IO
+    let child_end = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(FILE_FLAG_OVERLAPPED)
+        .open(&path)?;
+    server.connect().await?;
+
+    let child_end = OwnedHandle::from(child_end);
+    let handle = child_end.as_raw_handle();
+    if unsafe { SetHandleInformation(handle as HANDLE, HANDLE_FLAG_INHERIT, HANDLE_FLAG_INHERIT) }
+        == 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+    command.env(CONNECTION_ENV_VAR, (handle as usize).to_string());
+    let child = command.spawn()?;
+    drop(child_end);
+
+    Ok((child, Connection::wrap(NamedPipe::Server(server))))
+}
+
+pub(crate) fn connection_from_parent(handle: usize) -> io::Result<Connection> {
+    let handle = handle as RawHandle;
+    // An unrelated handle that happens to have the number in the environment variable mustn't be
+    // taken over
+    if unsafe { GetFileType(handle as HANDLE) } != FILE_TYPE_PIPE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{CONNECTION_ENV_VAR} doesn't refer to a pipe"),
+        ));
+    }
+    // Don't leak the connection into processes spawned by the child
+    if unsafe { SetHandleInformation(handle as HANDLE, HANDLE_FLAG_INHERIT, 0) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    from_owned_handle(unsafe { OwnedHandle::from_raw_handle(handle) })
+}
@@ -0,0 +1,151 @@
+//! Relaying bytes between two connections.
+//!
+//! [`bidirectional`] copies everything one side sends to the other until both sides reached the
+//! end of their stream, which is all that's needed for IPC-to-IPC or IPC-to-network relays. On
+//! Linux, bytes between two sockets are moved with `splice(2)` through a kernel pipe, so they're
+//! never copied into user space.
+//!
+//! Requires the `proxy` feature.
+
+use std::io;
+
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+use crate::Connection;
+
+mod sealed {
+    pub trait Sealed {
+        /// Returns the socket to splice to and from, if the stream is backed by one.
+        #[cfg(target_os = "linux")]
+        fn splice_socket(&self) -> Option<super::splice::Socket<'_>>;
+    }
+}
+
+/// A stream [`bidirectional`] can relay bytes to and from.
+///
+/// Implemented for [`Connection`] and Tokio's [`TcpStream`].
+pub trait ProxyStream: AsyncRead + AsyncWrite + Unpin + sealed::Sealed {}
+
+impl sealed::Sealed for Connection {
+    #[cfg(target_os = "linux")]
+    fn splice_socket(&self) -> Option<splice::Socket<'_>> {
+        Some(splice::Socket::Unix(&self.inner))
+    }
+}
+
+impl ProxyStream for Connection {}
+
+impl sealed::Sealed for TcpStream {
+    #[cfg(target_os = "linux")]
+    fn splice_socket(&self) -> Option<splice::Socket<'_>> {
+        Some(splice::Socket::Tcp(self))
+    }
+}
+
+impl ProxyStream for TcpStream {}
+
+/// Copies bytes from `a` to `b` and from `b` to `a` until both reached the end of their stream.
+///
+/// When one side reaches the end of its stream, the write half of the other side is shut down,
+/// while bytes keep flowing in the opposite direction. Returns the number of bytes copied from
+/// `a` to `b` and from `b` to `a`.
+///
+/// On Linux, bytes are spliced between the sockets without being copied into user space. Timeouts,
+/// busy polling, lifetime limits and statistics of a [`Connection`] don't apply then.
+pub async fn bidirectional<A, B>(a: &mut A, b: &mut B) -> io::Result<(u64, u64)>
+where
+    A: ProxyStream + ?Sized,
+    B: ProxyStream + ?Sized,
+{
+    #[cfg(target_os = "linux")]
+    if let (Some(a), Some(b)) = (a.splice_socket(), b.splice_socket()) {
+        return tokio::try_join!(splice::one_way(&a, &b), splice::one_way(&b, &a));
+    }
+    tokio::io::copy_bidirectional(a, b).await
+}
+
+#[cfg(target_os = "linux")]
+mod splice {
+    use std::os::fd::{AsRawFd, FromRawFd, OwnedFd, RawFd};
+    use std::{io, ptr};
+
+    use tokio::io::Interest;
+    use tokio::net::{TcpStream, UnixStream};
+
+    // The default capacity of a pipe on Linux
+    const PIPE_SIZE: usize = 64 * 1024;
+
+    pub enum Socket<'a> {
+        Unix(&'a UnixStream),
+        Tcp(&'a TcpStream),
+    }
+
+    impl Socket<'_> {
+        fn fd(&self) -> RawFd {
+            match self {
+                Self::Unix(stream) => stream.as_raw_fd(),
+                Self::Tcp(stream) => stream.as_raw_fd(),
+            }
+        }
+
+        async fn splice(
+            &self,
+            interest: Interest,
+            from: RawFd,
+            to: RawFd,
+            len: usize,
+        ) -> io::Result<usize> {
+            let op = || splice(from, to, len);
+            match self {
+                Self::Unix(stream) => stream.async_io(interest, op).await,
+                Self::Tcp(stream) => stream.async_io(interest, op).await,
+            }
+        }
+    }
+
+    fn splice(from: RawFd, to: RawFd, len: usize) -> io::Result<usize> {
+        let flags = libc::SPLICE_F_MOVE | libc::SPLICE_F_NONBLOCK;
+        match unsafe { libc::splice(from, ptr::null_mut(), to, ptr::null_mut(), len, flags) } {
+            -1 => Err(io::Error::last_os_error()),
+            moved => Ok(moved as usize),
+        }
+    }
+
+    fn pipe() -> io::Result<(OwnedFd, OwnedFd)> {
+        let mut fds = [0; 2];
+        if unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC | libc::O_NONBLOCK) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) })
+    }
+
+    pub async fn one_way(from: &Socket<'_>, to: &Socket<'_>) -> io::Result<u64> {
+        let (pipe_read, pipe_write) = pipe()?;
+        let mut copied = 0;
+        loop {
+            // The pipe is drained after every round, so EAGAIN here always comes from the socket
+            let filled = from
+                .splice(
+                    Interest::READABLE,
+                    from.fd(),
+                    pipe_write.as_raw_fd(),
+                    PIPE_SIZE,
+                )
+                .await?;
+            if filled == 0 {
+                if unsafe { libc::shutdown(to.fd(), libc::SHUT_WR) } == -1 {
+                    return Err(io::Error::last_os_error());
+                }
+                return Ok(copied);
+            }
+            let mut pending = filled;
+            while pending > 0 {
+                pending -= to
+                    .splice(Interest::WRITABLE, pipe_read.as_raw_fd(), to.fd(), pending)
+                    .await?;
+            }
+            copied += filled as u64;
+        }
+    }
+}
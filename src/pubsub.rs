@@ -0,0 +1,501 @@
+//! Topic-based publish/subscribe between a server and its clients.
+//!
+//! The server hands every accepted connection to a [`Publisher`], which keeps track of the
+//! topics each client is subscribed to and fans [`publish`](Publisher::publish)ed messages out to
+//! them. Clients wrap their connection in a [`Subscriber`] and get a [`Subscription`] stream of
+//! messages per topic.
+//!
+//! Messages wait in their subscription until they're read. The
+//! [cap on buffered data](crate::Connection::set_max_buffered) of the subscriber's connection
+//! bounds what all its subscriptions hold together; once it's reached, no more messages are read
+//! from the connection until some are read from the subscriptions.
+//!
+//! A [resumable](Subscriber::resumable) subscriber outlives its connection, so a server restart
+//! doesn't end its subscriptions. After reconnecting, [`Subscriber::resume`] subscribes to the
+//! same topics on the new server and the subscriptions carry on. The client is the one that knows
+//! what it subscribed to, so nothing needs to survive the restart on the server.
+//!
+//! Requires the `pubsub` feature.
+
+use std::collections::{HashMap, HashSet};
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, Weak};
+use std::task::{Context, Poll};
+use std::{fmt, io};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use futures::stream::{SplitSink, SplitStream};
+use futures::{SinkExt, Stream, StreamExt};
+use tokio::sync::{mpsc, watch};
+use tokio::task::AbortHandle;
+
+use crate::framing::FramedConnection;
+use crate::memory::{BufferBudget, Room};
+use crate::{Connection, QosClass};
+
+const SUBSCRIBE: u8 = 0;
+const UNSUBSCRIBE: u8 = 1;
+const MESSAGE: u8 = 2;
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn encode(kind: u8, topic: &str, payload: &[u8]) -> io::Result<Bytes> {
+    let topic_len = u16::try_from(topic.len())
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "topic is too long"))?;
+    let mut frame = BytesMut::with_capacity(3 + topic.len() + payload.len());
+    frame.put_u8(kind);
+    frame.put_u16(topic_len);
+    frame.put_slice(topic.as_bytes());
+    frame.put_slice(payload);
+    Ok(frame.freeze())
+}
+
+fn decode(mut frame: Bytes) -> Option<(u8, String, Bytes)> {
+    if frame.len() < 3 {
+        return None;
+    }
+    let kind = frame.get_u8();
+    let topic_len = usize::from(frame.get_u16());
+    if frame.len() < topic_len {
+        return None;
+    }
+    let topic = String::from_utf8(frame.split_to(topic_len).to_vec()).ok()?;
+    Some((kind, topic, frame))
+}
+
+async fn write_frames(mut sink: SplitSink<FramedConnection, Bytes>, mut outbound: impl Queue) {
+    while let Some(frame) = poll_fn(|cx| outbound.poll_recv(cx)).await {
+        if let Err(e) = sink.send(frame).await {
+            tracing::trace!("Failed to write pub/sub frame: {e:?}");
+            return;
+        }
+    }
+    let _ = sink.close().await;
+}
+
+/// The receiving end of a channel of outgoing frames, bounded or not.
+trait Queue: Send + 'static {
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<Bytes>>;
+}
+
+impl Queue for mpsc::Receiver<Bytes> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<Bytes>> {
+        Self::poll_recv(self, cx)
+    }
+}
+
+impl Queue for mpsc::UnboundedReceiver<Bytes> {
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<Bytes>> {
+        Self::poll_recv(self, cx)
+    }
+}
+
+/// What a [`Publisher`] does when a client's queue of undelivered messages is full.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum Backpressure {
+    /// Drop the message for that client only.
+    #[default]
+    DropMessage,
+    /// Disconnect the client.
+    Disconnect,
+}
+
+/// Options for a [`Publisher`].
+#[derive(Debug, Clone)]
+pub struct PublisherOptions {
+    /// Number of messages queued per client before [`backpressure`](Self::backpressure) kicks in.
+    ///
+    /// Clients whose connection is [`QosClass::Batch`] get half of it, so they're the first to
+    /// lose messages when publishing outpaces the clients.
+    pub queue_capacity: usize,
+    /// How to deal with clients that don't keep up.
+    pub backpressure: Backpressure,
+}
+
+impl Default for PublisherOptions {
+    fn default() -> Self {
+        Self {
+            queue_capacity: 64,
+            backpressure: Backpressure::default(),
+        }
+    }
+}
+
+struct Client {
+    topics: HashSet<String>,
+    queue: mpsc::Sender<Bytes>,
+    reader: AbortHandle,
+}
+
+struct PublisherShared {
+    clients: Mutex<HashMap<u64, Client>>,
+    next_id: AtomicU64,
+    options: PublisherOptions,
+}
+
+impl Drop for PublisherShared {
+    fn drop(&mut self) {
+        for client in lock(&self.clients).values() {
+            client.reader.abort();
+        }
+    }
+}
+
+/// The server side, tracking the subscriptions of every client added to it.
+///
+/// Cloning the publisher is cheap, all clones share the same clients. Dropping the last clone
+/// disconnects them.
+#[derive(Clone)]
+pub struct Publisher(Arc<PublisherShared>);
+
+impl Publisher {
+    /// Creates a publisher without any clients.
+    pub fn new(options: Option<PublisherOptions>) -> Self {
+        Self(Arc::new(PublisherShared {
+            clients: Mutex::default(),
+            next_id: AtomicU64::new(0),
+            options: options.unwrap_or_default(),
+        }))
+    }
+
+    /// Starts serving subscriptions made by the client on the other end of `conn`.
+    ///
+    /// The client is removed once it disconnects. Must be called from within a Tokio runtime.
+    pub fn add(&self, conn: Connection) {
+        let id = self.0.next_id.fetch_add(1, Ordering::Relaxed);
+        let capacity = match conn.qos_class() {
+            QosClass::Interactive => self.0.options.queue_capacity,
+            QosClass::Batch => self.0.options.queue_capacity / 2,
+        };
+        let (sink, stream) = FramedConnection::new(conn).split();
+        let (queue, queue_rx) = mpsc::channel(capacity.max(1));
+
+        // Hold the lock so the reader can't remove the client before it's inserted.
+        let mut clients = lock(&self.0.clients);
+        tokio::spawn(write_frames(sink, queue_rx));
+        let reader = tokio::spawn(read_subscriptions(stream, Arc::downgrade(&self.0), id));
+        clients.insert(
+            id,
+            Client {
+                topics: HashSet::new(),
+                queue,
+                reader: reader.abort_handle(),
+            },
+        );
+    }
+
+    /// Sends `payload` to every client subscribed to `topic`.
+    ///
+    /// Returns the number of clients the message was queued for. This never waits for slow
+    /// clients; see [`Backpressure`] for what happens to them.
+    pub fn publish(&self, topic: &str, payload: &[u8]) -> usize {
+        let Ok(frame) = encode(MESSAGE, topic, payload) else {
+            return 0;
+        };
+        let mut clients = lock(&self.0.clients);
+        let mut delivered = 0;
+        let mut slow = Vec::new();
+        for (id, client) in clients.iter() {
+            if !client.topics.contains(topic) {
+                continue;
+            }
+            match client.queue.try_send(frame.clone()) {
+                Ok(()) => delivered += 1,
+                Err(mpsc::error::TrySendError::Full(_)) => {
+                    tracing::trace!("Pub/sub client {id} isn't keeping up");
+                    if self.0.options.backpressure == Backpressure::Disconnect {
+                        slow.push(*id);
+                    }
+                }
+                Err(mpsc::error::TrySendError::Closed(_)) => {}
+            }
+        }
+        for id in slow {
+            if let Some(client) = clients.remove(&id) {
+                client.reader.abort();
+            }
+        }
+        delivered
+    }
+
+    /// Returns the number of connected clients.
+    pub fn client_count(&self) -> usize {
+        lock(&self.0.clients).len()
+    }
+}
+
+impl fmt::Debug for Publisher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Publisher")
+            .field("clients", &self.client_count())
+            .field("options", &self.0.options)
+            .finish()
+    }
+}
+
+async fn read_subscriptions(
+    mut stream: SplitStream<FramedConnection>,
+    publisher: Weak<PublisherShared>,
+    id: u64,
+) {
+    while let Some(Ok(frame)) = stream.next().await {
+        let Some(shared) = publisher.upgrade() else {
+            return;
+        };
+        let mut clients = lock(&shared.clients);
+        let Some(client) = clients.get_mut(&id) else {
+            return;
+        };
+        match decode(frame) {
+            Some((SUBSCRIBE, topic, _)) => {
+                client.topics.insert(topic);
+            }
+            Some((UNSUBSCRIBE, topic, _)) => {
+                client.topics.remove(&topic);
+            }
+            _ => tracing::trace!("Dropping invalid pub/sub frame from client {id}"),
+        }
+    }
+    if let Some(shared) = publisher.upgrade() {
+        lock(&shared.clients).remove(&id);
+    }
+}
+
+// A received message along with its share of the connection's buffer budget
+type Message = (Bytes, Room);
+type Topics = Arc<Mutex<HashMap<String, HashMap<u64, mpsc::UnboundedSender<Message>>>>>;
+// Frames for the current connection, replaced when the subscriber resumes on a new one
+type Outbound = Arc<Mutex<mpsc::UnboundedSender<Bytes>>>;
+
+fn send(outbound: &Outbound, frame: Bytes) -> io::Result<()> {
+    lock(outbound)
+        .send(frame)
+        .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "pub/sub connection closed"))
+}
+
+/// The client side, subscribing to topics of a [`Publisher`].
+pub struct Subscriber {
+    topics: Topics,
+    outbound: Outbound,
+    link: Mutex<Link>,
+    // Number of the last connection that was lost, plus one
+    lost: Arc<watch::Sender<u64>>,
+    next_id: AtomicU64,
+    resumable: bool,
+}
+
+// The connection the subscriber currently reads from
+struct Link {
+    number: u64,
+    reader: AbortHandle,
+}
+
+impl Subscriber {
+    /// Starts receiving messages over `conn`.
+    ///
+    /// Subscriptions end once the connection closes. Must be called from within a Tokio runtime.
+    pub fn new(conn: Connection) -> Self {
+        Self::start(conn, false)
+    }
+
+    /// Starts receiving messages over `conn`, keeping subscriptions open when it closes.
+    ///
+    /// Once [`disconnected`](Self::disconnected) returns, connect again and hand the new
+    /// connection to [`resume`](Self::resume). Must be called from within a Tokio runtime.
+    pub fn resumable(conn: Connection) -> Self {
+        Self::start(conn, true)
+    }
+
+    fn start(conn: Connection, resumable: bool) -> Self {
+        let topics = Topics::default();
+        let lost = Arc::new(watch::Sender::new(0));
+        let (outbound, reader) = connect(conn, &topics, &lost, 0, resumable);
+        Self {
+            topics,
+            outbound: Arc::new(Mutex::new(outbound)),
+            link: Mutex::new(Link { number: 0, reader }),
+            lost,
+            next_id: AtomicU64::new(0),
+            resumable,
+        }
+    }
+
+    /// Moves the subscriber over to `conn`, usually a new connection to a restarted server.
+    ///
+    /// The server keeps nothing across a restart, so the subscriber subscribes to every topic it
+    /// has subscriptions for again. Existing [`Subscription`]s carry on with the messages
+    /// published after the new server processed that; whatever was published while disconnected
+    /// is lost. A connection that's still open is closed.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn resume(&self, conn: Connection) -> io::Result<()> {
+        // Hold the lock so subscriptions made meanwhile aren't sent twice or not at all.
+        let topics = lock(&self.topics);
+        let mut link = lock(&self.link);
+        link.reader.abort();
+        mark_lost(&self.lost, link.number);
+        link.number += 1;
+        let (outbound, reader) =
+            connect(conn, &self.topics, &self.lost, link.number, self.resumable);
+        link.reader = reader;
+        *lock(&self.outbound) = outbound;
+        for topic in topics.keys() {
+            send(&self.outbound, encode(SUBSCRIBE, topic, &[])?)?;
+        }
+        Ok(())
+    }
+
+    /// Waits until the current connection is lost.
+    pub async fn disconnected(&self) {
+        let number = lock(&self.link).number;
+        let _ = self.lost.subscribe().wait_for(|lost| *lost > number).await;
+    }
+
+    /// Subscribes to `topic`.
+    ///
+    /// Messages published after the server processed the subscription are yielded by the
+    /// returned stream, which ends once the connection closes, unless the subscriber is
+    /// [resumable](Self::resumable). Dropping the last subscription to a topic unsubscribes from
+    /// it.
+    pub fn subscribe(&self, topic: &str) -> io::Result<Subscription> {
+        let frame = encode(SUBSCRIBE, topic, &[])?;
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, messages) = mpsc::unbounded_channel();
+
+        let mut topics = lock(&self.topics);
+        let subscriptions = topics.entry(topic.to_owned()).or_default();
+        if subscriptions.is_empty() {
+            match send(&self.outbound, frame) {
+                // Resuming subscribes again.
+                Err(_) if self.resumable => {}
+                result => result?,
+            }
+        }
+        subscriptions.insert(id, tx);
+
+        Ok(Subscription {
+            topic: topic.to_owned(),
+            id,
+            messages,
+            topics: self.topics.clone(),
+            outbound: self.outbound.clone(),
+        })
+    }
+}
+
+impl fmt::Debug for Subscriber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscriber")
+            .field("topics", &lock(&self.topics).keys().collect::<Vec<_>>())
+            .field("resumable", &self.resumable)
+            .finish_non_exhaustive()
+    }
+}
+
+fn connect(
+    conn: Connection,
+    topics: &Topics,
+    lost: &Arc<watch::Sender<u64>>,
+    number: u64,
+    resumable: bool,
+) -> (mpsc::UnboundedSender<Bytes>, AbortHandle) {
+    let budget = BufferBudget::new(&conn);
+    let (sink, stream) = FramedConnection::new(conn).split();
+    let (outbound, outbound_rx) = mpsc::unbounded_channel();
+
+    tokio::spawn(write_frames(sink, outbound_rx));
+    let topics = topics.clone();
+    let lost = lost.clone();
+    let reader = tokio::spawn(async move {
+        read_messages(stream, &topics, budget, resumable).await;
+        mark_lost(&lost, number);
+    });
+    (outbound, reader.abort_handle())
+}
+
+fn mark_lost(lost: &watch::Sender<u64>, number: u64) {
+    lost.send_modify(|lost| *lost = (*lost).max(number + 1));
+}
+
+async fn read_messages(
+    mut stream: SplitStream<FramedConnection>,
+    topics: &Topics,
+    budget: BufferBudget,
+    resumable: bool,
+) {
+    // Being shed from the memory budget ends reading like losing the connection.
+    'read: while let Some(Some(Ok(frame))) = budget.unless_shed(stream.next()).await {
+        match decode(frame) {
+            Some((MESSAGE, topic, payload)) => {
+                let subscriptions: Vec<_> = match lock(topics).get(&topic) {
+                    Some(subscriptions) => subscriptions.values().cloned().collect(),
+                    None => continue,
+                };
+                for tx in subscriptions {
+                    // Stops reading until the subscriptions read some of what they buffered
+                    let Some(room) = budget.reserve(payload.len()).await else {
+                        break 'read;
+                    };
+                    let _ = tx.send((payload.clone(), room));
+                }
+            }
+            _ => tracing::trace!("Dropping invalid pub/sub frame"),
+        }
+    }
+    if !resumable {
+        // Ends every subscription.
+        lock(topics).clear();
+    }
+}
+/// Messages published to a single topic, created by [`Subscriber::subscribe`].
+pub struct Subscription {
+    topic: String,
+    id: u64,
+    messages: mpsc::UnboundedReceiver<Message>,
+    topics: Topics,
+    outbound: Outbound,
+}
+
+impl Subscription {
+    /// Returns the topic this subscription is for.
+    pub fn topic(&self) -> &str {
+        &self.topic
+    }
+}
+
+impl fmt::Debug for Subscription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Subscription")
+            .field("topic", &self.topic)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Bytes;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Bytes>> {
+        let message = self.messages.poll_recv(cx);
+        message.map(|message| message.map(|(payload, _room)| payload))
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let mut topics = lock(&self.topics);
+        let Some(subscriptions) = topics.get_mut(&self.topic) else {
+            return;
+        };
+        subscriptions.remove(&self.id);
+        if subscriptions.is_empty() {
+            topics.remove(&self.topic);
+            if let Ok(frame) = encode(UNSUBSCRIBE, &self.topic, &[]) {
+                let _ = send(&self.outbound, frame);
+            }
+        }
+    }
+}
@@ -0,0 +1,238 @@
+use std::future::Future;
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use std::{fmt, io};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::sync::watch;
+
+use crate::{Connection, Endpoint, EndpointOptions, IntoIpcPath};
+
+/// How a [`ReconnectingConnection`] spaces out its reconnect attempts.
+///
+/// The first attempt is made right away, after that the delay starts at `initial_delay` and
+/// doubles with every failed attempt, up to `max_delay`.
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    /// Delay before the second attempt.
+    pub initial_delay: Duration,
+    /// Upper bound for the delay between attempts.
+    pub max_delay: Duration,
+    /// Number of attempts after which to give up, or `None` to keep trying forever.
+    pub max_attempts: Option<NonZeroU32>,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_secs(10),
+            max_attempts: None,
+        }
+    }
+}
+
+impl Backoff {
+    pub(crate) fn delay(&self, failed_attempts: u32) -> Duration {
+        let factor = 2u32.saturating_pow(failed_attempts.saturating_sub(1));
+        self.initial_delay
+            .saturating_mul(factor)
+            .min(self.max_delay)
+    }
+}
+
+/// Connection state reported by [`ReconnectingConnection::state_changes`].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ConnectionState {
+    /// Connected to the server.
+    Connected,
+    /// Trying to reconnect, `attempt` counts from 1.
+    Reconnecting {
+        /// The attempt that is in progress.
+        attempt: u32,
+    },
+    /// Gave up reconnecting after [`Backoff::max_attempts`]. The next read or write starts over.
+    Disconnected,
+}
+
+enum State {
+    Connected(Connection),
+    Reconnecting(Pin<Box<dyn Future<Output = io::Result<Connection>> + Send>>),
+    Disconnected,
+}
+
+/// A client connection that transparently reconnects when the connection is lost.
+///
+/// A failed read or write, or reading EOF, drops the connection and reconnects according to the
+/// [`Backoff`] policy, then retries the operation on the new connection. Data that was in flight
+/// when the connection broke is lost, so protocols that keep state across messages should watch
+/// [`state_changes`](Self::state_changes) to resynchronize after a reconnect.
+///
+/// Options set on the [`Connection`] itself, like timeouts, don't carry over to new connections.
+pub struct ReconnectingConnection {
+    path: Arc<Path>,
+    options: Option<EndpointOptions>,
+    backoff: Backoff,
+    state: State,
+    events: Arc<watch::Sender<ConnectionState>>,
+    shut_down: bool,
+}
+
+impl ReconnectingConnection {
+    /// Connects to `path`.
+    ///
+    /// The initial connection attempt isn't retried, so misconfigurations surface right away.
+    pub async fn connect(
+        path: impl IntoIpcPath,
+        options: Option<EndpointOptions>,
+        backoff: Backoff,
+    ) -> io::Result<Self> {
+        let path: Arc<Path> = path.into_ipc_path()?.into();
+        let conn = Endpoint::connect_shared(path.clone(), options).await?;
+        Ok(Self {
+            path,
+            options,
+            backoff,
+            state: State::Connected(conn),
+            events: Arc::new(watch::Sender::new(ConnectionState::Connected)),
+            shut_down: false,
+        })
+    }
+
+    /// Returns the current connection state.
+    pub fn state(&self) -> ConnectionState {
+        *self.events.borrow()
+    }
+
+    /// Returns a receiver that is notified whenever the connection state changes.
+    pub fn state_changes(&self) -> watch::Receiver<ConnectionState> {
+        self.events.subscribe()
+    }
+
+    fn reconnect(&mut self) {
+        let path = self.path.clone();
+        let options = self.options;
+        let backoff = self.backoff.clone();
+        let events = self.events.clone();
+        self.state = State::Reconnecting(Box::pin(async move {
+            let mut attempt = 0;
+            loop {
+                attempt += 1;
+                events.send_replace(ConnectionState::Reconnecting { attempt });
+                match Endpoint::connect_shared(path.clone(), options).await {
+                    Ok(conn) => return Ok(conn),
+                    Err(e) if backoff.max_attempts.is_some_and(|max| attempt >= max.get()) => {
+                        return Err(e);
+                    }
+                    Err(e) => {
+                        tracing::trace!("Reconnect attempt {attempt} failed: {e:?}");
+                        tokio::time::sleep(backoff.delay(attempt)).await;
+                    }
+                }
+            }
+        }));
+    }
+
+    /// Runs `op` on the current connection, reconnecting and retrying whenever it fails or
+    /// `is_eof` says the peer went away. Once shut down, results are passed through as they are.
+    fn poll_with<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        mut op: impl FnMut(Pin<&mut Connection>, &mut Context<'_>) -> Poll<io::Result<T>>,
+        is_eof: impl Fn(&T) -> bool,
+    ) -> Poll<io::Result<T>> {
+        loop {
+            match &mut self.state {
+                State::Connected(conn) => {
+                    let result = ready!(op(Pin::new(conn), cx));
+                    if self.shut_down {
+                        return Poll::Ready(result);
+                    }
+                    match result {
+                        Ok(value) if !is_eof(&value) => return Poll::Ready(Ok(value)),
+                        Ok(_) => tracing::trace!("Connection closed by peer, reconnecting"),
+                        Err(e) => tracing::trace!("Connection failed, reconnecting: {e:?}"),
+                    }
+                    self.reconnect();
+                }
+                State::Reconnecting(connecting) => match ready!(connecting.as_mut().poll(cx)) {
+                    Ok(conn) => {
+                        self.state = State::Connected(conn);
+                        self.events.send_replace(ConnectionState::Connected);
+                    }
+                    Err(e) => {
+                        self.state = State::Disconnected;
+                        self.events.send_replace(ConnectionState::Disconnected);
+                        return Poll::Ready(Err(e));
+                    }
+                },
+                State::Disconnected if self.shut_down => {
+                    return Poll::Ready(Err(io::ErrorKind::NotConnected.into()));
+                }
+                State::Disconnected => self.reconnect(),
+            }
+        }
+    }
+}
+
+impl AsyncRead for ReconnectingConnection {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = Pin::into_inner(self);
+        let read = |conn: Pin<&mut Connection>, cx: &mut Context<'_>| {
+            let filled = buf.filled().len();
+            let wants_data = buf.remaining() > 0;
+            conn.poll_read(cx, buf)
+                .map_ok(|()| wants_data && buf.filled().len() == filled)
+        };
+        this.poll_with(cx, read, |&eof| eof).map_ok(|_| ())
+    }
+}
+
+impl AsyncWrite for ReconnectingConnection {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = Pin::into_inner(self);
+        let write = |conn: Pin<&mut Connection>, cx: &mut Context<'_>| conn.poll_write(cx, buf);
+        this.poll_with(cx, write, |&written| written == 0 && !buf.is_empty())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = Pin::into_inner(self);
+        this.poll_with(cx, |conn, cx| conn.poll_flush(cx), |()| false)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = Pin::into_inner(self);
+        this.shut_down = true;
+        match &mut this.state {
+            State::Connected(conn) => Pin::new(conn).poll_shutdown(cx),
+            State::Reconnecting(_) | State::Disconnected => {
+                this.state = State::Disconnected;
+                Poll::Ready(Ok(()))
+            }
+        }
+    }
+}
+
+impl fmt::Debug for ReconnectingConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ReconnectingConnection")
+            .field("path", &self.path)
+            .field("options", &self.options)
+            .field("backoff", &self.backoff)
+            .field("state", &self.state())
+            .field("shut_down", &self.shut_down)
+            .finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,362 @@
+//! Request/response calls multiplexed over a single connection.
+//!
+//! [`RpcClient`] tags every request with a correlation ID, so any number of calls can be in
+//! flight at once and responses may arrive in any order. [`RpcServer`] on the other end hands
+//! out each request together with a [`Responder`] that sends the response back under the same ID.
+//! Messages are encoded with a [`Codec`] into frames of a [`FramedConnection`].
+//!
+//! Requests can carry the trace context of the caller, such as a W3C `traceparent`, so spans on
+//! both sides of the connection end up in the same trace. The client picks it up through the
+//! hook set with [`RpcClient::with_trace_context`], the server restores it through
+//! [`RpcServer::with_trace_context`] or reads it from [`Responder::trace_context`].
+//!
+//! Requires the `rpc` feature.
+
+use std::collections::HashMap;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::{fmt, io};
+
+use bytes::Bytes;
+use futures::stream::{SplitSink, SplitStream};
+use futures::{SinkExt, StreamExt};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use tokio::sync::{oneshot, Mutex as AsyncMutex};
+use tokio::task::JoinHandle;
+use tracing::{Instrument, Span};
+
+use crate::framing::FramedConnection;
+use crate::typed::Codec;
+
+type FrameSink = Arc<AsyncMutex<SplitSink<FramedConnection, Bytes>>>;
+type Pending<Resp> = Arc<Mutex<Option<HashMap<u64, oneshot::Sender<io::Result<Resp>>>>>>;
+type InjectContext = Arc<dyn Fn() -> Option<String> + Send + Sync>;
+type ExtractContext = Arc<dyn Fn(Option<&str>) -> Span + Send + Sync>;
+
+// Sent in place of a response when a responder is dropped without responding
+const UNANSWERED: &str = "RPC request was dropped without a response";
+
+fn connection_closed() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "RPC connection closed")
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+async fn send_frame(sink: &FrameSink, frame: Vec<u8>) -> io::Result<()> {
+    sink.lock().await.send(Bytes::from(frame)).await
+}
+
+/// Removes the pending entry of a call when the call completes or is cancelled.
+struct PendingGuard<'a, Resp> {
+    pending: &'a Pending<Resp>,
+    id: u64,
+}
+
+impl<Resp> Drop for PendingGuard<'_, Resp> {
+    fn drop(&mut self) {
+        if let Some(pending) = &mut *lock(self.pending) {
+            pending.remove(&self.id);
+        }
+    }
+}
+
+/// The calling side of an RPC connection.
+///
+/// Cloning the client is cheap, all clones share the same connection. Responses are read by a
+/// background task that is stopped once the last clone is dropped.
+pub struct RpcClient<Req, Resp, C> {
+    shared: Arc<ClientShared<Resp>>,
+    codec: C,
+    inject_context: Option<InjectContext>,
+    _marker: PhantomData<fn(Req)>,
+}
+
+struct ClientShared<Resp> {
+    sink: FrameSink,
+    pending: Pending<Resp>,
+    next_id: AtomicU64,
+    reader: JoinHandle<()>,
+}
+
+impl<Resp> Drop for ClientShared<Resp> {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+impl<Req, Resp, C> RpcClient<Req, Resp, C>
+where
+    Req: Serialize,
+    Resp: DeserializeOwned + Send + 'static,
+    C: Codec + Clone + Send + 'static,
+{
+    /// Starts making calls over `conn`.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn new(conn: FramedConnection, codec: C) -> Self {
+        let (sink, stream) = conn.split();
+        let pending: Pending<Resp> = Arc::new(Mutex::new(Some(HashMap::new())));
+        let reader = tokio::spawn(read_responses(stream, codec.clone(), pending.clone()));
+        Self {
+            shared: Arc::new(ClientShared {
+                sink: Arc::new(AsyncMutex::new(sink)),
+                pending,
+                next_id: AtomicU64::new(0),
+                reader,
+            }),
+            codec,
+            inject_context: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Sends the trace context returned by `inject` along with every request.
+    ///
+    /// `inject` runs in the context of each call, and typically returns the W3C `traceparent` of
+    /// the current span, for example by running an OpenTelemetry propagator on it. Returning
+    /// `None` sends the request without a trace context.
+    pub fn with_trace_context(
+        mut self,
+        inject: impl Fn() -> Option<String> + Send + Sync + 'static,
+    ) -> Self {
+        self.inject_context = Some(Arc::new(inject));
+        self
+    }
+
+    /// Sends a request and waits for its response.
+    ///
+    /// Fails with [`BrokenPipe`](io::ErrorKind::BrokenPipe) if the connection is closed before
+    /// the response arrives, and with [`Other`](io::ErrorKind::Other) if the server dropped the
+    /// request without responding. Responses that can't be decoded are dropped, since there's no
+    /// telling which call they belong to, so callers should apply their own timeout. Cancelling
+    /// the call forgets about it, a response that arrives later is dropped.
+    pub async fn call(&self, req: &Req) -> io::Result<Resp> {
+        let id = self.shared.next_id.fetch_add(1, Ordering::Relaxed);
+        let trace_context = self.inject_context.as_ref().and_then(|inject| inject());
+        let frame = self.codec.encode(&(id, req, trace_context))?;
+
+        let (tx, rx) = oneshot::channel();
+        match &mut *lock(&self.shared.pending) {
+            Some(pending) => pending.insert(id, tx),
+            None => return Err(connection_closed()),
+        };
+        let _guard = PendingGuard {
+            pending: &self.shared.pending,
+            id,
+        };
+        send_frame(&self.shared.sink, frame).await?;
+        rx.await.unwrap_or_else(|_| Err(connection_closed()))
+    }
+}
+
+impl<Req, Resp, C: Clone> Clone for RpcClient<Req, Resp, C> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            codec: self.codec.clone(),
+            inject_context: self.inject_context.clone(),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<Req, Resp, C: fmt::Debug> fmt::Debug for RpcClient<Req, Resp, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RpcClient")
+            .field("codec", &self.codec)
+            .finish_non_exhaustive()
+    }
+}
+
+async fn read_responses<Resp, C>(
+    mut stream: SplitStream<FramedConnection>,
+    codec: C,
+    pending: Pending<Resp>,
+) where
+    Resp: DeserializeOwned,
+    C: Codec,
+{
+    while let Some(Ok(frame)) = stream.next().await {
+        match codec.decode::<(u64, Result<Resp, String>)>(&frame) {
+            Ok((id, resp)) => {
+                let tx = lock(&pending).as_mut().and_then(|p| p.remove(&id));
+                match tx {
+                    Some(tx) => {
+                        let resp = resp.map_err(|e| io::Error::new(io::ErrorKind::Other, e));
+                        let _ = tx.send(resp);
+                    }
+                    None => tracing::trace!("Dropping RPC response for unknown call {id}"),
+                }
+            }
+            Err(e) => tracing::trace!("Dropping RPC response that failed to decode: {e:?}"),
+        }
+    }
+    // Dropping the senders fails every call that is still waiting.
+    lock(&pending).take();
+}
+
+/// The serving side of an RPC connection.
+pub struct RpcServer<Req, Resp, C> {
+    stream: SplitStream<FramedConnection>,
+    sink: FrameSink,
+    codec: C,
+    extract_context: Option<ExtractContext>,
+    _marker: PhantomData<fn(Resp) -> Req>,
+}
+
+impl<Req, Resp, C> RpcServer<Req, Resp, C>
+where
+    Req: DeserializeOwned,
+    Resp: Serialize,
+    C: Codec + Clone,
+{
+    /// Starts serving calls made over `conn`.
+    pub fn new(conn: FramedConnection, codec: C) -> Self {
+        let (sink, stream) = conn.split();
+        Self {
+            stream,
+            sink: Arc::new(AsyncMutex::new(sink)),
+            codec,
+            extract_context: None,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Runs the handlers spawned by [`serve`](Self::serve) in the span returned by `extract`.
+    ///
+    /// `extract` receives the trace context the client sent with the request, if any, and
+    /// typically creates a span whose parent is restored from it, for example with an
+    /// OpenTelemetry propagator.
+    pub fn with_trace_context(
+        mut self,
+        extract: impl Fn(Option<&str>) -> Span + Send + Sync + 'static,
+    ) -> Self {
+        self.extract_context = Some(Arc::new(extract));
+        self
+    }
+
+    /// Waits for the next request, or `None` once the client closed the connection.
+    ///
+    /// Requests that can't be decoded fail with [`InvalidData`](io::ErrorKind::InvalidData). The
+    /// client never gets a response for them.
+    pub async fn next(&mut self) -> Option<io::Result<(Req, Responder<Resp, C>)>> {
+        let frame = match self.stream.next().await? {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+        let request = self.codec.decode::<(u64, Req, Option<String>)>(&frame);
+        Some(request.map(|(id, req, trace_context)| {
+            let responder = Responder {
+                id,
+                trace_context,
+                sink: self.sink.clone(),
+                codec: self.codec.clone(),
+                unanswered: self.codec.encode(&(id, Err::<(), _>(UNANSWERED))).ok(),
+                _marker: PhantomData,
+            };
+            (req, responder)
+        }))
+    }
+
+    /// Hands every request to `handler` until the client closes the connection.
+    ///
+    /// Each handler future is spawned onto its own task, so slow requests don't hold up the
+    /// ones behind them. Requests that can't be decoded are skipped.
+    pub async fn serve<F, Fut>(mut self, mut handler: F) -> io::Result<()>
+    where
+        F: FnMut(Req, Responder<Resp, C>) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        while let Some(next) = self.next().await {
+            match next {
+                Ok((req, responder)) => {
+                    let span = match &self.extract_context {
+                        Some(extract) => extract(responder.trace_context()),
+                        None => Span::none(),
+                    };
+                    tokio::spawn(handler(req, responder).instrument(span));
+                }
+                Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                    tracing::trace!("Skipping RPC request that failed to decode: {e:?}");
+                }
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<Req, Resp, C: fmt::Debug> fmt::Debug for RpcServer<Req, Resp, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RpcServer")
+            .field("codec", &self.codec)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Sends the response to a single request received by an [`RpcServer`].
+///
+/// Dropping the responder without responding, for example when the handler panics, fails the
+/// call on the client.
+pub struct Responder<Resp, C> {
+    id: u64,
+    trace_context: Option<String>,
+    sink: FrameSink,
+    codec: C,
+    // The frame that fails the call if no response is sent, `None` once one was
+    unanswered: Option<Vec<u8>>,
+    _marker: PhantomData<fn(Resp)>,
+}
+
+impl<Resp: Serialize, C: Codec> Responder<Resp, C> {
+    /// Returns the correlation ID of the request.
+    pub fn id(&self) -> u64 {
+        self.id
+    }
+
+    /// Returns the trace context the client sent along with the request.
+    pub fn trace_context(&self) -> Option<&str> {
+        self.trace_context.as_deref()
+    }
+
+    /// Sends the response back to the client.
+    pub async fn respond(mut self, resp: &Resp) -> io::Result<()> {
+        let frame = self.codec.encode(&(self.id, Ok::<_, ()>(resp)))?;
+        send_frame(&self.sink, frame).await?;
+        self.unanswered = None;
+        Ok(())
+    }
+}
+
+impl<Resp, C> Drop for Responder<Resp, C> {
+    fn drop(&mut self) {
+        let Some(frame) = self.unanswered.take() else {
+            return;
+        };
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            tracing::trace!("Can't fail RPC call {} outside of a runtime", self.id);
+            return;
+        };
+        let sink = self.sink.clone();
+        runtime.spawn(async move {
+            if let Err(e) = send_frame(&sink, frame).await {
+                tracing::trace!("Failed to fail an unanswered RPC call: {e:?}");
+            }
+        });
+    }
+}
+
+impl<Resp, C: fmt::Debug> fmt::Debug for Responder<Resp, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Responder")
+            .field("id", &self.id)
+            .field("trace_context", &self.trace_context)
+            .field("codec", &self.codec)
+            .finish_non_exhaustive()
+    }
+}
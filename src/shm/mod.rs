@@ -0,0 +1,290 @@
+//! Shared memory fast path for large payloads.
+//!
+//! Copying big payloads through a socket or pipe costs a round trip through the kernel for every
+//! buffer-sized chunk. [`ShmConnection`] negotiates a shared memory ring buffer for each direction
+//! when it's created and moves payloads above a size threshold through it, sending only a small
+//! notification frame over the connection. Smaller payloads, and large ones that don't fit into
+//! the ring at the moment, are sent over the connection itself. Both sides of the connection must
+//! use it.
+//!
+//! On Linux, the ring is a `memfd` that's passed to the peer with `SCM_RIGHTS`. It's sealed
+//! against resizing, and the peer refuses rings that aren't, so neither side can make the other
+//! fault by truncating the memory. Other Unix systems send everything over the connection. On
+//! Windows, the ring is a named file mapping in the session namespace whose name is sent to the
+//! peer.
+//!
+//! For one-off blobs on Linux, [`send_memfd`] hands over a sealed `memfd` instead, which the peer
+//! maps read-only with [`recv_memfd`] without any copy through the socket.
+//!
+//! Requires the `shm` feature.
+
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
+mod win;
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::{fmt, io, ptr};
+
+use bytes::{BufMut, Bytes, BytesMut};
+
+#[cfg(unix)]
+use self::unix as platform;
+#[cfg(windows)]
+use self::win as platform;
+use crate::framing::FramedConnection;
+use crate::Connection;
+
+const INLINE: u8 = 0;
+const SHARED: u8 = 1;
+
+const OFFER_MAGIC: [u8; 4] = *b"SHM1";
+const OFFER_LEN: usize = 12;
+
+// The read position of the consumer lives in its own cache line in front of the payload bytes
+const RING_HEADER_LEN: usize = 64;
+
+/// The default capacity of a ring buffer of 4 MiB.
+pub const DEFAULT_CAPACITY: usize = 4 * 1024 * 1024;
+
+/// The default size from which payloads go through shared memory of 64 KiB.
+pub const DEFAULT_THRESHOLD: usize = 64 * 1024;
+
+/// Options for [`ShmConnection::negotiate`].
+#[derive(Debug, Clone, Copy)]
+pub struct ShmOptions {
+    /// Size of the ring buffer for payloads sent by this side.
+    ///
+    /// 0 sends everything over the connection, the peer may still use its own ring.
+    pub capacity: usize,
+    /// Payloads of at least this many bytes go through the ring buffer.
+    pub threshold: usize,
+}
+
+impl Default for ShmOptions {
+    fn default() -> Self {
+        Self {
+            capacity: DEFAULT_CAPACITY,
+            threshold: DEFAULT_THRESHOLD,
+        }
+    }
+}
+
+fn encode_offer(len: usize) -> [u8; OFFER_LEN] {
+    let mut offer = [0; OFFER_LEN];
+    offer[..4].copy_from_slice(&OFFER_MAGIC);
+    offer[4..].copy_from_slice(&(len as u64).to_le_bytes());
+    offer
+}
+
+/// Returns the size of the offered mapping, 0 if the peer doesn't offer one.
+fn decode_offer(offer: &[u8; OFFER_LEN]) -> io::Result<usize> {
+    if offer[..4] != OFFER_MAGIC {
+        return Err(invalid_data("peer didn't negotiate shared memory"));
+    }
+    let len = u64::from_le_bytes(offer[4..].try_into().expect("offer has a u64 length"));
+    match usize::try_from(len) {
+        Ok(0) => Ok(0),
+        Ok(len) if len > RING_HEADER_LEN => Ok(len),
+        _ => Err(invalid_data("invalid shared memory size")),
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// One direction of shared memory, written by the side that created it.
+struct Ring {
+    mapping: platform::Mapping,
+    // Bytes written so far for the producer, bytes read so far for the consumer
+    position: u64,
+}
+
+impl Ring {
+    fn new(mapping: platform::Mapping) -> Self {
+        Self {
+            mapping,
+            position: 0,
+        }
+    }
+
+    fn capacity(&self) -> usize {
+        self.mapping.len() - RING_HEADER_LEN
+    }
+
+    fn read_position(&self) -> &AtomicU64 {
+        // The mapping is page aligned and at least RING_HEADER_LEN bytes long
+        unsafe { &*self.mapping.as_ptr().cast::<AtomicU64>() }
+    }
+
+    /// Calls `copy` with the offsets into the payload and into the ring of both parts of a
+    /// `len` byte range starting at the current position, which wraps around at the end.
+    fn split(&self, len: usize, mut copy: impl FnMut(usize, usize, usize)) {
+        let start = (self.position % self.capacity() as u64) as usize;
+        let first = len.min(self.capacity() - start);
+        copy(0, start, first);
+        copy(first, 0, len - first);
+    }
+
+    /// Copies `payload` into the ring, returning `false` if there's not enough room right now.
+    fn try_write(&mut self, payload: &[u8]) -> bool {
+        let read = self.read_position().load(Ordering::Acquire);
+        // A misbehaving peer could move its read position past ours
+        let free = match self.position.checked_sub(read) {
+            Some(used) if used <= self.capacity() as u64 => self.capacity() as u64 - used,
+            _ => return false,
+        };
+        if (payload.len() as u64) > free {
+            return false;
+        }
+        let data = unsafe { self.mapping.as_ptr().add(RING_HEADER_LEN) };
+        self.split(payload.len(), |from, to, len| unsafe {
+            ptr::copy_nonoverlapping(payload[from..].as_ptr(), data.add(to), len);
+        });
+        self.position += payload.len() as u64;
+        true
+    }
+
+    /// Copies the next `len` bytes out of the ring and hands the room back to the producer.
+    fn read(&mut self, len: u64) -> io::Result<Vec<u8>> {
+        let len = match usize::try_from(len) {
+            Ok(len) if len <= self.capacity() => len,
+            _ => {
+                return Err(invalid_data(
+                    "shared memory payload exceeds the ring buffer",
+                ))
+            }
+        };
+        let mut payload = vec![0; len];
+        let data = unsafe { self.mapping.as_ptr().add(RING_HEADER_LEN) };
+        self.split(len, |to, from, len| unsafe {
+            ptr::copy_nonoverlapping(data.add(from), payload[to..].as_mut_ptr(), len);
+        });
+        self.position += len as u64;
+        self.read_position().store(self.position, Ordering::Release);
+        Ok(payload)
+    }
+}
+
+/// A [`FramedConnection`] that moves large payloads through shared memory.
+///
+/// Payloads are sent and received whole, like frames. Sending is not cancellation safe: a
+/// payload that was copied into the ring before the future was dropped desynchronizes the ring,
+/// so the connection should be dropped along with such a future.
+pub struct ShmConnection {
+    inner: FramedConnection,
+    threshold: usize,
+    tx: Option<Ring>,
+    rx: Option<Ring>,
+}
+
+impl ShmConnection {
+    /// Sets up shared memory with the peer and wraps the connection.
+    ///
+    /// Both sides have to call this right after connecting, before anything else is sent. Each
+    /// side offers a ring buffer for its own payloads and maps the one offered by the peer, which
+    /// fails if the peer isn't negotiating. Payloads that fall back to the connection are limited
+    /// to [`DEFAULT_MAX_FRAME_LENGTH`](crate::framing::DEFAULT_MAX_FRAME_LENGTH).
+    pub async fn negotiate(mut conn: Connection, options: Option<ShmOptions>) -> io::Result<Self> {
+        let options = options.unwrap_or_default();
+        let mapping = match options.capacity {
+            0 => None,
+            capacity => match platform::Mapping::create(RING_HEADER_LEN + capacity) {
+                Ok(mapping) => Some(mapping),
+                Err(e) if e.kind() == io::ErrorKind::Unsupported => None,
+                Err(e) => return Err(e),
+            },
+        };
+        platform::send_offer(&mut conn, mapping.as_ref()).await?;
+        let peer_mapping = platform::recv_offer(&mut conn).await?;
+        Ok(Self {
+            inner: FramedConnection::new(conn),
+            threshold: options.threshold,
+            tx: mapping.map(Ring::new),
+            rx: peer_mapping.map(Ring::new),
+        })
+    }
+
+    /// Returns whether payloads sent by this side can go through shared memory.
+    pub fn is_shared(&self) -> bool {
+        self.tx.is_some()
+    }
+
+    /// Sends a single payload and flushes it.
+    pub async fn send(&mut self, payload: &[u8]) -> io::Result<()> {
+        let frame = match &mut self.tx {
+            Some(ring) if payload.len() >= self.threshold && ring.try_write(payload) => {
+                let mut frame = BytesMut::with_capacity(9);
+                frame.put_u8(SHARED);
+                frame.put_u64_le(payload.len() as u64);
+                frame
+            }
+            _ => {
+                let mut frame = BytesMut::with_capacity(1 + payload.len());
+                frame.put_u8(INLINE);
+                frame.put_slice(payload);
+                frame
+            }
+        };
+        self.inner.send(frame.freeze()).await
+    }
+
+    /// Receives the next payload, or `None` once the peer closed the connection.
+    pub async fn recv(&mut self) -> Option<io::Result<Bytes>> {
+        let frame = match self.inner.recv().await? {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(self.decode(frame))
+    }
+
+    fn decode(&mut self, frame: Bytes) -> io::Result<Bytes> {
+        match frame.first() {
+            Some(&INLINE) => Ok(frame.slice(1..)),
+            Some(&SHARED) if frame.len() == 9 => {
+                let len = u64::from_le_bytes(frame[1..].try_into().expect("frame has a u64"));
+                let ring = self.rx.as_mut().ok_or_else(|| {
+                    invalid_data("peer sent through shared memory it didn't offer")
+                })?;
+                ring.read(len).map(Bytes::from)
+            }
+            _ => Err(invalid_data("malformed shared memory frame")),
+        }
+    }
+
+    /// Returns a reference to the underlying connection.
+    pub fn get_ref(&self) -> &Connection {
+        self.inner.get_ref()
+    }
+}
+
+impl fmt::Debug for ShmConnection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShmConnection")
+            .field("threshold", &self.threshold)
+            .field("tx_capacity", &self.tx.as_ref().map(Ring::capacity))
+            .field("rx_capacity", &self.rx.as_ref().map(Ring::capacity))
+            .finish_non_exhaustive()
+    }
+}
+
+/// Sends `data` as a sealed `memfd` attached to a small header.
+///
+/// The data is copied into the memfd once, which is then sealed against writes and resizing so
+/// the peer can map it without trusting this process. The peer has to receive it with
+/// [`recv_memfd`], plain reads discard the descriptor. Only available on Linux.
+#[cfg(target_os = "linux")]
+pub async fn send_memfd(conn: &mut Connection, data: &[u8]) -> io::Result<()> {
+    platform::send_memfd(conn, data).await
+}
+
+/// Receives a buffer sent with [`send_memfd`], backed by a read-only mapping of the memfd.
+///
+/// The mapping is released once the returned `Bytes` and all its clones are dropped. Fails with
+/// [`InvalidData`](io::ErrorKind::InvalidData) if the memfd isn't sealed or doesn't match the
+/// announced size. Only available on Linux.
+#[cfg(target_os = "linux")]
+pub async fn recv_memfd(conn: &mut Connection) -> io::Result<Bytes> {
+    platform::recv_memfd(conn).await
+}
@@ -0,0 +1,257 @@
+use std::os::fd::{AsFd, AsRawFd, OwnedFd};
+use std::ptr::NonNull;
+use std::{io, mem, ptr};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+use super::{decode_offer, encode_offer, invalid_data, OFFER_LEN};
+use crate::Connection;
+
+// Seals that keep the creator of a ring from resizing it once the peer mapped it
+#[cfg(any(target_os = "linux", target_os = "android"))]
+const RING_SEALS: libc::c_int = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW;
+
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn get_seals(fd: &OwnedFd) -> io::Result<libc::c_int> {
+    match unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_GET_SEALS) } {
+        -1 => Err(io::Error::last_os_error()),
+        seals => Ok(seals),
+    }
+}
+
+pub(crate) struct Mapping {
+    ptr: NonNull<u8>,
+    len: usize,
+    fd: OwnedFd,
+}
+
+// The mapping is only accessed through the ring, which synchronizes with atomics
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub(crate) fn create(len: usize) -> io::Result<Self> {
+        use std::ffi::CStr;
+        use std::os::fd::FromRawFd;
+
+        let name = CStr::from_bytes_with_nul(b"tokio-ipc-shm\0").expect("name is nul-terminated");
+        let fd = unsafe {
+            libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING)
+        };
+        if fd == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        let size = libc::off_t::try_from(len)
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "capacity is too large"))?;
+        if unsafe { libc::ftruncate(fd.as_raw_fd(), size) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        let seals = RING_SEALS | libc::F_SEAL_SEAL;
+        if unsafe { libc::fcntl(fd.as_raw_fd(), libc::F_ADD_SEALS, seals) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Self::map(fd, len)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    pub(crate) fn create(_len: usize) -> io::Result<Self> {
+        Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "shared memory rings are only created on Linux",
+        ))
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    fn open(fd: OwnedFd, len: usize) -> io::Result<Self> {
+        // A peer that could still resize the memfd could make every access to the ring fault
+        if get_seals(&fd)? & RING_SEALS != RING_SEALS {
+            return Err(invalid_data("shared memory isn't sealed against resizing"));
+        }
+        let mut stat = unsafe { mem::zeroed::<libc::stat>() };
+        if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        // Mapping past the end of the file would fault on access
+        if u64::try_from(stat.st_size).map_or(true, |size| size < len as u64) {
+            return Err(invalid_data("shared memory is smaller than offered"));
+        }
+        Self::map(fd, len)
+    }
+
+    #[cfg(not(any(target_os = "linux", target_os = "android")))]
+    fn open(_fd: OwnedFd, _len: usize) -> io::Result<Self> {
+        // Without seals there's no telling whether the peer shrinks the memory later
+        Err(invalid_data(
+            "shared memory can't be checked for seals on this platform",
+        ))
+    }
+
+    fn map(fd: OwnedFd, len: usize) -> io::Result<Self> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            ptr: NonNull::new(ptr.cast()).expect("mmap doesn't map at null"),
+            len,
+            fd,
+        })
+    }
+
+    pub(crate) fn as_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr.as_ptr().cast(), self.len) };
+    }
+}
+
+pub(crate) async fn send_offer(conn: &mut Connection, mapping: Option<&Mapping>) -> io::Result<()> {
+    let offer = encode_offer(mapping.map_or(0, Mapping::len));
+    let sent = match mapping {
+        Some(mapping) => conn.send_with_fds(&offer, &[mapping.fd.as_fd()]).await?,
+        None => 0,
+    };
+    conn.write_all(&offer[sent..]).await
+}
+
+pub(crate) async fn recv_offer(conn: &mut Connection) -> io::Result<Option<Mapping>> {
+    let mut offer = [0; OFFER_LEN];
+    // A descriptor lost to truncation shows up as a missing one
+    let (received, fds, _) = conn.recv_with_fds(&mut offer).await?;
+    if received == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    conn.read_exact(&mut offer[received..]).await?;
+    match (decode_offer(&offer)?, fds.into_iter().next()) {
+        (0, _) => Ok(None),
+        (len, Some(fd)) => Mapping::open(fd, len).map(Some),
+        (_, None) => Err(invalid_data(
+            "shared memory offer without a file descriptor",
+        )),
+    }
+}
+
+/// A read-only mapping of a sealed memfd that backs received `Bytes`.
+#[cfg(target_os = "linux")]
+struct SealedMapping {
+    ptr: NonNull<u8>,
+    len: usize,
+}
+
+// The contents can't change anymore once the memfd is sealed
+#[cfg(target_os = "linux")]
+unsafe impl Send for SealedMapping {}
+#[cfg(target_os = "linux")]
+unsafe impl Sync for SealedMapping {}
+
+#[cfg(target_os = "linux")]
+impl AsRef<[u8]> for SealedMapping {
+    fn as_ref(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for SealedMapping {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.ptr.as_ptr().cast(), self.len) };
+    }
+}
+
+// Seals that keep the sender from changing the contents or size after handing the memfd over
+#[cfg(target_os = "linux")]
+const REQUIRED_SEALS: libc::c_int = libc::F_SEAL_SHRINK | libc::F_SEAL_GROW | libc::F_SEAL_WRITE;
+
+#[cfg(target_os = "linux")]
+pub(crate) async fn send_memfd(conn: &mut Connection, data: &[u8]) -> io::Result<()> {
+    use std::ffi::CStr;
+    use std::io::Write;
+    use std::os::fd::FromRawFd;
+
+    let name = CStr::from_bytes_with_nul(b"tokio-ipc-sealed\0").expect("name is nul-terminated");
+    let fd =
+        unsafe { libc::memfd_create(name.as_ptr(), libc::MFD_CLOEXEC | libc::MFD_ALLOW_SEALING) };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let mut file = unsafe { std::fs::File::from_raw_fd(fd) };
+    // Writing instead of mapping leaves no writable mapping behind that would block F_SEAL_WRITE
+    file.write_all(data)?;
+    if unsafe { libc::fcntl(fd, libc::F_ADD_SEALS, REQUIRED_SEALS | libc::F_SEAL_SEAL) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let header = (data.len() as u64).to_le_bytes();
+    let sent = conn.send_with_fds(&header, &[file.as_fd()]).await?;
+    conn.write_all(&header[sent..]).await
+}
+
+#[cfg(target_os = "linux")]
+pub(crate) async fn recv_memfd(conn: &mut Connection) -> io::Result<bytes::Bytes> {
+    let mut header = [0; 8];
+    let (received, fds, _) = conn.recv_with_fds(&mut header).await?;
+    if received == 0 {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    conn.read_exact(&mut header[received..]).await?;
+    let fd = fds
+        .into_iter()
+        .next()
+        .ok_or_else(|| invalid_data("sealed buffer without a file descriptor"))?;
+
+    if get_seals(&fd)? & REQUIRED_SEALS != REQUIRED_SEALS {
+        return Err(invalid_data("received a memfd that isn't sealed"));
+    }
+    let mut stat = unsafe { mem::zeroed::<libc::stat>() };
+    if unsafe { libc::fstat(fd.as_raw_fd(), &mut stat) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    let len = u64::from_le_bytes(header);
+    if u64::try_from(stat.st_size).ok() != Some(len) {
+        return Err(invalid_data(
+            "sealed buffer doesn't have the announced size",
+        ));
+    }
+    if len == 0 {
+        return Ok(bytes::Bytes::new());
+    }
+
+    let len = usize::try_from(len).map_err(|_| invalid_data("sealed buffer is too large"))?;
+    let ptr = unsafe {
+        libc::mmap(
+            ptr::null_mut(),
+            len,
+            libc::PROT_READ,
+            libc::MAP_SHARED,
+            fd.as_raw_fd(),
+            0,
+        )
+    };
+    if ptr == libc::MAP_FAILED {
+        return Err(io::Error::last_os_error());
+    }
+    // The mapping stays valid after the descriptor is closed
+    Ok(bytes::Bytes::from_owner(SealedMapping {
+        ptr: NonNull::new(ptr.cast()).expect("mmap doesn't map at null"),
+        len,
+    }))
+}
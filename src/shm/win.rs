@@ -0,0 +1,137 @@
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle, RawHandle};
+use std::{io, ptr};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use windows_sys::Win32::Foundation::{
+    GetLastError, ERROR_ALREADY_EXISTS, HANDLE, INVALID_HANDLE_VALUE,
+};
+use windows_sys::Win32::System::Memory::{
+    CreateFileMappingW, MapViewOfFile, OpenFileMappingW, UnmapViewOfFile, FILE_MAP_ALL_ACCESS,
+    MEMORY_MAPPED_VIEW_ADDRESS, PAGE_READWRITE,
+};
+
+use super::{decode_offer, encode_offer, invalid_data, OFFER_LEN};
+use crate::Connection;
+
+// Keeps a peer from getting us to map arbitrary objects of the session
+const NAME_PREFIX: &str = "tokio-ipc-shm-";
+
+pub(crate) struct Mapping {
+    view: MEMORY_MAPPED_VIEW_ADDRESS,
+    len: usize,
+    name: String,
+    _handle: OwnedHandle,
+}
+
+// The mapping is only accessed through the ring, which synchronizes with atomics
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+fn object_name(name: &str) -> Vec<u16> {
+    OsStr::new(&format!(r"Local\{name}"))
+        .encode_wide()
+        .chain(Some(0))
+        .collect()
+}
+
+impl Mapping {
+    pub(crate) fn create(len: usize) -> io::Result<Self> {
+        let mut bytes = [0; 16];
+        getrandom::getrandom(&mut bytes)?;
+        let suffix: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+        let name = format!("{NAME_PREFIX}{suffix}");
+
+        let size = len as u64;
+        let handle = unsafe {
+            CreateFileMappingW(
+                INVALID_HANDLE_VALUE,
+                ptr::null(),
+                PAGE_READWRITE,
+                (size >> 32) as u32,
+                size as u32,
+                object_name(&name).as_ptr(),
+            )
+        };
+        if handle == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let handle = unsafe { OwnedHandle::from_raw_handle(handle as RawHandle) };
+        if unsafe { GetLastError() } == ERROR_ALREADY_EXISTS {
+            return Err(io::ErrorKind::AlreadyExists.into());
+        }
+        Self::map(handle, len, name)
+    }
+
+    fn open(name: String, len: usize) -> io::Result<Self> {
+        let handle =
+            unsafe { OpenFileMappingW(FILE_MAP_ALL_ACCESS, 0, object_name(&name).as_ptr()) };
+        if handle == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let handle = unsafe { OwnedHandle::from_raw_handle(handle as RawHandle) };
+        // Mapping a view larger than the section fails, so a short mapping is caught here
+        Self::map(handle, len, name)
+    }
+
+    fn map(handle: OwnedHandle, len: usize, name: String) -> io::Result<Self> {
+        let view = unsafe {
+            MapViewOfFile(
+                handle.as_raw_handle() as HANDLE,
+                FILE_MAP_ALL_ACCESS,
+                0,
+                0,
+                len,
+            )
+        };
+        if view.Value.is_null() {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            view,
+            len,
+            name,
+            _handle: handle,
+        })
+    }
+
+    pub(crate) fn as_ptr(&self) -> *mut u8 {
+        self.view.Value.cast()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe { UnmapViewOfFile(self.view) };
+    }
+}
+
+pub(crate) async fn send_offer(conn: &mut Connection, mapping: Option<&Mapping>) -> io::Result<()> {
+    let mut offer = encode_offer(mapping.map_or(0, Mapping::len)).to_vec();
+    if let Some(mapping) = mapping {
+        offer.push(mapping.name.len() as u8);
+        offer.extend_from_slice(mapping.name.as_bytes());
+    }
+    conn.write_all(&offer).await
+}
+
+pub(crate) async fn recv_offer(conn: &mut Connection) -> io::Result<Option<Mapping>> {
+    let mut offer = [0; OFFER_LEN];
+    conn.read_exact(&mut offer).await?;
+    let len = decode_offer(&offer)?;
+    if len == 0 {
+        return Ok(None);
+    }
+    let mut name = vec![0; usize::from(conn.read_u8().await?)];
+    conn.read_exact(&mut name).await?;
+    let name = match String::from_utf8(name) {
+        Ok(name) if name.starts_with(NAME_PREFIX) => name,
+        _ => return Err(invalid_data("invalid shared memory name")),
+    };
+    Mapping::open(name, len).map(Some)
+}
@@ -0,0 +1,55 @@
+use std::sync::atomic::Ordering;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use tokio::sync::oneshot;
+
+use crate::stats::EndpointStats;
+
+/// Stops an [`IpcStream`](crate::IpcStream) from accepting and drains its connections.
+///
+/// Obtained from [`IpcStream::shutdown_handle`](crate::IpcStream::shutdown_handle). The handle is
+/// cheap to clone, so it can be handed to whatever decides when the server stops.
+#[derive(Clone, Debug)]
+pub struct ShutdownHandle {
+    trigger: Arc<Mutex<Option<oneshot::Sender<()>>>>,
+    stats: Arc<EndpointStats>,
+}
+
+impl ShutdownHandle {
+    pub(crate) fn new(stats: Arc<EndpointStats>) -> (Self, oneshot::Receiver<()>) {
+        let (tx, rx) = oneshot::channel();
+        let handle = Self {
+            trigger: Arc::new(Mutex::new(Some(tx))),
+            stats,
+        };
+        (handle, rx)
+    }
+
+    /// Stops accepting, then waits up to `drain_timeout` for the connections accepted so far to
+    /// be dropped.
+    ///
+    /// Pending and future accepts on the stream fail and the `Stream` implementation ends, so the
+    /// accept loop can finish and drop the stream, which closes the listener and removes the
+    /// socket file. Connections are never closed forcibly; with a `drain_timeout` of `None` this
+    /// doesn't wait for them at all.
+    ///
+    /// Returns whether all connections were dropped by the time this returns.
+    pub async fn shutdown(self, drain_timeout: Option<Duration>) -> bool {
+        let trigger = self
+            .trigger
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(trigger) = trigger {
+            let _ = trigger.send(());
+        }
+
+        match drain_timeout {
+            Some(drain_timeout) => tokio::time::timeout(drain_timeout, self.stats.wait_drained())
+                .await
+                .is_ok(),
+            None => self.stats.active_connections.load(Ordering::Relaxed) == 0,
+        }
+    }
+}
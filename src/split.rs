@@ -0,0 +1,127 @@
+use std::io;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tracing::Span;
+
+use crate::busy_poll::BusyPoll;
+use crate::lifetime::Lifetime;
+use crate::stats::ConnectionStats;
+use crate::timeout::Timeout;
+use crate::{instrument, platform};
+
+/// Owned read half of a [`Connection`](crate::Connection), created by
+/// [`Connection::into_split`](crate::Connection::into_split).
+pub struct OwnedReadHalf {
+    pub(crate) inner: platform::OwnedReadHalf,
+    pub(crate) timeout: Timeout,
+    pub(crate) spin: BusyPoll,
+    pub(crate) lifetime: Lifetime,
+    pub(crate) stats: Option<Arc<ConnectionStats>>,
+    pub(crate) span: Span,
+}
+
+/// Owned write half of a [`Connection`](crate::Connection), created by
+/// [`Connection::into_split`](crate::Connection::into_split).
+pub struct OwnedWriteHalf {
+    pub(crate) inner: platform::OwnedWriteHalf,
+    pub(crate) timeout: Timeout,
+    pub(crate) spin: BusyPoll,
+    pub(crate) lifetime: Lifetime,
+    pub(crate) stats: Option<Arc<ConnectionStats>>,
+    pub(crate) span: Span,
+}
+
+impl AsyncRead for OwnedReadHalf {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = Pin::into_inner(self);
+        let _entered = this.span.enter();
+        // The write half takes care of shutting the connection down
+        if this.lifetime.poll_expired(ctx) {
+            return Poll::Ready(Ok(()));
+        }
+        let filled = buf.filled().len();
+        let result = this.timeout.poll_op(ctx, |ctx| {
+            let result = Pin::new(&mut this.inner).poll_read(ctx, buf);
+            this.spin.poll_op(ctx, result)
+        });
+        if buf.filled().len() > filled {
+            this.lifetime.record_activity();
+        }
+        if let Poll::Ready(result) = &result {
+            instrument::record_read(result, buf.filled().len() - filled);
+            if let Some(stats) = &this.stats {
+                stats.record_read(result, buf.filled().len() - filled);
+            }
+        }
+        result
+    }
+}
+
+impl OwnedWriteHalf {
+    fn poll_lifetime(&mut self, ctx: &mut Context<'_>) -> Poll<bool> {
+        if !self.lifetime.poll_expired(ctx) {
+            return Poll::Ready(false);
+        }
+        if self.lifetime.needs_shutdown() {
+            let _ = ready!(Pin::new(&mut self.inner).poll_shutdown(ctx));
+            self.lifetime.mark_shut_down();
+        }
+        Poll::Ready(true)
+    }
+}
+
+impl AsyncWrite for OwnedWriteHalf {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = Pin::into_inner(self);
+        let _entered = this.span.enter();
+        if ready!(this.poll_lifetime(ctx)) {
+            return Poll::Ready(Err(this.lifetime.expired_error()));
+        }
+        let result = this.timeout.poll_op(ctx, |ctx| {
+            let result = Pin::new(&mut this.inner).poll_write(ctx, buf);
+            this.spin.poll_op(ctx, result)
+        });
+        if let Poll::Ready(Ok(1..)) = result {
+            this.lifetime.record_activity();
+        }
+        if let Poll::Ready(result) = &result {
+            instrument::record_write(result);
+            if let Some(stats) = &this.stats {
+                stats.record_write(result, *result.as_ref().unwrap_or(&0));
+            }
+        }
+        result
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = Pin::into_inner(self);
+        this.timeout.poll_op(ctx, |ctx| {
+            let result = Pin::new(&mut this.inner).poll_flush(ctx);
+            this.spin.poll_op(ctx, result)
+        })
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
+        let this = Pin::into_inner(self);
+        let _entered = this.span.enter();
+        let result = this.timeout.poll_op(ctx, |ctx| {
+            let result = Pin::new(&mut this.inner).poll_shutdown(ctx);
+            this.spin.poll_op(ctx, result)
+        });
+        if let Poll::Ready(result) = &result {
+            instrument::record_shutdown(result);
+        }
+        result
+    }
+}
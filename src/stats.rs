@@ -0,0 +1,200 @@
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+use tokio::sync::Notify;
+
+use crate::metrics::EndpointMetrics;
+
+/// Counters shared between an [`IpcStream`](crate::IpcStream) and its accepted connections.
+#[derive(Default, Debug)]
+pub(crate) struct EndpointStats {
+    pub(crate) active_connections: AtomicU64,
+    accepted_connections: AtomicU64,
+    accept_errors: AtomicU64,
+    io_errors: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+    round_trips: AtomicU64,
+    round_trip_micros: AtomicU64,
+    max_round_trip_micros: AtomicU64,
+    // notified whenever the number of active connections drops to zero
+    drained: Notify,
+    // woken whenever an active connection is closed
+    released: Mutex<Option<Waker>>,
+    metrics: EndpointMetrics,
+}
+
+impl EndpointStats {
+    /// Creates the counters of the endpoint at `path`, which labels its metrics.
+    pub(crate) fn new(path: Option<&Path>) -> Self {
+        Self {
+            metrics: EndpointMetrics::new(path),
+            ..Self::default()
+        }
+    }
+
+    pub(crate) fn record_accept_error(&self) {
+        self.accept_errors.fetch_add(1, Ordering::Relaxed);
+        self.metrics.record_accept_error();
+    }
+
+    pub(crate) fn record_rejected(&self) {
+        self.metrics.record_rejected();
+    }
+
+    fn released(&self) -> MutexGuard<'_, Option<Waker>> {
+        self.released.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Returns `Ready` once fewer than `max` accepted connections are active, with the number of
+    /// connections that can still be accepted.
+    pub(crate) fn poll_below(&self, max: u64, cx: &mut Context<'_>) -> Poll<u64> {
+        let active = self.active_connections.load(Ordering::Relaxed);
+        if active < max {
+            return Poll::Ready(max - active);
+        }
+        *self.released() = Some(cx.waker().clone());
+        // Check again so a drop in between isn't missed
+        let active = self.active_connections.load(Ordering::Relaxed);
+        if active < max {
+            return Poll::Ready(max - active);
+        }
+        Poll::Pending
+    }
+
+    /// Waits until no accepted connection is active anymore.
+    pub(crate) async fn wait_drained(&self) {
+        loop {
+            // Register before checking so a drop in between isn't missed
+            let drained = self.drained.notified();
+            if self.active_connections.load(Ordering::Relaxed) == 0 {
+                return;
+            }
+            drained.await;
+        }
+    }
+}
+
+/// Handle to the aggregated statistics of all connections accepted by an
+/// [`IpcStream`](crate::IpcStream).
+///
+/// The handle is cheap to clone and stays valid after the stream is dropped.
+#[derive(Clone, Debug)]
+pub struct EndpointStatsHandle(pub(crate) Arc<EndpointStats>);
+
+impl EndpointStatsHandle {
+    /// Takes a snapshot of the current statistics.
+    ///
+    /// Throughput can be derived by comparing the byte counters of two snapshots, the mean
+    /// round-trip time over an interval by comparing the round-trip counters.
+    pub fn snapshot(&self) -> EndpointStatsSnapshot {
+        let stats = &self.0;
+        let micros = |counter: &AtomicU64| Duration::from_micros(counter.load(Ordering::Relaxed));
+        EndpointStatsSnapshot {
+            active_connections: stats.active_connections.load(Ordering::Relaxed),
+            accepted_connections: stats.accepted_connections.load(Ordering::Relaxed),
+            accept_errors: stats.accept_errors.load(Ordering::Relaxed),
+            io_errors: stats.io_errors.load(Ordering::Relaxed),
+            bytes_read: stats.bytes_read.load(Ordering::Relaxed),
+            bytes_written: stats.bytes_written.load(Ordering::Relaxed),
+            round_trips: stats.round_trips.load(Ordering::Relaxed),
+            round_trip_time: micros(&stats.round_trip_micros),
+            max_round_trip_time: micros(&stats.max_round_trip_micros),
+        }
+    }
+}
+
+/// Point-in-time view of an [`EndpointStatsHandle`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct EndpointStatsSnapshot {
+    /// Number of accepted connections that are still open.
+    pub active_connections: u64,
+    /// Total number of accepted connections.
+    pub accepted_connections: u64,
+    /// Number of failed accept attempts.
+    pub accept_errors: u64,
+    /// Number of failed reads and writes across all connections.
+    pub io_errors: u64,
+    /// Total number of bytes read across all connections.
+    pub bytes_read: u64,
+    /// Total number of bytes written across all connections.
+    pub bytes_written: u64,
+    /// Number of round trips measured across all connections, by the pings of
+    /// [`HeartbeatConnection`](crate::heartbeat::HeartbeatConnection)s.
+    pub round_trips: u64,
+    /// Sum of all measured round-trip times.
+    pub round_trip_time: Duration,
+    /// Longest measured round-trip time.
+    pub max_round_trip_time: Duration,
+}
+
+/// Per-connection guard that reports into the endpoint statistics.
+///
+/// Counts the connection as active until it's dropped.
+#[derive(Debug)]
+pub(crate) struct ConnectionStats(Arc<EndpointStats>);
+
+impl ConnectionStats {
+    pub(crate) fn new(stats: Arc<EndpointStats>) -> Self {
+        stats.accepted_connections.fetch_add(1, Ordering::Relaxed);
+        stats.active_connections.fetch_add(1, Ordering::Relaxed);
+        stats.metrics.record_accepted();
+        Self(stats)
+    }
+
+    pub(crate) fn record_read<T>(&self, result: &io::Result<T>, bytes: usize) {
+        match result {
+            Ok(_) => {
+                self.0.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+                self.0.metrics.record_read(bytes);
+            }
+            Err(_) => {
+                self.0.io_errors.fetch_add(1, Ordering::Relaxed);
+                self.0.metrics.record_io_error();
+            }
+        }
+    }
+
+    pub(crate) fn record_write<T>(&self, result: &io::Result<T>, bytes: usize) {
+        match result {
+            Ok(_) => {
+                self.0
+                    .bytes_written
+                    .fetch_add(bytes as u64, Ordering::Relaxed);
+                self.0.metrics.record_written(bytes);
+            }
+            Err(_) => {
+                self.0.io_errors.fetch_add(1, Ordering::Relaxed);
+                self.0.metrics.record_io_error();
+            }
+        }
+    }
+
+    pub(crate) fn record_round_trip(&self, rtt: Duration) {
+        let micros = u64::try_from(rtt.as_micros()).unwrap_or(u64::MAX);
+        self.0.round_trips.fetch_add(1, Ordering::Relaxed);
+        self.0
+            .round_trip_micros
+            .fetch_add(micros, Ordering::Relaxed);
+        self.0
+            .max_round_trip_micros
+            .fetch_max(micros, Ordering::Relaxed);
+        self.0.metrics.record_round_trip(rtt);
+    }
+}
+
+impl Drop for ConnectionStats {
+    fn drop(&mut self) {
+        self.0.metrics.record_closed();
+        if self.0.active_connections.fetch_sub(1, Ordering::Relaxed) == 1 {
+            self.0.drained.notify_waiters();
+        }
+        if let Some(waker) = self.0.released().take() {
+            waker.wake();
+        }
+    }
+}
@@ -0,0 +1,62 @@
+//! Named signaling primitives for coordinating processes alongside IPC connections.
+//!
+//! On Unix, the primitives are backed by files in the same folder that
+//! [`ServerId`](crate::ServerId) uses for sockets: an advisory `flock` for [`NamedLock`] and a FIFO
+//! for [`NamedEvent`]. On Windows, they map to a named mutex and a named auto-reset event in the
+//! session namespace.
+
+#[cfg(unix)]
+mod unix;
+#[cfg(windows)]
+mod win;
+
+use std::io;
+
+#[cfg(unix)]
+use self::unix as platform;
+#[cfg(windows)]
+use self::win as platform;
+
+/// A system-wide named lock, typically used to make sure only a single instance of a process is
+/// running.
+///
+/// The lock is released when this is dropped or the process exits.
+#[derive(Debug)]
+pub struct NamedLock(platform::NamedLock);
+
+impl NamedLock {
+    /// Tries to acquire the lock with the given name.
+    ///
+    /// Returns `None` if another process (or another [`NamedLock`] in this process) holds the lock.
+    pub fn try_acquire(name: &str) -> io::Result<Option<Self>> {
+        Ok(platform::NamedLock::try_acquire(name)?.map(Self))
+    }
+}
+
+/// A system-wide named event that one process waits on and others signal.
+///
+/// Signals don't carry any data. They're meant as wake-ups, for example to tell a running
+/// instance to reload its configuration or to bring its window to the front.
+#[derive(Debug)]
+pub struct NamedEvent(platform::NamedEvent);
+
+impl NamedEvent {
+    /// Creates the event with the given name, making this process the one that waits on it.
+    pub fn create(name: &str) -> io::Result<Self> {
+        Ok(Self(platform::NamedEvent::create(name)?))
+    }
+
+    /// Waits until the event is signaled.
+    ///
+    /// Multiple signals sent before the next call may be coalesced into a single wake-up.
+    pub async fn wait(&mut self) -> io::Result<()> {
+        self.0.wait().await
+    }
+
+    /// Signals the event with the given name.
+    ///
+    /// Does nothing if no process has created the event.
+    pub fn signal(name: &str) -> io::Result<()> {
+        platform::NamedEvent::signal(name)
+    }
+}
@@ -0,0 +1,122 @@
+use std::ffi::CString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use tokio::net::unix::pipe;
+
+use crate::unix::default_folder;
+
+fn primitive_path(name: &str, extension: &str) -> io::Result<PathBuf> {
+    let path = default_folder()?.join(format!("{name}.{extension}"));
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    Ok(path)
+}
+
+#[derive(Debug)]
+pub(crate) struct NamedLock {
+    // The lock is tied to the open file description and released when it's closed
+    _file: File,
+}
+
+impl NamedLock {
+    pub(crate) fn try_acquire(name: &str) -> io::Result<Option<Self>> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .mode(0o600)
+            .open(primitive_path(name, "lock")?)?;
+
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == -1 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::WouldBlock {
+                return Ok(None);
+            }
+            return Err(e);
+        }
+
+        Ok(Some(Self { _file: file }))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct NamedEvent {
+    receiver: pipe::Receiver,
+    // Keeping a writer open ourselves prevents the FIFO from reporting EOF whenever the last
+    // signaling process closes its end
+    _sender: pipe::Sender,
+}
+
+impl NamedEvent {
+    pub(crate) fn create(name: &str) -> io::Result<Self> {
+        let path = primitive_path(name, "event")?;
+        make_fifo(&path)?;
+        let receiver = pipe::OpenOptions::new().open_receiver(&path)?;
+        let sender = pipe::OpenOptions::new().open_sender(&path)?;
+        Ok(Self {
+            receiver,
+            _sender: sender,
+        })
+    }
+
+    pub(crate) async fn wait(&mut self) -> io::Result<()> {
+        let mut buf = [0u8; 64];
+        loop {
+            self.receiver.readable().await?;
+            match self.receiver.try_read(&mut buf) {
+                Ok(_) => return Ok(()),
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub(crate) fn signal(name: &str) -> io::Result<()> {
+        let path = primitive_path(name, "event")?;
+        let mut fifo = match OpenOptions::new()
+            .write(true)
+            .custom_flags(libc::O_NONBLOCK)
+            .open(path)
+        {
+            Ok(fifo) => fifo,
+            // Nobody created the event, or the process that did isn't running anymore
+            Err(e)
+                if e.kind() == io::ErrorKind::NotFound || e.raw_os_error() == Some(libc::ENXIO) =>
+            {
+                return Ok(());
+            }
+            Err(e) => return Err(e),
+        };
+
+        match fifo.write(&[1]) {
+            // A full pipe means there are plenty of pending wake-ups already
+            Err(e) if e.kind() != io::ErrorKind::WouldBlock => Err(e),
+            _ => Ok(()),
+        }
+    }
+}
+
+fn make_fifo(path: &Path) -> io::Result<()> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    if unsafe { libc::mkfifo(c_path.as_ptr(), 0o600) } == -1 {
+        let e = io::Error::last_os_error();
+        if e.kind() != io::ErrorKind::AlreadyExists {
+            return Err(e);
+        }
+        // Don't reuse something that isn't a FIFO, like a leftover regular file
+        if !fs::symlink_metadata(path)?.file_type().is_fifo() {
+            return Err(io::Error::new(
+                io::ErrorKind::AlreadyExists,
+                format!("{path:?} already exists and is not a FIFO"),
+            ));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,105 @@
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+use std::os::windows::io::{AsRawHandle, FromRawHandle, OwnedHandle, RawHandle};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::{io, ptr};
+
+use windows_sys::Win32::Foundation::{
+    GetLastError, ERROR_ALREADY_EXISTS, ERROR_FILE_NOT_FOUND, HANDLE, WAIT_OBJECT_0, WAIT_TIMEOUT,
+};
+use windows_sys::Win32::System::Threading::{
+    CreateEventW, CreateMutexW, OpenEventW, SetEvent, WaitForSingleObject, EVENT_MODIFY_STATE,
+};
+
+// How often a blocked wait checks whether its future was dropped
+const WAIT_POLL_INTERVAL_MS: u32 = 100;
+
+fn object_name(name: &str) -> Vec<u16> {
+    OsStr::new(&format!(r"Local\{name}"))
+        .encode_wide()
+        .chain(Some(0))
+        .collect()
+}
+
+fn owned_handle(handle: HANDLE) -> io::Result<OwnedHandle> {
+    if handle == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedHandle::from_raw_handle(handle as RawHandle) })
+}
+
+#[derive(Debug)]
+pub(crate) struct NamedLock {
+    _mutex: OwnedHandle,
+}
+
+impl NamedLock {
+    pub(crate) fn try_acquire(name: &str) -> io::Result<Option<Self>> {
+        let name = object_name(name);
+        // The mutex is never locked, holding a handle to it is what marks the name as taken
+        let mutex = owned_handle(unsafe { CreateMutexW(ptr::null(), 0, name.as_ptr()) })?;
+        if unsafe { GetLastError() } == ERROR_ALREADY_EXISTS {
+            return Ok(None);
+        }
+        Ok(Some(Self { _mutex: mutex }))
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct NamedEvent {
+    event: Arc<OwnedHandle>,
+}
+
+struct CancelOnDrop(Arc<AtomicBool>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+}
+
+impl NamedEvent {
+    pub(crate) fn create(name: &str) -> io::Result<Self> {
+        let name = object_name(name);
+        // Auto-reset, so each wake-up resets the event for the next wait
+        let event = owned_handle(unsafe { CreateEventW(ptr::null(), 0, 0, name.as_ptr()) })?;
+        Ok(Self {
+            event: Arc::new(event),
+        })
+    }
+
+    pub(crate) async fn wait(&mut self) -> io::Result<()> {
+        let event = self.event.clone();
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let _guard = CancelOnDrop(cancelled.clone());
+
+        tokio::task::spawn_blocking(move || loop {
+            let result = unsafe {
+                WaitForSingleObject(event.as_raw_handle() as HANDLE, WAIT_POLL_INTERVAL_MS)
+            };
+            match result {
+                WAIT_OBJECT_0 => return Ok(()),
+                WAIT_TIMEOUT if !cancelled.load(Ordering::Relaxed) => continue,
+                WAIT_TIMEOUT => return Err(io::Error::from(io::ErrorKind::Interrupted)),
+                _ => return Err(io::Error::last_os_error()),
+            }
+        })
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?
+    }
+
+    pub(crate) fn signal(name: &str) -> io::Result<()> {
+        let name = object_name(name);
+        let event = match owned_handle(unsafe { OpenEventW(EVENT_MODIFY_STATE, 0, name.as_ptr()) })
+        {
+            Ok(event) => event,
+            Err(e) if e.raw_os_error() == Some(ERROR_FILE_NOT_FOUND as i32) => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        if unsafe { SetEvent(event.as_raw_handle() as HANDLE) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
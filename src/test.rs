@@ -0,0 +1,448 @@
+//! Utilities for testing code built on top of this crate.
+//!
+//! All timers of the crate, from read and write timeouts over lifetimes, idle timeouts and
+//! heartbeats to reconnect backoff, run on Tokio's clock. Tests can drive them with
+//! [`tokio::time::pause`] and [`tokio::time::advance`] instead of waiting in real time, and
+//! [`expire`] ends the lifetime of a connection without involving the clock at all.
+//!
+//! Also available as `tokio_ipc::testing`. Requires the `test-util` feature.
+
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{ready, Context, Poll};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::{fmt, io};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{sleep, Sleep};
+
+use crate::{Connection, Endpoint, ExpiryReason, IntoIpcPath};
+
+static ENDPOINT_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A unique, collision-free IPC path that is cleaned up when dropped.
+///
+/// On Unix, the socket is placed inside a fresh private temporary folder which is removed along
+/// with everything in it on drop. Named pipes on Windows don't leave anything behind, so only a
+/// unique pipe name is generated.
+pub struct TempEndpoint {
+    path: PathBuf,
+}
+
+impl TempEndpoint {
+    /// Returns the path of the endpoint.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl fmt::Debug for TempEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TempEndpoint")
+            .field("path", &self.path)
+            .finish()
+    }
+}
+
+impl IntoIpcPath for &TempEndpoint {
+    fn into_ipc_path(self) -> io::Result<PathBuf> {
+        Ok(self.path.clone())
+    }
+}
+
+impl Drop for TempEndpoint {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(parent) = self.path.parent() {
+            let _ = std::fs::remove_dir_all(parent);
+        }
+    }
+}
+
+fn unique_name() -> String {
+    let count = ENDPOINT_COUNTER.fetch_add(1, Ordering::Relaxed);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.subsec_nanos())
+        .unwrap_or_default();
+    format!("tokio-ipc-test-{}-{count}-{nanos}", std::process::id())
+}
+
+/// Creates a new unique endpoint path.
+pub fn temp_endpoint() -> io::Result<TempEndpoint> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+
+        let folder = std::env::temp_dir().join(unique_name());
+        std::fs::DirBuilder::new().mode(0o700).create(&folder)?;
+        Ok(TempEndpoint {
+            path: folder.join("test.sock"),
+        })
+    }
+    #[cfg(windows)]
+    {
+        Ok(TempEndpoint {
+            path: PathBuf::from(format!(r"\\.\pipe\{}", unique_name())),
+        })
+    }
+}
+
+/// Creates two connections that are connected to each other.
+///
+/// This is [`Endpoint::pair`]: nothing is created on disk on Unix, and no listener or temp dir is
+/// needed on any platform.
+///
+/// The connections are backed by a real socket or pipe, not by [`tokio::io::duplex`]. A
+/// [`Connection`] hands out its OS handle through `into_inner`, `AsFd` and `AsRawHandle`, and
+/// peer credentials, buffer sizes and fd passing all query it, so an in-memory backing would turn
+/// each of these into a call that fails or panics at runtime. Every pair holds two OS handles
+/// until it's dropped, and tests that keep many pairs open at once count against the process's
+/// limit on open files. Code that only needs a byte stream can take `AsyncRead + AsyncWrite` and
+/// be tested with [`tokio::io::duplex`] directly.
+pub async fn pair() -> io::Result<(Connection, Connection)> {
+    Endpoint::pair().await
+}
+
+/// Ends the lifetime of `conn` right away, as if its limit for `reason` ran out.
+///
+/// The connection behaves as after a real expiry: [`Connection::expired`] resolves, the next read
+/// or write shuts the connection down, and a framed connection sends its close notice. No limit
+/// needs to be set. The halves of a split connection notice the expiry on their next read or
+/// write.
+pub fn expire(conn: &Connection, reason: ExpiryReason) {
+    conn.expire_now(reason);
+}
+
+/// An in-process stand-in for an [`Endpoint`] that doesn't listen on any path.
+///
+/// Connections made through a [`MockConnector`] are created with [`pair`] and yielded by the
+/// [`MockIncoming`] stream, so server code can be tested without socket files or pipe names. Like
+/// [`pair`], each connection is backed by a real socket or pipe rather than an in-memory stream.
+#[derive(Debug)]
+pub struct MockEndpoint {
+    tx: mpsc::UnboundedSender<Connection>,
+    rx: mpsc::UnboundedReceiver<Connection>,
+}
+
+impl MockEndpoint {
+    /// Creates a new mock endpoint.
+    pub fn new() -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        Self { tx, rx }
+    }
+
+    /// Returns a handle for connecting clients to this endpoint.
+    pub fn connector(&self) -> MockConnector {
+        MockConnector {
+            tx: self.tx.clone(),
+        }
+    }
+
+    /// Stream of incoming connections, like [`Endpoint::incoming`].
+    ///
+    /// Connectors have to be created before, this consumes the endpoint.
+    pub fn incoming(self) -> MockIncoming {
+        MockIncoming { rx: self.rx }
+    }
+}
+
+impl Default for MockEndpoint {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Connects clients to a [`MockEndpoint`].
+#[derive(Clone, Debug)]
+pub struct MockConnector {
+    tx: mpsc::UnboundedSender<Connection>,
+}
+
+impl MockConnector {
+    /// Connects a new client, handing the server end to the endpoint's [`MockIncoming`].
+    ///
+    /// Fails with [`ConnectionRefused`](io::ErrorKind::ConnectionRefused) once the incoming
+    /// stream was dropped.
+    pub async fn connect(&self) -> io::Result<Connection> {
+        let (client, server) = pair().await?;
+        self.tx
+            .send(server)
+            .map_err(|_| io::Error::from(io::ErrorKind::ConnectionRefused))?;
+        Ok(client)
+    }
+}
+
+/// Incoming connections of a [`MockEndpoint`].
+#[derive(Debug)]
+pub struct MockIncoming {
+    rx: mpsc::UnboundedReceiver<Connection>,
+}
+
+impl MockIncoming {
+    /// Waits for the next connection made through a [`MockConnector`].
+    ///
+    /// Fails with [`io::ErrorKind::NotConnected`] once the endpoint and all its connectors were
+    /// dropped and every connection was accepted.
+    pub async fn accept(&mut self) -> io::Result<Connection> {
+        self.rx
+            .recv()
+            .await
+            .ok_or_else(|| io::Error::from(io::ErrorKind::NotConnected))
+    }
+}
+
+#[cfg(feature = "futures")]
+impl futures::Stream for MockIncoming {
+    type Item = io::Result<Connection>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx).map(|conn| conn.map(Ok))
+    }
+}
+
+/// A server that echoes back everything it receives on every connection.
+///
+/// The server is stopped and its endpoint is cleaned up when this is dropped.
+pub struct EchoServer {
+    endpoint: TempEndpoint,
+    handle: JoinHandle<()>,
+}
+
+impl EchoServer {
+    /// Returns the path the server is listening on.
+    pub fn path(&self) -> &Path {
+        self.endpoint.path()
+    }
+
+    /// Connects a new client to the server.
+    pub async fn connect(&self) -> io::Result<Connection> {
+        Endpoint::connect(&self.endpoint, None).await
+    }
+}
+
+impl fmt::Debug for EchoServer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EchoServer")
+            .field("endpoint", &self.endpoint)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Drop for EchoServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Spawns an [`EchoServer`] listening on a new [`temp_endpoint`].
+///
+/// Must be called from within a Tokio runtime.
+pub fn spawn_echo_server() -> io::Result<EchoServer> {
+    let endpoint = temp_endpoint()?;
+    let mut incoming = Endpoint::new(&endpoint, None)?.incoming()?;
+
+    let handle = tokio::spawn(async move {
+        while let Ok(conn) = incoming.accept().await {
+            tokio::spawn(async move {
+                let (mut reader, mut writer) = tokio::io::split(conn);
+                let _ = tokio::io::copy(&mut reader, &mut writer).await;
+            });
+        }
+    });
+
+    Ok(EchoServer { endpoint, handle })
+}
+
+/// Writes `msg` to the connection and asserts that the same bytes are read back.
+///
+/// # Panics
+///
+/// Panics if the connection fails or the echoed bytes don't match.
+pub async fn assert_roundtrip<C>(conn: &mut C, msg: &[u8])
+where
+    C: AsyncRead + AsyncWrite + Unpin,
+{
+    conn.write_all(msg)
+        .await
+        .expect("failed to write message to connection");
+    let mut buf = vec![0u8; msg.len()];
+    conn.read_exact(&mut buf)
+        .await
+        .expect("failed to read message from connection");
+    assert_eq!(msg, &buf[..], "echoed message doesn't match");
+}
+
+/// What a [`FaultyConnection`] does once its fault is triggered.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Fault {
+    /// The peer seems to be gone: reads see the end of the stream and writes fail with
+    /// [`BrokenPipe`](io::ErrorKind::BrokenPipe).
+    #[default]
+    Disconnect,
+    /// Reads and writes fail with an error of the given kind.
+    Error(io::ErrorKind),
+}
+
+/// The faults a [`FaultyConnection`] injects.
+///
+/// The default policy doesn't inject anything. Faults are deterministic, so the same policy and
+/// traffic always fail in the same way.
+#[derive(Debug, Clone, Default)]
+pub struct FaultPolicy {
+    /// Delay before every read and write.
+    pub latency: Option<Duration>,
+    /// Reads return at most this many bytes at once.
+    pub max_read_len: Option<usize>,
+    /// Writes accept at most this many bytes at once.
+    pub max_write_len: Option<usize>,
+    /// Triggers `fault` once this many bytes were read and written in total.
+    ///
+    /// Reads and writes are cut short at exactly that many bytes.
+    pub fault_after: Option<u64>,
+    /// What happens once the fault is triggered.
+    pub fault: Fault,
+}
+
+/// A connection that injects latency, short reads, partial writes and failures according to a
+/// [`FaultPolicy`], for testing how code copes with misbehaving peers.
+pub struct FaultyConnection<T = Connection> {
+    inner: T,
+    policy: FaultPolicy,
+    transferred: u64,
+    read_delay: Option<Pin<Box<Sleep>>>,
+    write_delay: Option<Pin<Box<Sleep>>>,
+}
+
+impl<T> FaultyConnection<T> {
+    /// Wraps a connection.
+    pub fn new(inner: T, policy: FaultPolicy) -> Self {
+        Self {
+            inner,
+            policy,
+            transferred: 0,
+            read_delay: None,
+            write_delay: None,
+        }
+    }
+
+    /// Returns a reference to the underlying connection.
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying connection.
+    pub fn get_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    /// Returns the underlying connection.
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Returns how many more bytes can be transferred before the fault, `None` if it's not
+    /// triggered by traffic.
+    fn remaining(&self) -> Option<u64> {
+        self.policy
+            .fault_after
+            .map(|limit| limit.saturating_sub(self.transferred))
+    }
+}
+
+fn poll_latency(
+    latency: Option<Duration>,
+    delay: &mut Option<Pin<Box<Sleep>>>,
+    cx: &mut Context<'_>,
+) -> Poll<()> {
+    match latency {
+        Some(latency) => delay
+            .get_or_insert_with(|| Box::pin(sleep(latency)))
+            .as_mut()
+            .poll(cx),
+        None => Poll::Ready(()),
+    }
+}
+
+fn limit(len: usize, max_len: Option<usize>, remaining: Option<u64>) -> usize {
+    let len = max_len.map_or(len, |max_len| len.min(max_len));
+    remaining.map_or(len, |remaining| {
+        len.min(usize::try_from(remaining).unwrap_or(usize::MAX))
+    })
+}
+
+impl<T> fmt::Debug for FaultyConnection<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FaultyConnection")
+            .field("policy", &self.policy)
+            .field("transferred", &self.transferred)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for FaultyConnection<T> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = Pin::into_inner(self);
+        ready!(poll_latency(this.policy.latency, &mut this.read_delay, cx));
+        if this.remaining() == Some(0) {
+            this.read_delay = None;
+            return match this.policy.fault {
+                Fault::Disconnect => Poll::Ready(Ok(())),
+                Fault::Error(kind) => Poll::Ready(Err(kind.into())),
+            };
+        }
+
+        let len = limit(buf.remaining(), this.policy.max_read_len, this.remaining());
+        let mut limited = buf.take(len);
+        let result = ready!(Pin::new(&mut this.inner).poll_read(cx, &mut limited));
+        this.read_delay = None;
+        let read = limited.filled().len();
+        // The bytes were initialized and filled by the inner read
+        unsafe { buf.assume_init(read) };
+        buf.advance(read);
+        this.transferred += read as u64;
+        Poll::Ready(result)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for FaultyConnection<T> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = Pin::into_inner(self);
+        ready!(poll_latency(this.policy.latency, &mut this.write_delay, cx));
+        if this.remaining() == Some(0) {
+            this.write_delay = None;
+            return match this.policy.fault {
+                Fault::Disconnect => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+                Fault::Error(kind) => Poll::Ready(Err(kind.into())),
+            };
+        }
+
+        let len = limit(buf.len(), this.policy.max_write_len, this.remaining());
+        let result = ready!(Pin::new(&mut this.inner).poll_write(cx, &buf[..len]));
+        this.write_delay = None;
+        if let Ok(written) = result {
+            this.transferred += written as u64;
+        }
+        Poll::Ready(result)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
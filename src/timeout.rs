@@ -0,0 +1,112 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::task::{Context, Poll, Wake, Waker};
+use std::time::Duration;
+
+use tokio::time::{sleep, Sleep};
+
+/// Timer guarding a single direction (read or write) of a connection.
+///
+/// The timer is armed the first time an operation returns [`Poll::Pending`] and disarmed as soon
+/// as an operation completes, so the timeout applies to each individual operation rather than to
+/// the connection as a whole.
+///
+/// An operation may also be dropped while pending, for example by `select!` or a cancelled task,
+/// which the connection can't observe directly. A pending operation is only polled again once
+/// it's woken, so a poll that wasn't preceded by a wake-up of this direction starts a new
+/// operation with a fresh timer.
+pub(crate) struct Timeout {
+    duration: Option<Duration>,
+    sleep: Option<Pin<Box<Sleep>>>,
+    waiter: Arc<Waiter>,
+    kind: &'static str,
+}
+
+/// Forwards wake-ups of the guarded operation to its task, noting that they happened.
+#[derive(Default)]
+struct Waiter {
+    woken: AtomicBool,
+    waker: Mutex<Option<Waker>>,
+}
+
+impl Waiter {
+    fn register(&self, waker: &Waker) {
+        let mut registered = self.waker.lock().unwrap_or_else(PoisonError::into_inner);
+        match &*registered {
+            Some(registered) if registered.will_wake(waker) => {}
+            _ => *registered = Some(waker.clone()),
+        }
+    }
+}
+
+impl Wake for Waiter {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+        let waker = self.waker.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(waker) = &*waker {
+            waker.wake_by_ref();
+        }
+    }
+}
+
+impl Timeout {
+    pub(crate) fn new(kind: &'static str) -> Self {
+        Self {
+            duration: None,
+            sleep: None,
+            waiter: Arc::default(),
+            kind,
+        }
+    }
+
+    pub(crate) fn get(&self) -> Option<Duration> {
+        self.duration
+    }
+
+    pub(crate) fn set(&mut self, duration: Option<Duration>) {
+        self.duration = duration;
+        self.sleep = None;
+    }
+
+    /// Polls the guarded operation through `op`, failing it with [`io::ErrorKind::TimedOut`] once
+    /// it was pending for the configured duration.
+    pub(crate) fn poll_op<T>(
+        &mut self,
+        cx: &mut Context<'_>,
+        op: impl FnOnce(&mut Context<'_>) -> Poll<io::Result<T>>,
+    ) -> Poll<io::Result<T>> {
+        let Some(duration) = self.duration else {
+            return op(cx);
+        };
+        // The operation the timer was armed for was dropped while pending
+        if !self.waiter.woken.swap(false, Ordering::AcqRel) {
+            self.sleep = None;
+        }
+        self.waiter.register(cx.waker());
+        let waker = Waker::from(self.waiter.clone());
+        let mut op_cx = Context::from_waker(&waker);
+
+        if let Poll::Ready(result) = op(&mut op_cx) {
+            self.sleep = None;
+            return Poll::Ready(result);
+        }
+        let timer = self.sleep.get_or_insert_with(|| Box::pin(sleep(duration)));
+        match timer.as_mut().poll(&mut op_cx) {
+            Poll::Ready(()) => {
+                self.sleep = None;
+                Poll::Ready(Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("{} timed out after {duration:?}", self.kind),
+                )))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
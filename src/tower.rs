@@ -0,0 +1,187 @@
+//! Adapters to use IPC connections as `tower` services.
+//!
+//! [`Client`] sends every request as a frame of a [`FramedConnection`] and resolves with the next
+//! frame the server sends back, so middleware like retries, timeouts or load shedding can be
+//! layered around IPC calls. [`Connector`] makes new connections to an endpoint, for
+//! middleware and clients that take a connector service.
+//!
+//! Requires the `tower` feature.
+
+use std::collections::VecDeque;
+use std::future::Future;
+use std::path::Path;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll};
+use std::{fmt, io};
+
+use bytes::Bytes;
+use futures::stream::{SplitSink, SplitStream};
+use futures::{SinkExt, StreamExt};
+use tokio::sync::{oneshot, Mutex as AsyncMutex};
+use tokio::task::JoinHandle;
+use tower_service::Service;
+
+use crate::framing::FramedConnection;
+use crate::{Connection, Endpoint, EndpointOptions, IntoIpcPath};
+
+type Waiting = Arc<Mutex<Option<VecDeque<oneshot::Sender<io::Result<Bytes>>>>>>;
+
+fn connection_closed() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "connection closed")
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// A [`Service`] that sends requests over a connection and resolves with their responses.
+///
+/// The server has to answer every request with exactly one frame, in the order the requests
+/// arrived. Requests may be pipelined, a call doesn't wait for earlier calls to be answered
+/// before sending its request. Cloning the client is cheap, all clones share the same connection.
+/// Responses are read by a background task that is stopped once the last clone and the last call
+/// are dropped.
+#[derive(Clone)]
+pub struct Client {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    sink: AsyncMutex<SplitSink<FramedConnection, Bytes>>,
+    waiting: Waiting,
+    reader: JoinHandle<()>,
+}
+
+impl Drop for Shared {
+    fn drop(&mut self) {
+        self.reader.abort();
+    }
+}
+
+impl Client {
+    /// Starts making calls over `conn`.
+    ///
+    /// Must be called from within a Tokio runtime.
+    pub fn new(conn: FramedConnection) -> Self {
+        let (sink, stream) = conn.split();
+        let waiting: Waiting = Arc::new(Mutex::new(Some(VecDeque::new())));
+        let reader = tokio::spawn(read_responses(stream, waiting.clone()));
+        Self {
+            shared: Arc::new(Shared {
+                sink: AsyncMutex::new(sink),
+                waiting,
+                reader,
+            }),
+        }
+    }
+
+    /// Connects to the endpoint at `path` and starts making calls over the connection.
+    pub async fn connect(
+        path: impl IntoIpcPath,
+        options: Option<EndpointOptions>,
+    ) -> io::Result<Self> {
+        let conn = Endpoint::connect(path, options).await?;
+        Ok(Self::new(FramedConnection::new(conn)))
+    }
+}
+
+impl Shared {
+    async fn call(&self, req: Bytes) -> io::Result<Bytes> {
+        let (tx, rx) = oneshot::channel();
+        {
+            // Holding the sink keeps the waiting calls in the order their requests were sent
+            let mut sink = self.sink.lock().await;
+            // The waiter is only queued once the request is, so a call that's cancelled before
+            // doesn't leave a waiter behind that would get the response to the next request
+            sink.feed(req).await?;
+            match &mut *lock(&self.waiting) {
+                Some(waiting) => waiting.push_back(tx),
+                None => return Err(connection_closed()),
+            }
+            if let Err(e) = sink.flush().await {
+                if let Some(waiting) = &mut *lock(&self.waiting) {
+                    waiting.pop_back();
+                }
+                return Err(e);
+            }
+        }
+        rx.await.unwrap_or_else(|_| Err(connection_closed()))
+    }
+}
+
+impl Service<Bytes> for Client {
+    type Response = Bytes;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Bytes>> + Send>>;
+
+    /// Fails with [`BrokenPipe`](io::ErrorKind::BrokenPipe) once the connection is closed.
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &*lock(&self.shared.waiting) {
+            Some(_) => Poll::Ready(Ok(())),
+            None => Poll::Ready(Err(connection_closed())),
+        }
+    }
+
+    fn call(&mut self, req: Bytes) -> Self::Future {
+        let shared = self.shared.clone();
+        Box::pin(async move { shared.call(req).await })
+    }
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let waiting = lock(&self.shared.waiting).as_ref().map(VecDeque::len);
+        f.debug_struct("Client")
+            .field("waiting", &waiting)
+            .finish_non_exhaustive()
+    }
+}
+
+async fn read_responses(mut stream: SplitStream<FramedConnection>, waiting: Waiting) {
+    while let Some(Ok(frame)) = stream.next().await {
+        let tx = lock(&waiting).as_mut().and_then(VecDeque::pop_front);
+        match tx {
+            Some(tx) => {
+                let _ = tx.send(Ok(frame));
+            }
+            None => tracing::trace!("Dropping a response that no call is waiting for"),
+        }
+    }
+    // Dropping the senders fails every call that is still waiting.
+    lock(&waiting).take();
+}
+
+/// A [`Service`] that connects to an endpoint for every request.
+///
+/// The request itself is ignored, so the connector can stand in wherever a connector service is
+/// expected, such as a `Service<Uri>` of an HTTP client.
+#[derive(Clone, Debug)]
+pub struct Connector {
+    path: Arc<Path>,
+    options: Option<EndpointOptions>,
+}
+
+impl Connector {
+    /// Creates a connector to the endpoint at `path`.
+    pub fn new(path: impl IntoIpcPath, options: Option<EndpointOptions>) -> io::Result<Self> {
+        Ok(Self {
+            path: path.into_ipc_path()?.into(),
+            options,
+        })
+    }
+}
+
+impl<R> Service<R> for Connector {
+    type Response = Connection;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Connection>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, _req: R) -> Self::Future {
+        Box::pin(Endpoint::connect_shared(self.path.clone(), self.options))
+    }
+}
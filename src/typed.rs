@@ -0,0 +1,402 @@
+//! Typed messages on top of a [`FramedConnection`].
+//!
+//! Every message is serialized into a single frame with a [`Codec`]. The codecs shipped with
+//! this crate are enabled through features: `Json` with `json`, `Bincode` with `bincode`,
+//! `MessagePack` with `msgpack` and `Postcard` with `postcard`. Other formats can be plugged in by
+//! implementing [`Codec`].
+//!
+//! With the `rkyv` feature, `ArchivedConnection` sends messages archived with rkyv and hands out
+//! received ones in place after validating them, without deserializing. rkyv isn't built on serde,
+//! so it comes as a connection of its own rather than as a [`Codec`].
+//!
+//! Requires the `typed` feature.
+
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::{fmt, io};
+
+use bytes::Bytes;
+use futures::Stream;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::framing::{FramedConnection, IncomingFramed};
+use crate::Connection;
+
+/// Converts messages to and from the bytes of a single frame.
+pub trait Codec {
+    /// Serializes `msg` into the contents of a frame.
+    fn encode<T: Serialize>(&self, msg: &T) -> io::Result<Vec<u8>>;
+
+    /// Deserializes a message from the contents of a frame.
+    fn decode<T: DeserializeOwned>(&self, frame: &[u8]) -> io::Result<T>;
+}
+
+/// Encodes messages as JSON.
+///
+/// Requires the `json` feature.
+#[cfg(feature = "json")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Json;
+
+#[cfg(feature = "json")]
+impl Codec for Json {
+    fn encode<T: Serialize>(&self, msg: &T) -> io::Result<Vec<u8>> {
+        serde_json::to_vec(msg).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, frame: &[u8]) -> io::Result<T> {
+        serde_json::from_slice(frame).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Encodes messages with bincode.
+///
+/// Requires the `bincode` feature.
+#[cfg(feature = "bincode")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Bincode;
+
+#[cfg(feature = "bincode")]
+impl Codec for Bincode {
+    fn encode<T: Serialize>(&self, msg: &T) -> io::Result<Vec<u8>> {
+        bincode::serialize(msg).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, frame: &[u8]) -> io::Result<T> {
+        bincode::deserialize(frame).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Encodes messages as MessagePack, for peers written in other languages that speak it.
+///
+/// Structs are encoded as maps keyed by field name rather than as arrays, which is what
+/// MessagePack libraries of dynamic languages produce and expect.
+///
+/// Requires the `msgpack` feature.
+#[cfg(feature = "msgpack")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MessagePack;
+
+#[cfg(feature = "msgpack")]
+impl Codec for MessagePack {
+    fn encode<T: Serialize>(&self, msg: &T) -> io::Result<Vec<u8>> {
+        rmp_serde::to_vec_named(msg).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, frame: &[u8]) -> io::Result<T> {
+        rmp_serde::from_slice(frame).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// Encodes messages with postcard, the compact format common on embedded and `no_std` peers.
+///
+/// Requires the `postcard` feature.
+#[cfg(feature = "postcard")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Postcard;
+
+#[cfg(feature = "postcard")]
+impl Codec for Postcard {
+    fn encode<T: Serialize>(&self, msg: &T) -> io::Result<Vec<u8>> {
+        postcard::to_stdvec(msg).map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))
+    }
+
+    fn decode<T: DeserializeOwned>(&self, frame: &[u8]) -> io::Result<T> {
+        postcard::from_bytes(frame).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+/// A connection that sends and receives messages of type `T`, encoded with `C`.
+pub struct TypedConnection<T, C> {
+    inner: FramedConnection,
+    codec: C,
+    _marker: PhantomData<fn(T) -> T>,
+}
+
+impl<T, C> TypedConnection<T, C>
+where
+    T: Serialize + DeserializeOwned,
+    C: Codec,
+{
+    /// Wraps a connection with the default framing.
+    pub fn new(conn: Connection, codec: C) -> Self {
+        Self::from_framed(FramedConnection::new(conn), codec)
+    }
+
+    /// Wraps an already framed connection, keeping its maximum frame length.
+    pub fn from_framed(inner: FramedConnection, codec: C) -> Self {
+        Self {
+            inner,
+            codec,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Encodes and sends a message.
+    ///
+    /// Messages that can't be encoded fail with [`InvalidInput`](io::ErrorKind::InvalidInput)
+    /// without sending anything.
+    pub async fn send(&mut self, msg: &T) -> io::Result<()> {
+        let frame = self.codec.encode(msg)?;
+        self.inner.send(Bytes::from(frame)).await
+    }
+
+    /// Receives and decodes the next message, or `None` once the peer closed the connection.
+    ///
+    /// Frames that can't be decoded fail with [`InvalidData`](io::ErrorKind::InvalidData). The
+    /// connection stays usable, so the caller decides whether to skip the message or give up.
+    pub async fn recv(&mut self) -> Option<io::Result<T>> {
+        let frame = match self.inner.recv().await? {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(self.codec.decode(&frame))
+    }
+
+    /// Returns a reference to the underlying framed connection.
+    pub fn get_ref(&self) -> &FramedConnection {
+        &self.inner
+    }
+
+    /// Consumes the typed layer, returning the underlying framed connection.
+    pub fn into_inner(self) -> FramedConnection {
+        self.inner
+    }
+}
+
+impl<T, C: fmt::Debug> fmt::Debug for TypedConnection<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TypedConnection")
+            .field("inner", &self.inner)
+            .field("codec", &self.codec)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Stream of incoming connections, wrapped in [`TypedConnection`]s as they're accepted.
+///
+/// Created by [`Endpoint::incoming_typed`](crate::Endpoint::incoming_typed) or
+/// [`IncomingFramed::typed`]. Every connection gets a clone of the codec.
+pub struct IncomingTyped<T, C> {
+    inner: IncomingFramed,
+    codec: C,
+    _marker: PhantomData<fn(T) -> T>,
+}
+
+impl<T, C> IncomingTyped<T, C>
+where
+    T: Serialize + DeserializeOwned,
+    C: Codec + Clone,
+{
+    pub(crate) fn new(inner: IncomingFramed, codec: C) -> Self {
+        Self {
+            inner,
+            codec,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Waits for the next incoming connection.
+    ///
+    /// Fails with [`io::ErrorKind::NotConnected`] once the stream was shut down.
+    pub async fn accept(&mut self) -> io::Result<TypedConnection<T, C>> {
+        let conn = self.inner.accept().await?;
+        Ok(TypedConnection::from_framed(conn, self.codec.clone()))
+    }
+
+    /// Returns a reference to the underlying stream of framed connections.
+    pub fn get_ref(&self) -> &IncomingFramed {
+        &self.inner
+    }
+
+    /// Returns a mutable reference to the underlying stream of framed connections.
+    pub fn get_mut(&mut self) -> &mut IncomingFramed {
+        &mut self.inner
+    }
+
+    /// Consumes the typed layer, returning the underlying stream of framed connections.
+    pub fn into_inner(self) -> IncomingFramed {
+        self.inner
+    }
+}
+
+impl<T, C: fmt::Debug> fmt::Debug for IncomingTyped<T, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IncomingTyped")
+            .field("inner", &self.inner)
+            .field("codec", &self.codec)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T, C> Stream for IncomingTyped<T, C>
+where
+    T: Serialize + DeserializeOwned,
+    C: Codec + Clone,
+{
+    type Item = io::Result<TypedConnection<T, C>>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let conn = ready!(Pin::new(&mut this.inner).poll_next(cx));
+        Poll::Ready(conn.map(|conn| Ok(TypedConnection::from_framed(conn?, this.codec.clone()))))
+    }
+}
+
+/// Bytes of scratch space the serializer keeps inline before allocating.
+#[cfg(feature = "rkyv")]
+const SCRATCH_SPACE: usize = 256;
+
+#[cfg(feature = "rkyv")]
+enum ArchiveBuffer {
+    Frame(Bytes),
+    Aligned(rkyv::AlignedVec),
+}
+
+#[cfg(feature = "rkyv")]
+impl ArchiveBuffer {
+    fn as_slice(&self) -> &[u8] {
+        match self {
+            Self::Frame(frame) => frame,
+            Self::Aligned(aligned) => aligned,
+        }
+    }
+}
+
+/// A message received by an [`ArchivedConnection`], read in place from the received frame.
+///
+/// Dereferences to the archived form of `T`. The frame is used directly if it happens to be
+/// aligned for rkyv and copied into an aligned buffer otherwise, but the message is never
+/// deserialized.
+///
+/// Requires the `rkyv` feature.
+#[cfg(feature = "rkyv")]
+pub struct ArchivedMessage<T: rkyv::Archive> {
+    buffer: ArchiveBuffer,
+    _marker: PhantomData<fn() -> T>,
+}
+
+#[cfg(feature = "rkyv")]
+impl<T> ArchivedMessage<T>
+where
+    T: rkyv::Archive,
+    T::Archived: for<'a> rkyv::CheckBytes<rkyv::validation::validators::DefaultValidator<'a>>,
+{
+    /// Validates the archive in `frame`, so it can be accessed without checks afterwards.
+    fn new(frame: Bytes) -> io::Result<Self> {
+        let buffer = if frame.as_ptr() as usize % rkyv::AlignedVec::ALIGNMENT == 0 {
+            ArchiveBuffer::Frame(frame)
+        } else {
+            let mut aligned = rkyv::AlignedVec::with_capacity(frame.len());
+            aligned.extend_from_slice(&frame);
+            ArchiveBuffer::Aligned(aligned)
+        };
+        // The error type depends on `T` and isn't guaranteed to be printable
+        rkyv::check_archived_root::<T>(buffer.as_slice())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid rkyv archive"))?;
+        Ok(Self {
+            buffer,
+            _marker: PhantomData,
+        })
+    }
+}
+
+#[cfg(feature = "rkyv")]
+impl<T: rkyv::Archive> std::ops::Deref for ArchivedMessage<T> {
+    type Target = T::Archived;
+
+    fn deref(&self) -> &T::Archived {
+        // Safety: the archive was validated when the message was received, and the buffer isn't
+        // modified afterwards
+        unsafe { rkyv::archived_root::<T>(self.buffer.as_slice()) }
+    }
+}
+
+#[cfg(feature = "rkyv")]
+impl<T> fmt::Debug for ArchivedMessage<T>
+where
+    T: rkyv::Archive,
+    T::Archived: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// A connection that sends messages of type `T` archived with rkyv and receives them as
+/// [`ArchivedMessage`]s.
+///
+/// Meant for high-rate traffic like telemetry, where deserializing every message would cost more
+/// than reading the fields that are actually needed. Both sides have to use the same definition
+/// of `T`, since archives carry no schema.
+///
+/// Requires the `rkyv` feature.
+#[cfg(feature = "rkyv")]
+pub struct ArchivedConnection<T> {
+    inner: FramedConnection,
+    _marker: PhantomData<fn(T) -> T>,
+}
+
+#[cfg(feature = "rkyv")]
+impl<T> ArchivedConnection<T>
+where
+    T: rkyv::Serialize<rkyv::ser::serializers::AllocSerializer<SCRATCH_SPACE>>,
+    T::Archived: for<'a> rkyv::CheckBytes<rkyv::validation::validators::DefaultValidator<'a>>,
+{
+    /// Wraps a connection with the default framing.
+    pub fn new(conn: Connection) -> Self {
+        Self::from_framed(FramedConnection::new(conn))
+    }
+
+    /// Wraps an already framed connection, keeping its maximum frame length.
+    pub fn from_framed(inner: FramedConnection) -> Self {
+        Self {
+            inner,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Archives and sends a message.
+    ///
+    /// Messages that can't be archived fail with [`InvalidInput`](io::ErrorKind::InvalidInput)
+    /// without sending anything.
+    pub async fn send(&mut self, msg: &T) -> io::Result<()> {
+        let archive = rkyv::to_bytes::<_, SCRATCH_SPACE>(msg)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))?;
+        self.inner.send(Bytes::copy_from_slice(&archive)).await
+    }
+
+    /// Receives and validates the next message, or `None` once the peer closed the connection.
+    ///
+    /// Frames that don't hold a valid archive of `T` fail with
+    /// [`InvalidData`](io::ErrorKind::InvalidData). The connection stays usable, so the caller
+    /// decides whether to skip the message or give up.
+    pub async fn recv(&mut self) -> Option<io::Result<ArchivedMessage<T>>> {
+        let frame = match self.inner.recv().await? {
+            Ok(frame) => frame,
+            Err(e) => return Some(Err(e)),
+        };
+        Some(ArchivedMessage::new(frame))
+    }
+
+    /// Returns a reference to the underlying framed connection.
+    pub fn get_ref(&self) -> &FramedConnection {
+        &self.inner
+    }
+
+    /// Consumes the archived layer, returning the underlying framed connection.
+    pub fn into_inner(self) -> FramedConnection {
+        self.inner
+    }
+}
+
+#[cfg(feature = "rkyv")]
+impl<T> fmt::Debug for ArchivedConnection<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArchivedConnection")
+            .field("inner", &self.inner)
+            .finish_non_exhaustive()
+    }
+}
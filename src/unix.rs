@@ -1,27 +1,53 @@
+use std::collections::VecDeque;
 use std::env::temp_dir;
 use std::ffi::CString;
 use std::fs;
 use std::io;
+use std::mem;
+use std::num::NonZeroUsize;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd};
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, MetadataExt, OpenOptionsExt};
 use std::path::{Path, PathBuf};
-use std::pin::Pin;
+use std::ptr;
+use std::sync::Arc;
 use std::task::{Context, Poll};
 
-use futures::Stream;
 use libc::chmod;
+#[cfg(feature = "notify")]
+use notify::{RecursiveMode, Watcher};
+use tokio::io::Interest;
 use tokio::net::{UnixListener, UnixStream};
+#[cfg(feature = "notify")]
+use tokio::sync::mpsc;
 use tracing::trace;
 
-use crate::{IntoIpcPath, OnConflict, ServerId};
+use crate::{
+    IntoIpcPath, OnConflict, OnSocketRemoved, PeerCredentials, ServerId, ServerIdentity,
+    SocketRemoved,
+};
 
 pub(crate) struct SecurityAttributes {
     // read/write permissions for owner, group and others in unix octal.
     mode: Option<u16>,
+    // reject peers that don't run as the same user as the server
+    same_user_only: bool,
+    // user and group the socket file is handed over to after bind
+    owner: Option<u32>,
+    group: Option<u32>,
 }
 
 impl SecurityAttributes {
     fn apply_permissions(&self, path: &str) -> io::Result<()> {
+        let path = CString::new(path)?;
+        if self.owner.is_some() || self.group.is_some() {
+            // -1 leaves the owner or group unchanged
+            let owner = self.owner.unwrap_or(u32::MAX);
+            let group = self.group.unwrap_or(u32::MAX);
+            if unsafe { libc::chown(path.as_ptr(), owner, group) } == -1 {
+                return Err(io::Error::last_os_error());
+            }
+        }
         if let Some(mode) = self.mode {
-            let path = CString::new(path)?;
             // mode_t doesn't need into() on mac but does on linux
             #[allow(clippy::useless_conversion)]
             if unsafe { chmod(path.as_ptr(), mode.into()) } == -1 {
@@ -33,7 +59,12 @@ impl SecurityAttributes {
     }
 
     pub(crate) fn empty() -> Self {
-        Self { mode: Some(0o600) }
+        Self {
+            mode: Some(0o600),
+            same_user_only: false,
+            owner: None,
+            group: None,
+        }
     }
 
     pub(crate) fn allow_everyone_connect(mut self) -> io::Result<Self> {
@@ -46,8 +77,62 @@ impl SecurityAttributes {
         Ok(self)
     }
 
+    pub(crate) fn set_owner(mut self, uid: u32) -> io::Result<Self> {
+        self.owner = Some(uid);
+        Ok(self)
+    }
+
+    pub(crate) fn set_group(mut self, gid: u32) -> io::Result<Self> {
+        self.group = Some(gid);
+        Ok(self)
+    }
+
     pub(crate) fn allow_everyone_create() -> io::Result<Self> {
-        Ok(Self { mode: None })
+        Ok(Self {
+            mode: None,
+            same_user_only: false,
+            owner: None,
+            group: None,
+        })
+    }
+
+    pub(crate) fn same_user_only() -> io::Result<Self> {
+        Ok(Self {
+            mode: Some(0o600),
+            same_user_only: true,
+            owner: None,
+            group: None,
+        })
+    }
+}
+
+/// Looks up the ID of the group called `name` in the system's group database.
+pub(crate) fn group_id(name: &str) -> io::Result<u32> {
+    let c_name = CString::new(name)?;
+    let mut group = unsafe { mem::zeroed::<libc::group>() };
+    let mut buf = vec![0 as libc::c_char; 1024];
+    let mut result = ptr::null_mut();
+    loop {
+        let err = unsafe {
+            libc::getgrnam_r(
+                c_name.as_ptr(),
+                &mut group,
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        match err {
+            0 if result.is_null() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("No group named {name:?}"),
+                ))
+            }
+            0 => return Ok(group.gr_gid),
+            libc::ERANGE => buf.resize(buf.len() * 2, 0),
+            err => return Err(io::Error::from_raw_os_error(err)),
+        }
     }
 }
 
@@ -57,11 +142,11 @@ where
 {
     pub(crate) fn into_ipc_path(self) -> io::Result<PathBuf> {
         let sock_name = format!("{}.sock", self.id.into());
-        let path = self
-            .parent_folder
-            .or_else(dirs::runtime_dir)
-            .unwrap_or_else(temp_dir)
-            .join(sock_name);
+        let parent_folder = match self.parent_folder {
+            Some(folder) => folder,
+            None => default_folder()?,
+        };
+        let path = parent_folder.join(sock_name);
 
         if let Some(parent) = path.parent() {
             fs::create_dir_all(parent)?;
@@ -70,34 +155,295 @@ where
     }
 }
 
+const STICKY_BIT: u32 = 0o1000;
+
+/// Folder used for sockets and other IPC files when no parent folder is given explicitly.
+pub(crate) fn default_folder() -> io::Result<PathBuf> {
+    match dirs::runtime_dir() {
+        Some(folder) => Ok(folder),
+        None => secure_temp_dir(),
+    }
+}
+
+/// Returns a per-user folder inside the temp dir that only the current user can access.
+///
+/// The temp dir is usually world-writable, so placing sockets there directly lets other users
+/// squat on the socket path or replace it.
+fn secure_temp_dir() -> io::Result<PathBuf> {
+    let temp = temp_dir();
+    let temp_mode = fs::metadata(&temp)?.mode();
+    if temp_mode & 0o002 != 0 && temp_mode & STICKY_BIT == 0 {
+        return Err(insecure_folder(
+            &temp,
+            "it is world-writable without the sticky bit set",
+        ));
+    }
+
+    let uid = unsafe { libc::geteuid() };
+    let folder = temp.join(format!("tokio-ipc-{uid}"));
+    match fs::DirBuilder::new().mode(0o700).create(&folder) {
+        Ok(()) => {}
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+        Err(e) => return Err(e),
+    }
+
+    // Don't follow symlinks, someone else may have created the folder first
+    let metadata = fs::symlink_metadata(&folder)?;
+    if !metadata.is_dir() {
+        return Err(insecure_folder(&folder, "it is not a directory"));
+    }
+    if metadata.uid() != uid {
+        return Err(insecure_folder(&folder, "it is owned by another user"));
+    }
+    if metadata.mode() & 0o077 != 0 {
+        return Err(insecure_folder(&folder, "it is accessible by other users"));
+    }
+
+    Ok(folder)
+}
+
+pub(crate) fn ensure_secure_parent(path: &Path) -> io::Result<()> {
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    let metadata = fs::metadata(parent)?;
+    // The owner can always change the permissions, so it has to be trusted as well
+    let owner = metadata.uid();
+    if owner != 0 && owner != unsafe { libc::geteuid() } {
+        return Err(insecure_folder(parent, "it is owned by another user"));
+    }
+    let mode = metadata.mode();
+    if mode & 0o022 != 0 && mode & STICKY_BIT == 0 {
+        return Err(insecure_folder(parent, "it is writable by other users"));
+    }
+    Ok(())
+}
+
+fn expected_uid(identity: ServerIdentity) -> u32 {
+    match identity {
+        ServerIdentity::CurrentUser => unsafe { libc::geteuid() },
+        ServerIdentity::System => 0,
+        ServerIdentity::Uid(uid) => uid,
+    }
+}
+
+fn verify_socket_owner(path: &Path, identity: ServerIdentity) -> io::Result<()> {
+    let owner = fs::metadata(path)?.uid();
+    let expected = expected_uid(identity);
+    if owner != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!(
+                "Socket {path:?} is owned by uid {owner} instead of the expected uid {expected}"
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn verify_peer(stream: &UnixStream, path: &Path, identity: ServerIdentity) -> io::Result<()> {
+    let uid = stream.peer_cred()?.uid();
+    let expected = expected_uid(identity);
+    if uid != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("Server at {path:?} runs as uid {uid} instead of the expected uid {expected}"),
+        ));
+    }
+    Ok(())
+}
+
+/// Takes the lock that makes sure only one server runs on `path`.
+fn lock_instance(path: &Path) -> io::Result<fs::File> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .mode(0o600)
+        .open(lock_path)?;
+    if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } == -1 {
+        let e = io::Error::last_os_error();
+        if e.kind() == io::ErrorKind::WouldBlock {
+            return Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("Another server is already running on {path:?}"),
+            ));
+        }
+        return Err(e);
+    }
+    Ok(file)
+}
+
+/// Makes way for binding a socket to `path` according to `on_conflict`.
+///
+/// `connect` is used to probe whether a server is still listening on an existing socket.
+pub(crate) fn resolve_conflict(
+    path: &Path,
+    on_conflict: OnConflict,
+    connect: impl FnOnce(&Path) -> io::Result<()>,
+) -> io::Result<()> {
+    let Ok(metadata) = fs::metadata(path) else {
+        return Ok(());
+    };
+    let already_exists = || {
+        io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("Unable to bind to {path:?} because the path already exists"),
+        )
+    };
+    match on_conflict {
+        OnConflict::Error => Err(already_exists()),
+        OnConflict::Overwrite => fs::remove_file(path),
+        OnConflict::OverwriteIfStale if !metadata.file_type().is_socket() => Err(already_exists()),
+        OnConflict::OverwriteIfStale => match connect(path) {
+            Ok(()) => Err(io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("Unable to bind to {path:?} because a server is listening on it"),
+            )),
+            Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+                trace!("Removing stale socket file at {path:?}");
+                fs::remove_file(path)
+            }
+            Err(e) => Err(e),
+        },
+    }
+}
+
+fn insecure_folder(folder: &Path, reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::PermissionDenied,
+        format!("Refusing to place socket in {folder:?} because {reason}"),
+    )
+}
+
 /// Endpoint options implementation for unix systems
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, Default)]
 pub struct EndpointOptions {
     /// How to proceed when the socket path already exists
     pub on_conflict: OnConflict,
+    /// Allow binding even if the socket's parent folder can be modified by other users.
+    ///
+    /// By default, binding fails in that case since anyone with write access to the folder can
+    /// unlink the socket and replace it with their own. Folders with the sticky bit set (like
+    /// `/tmp`) are considered safe. Binding also fails if the folder is owned by anyone but the
+    /// current user or root, since its owner can make it writable at any time.
+    pub allow_insecure_folder: bool,
+    /// Verify that the socket is owned by the expected user before connecting to it, and that
+    /// the server process runs as that user once connected.
+    ///
+    /// Only used by [`Endpoint::connect`](crate::Endpoint::connect).
+    pub server_identity: Option<ServerIdentity>,
+    /// Maximum number of pending connections to accept each time the listener becomes ready.
+    ///
+    /// Connections beyond the first are queued and handed out by subsequent polls without
+    /// another trip through the reactor, which improves throughput under connection storms.
+    /// Defaults to accepting a single connection per wakeup.
+    pub accept_batch_size: Option<NonZeroUsize>,
+    /// How to react when the socket file is deleted or replaced while the server is running.
+    ///
+    /// Only used by [`Endpoint::incoming`](crate::Endpoint::incoming).
+    pub on_socket_removed: OnSocketRemoved,
+    /// Maximum number of accepted connections that may be open at the same time.
+    ///
+    /// Once reached, [`IpcStream`](crate::IpcStream) stops accepting until one of the connections
+    /// is closed, leaving new clients waiting in the listen backlog. Defaults to no limit.
+    pub max_connections: Option<NonZeroUsize>,
+    /// Refuse to start a second server on the same path.
+    ///
+    /// The server holds an advisory lock on a `.lock` file next to the socket for as long as the
+    /// endpoint and its [`IpcStream`](crate::IpcStream) are alive, and
+    /// [`Endpoint::new`](crate::Endpoint::new) fails with [`io::ErrorKind::AddrInUse`] while
+    /// another server holds it. Conflicts are resolved only once the lock is taken, so this also
+    /// makes [`OnConflict::Overwrite`] safe to use. The lock file itself is left in place. Named
+    /// pipes on Windows always behave this way.
+    pub single_instance: bool,
+    /// Bind the socket in a private temporary folder and move it into place only once its
+    /// permissions are applied.
+    ///
+    /// Otherwise the socket briefly exists with the default permissions of the process between
+    /// binding it and applying the [`SecurityAttributes`](crate::SecurityAttributes), and clients
+    /// can connect in that window. The folder is created next to the socket, so the parent
+    /// folder has to be writable and the temporary path is slightly longer than the socket path.
+    pub atomic_bind: bool,
+    /// Size of the kernel send buffer of each connection (`SO_SNDBUF`), in bytes.
+    ///
+    /// Applies to accepted connections and to connections made with
+    /// [`Endpoint::connect`](crate::Endpoint::connect). Linux doubles the value to make room for
+    /// bookkeeping and caps it at `net.core.wmem_max`. Defaults to the system default.
+    pub send_buffer_size: Option<usize>,
+    /// Size of the kernel receive buffer of each connection (`SO_RCVBUF`), in bytes.
+    ///
+    /// Applies like `send_buffer_size`, capped at `net.core.rmem_max` on Linux.
+    pub recv_buffer_size: Option<usize>,
+}
+
+/// Kernel buffer sizes applied to connections, `None` keeping the system default
+#[derive(Clone, Copy, Default)]
+struct BufferSizes {
+    send: Option<usize>,
+    recv: Option<usize>,
+}
+
+impl BufferSizes {
+    fn from_options(options: Option<EndpointOptions>) -> Self {
+        Self {
+            send: options.and_then(|options| options.send_buffer_size),
+            recv: options.and_then(|options| options.recv_buffer_size),
+        }
+    }
+
+    fn apply(&self, stream: &UnixStream) -> io::Result<()> {
+        if let Some(size) = self.send {
+            set_send_buffer_size(stream, size)?;
+        }
+        if let Some(size) = self.recv {
+            set_recv_buffer_size(stream, size)?;
+        }
+        Ok(())
+    }
 }
 
 /// Endpoint implementation for unix systems
 pub(crate) struct Endpoint {
-    path: PathBuf,
+    path: Arc<Path>,
     security_attributes: SecurityAttributes,
+    allow_insecure_folder: bool,
+    accept_batch_size: usize,
+    on_socket_removed: OnSocketRemoved,
+    max_connections: Option<NonZeroUsize>,
+    instance_lock: Option<fs::File>,
+    atomic_bind: bool,
+    buffer_sizes: BufferSizes,
 }
 
 impl Endpoint {
-    /// Inner platform-dependant state of the endpoint
-    pub(crate) fn inner(&self) -> io::Result<UnixListener> {
-        UnixListener::bind(&self.path)
-    }
-
     pub(crate) fn incoming(self) -> io::Result<IpcStream> {
-        let listener = self.inner()?;
-        // the call to bind in `inner()` creates the file
-        // `apply_permission()` will set the file permissions.
-        self.security_attributes
-            .apply_permissions(&self.path.to_string_lossy())?;
+        if !self.allow_insecure_folder {
+            ensure_secure_parent(&self.path)?;
+        }
+        let listener = bind(&self.path, &self.security_attributes, self.atomic_bind)?;
+        let same_user_only = self.security_attributes.same_user_only;
+        let removal_watch = match self.on_socket_removed {
+            OnSocketRemoved::Ignore => None,
+            OnSocketRemoved::Rebind => Some(RemovalWatch::new(
+                &self.path,
+                Some(self.security_attributes),
+                self.atomic_bind,
+            )?),
+            OnSocketRemoved::Error => Some(RemovalWatch::new(&self.path, None, false)?),
+        };
         Ok(IpcStream {
-            path: Some(self.path),
+            socket_file: SocketFile(Some(self.path)),
             listener,
+            same_user_only,
+            accept_batch_size: self.accept_batch_size,
+            pending: VecDeque::new(),
+            removal_watch,
+            _instance_lock: self.instance_lock,
+            buffer_sizes: self.buffer_sizes,
         })
     }
 
@@ -106,43 +452,181 @@ impl Endpoint {
         self
     }
 
-    pub(crate) async fn connect(path: impl IntoIpcPath, _options: Option<EndpointOptions>) -> io::Result<Connection> {
-        UnixStream::connect(path.into_ipc_path()?).await
+    pub(crate) async fn connect(
+        path: &Path,
+        options: Option<EndpointOptions>,
+    ) -> io::Result<Connection> {
+        let identity = options.and_then(|options| options.server_identity);
+        if let Some(identity) = identity {
+            verify_socket_owner(path, identity)?;
+        }
+        let stream = UnixStream::connect(path).await?;
+        if let Some(identity) = identity {
+            // The socket file may have been replaced since it was checked
+            verify_peer(&stream, path, identity)?;
+        }
+        BufferSizes::from_options(options).apply(&stream)?;
+        Ok(stream)
     }
 
     pub(crate) fn path(&self) -> &Path {
         &self.path
     }
 
+    pub(crate) fn max_connections(&self) -> Option<NonZeroUsize> {
+        self.max_connections
+    }
+
     pub(crate) fn new(
         endpoint: impl IntoIpcPath,
         options: Option<EndpointOptions>,
     ) -> io::Result<Self> {
         let path = endpoint.into_ipc_path()?;
-        if Path::new(&path).exists() {
-            match options {
-                None => {}
-                Some(options) => match options.on_conflict {
-                    OnConflict::Error => {
-                        return Err(io::Error::new(
-                            io::ErrorKind::AlreadyExists,
-                            format!("Unable to bind to {path:?} because the path already exists"),
-                        ));
-                    }
-                    OnConflict::Overwrite => {
-                        fs::remove_file(&path)?;
-                    }
-                },
-            }
+        let instance_lock = match options {
+            Some(options) if options.single_instance => Some(lock_instance(&path)?),
+            _ => None,
+        };
+        if let Some(options) = options {
+            resolve_conflict(&path, options.on_conflict, |path| {
+                std::os::unix::net::UnixStream::connect(path).map(drop)
+            })?;
         }
 
         Ok(Self {
-            path,
+            path: path.into(),
             security_attributes: SecurityAttributes::empty(),
+            allow_insecure_folder: options.is_some_and(|options| options.allow_insecure_folder),
+            accept_batch_size: options
+                .and_then(|options| options.accept_batch_size)
+                .map_or(1, NonZeroUsize::get),
+            on_socket_removed: options
+                .map_or(OnSocketRemoved::Ignore, |options| options.on_socket_removed),
+            max_connections: options.and_then(|options| options.max_connections),
+            instance_lock,
+            atomic_bind: options.is_some_and(|options| options.atomic_bind),
+            buffer_sizes: BufferSizes::from_options(options),
         })
     }
 }
 
+/// Binds a listener at `path` and applies the security attributes to the socket file.
+fn bind(
+    path: &Path,
+    security_attributes: &SecurityAttributes,
+    atomic: bool,
+) -> io::Result<UnixListener> {
+    if atomic {
+        return bind_atomically(path, security_attributes);
+    }
+    let listener = UnixListener::bind(path)?;
+    // bind creates the file, the permissions can only be set afterwards
+    security_attributes.apply_permissions(&path.to_string_lossy())?;
+    Ok(listener)
+}
+
+/// Binds the socket inside a private folder next to `path` and links it into place once its
+/// permissions are applied, so it's never reachable with the default ones.
+fn bind_atomically(
+    path: &Path,
+    security_attributes: &SecurityAttributes,
+) -> io::Result<UnixListener> {
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("Invalid socket path {path:?}"),
+        ));
+    };
+    let mut bytes = [0; 8];
+    getrandom::getrandom(&mut bytes)?;
+    let suffix: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    let staging = parent.join(format!(".tokio-ipc-{suffix}"));
+    fs::DirBuilder::new().mode(0o700).create(&staging)?;
+
+    let staged = staging.join(name);
+    let result = UnixListener::bind(&staged).and_then(|listener| {
+        security_attributes.apply_permissions(&staged.to_string_lossy())?;
+        // Unlike renaming, linking doesn't replace a file that took the path in the meantime
+        fs::hard_link(&staged, path).map_err(|e| match e.kind() {
+            io::ErrorKind::AlreadyExists => io::Error::new(
+                io::ErrorKind::AddrInUse,
+                format!("Unable to bind to {path:?} because the path already exists"),
+            ),
+            _ => e,
+        })?;
+        Ok(listener)
+    });
+    // The listener stays bound to the socket through the link
+    if let Err(e) = fs::remove_dir_all(&staging) {
+        trace!("Failed to remove staging folder {staging:?}: {e}");
+    }
+    result
+}
+
+pub(crate) fn set_cloexec(fd: RawFd, cloexec: bool) -> io::Result<()> {
+    let flags = if cloexec { libc::FD_CLOEXEC } else { 0 };
+    match unsafe { libc::fcntl(fd, libc::F_SETFD, flags) } {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+pub(crate) async fn pair() -> io::Result<(Connection, Connection)> {
+    UnixStream::pair()
+}
+
+// How often the socket file is checked when filesystem notifications aren't available
+#[cfg(not(feature = "notify"))]
+const POLL_INTERVAL: std::time::Duration = std::time::Duration::from_millis(100);
+
+#[cfg(feature = "notify")]
+pub(crate) async fn wait_for_path(path: &Path) -> io::Result<()> {
+    let parent = path
+        .parent()
+        .filter(|parent| !parent.as_os_str().is_empty())
+        .unwrap_or_else(|| Path::new("."));
+
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let _ = tx.send(event);
+    })
+    .map_err(watch_error)?;
+    watcher
+        .watch(parent, RecursiveMode::NonRecursive)
+        .map_err(watch_error)?;
+
+    // Check after the watch is registered so a socket created in between isn't missed
+    while !path.exists() {
+        match rx.recv().await {
+            Some(Ok(event)) => trace!("Received file event while waiting for socket: {event:?}"),
+            Some(Err(e)) => return Err(watch_error(e)),
+            None => {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    "File watcher stopped unexpectedly",
+                ));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(not(feature = "notify"))]
+pub(crate) async fn wait_for_path(path: &Path) -> io::Result<()> {
+    while !path.exists() {
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+    Ok(())
+}
+
+#[cfg(feature = "notify")]
+fn watch_error(error: notify::Error) -> io::Error {
+    match error.kind {
+        notify::ErrorKind::Io(e) => e,
+        _ => io::Error::new(io::ErrorKind::Other, error),
+    }
+}
+
 pub(crate) async fn from_std_stream(
     stream: std::os::unix::net::UnixStream,
 ) -> io::Result<Connection> {
@@ -151,8 +635,94 @@ pub(crate) async fn from_std_stream(
 }
 
 pub(crate) struct IpcStream {
-    path: Option<PathBuf>,
+    socket_file: SocketFile,
     listener: UnixListener,
+    same_user_only: bool,
+    accept_batch_size: usize,
+    // connections accepted in the current batch that haven't been handed out yet
+    pending: VecDeque<io::Result<UnixStream>>,
+    removal_watch: Option<RemovalWatch>,
+    _instance_lock: Option<fs::File>,
+    buffer_sizes: BufferSizes,
+}
+
+/// Watches the socket file for being deleted or replaced by someone else
+struct RemovalWatch {
+    #[cfg(feature = "notify")]
+    _watcher: notify::RecommendedWatcher,
+    #[cfg(feature = "notify")]
+    events: mpsc::UnboundedReceiver<notify::Result<notify::Event>>,
+    // checks the socket file periodically instead of being told about changes
+    #[cfg(not(feature = "notify"))]
+    interval: tokio::time::Interval,
+    // device and inode of the socket this server bound
+    socket_id: (u64, u64),
+    // set to rebind the socket when it's gone, surfacing an error otherwise
+    rebind_with: Option<SecurityAttributes>,
+    atomic_bind: bool,
+    // set once the socket is gone without being rebound, failing every further accept
+    removed: bool,
+}
+
+impl RemovalWatch {
+    fn new(
+        path: &Path,
+        rebind_with: Option<SecurityAttributes>,
+        atomic_bind: bool,
+    ) -> io::Result<Self> {
+        #[cfg(feature = "notify")]
+        let (tx, events) = mpsc::unbounded_channel();
+        #[cfg(feature = "notify")]
+        let mut watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+                let _ = tx.send(event);
+            })
+            .map_err(watch_error)?;
+        #[cfg(feature = "notify")]
+        if let Some(parent) = path.parent() {
+            watcher
+                .watch(parent, RecursiveMode::NonRecursive)
+                .map_err(watch_error)?;
+        }
+        Ok(Self {
+            #[cfg(feature = "notify")]
+            _watcher: watcher,
+            #[cfg(feature = "notify")]
+            events,
+            #[cfg(not(feature = "notify"))]
+            interval: tokio::time::interval(POLL_INTERVAL),
+            socket_id: socket_id(path)?,
+            rebind_with,
+            atomic_bind,
+            removed: false,
+        })
+    }
+
+    /// Whether the folder of the socket may have changed since the last call.
+    #[cfg(feature = "notify")]
+    fn poll_changed(&mut self, cx: &mut Context<'_>) -> io::Result<bool> {
+        let mut changed = false;
+        while let Poll::Ready(Some(event)) = self.events.poll_recv(cx) {
+            event.map_err(watch_error)?;
+            changed = true;
+        }
+        Ok(changed)
+    }
+
+    /// Whether the folder of the socket may have changed since the last call.
+    #[cfg(not(feature = "notify"))]
+    fn poll_changed(&mut self, cx: &mut Context<'_>) -> io::Result<bool> {
+        let mut changed = false;
+        while self.interval.poll_tick(cx).is_ready() {
+            changed = true;
+        }
+        Ok(changed)
+    }
+}
+
+fn socket_id(path: &Path) -> io::Result<(u64, u64)> {
+    let metadata = fs::symlink_metadata(path)?;
+    Ok((metadata.dev(), metadata.ino()))
 }
 
 impl IpcStream {
@@ -162,29 +732,290 @@ impl IpcStream {
         listener.set_nonblocking(true)?;
         let listener = UnixListener::from_std(listener)?;
         Ok(Self {
-            path: None,
+            socket_file: SocketFile(None),
             listener,
+            same_user_only: false,
+            accept_batch_size: 1,
+            pending: VecDeque::new(),
+            removal_watch: None,
+            _instance_lock: None,
+            buffer_sizes: BufferSizes::default(),
         })
     }
 }
 
+impl AsFd for IpcStream {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.listener.as_fd()
+    }
+}
+
+impl AsRawFd for IpcStream {
+    fn as_raw_fd(&self) -> RawFd {
+        self.listener.as_raw_fd()
+    }
+}
+
 pub(crate) type Connection = UnixStream;
+pub(crate) use tokio::net::unix::{OwnedReadHalf, OwnedWriteHalf};
 
-impl Stream for IpcStream {
-    type Item = io::Result<Connection>;
+impl IpcStream {
+    pub(crate) fn into_inner(self) -> UnixListener {
+        let Self {
+            mut socket_file,
+            listener,
+            ..
+        } = self;
+        // The listener is still bound, so the socket file has to outlive this
+        socket_file.0 = None;
+        listener
+    }
+
+    pub(crate) fn into_raw_parts(self) -> io::Result<(OwnedFd, Option<PathBuf>)> {
+        let Self {
+            mut socket_file,
+            listener,
+            ..
+        } = self;
+        let path = socket_file.0.take().map(|path| path.to_path_buf());
+        let listener = OwnedFd::from(listener.into_std()?);
+        // Keep the listener open across exec
+        set_cloexec(listener.as_raw_fd(), false)?;
+        Ok((listener, path))
+    }
 
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let this = Pin::into_inner(self);
-        match Pin::new(&mut this.listener).poll_accept(cx) {
-            Poll::Pending => Poll::Pending,
-            Poll::Ready(result) => Poll::Ready(Some(result.map(|(stream, _addr)| stream))),
+    pub(crate) fn local_path(&self) -> Option<Arc<Path>> {
+        match &self.socket_file.0 {
+            Some(path) => Some(path.clone()),
+            None => socket_path(self.listener.local_addr().ok()?).map(Arc::from),
         }
     }
+
+    pub(crate) fn from_raw_parts(listener: OwnedFd, path: Option<PathBuf>) -> io::Result<Self> {
+        set_cloexec(listener.as_raw_fd(), true)?;
+        let mut stream = Self::from_std_listener(listener.into())?;
+        stream.socket_file.0 = path.map(Arc::from);
+        Ok(stream)
+    }
+
+    /// Checks whether the socket file is still ours, rebinding it if configured to.
+    fn poll_socket_file(&mut self, cx: &mut Context<'_>) -> io::Result<()> {
+        let (Some(watch), Some(path)) = (&mut self.removal_watch, &self.socket_file.0) else {
+            return Ok(());
+        };
+
+        if watch.removed {
+            return Err(SocketRemoved {
+                path: path.to_path_buf(),
+            }
+            .into());
+        }
+        if !watch.poll_changed(cx)? || socket_id(path).ok() == Some(watch.socket_id) {
+            return Ok(());
+        }
+
+        let Some(security_attributes) = &watch.rebind_with else {
+            watch.removed = true;
+            return Err(SocketRemoved {
+                path: path.to_path_buf(),
+            }
+            .into());
+        };
+        // Whatever took the socket's place has to go to bind it again
+        if let Err(e) = fs::remove_file(path) {
+            if e.kind() != io::ErrorKind::NotFound {
+                return Err(e);
+            }
+        }
+        self.listener = bind(path, security_attributes, watch.atomic_bind)?;
+        watch.socket_id = socket_id(path)?;
+        trace!("Rebound socket file at: {:?}", path);
+        Ok(())
+    }
+
+    /// Accepts the next connection, accepting at most `limit` ahead of it in one go.
+    pub(crate) fn poll_accept(
+        &mut self,
+        cx: &mut Context<'_>,
+        limit: usize,
+    ) -> Poll<io::Result<Connection>> {
+        if let Some(result) = self.pending.pop_front() {
+            return Poll::Ready(result);
+        }
+        if let Err(e) = self.poll_socket_file(cx) {
+            return Poll::Ready(Err(e));
+        }
+        while self.pending.len() < self.accept_batch_size.min(limit) {
+            let result = match self.listener.poll_accept(cx) {
+                Poll::Ready(result) => result.map(|(stream, _addr)| stream),
+                Poll::Pending => break,
+            };
+            if matches!(&result, Ok(stream) if self.same_user_only && !is_same_user(stream)) {
+                continue;
+            }
+            let buffer_sizes = self.buffer_sizes;
+            let result = result.and_then(|stream| buffer_sizes.apply(&stream).map(|()| stream));
+            self.pending.push_back(result);
+        }
+        match self.pending.pop_front() {
+            Some(result) => Poll::Ready(result),
+            None => Poll::Pending,
+        }
+    }
+}
+
+pub(crate) async fn peek(stream: &UnixStream, buf: &mut [u8]) -> io::Result<usize> {
+    stream
+        .async_io(Interest::READABLE, || {
+            let fd = stream.as_raw_fd();
+            match unsafe { libc::recv(fd, buf.as_mut_ptr().cast(), buf.len(), libc::MSG_PEEK) } {
+                -1 => Err(io::Error::last_os_error()),
+                peeked => Ok(peeked as usize),
+            }
+        })
+        .await
+}
+
+/// Whether nothing can be read from `stream` yet, without waiting or consuming anything.
+///
+/// EOF, pending data and errors all count as not idle.
+pub(crate) fn is_idle(stream: &UnixStream) -> bool {
+    let mut byte = 0u8;
+    let peeked = unsafe {
+        libc::recv(
+            stream.as_raw_fd(),
+            (&mut byte as *mut u8).cast(),
+            1,
+            libc::MSG_PEEK | libc::MSG_DONTWAIT,
+        )
+    };
+    peeked == -1 && io::Error::last_os_error().kind() == io::ErrorKind::WouldBlock
+}
+
+fn socket_path(addr: tokio::net::unix::SocketAddr) -> Option<PathBuf> {
+    addr.as_pathname().map(Path::to_path_buf)
+}
+
+pub(crate) fn local_path(stream: &UnixStream) -> io::Result<Option<PathBuf>> {
+    Ok(socket_path(stream.local_addr()?))
+}
+
+pub(crate) fn peer_path(stream: &UnixStream) -> io::Result<Option<PathBuf>> {
+    Ok(socket_path(stream.peer_addr()?))
+}
+
+pub(crate) fn peer_credentials(stream: &UnixStream) -> io::Result<PeerCredentials> {
+    let cred = stream.peer_cred()?;
+    Ok(PeerCredentials {
+        uid: cred.uid(),
+        gid: cred.gid(),
+        pid: cred.pid().and_then(|pid| u32::try_from(pid).ok()),
+    })
+}
+
+fn socket_option(stream: &UnixStream, option: libc::c_int) -> io::Result<usize> {
+    let mut value: libc::c_int = 0;
+    let mut len = mem::size_of_val(&value) as libc::socklen_t;
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            ptr::addr_of_mut!(value).cast(),
+            &mut len,
+        )
+    };
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(usize::try_from(value).unwrap_or(0)),
+    }
+}
+
+fn set_socket_option(stream: &UnixStream, option: libc::c_int, value: usize) -> io::Result<()> {
+    let value = libc::c_int::try_from(value).unwrap_or(libc::c_int::MAX);
+    let result = unsafe {
+        libc::setsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            option,
+            ptr::addr_of!(value).cast(),
+            mem::size_of_val(&value) as libc::socklen_t,
+        )
+    };
+    match result {
+        -1 => Err(io::Error::last_os_error()),
+        _ => Ok(()),
+    }
+}
+
+pub(crate) fn send_buffer_size(stream: &UnixStream) -> io::Result<usize> {
+    socket_option(stream, libc::SO_SNDBUF)
+}
+
+pub(crate) fn set_send_buffer_size(stream: &UnixStream, size: usize) -> io::Result<()> {
+    set_socket_option(stream, libc::SO_SNDBUF, size)
+}
+
+pub(crate) fn recv_buffer_size(stream: &UnixStream) -> io::Result<usize> {
+    socket_option(stream, libc::SO_RCVBUF)
+}
+
+pub(crate) fn set_recv_buffer_size(stream: &UnixStream, size: usize) -> io::Result<()> {
+    set_socket_option(stream, libc::SO_RCVBUF, size)
 }
 
-impl Drop for IpcStream {
+#[cfg(target_os = "linux")]
+pub(crate) fn peer_security_context(stream: &UnixStream) -> io::Result<String> {
+    let mut buf = vec![0u8; 256];
+    loop {
+        let mut len = buf.len() as libc::socklen_t;
+        let result = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_PEERSEC,
+                buf.as_mut_ptr().cast(),
+                &mut len,
+            )
+        };
+        if result == 0 {
+            buf.truncate(len as usize);
+            break;
+        }
+        let e = io::Error::last_os_error();
+        // The kernel reports the size it needs
+        if e.raw_os_error() != Some(libc::ERANGE) || len as usize <= buf.len() {
+            return Err(e);
+        }
+        buf.resize(len as usize, 0);
+    }
+    // Some security modules include the terminating NUL, others don't
+    if buf.last() == Some(&0) {
+        buf.pop();
+    }
+    String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn is_same_user(stream: &UnixStream) -> bool {
+    match stream.peer_cred() {
+        Ok(cred) if cred.uid() == unsafe { libc::geteuid() } => true,
+        Ok(cred) => {
+            trace!("Rejected connection from uid {}", cred.uid());
+            false
+        }
+        Err(e) => {
+            trace!("Rejected connection with unknown peer credentials: {e:?}");
+            false
+        }
+    }
+}
+
+/// Removes the socket file once the listener is dropped
+struct SocketFile(Option<Arc<Path>>);
+
+impl Drop for SocketFile {
     fn drop(&mut self) {
-        if let Some(path) = &self.path {
+        if let Some(path) = &self.0 {
             if let Ok(()) = fs::remove_file(path) {
                 trace!("Removed socket file at: {:?}", path);
             }
@@ -1,39 +1,90 @@
+use std::ffi::OsString;
+use std::future::{poll_fn, Future};
+use std::num::NonZeroUsize;
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::os::windows::io::{
+    AsHandle, AsRawHandle, BorrowedHandle, FromRawHandle, IntoRawHandle, OwnedHandle, RawHandle,
+};
 use std::path::{Path, PathBuf};
 use std::pin::Pin;
-use std::task::{Context, Poll};
-use std::time::{Duration, Instant};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
 use std::{io, marker, mem, ptr};
 
-use futures::{Stream, StreamExt};
-use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::io::{AsyncRead, AsyncWrite, Interest, Ready};
 use tokio::net::windows::named_pipe;
+use tokio::time::Instant;
+use tracing::trace;
 use windows_sys::Win32::Foundation::{
-    LocalFree, ERROR_PIPE_BUSY, ERROR_SUCCESS, GENERIC_READ, GENERIC_WRITE, HLOCAL, PSID,
+    LocalFree, ERROR_ACCESS_DENIED, ERROR_FILE_NOT_FOUND, ERROR_INSUFFICIENT_BUFFER,
+    ERROR_PIPE_BUSY, ERROR_PIPE_LOCAL, ERROR_SEM_TIMEOUT, ERROR_SUCCESS, GENERIC_READ,
+    GENERIC_WRITE, HANDLE, HLOCAL, PSID,
 };
 use windows_sys::Win32::Security::Authorization::{
-    SetEntriesInAclW, ACCESS_MODE, EXPLICIT_ACCESS_W, SET_ACCESS, TRUSTEE_IS_SID,
+    ConvertSidToStringSidW, ConvertStringSecurityDescriptorToSecurityDescriptorW, SetEntriesInAclW,
+    ACCESS_MODE, EXPLICIT_ACCESS_W, SDDL_REVISION_1, SET_ACCESS, TRUSTEE_IS_SID,
     TRUSTEE_IS_WELL_KNOWN_GROUP, TRUSTEE_TYPE,
 };
 use windows_sys::Win32::Security::{
-    AllocateAndInitializeSid, FreeSid, InitializeSecurityDescriptor, SetSecurityDescriptorDacl,
-    ACL, PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES, SECURITY_DESCRIPTOR, SID_IDENTIFIER_AUTHORITY,
+    AllocateAndInitializeSid, EqualSid, FreeSid, GetTokenInformation, InitializeSecurityDescriptor,
+    IsWellKnownSid, RevertToSelf, SetSecurityDescriptorDacl, TokenUser, WinLocalSystemSid, ACL,
+    PSECURITY_DESCRIPTOR, SECURITY_ATTRIBUTES, SECURITY_DESCRIPTOR, SID_IDENTIFIER_AUTHORITY,
+    TOKEN_QUERY, TOKEN_USER,
+};
+use windows_sys::Win32::Storage::FileSystem::{
+    FileNameInfo, GetFileInformationByHandleEx, FILE_WRITE_DATA, SECURITY_IMPERSONATION,
 };
-use windows_sys::Win32::Storage::FileSystem::FILE_WRITE_DATA;
 use windows_sys::Win32::System::Memory::{LocalAlloc, LPTR};
+use windows_sys::Win32::System::Pipes::{
+    GetNamedPipeClientComputerNameW, GetNamedPipeClientProcessId, GetNamedPipeClientSessionId,
+    GetNamedPipeHandleStateW, GetNamedPipeInfo, GetNamedPipeServerProcessId,
+    GetNamedPipeServerSessionId, ImpersonateNamedPipeClient, PeekNamedPipe, WaitNamedPipeW,
+    PIPE_READMODE_MESSAGE, PIPE_SERVER_END,
+};
 use windows_sys::Win32::System::SystemServices::{
     SECURITY_DESCRIPTOR_REVISION, SECURITY_WORLD_RID,
 };
+use windows_sys::Win32::System::Threading::{
+    GetCurrentProcess, GetCurrentThread, OpenProcess, OpenProcessToken, OpenThreadToken,
+    PROCESS_QUERY_LIMITED_INFORMATION,
+};
+use windows_sys::core::PWSTR;
 
-use crate::{IntoIpcPath, ServerId};
+use crate::{IntoIpcPath, PeerCredentials, ServerId, ServerIdentity};
 
 pub use tokio::net::windows::named_pipe::PipeMode;
 
-enum NamedPipe {
+/// The named pipe behind a [`Connection`](crate::Connection), depending on which side it's on
+#[derive(Debug)]
+pub enum NamedPipe {
+    /// The server end of an accepted connection
     Server(named_pipe::NamedPipeServer),
+    /// The client end of a connection made with [`Endpoint::connect`](crate::Endpoint::connect)
     Client(named_pipe::NamedPipeClient),
 }
 
+impl AsHandle for NamedPipe {
+    fn as_handle(&self) -> BorrowedHandle<'_> {
+        match self {
+            Self::Client(c) => c.as_handle(),
+            Self::Server(s) => s.as_handle(),
+        }
+    }
+}
+
+impl AsRawHandle for NamedPipe {
+    fn as_raw_handle(&self) -> RawHandle {
+        match self {
+            Self::Client(c) => c.as_raw_handle(),
+            Self::Server(s) => s.as_raw_handle(),
+        }
+    }
+}
+
 const PIPE_AVAILABILITY_TIMEOUT: Duration = Duration::from_secs(5);
+const MAX_PIPE_WAIT_BACKOFF: Duration = Duration::from_millis(500);
+const DEFAULT_BUFFER_SIZE: u32 = 65536;
 
 impl<T> ServerId<T>
 where
@@ -41,78 +92,168 @@ where
 {
     pub(crate) fn into_ipc_path(self) -> io::Result<PathBuf> {
         Ok(PathBuf::from(format!(
-            r"\\.\pipe\{}",
+            r"\\{}\pipe\{}",
+            self.remote_host.as_deref().unwrap_or("."),
             self.id.into().replace('/', "\\")
         )))
     }
 }
 
+/// Folder used for files backing IPC helpers like broadcast groups and discovery.
+///
+/// The temp dir lives in the user's profile on Windows, so it's private to the current user.
+pub(crate) fn default_folder() -> io::Result<PathBuf> {
+    let folder = std::env::temp_dir().join("tokio-ipc");
+    std::fs::create_dir_all(&folder)?;
+    Ok(folder)
+}
+
 /// Endpoint options implementation for Windows systems
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug)]
 pub struct EndpointOptions {
     /// The pipe mode of a named pipe.
     pub pipe_mode: PipeMode,
+    /// Verify that the pipe server runs as the expected user after connecting to it.
+    ///
+    /// Only used by [`Endpoint::connect`](crate::Endpoint::connect).
+    pub server_identity: Option<ServerIdentity>,
+    /// Allow named pipe traffic across machines over SMB.
+    ///
+    /// For [`Endpoint::new`](crate::Endpoint::new), this lets clients on other machines connect
+    /// to the pipe. For [`Endpoint::connect`](crate::Endpoint::connect), this allows connecting to
+    /// `\\{host}\pipe\{name}` paths on other machines. Remote pipes are exposed to the network
+    /// and authenticated with the caller's Windows credentials, so this is off by default and
+    /// meant for things like admin tooling managing services on other machines.
+    pub allow_remote: bool,
+    /// Maximum number of accepted connections that may be open at the same time.
+    ///
+    /// Once reached, [`IpcStream`](crate::IpcStream) stops accepting until one of the connections
+    /// is closed, leaving new clients waiting for a free pipe instance. Defaults to no limit.
+    pub max_connections: Option<NonZeroUsize>,
+    /// Let the server act on behalf of this client through
+    /// [`Connection::impersonate_client`](crate::Connection::impersonate_client).
+    ///
+    /// By default, clients only allow the server to identify them, so impersonating lets the
+    /// server query the client's token but not access resources with it. Only used by
+    /// [`Endpoint::connect`](crate::Endpoint::connect).
+    pub allow_impersonation: bool,
+    /// Size of the buffer for data the server sends (`nOutBufferSize`), in bytes.
+    ///
+    /// The system treats it as a hint. Defaults to 64 KiB. Only used by
+    /// [`Endpoint::incoming`](crate::Endpoint::incoming), the server decides for both ends.
+    pub send_buffer_size: Option<usize>,
+    /// Size of the buffer for data the server receives (`nInBufferSize`), in bytes.
+    ///
+    /// The system treats it as a hint. Defaults to 64 KiB. Only used by
+    /// [`Endpoint::incoming`](crate::Endpoint::incoming), the server decides for both ends.
+    pub recv_buffer_size: Option<usize>,
+}
+
+impl Default for EndpointOptions {
+    fn default() -> Self {
+        Self {
+            pipe_mode: PipeMode::Byte,
+            server_identity: None,
+            allow_remote: false,
+            max_connections: None,
+            allow_impersonation: false,
+            send_buffer_size: None,
+            recv_buffer_size: None,
+        }
+    }
 }
 
 /// Endpoint implementation for Windows systems
 pub(crate) struct Endpoint {
-    path: PathBuf,
+    path: Arc<Path>,
     security_attributes: SecurityAttributes,
     created_listener: bool,
     mode: PipeMode,
+    allow_remote: bool,
+    max_connections: Option<NonZeroUsize>,
+    in_buffer_size: u32,
+    out_buffer_size: u32,
 }
 
 impl Endpoint {
     fn create_listener(&mut self) -> io::Result<named_pipe::NamedPipeServer> {
+        // The first instance is created with FILE_FLAG_FIRST_PIPE_INSTANCE, so a process that
+        // squats the name ahead of us can't receive our clients
         let server = unsafe {
             named_pipe::ServerOptions::new()
                 .first_pipe_instance(!self.created_listener)
                 .pipe_mode(self.mode)
-                .reject_remote_clients(true)
+                .reject_remote_clients(!self.allow_remote)
                 .access_inbound(true)
                 .access_outbound(true)
-                .in_buffer_size(65536)
-                .out_buffer_size(65536)
+                .in_buffer_size(self.in_buffer_size)
+                .out_buffer_size(self.out_buffer_size)
                 .create_with_security_attributes_raw(
-                    &self.path,
+                    &*self.path,
                     self.security_attributes.as_ptr().cast_mut().cast(),
                 )
-        }?;
+        };
+        let server = match server {
+            Err(e)
+                if !self.created_listener
+                    && e.raw_os_error() == Some(ERROR_ACCESS_DENIED as i32) =>
+            {
+                let path = &self.path;
+                return Err(io::Error::new(
+                    io::ErrorKind::AddrInUse,
+                    format!("Unable to create pipe {path:?} because it already exists"),
+                ));
+            }
+            server => server?,
+        };
         self.created_listener = true;
 
         Ok(server)
     }
 
     pub(crate) async fn connect(
-        path: impl IntoIpcPath,
+        path: &Path,
         options: Option<EndpointOptions>,
     ) -> io::Result<Connection> {
-        let path = path.into_ipc_path()?;
+        if is_remote_pipe(path) && !options.is_some_and(|options| options.allow_remote) {
+            return Err(io::Error::new(
+                io::ErrorKind::PermissionDenied,
+                format!("Refusing to connect to remote pipe {path:?} without allow_remote"),
+            ));
+        }
 
-        // There is not async equivalent of waiting for a named pipe in Windows,
-        // so we keep trying or sleeping for a bit, until we hit a timeout
+        // All instances being busy is common under load, so wait for one to free up for a bit.
+        // Tokio's clock is used so this plays along with `tokio::time::pause`.
         let attempt_start = Instant::now();
 
         let mut client_options = named_pipe::ClientOptions::new();
         if let Some(options) = options {
             client_options.pipe_mode(options.pipe_mode);
+            if options.allow_impersonation {
+                client_options.security_qos_flags(SECURITY_IMPERSONATION);
+            }
         }
 
         let client = loop {
-            match client_options.read(true).write(true).open(&path) {
+            match client_options.read(true).write(true).open(path) {
                 Ok(client) => break client,
                 Err(e) if e.raw_os_error() == Some(ERROR_PIPE_BUSY as i32) => {
-                    if attempt_start.elapsed() < PIPE_AVAILABILITY_TIMEOUT {
-                        tokio::time::sleep(Duration::from_millis(50)).await;
-                        continue;
-                    } else {
-                        return Err(e);
+                    let remaining =
+                        PIPE_AVAILABILITY_TIMEOUT.saturating_sub(attempt_start.elapsed());
+                    if remaining.is_zero() {
+                        return Err(pipe_wait_timeout(path));
                     }
+                    // Another client may still grab the instance first, so open it in a loop
+                    wait_for_pipe(path, remaining).await?;
                 }
                 Err(e) => return Err(e),
             }
         };
 
+        if let Some(identity) = options.and_then(|options| options.server_identity) {
+            verify_server_identity(&client, identity)?;
+        }
+
         Ok(Connection::wrap(NamedPipe::Client(client)))
     }
 
@@ -129,6 +270,10 @@ impl Endpoint {
         &self.path
     }
 
+    pub(crate) fn max_connections(&self) -> Option<NonZeroUsize> {
+        self.max_connections
+    }
+
     pub(crate) fn new(
         path: impl IntoIpcPath,
         options: Option<EndpointOptions>,
@@ -139,53 +284,561 @@ impl Endpoint {
         };
 
         Ok(Self {
-            path: path.into_ipc_path()?,
+            path: path.into_ipc_path()?.into(),
             security_attributes: SecurityAttributes::empty(),
             created_listener: false,
             mode,
+            allow_remote: options.is_some_and(|options| options.allow_remote),
+            max_connections: options.and_then(|options| options.max_connections),
+            in_buffer_size: buffer_size(options.and_then(|options| options.recv_buffer_size)),
+            out_buffer_size: buffer_size(options.and_then(|options| options.send_buffer_size)),
+        })
+    }
+}
+
+fn buffer_size(size: Option<usize>) -> u32 {
+    size.map_or(DEFAULT_BUFFER_SIZE, |size| {
+        u32::try_from(size).unwrap_or(u32::MAX)
+    })
+}
+
+pub(crate) async fn pair() -> io::Result<(Connection, Connection)> {
+    // Nobody else can connect first without knowing the name
+    let path = ServerId::randomized("tokio-ipc-pair")?.into_ipc_path()?;
+    let server = named_pipe::ServerOptions::new()
+        .first_pipe_instance(true)
+        .max_instances(1)
+        .reject_remote_clients(true)
+        .create(&path)?;
+    let client = named_pipe::ClientOptions::new().open(&path)?;
+    server.connect().await?;
+    Ok((
+        Connection::wrap(NamedPipe::Server(server)),
+        Connection::wrap(NamedPipe::Client(client)),
+    ))
+}
+
+pub(crate) async fn wait_for_pipe(path: &Path, timeout: Duration) -> io::Result<()> {
+    let name: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let deadline = Instant::now() + timeout;
+    let mut backoff = Duration::from_millis(10);
+
+    loop {
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(pipe_wait_timeout(path));
+        }
+        // A timeout of 0 means "use the server's default", so always wait for at least 1 ms
+        let wait_ms = remaining.as_millis().clamp(1, u128::from(u32::MAX - 1)) as u32;
+        let wait_name = name.clone();
+        let result = tokio::task::spawn_blocking(move || {
+            if unsafe { WaitNamedPipeW(wait_name.as_ptr(), wait_ms) } == 0 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
         })
+        .await
+        .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+
+        match result {
+            Ok(()) => return Ok(()),
+            // WaitNamedPipe fails immediately if no server has created the pipe yet
+            Err(e) if e.raw_os_error() == Some(ERROR_FILE_NOT_FOUND as i32) => {
+                tokio::time::sleep(backoff.min(remaining)).await;
+                backoff = (backoff * 2).min(MAX_PIPE_WAIT_BACKOFF);
+            }
+            Err(e) if e.raw_os_error() == Some(ERROR_SEM_TIMEOUT as i32) => {
+                return Err(pipe_wait_timeout(path));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Whether the pipe path points at another machine, like `\\host\pipe\name`
+fn is_remote_pipe(path: &Path) -> bool {
+    let path = path.to_string_lossy();
+    let host = path
+        .strip_prefix(r"\\")
+        .and_then(|rest| rest.split('\\').next());
+    host.is_some_and(|host| host != "." && host != "?")
+}
+
+fn pipe_wait_timeout(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::TimedOut,
+        format!("Timed out waiting for pipe {path:?} to become available"),
+    )
+}
+
+fn verify_server_identity(
+    client: &named_pipe::NamedPipeClient,
+    identity: ServerIdentity,
+) -> io::Result<()> {
+    let mut server_pid = 0;
+    if unsafe { GetNamedPipeServerProcessId(client.as_raw_handle() as HANDLE, &mut server_pid) }
+        == 0
+    {
+        return Err(io::Error::last_os_error());
+    }
+
+    let server_user = ProcessUser::for_process(server_pid)?;
+    let matches = match identity {
+        ServerIdentity::CurrentUser => server_user.same_as(&ProcessUser::current()?),
+        ServerIdentity::System => server_user.is_local_system(),
+    };
+    if !matches {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            format!("Pipe server process {server_pid} doesn't run as the expected user"),
+        ));
+    }
+    Ok(())
+}
+
+/// Returns the path of the named pipe, which is the same for both ends of a connection.
+pub(crate) fn local_path(conn: &Connection) -> io::Result<Option<PathBuf>> {
+    // A FILE_NAME_INFO followed by room for the name, in u32 for its alignment
+    let mut info = [0u32; 1024];
+    let ok = unsafe {
+        GetFileInformationByHandleEx(
+            conn.as_raw_handle() as HANDLE,
+            FileNameInfo,
+            info.as_mut_ptr().cast(),
+            mem::size_of_val(&info) as u32,
+        )
+    };
+    if ok == 0 {
+        return Err(io::Error::last_os_error());
     }
+    let name_bytes = (info[0] as usize).min(mem::size_of_val(&info[1..]));
+    let name =
+        unsafe { std::slice::from_raw_parts(info[1..].as_ptr().cast::<u16>(), name_bytes / 2) };
+    // The name is relative to the named pipe file system, starting with a backslash
+    let mut path = OsString::from(r"\\.\pipe");
+    path.push(OsString::from_wide(name));
+    Ok(Some(path.into()))
 }
 
+pub(crate) fn peer_path(conn: &Connection) -> io::Result<Option<PathBuf>> {
+    local_path(conn)
+}
+
+pub(crate) fn from_owned_handle(handle: OwnedHandle) -> io::Result<Connection> {
+    let mut flags = 0;
+    let is_pipe = unsafe {
+        GetNamedPipeInfo(
+            handle.as_raw_handle() as HANDLE,
+            &mut flags,
+            ptr::null_mut(),
+            ptr::null_mut(),
+            ptr::null_mut(),
+        )
+    };
+    if is_pipe == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    // The handle is owned and refers to a named pipe, which is all tokio requires
+    let handle = handle.into_raw_handle();
+    let pipe = if flags & PIPE_SERVER_END != 0 {
+        NamedPipe::Server(unsafe { named_pipe::NamedPipeServer::from_raw_handle(handle)? })
+    } else {
+        NamedPipe::Client(unsafe { named_pipe::NamedPipeClient::from_raw_handle(handle)? })
+    };
+    Ok(Connection::wrap(pipe))
+}
+
+pub(crate) fn is_idle(conn: &Connection) -> bool {
+    conn.is_idle()
+}
+
+pub(crate) async fn peek(conn: &mut Connection, buf: &mut [u8]) -> io::Result<usize> {
+    conn.peek(buf).await
+}
+
+pub(crate) fn peer_credentials(conn: &Connection) -> io::Result<PeerCredentials> {
+    let mut pid = 0;
+    let mut session_id = 0;
+    let server = match conn.inner {
+        NamedPipe::Server(ref s) => s.as_raw_handle() as HANDLE,
+        NamedPipe::Client(ref c) => {
+            let handle = c.as_raw_handle() as HANDLE;
+            if unsafe {
+                GetNamedPipeServerProcessId(handle, &mut pid) == 0
+                    || GetNamedPipeServerSessionId(handle, &mut session_id) == 0
+            } {
+                return Err(io::Error::last_os_error());
+            }
+            return Ok(PeerCredentials {
+                session_id,
+                sid: ProcessUser::for_process(pid)?.sid_string()?,
+                pid: Some(pid),
+                remote_computer: None,
+            });
+        }
+    };
+    if unsafe {
+        GetNamedPipeClientProcessId(server, &mut pid) == 0
+            || GetNamedPipeClientSessionId(server, &mut session_id) == 0
+    } {
+        return Err(io::Error::last_os_error());
+    }
+
+    let remote_computer = client_computer_name(server)?;
+    // The client's token holds the user the pipe authenticated, which for remote clients is the
+    // Negotiate logon of their SMB session rather than any process on this machine
+    let user = match ProcessUser::for_pipe_client(server) {
+        Ok(user) => user,
+        Err(e) if remote_computer.is_some() => return Err(e),
+        // Local clients that connected anonymously don't hand out their token
+        Err(_) => ProcessUser::for_process(pid)?,
+    };
+    Ok(PeerCredentials {
+        session_id,
+        sid: user.sid_string()?,
+        // A remote process ID means nothing on this machine
+        pid: remote_computer.is_none().then_some(pid),
+        remote_computer,
+    })
+}
+
+/// The computer a pipe client connected from, `None` if it's on this machine.
+fn client_computer_name(pipe: HANDLE) -> io::Result<Option<String>> {
+    let mut name = [0u16; 256];
+    let ok = unsafe {
+        GetNamedPipeClientComputerNameW(pipe, name.as_mut_ptr(), mem::size_of_val(&name) as u32)
+    };
+    if ok == 0 {
+        let e = io::Error::last_os_error();
+        if e.raw_os_error() == Some(ERROR_PIPE_LOCAL as i32) {
+            return Ok(None);
+        }
+        return Err(e);
+    }
+    let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    Ok(Some(String::from_utf16_lossy(&name[..len])))
+}
+
+fn is_same_user(pipe: &named_pipe::NamedPipeServer) -> bool {
+    let mut client_pid = 0;
+    if unsafe { GetNamedPipeClientProcessId(pipe.as_raw_handle() as HANDLE, &mut client_pid) } == 0
+    {
+        trace!(
+            "Rejected pipe client with unknown process id: {:?}",
+            io::Error::last_os_error()
+        );
+        return false;
+    }
+
+    match (ProcessUser::for_process(client_pid), ProcessUser::current()) {
+        (Ok(client), Ok(server)) if client.same_as(&server) => true,
+        (Ok(_), Ok(_)) => {
+            trace!("Rejected pipe client process {client_pid} running as another user");
+            false
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            trace!("Rejected pipe client process {client_pid} with unknown user: {e:?}");
+            false
+        }
+    }
+}
+
+type AcceptFuture = Pin<Box<dyn Future<Output = (Listener, io::Result<Connection>)> + Send>>;
+
 pub(crate) struct IpcStream {
-    inner: Pin<Box<dyn Stream<Item = io::Result<Connection>> + Send>>,
+    accept: AcceptFuture,
+    path: Arc<Path>,
+}
+
+struct Listener {
+    endpoint: Endpoint,
+    // the pipe instance waiting for the next client, recreated if connecting on it failed
+    pipe: Option<named_pipe::NamedPipeServer>,
+}
+
+impl Listener {
+    async fn accept(mut self) -> (Self, io::Result<Connection>) {
+        let result = self.accept_one().await;
+        (self, result)
+    }
+
+    async fn accept_one(&mut self) -> io::Result<Connection> {
+        loop {
+            let pipe = match self.pipe.take() {
+                Some(pipe) => pipe,
+                None => self.endpoint.create_listener()?,
+            };
+            pipe.connect().await?;
+            // Have the next instance ready before handing this one out so clients don't see
+            // ERROR_PIPE_BUSY in between
+            self.pipe = Some(self.endpoint.create_listener()?);
+            if self.endpoint.security_attributes.same_user_only && !is_same_user(&pipe) {
+                continue;
+            }
+            return Ok(Connection::wrap(NamedPipe::Server(pipe)));
+        }
+    }
 }
 
 impl IpcStream {
     pub(crate) fn new(mut endpoint: Endpoint) -> io::Result<Self> {
         let pipe = endpoint.create_listener()?;
-
-        let stream =
-            futures::stream::try_unfold((pipe, endpoint), |(listener, mut endpoint)| async move {
-                listener.connect().await?;
-                let new_listener = endpoint.create_listener()?;
-                let conn = Connection::wrap(NamedPipe::Server(listener));
-
-                Ok(Some((conn, (new_listener, endpoint))))
-            });
+        let path = endpoint.path.clone();
+        let listener = Listener {
+            endpoint,
+            pipe: Some(pipe),
+        };
         Ok(Self {
-            inner: Box::pin(stream),
+            accept: Box::pin(listener.accept()),
+            path,
         })
     }
-}
 
-impl Stream for IpcStream {
-    type Item = io::Result<Connection>;
+    pub(crate) fn local_path(&self) -> Option<Arc<Path>> {
+        Some(self.path.clone())
+    }
 
-    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let this = Pin::into_inner(self);
-        Pin::new(&mut this.inner).poll_next_unpin(cx)
+    /// Accepts the next connection. Pipe clients are accepted one at a time, so `limit` doesn't
+    /// matter here.
+    pub(crate) fn poll_accept(
+        &mut self,
+        cx: &mut Context<'_>,
+        _limit: usize,
+    ) -> Poll<io::Result<Connection>> {
+        let (listener, result) = ready!(self.accept.as_mut().poll(cx));
+        self.accept = Box::pin(listener.accept());
+        Poll::Ready(result)
     }
 }
 
 pub(crate) struct Connection {
     inner: NamedPipe,
+    // Bytes read ahead by `peek`, handed out before anything else is read from the pipe. The
+    // pipe itself can't be peeked reliably since mio reads ahead into its own buffer.
+    peeked: Mutex<Vec<u8>>,
 }
 
 impl Connection {
     /// Wraps an existing named pipe
-    fn wrap(pipe: NamedPipe) -> Self {
-        Self { inner: pipe }
+    pub(crate) fn wrap(pipe: NamedPipe) -> Self {
+        Self {
+            inner: pipe,
+            peeked: Mutex::default(),
+        }
+    }
+
+    fn peeked(&self) -> MutexGuard<'_, Vec<u8>> {
+        self.peeked.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub(crate) fn into_inner(self) -> NamedPipe {
+        self.inner
+    }
+
+    pub(crate) async fn ready(&self, interest: Interest) -> io::Result<Ready> {
+        if interest.is_readable() && !self.peeked().is_empty() {
+            return Ok(Ready::READABLE);
+        }
+        match self.inner {
+            NamedPipe::Client(ref c) => c.ready(interest).await,
+            NamedPipe::Server(ref s) => s.ready(interest).await,
+        }
+    }
+
+    pub(crate) fn try_read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut read_buf = tokio::io::ReadBuf::new(buf);
+        if read_peeked(&mut self.peeked(), &mut read_buf) {
+            return Ok(read_buf.filled().len());
+        }
+        self.inner.try_read(buf)
+    }
+
+    pub(crate) async fn peek(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let peeked = self
+            .peeked
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        if peeked.is_empty() && !buf.is_empty() {
+            let mut ahead = vec![0; buf.len()];
+            let read = poll_fn(|cx| loop {
+                ready!(self.inner.poll_read_ready(cx))?;
+                match self.inner.try_read(&mut ahead) {
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                    result => return Poll::Ready(result),
+                }
+            })
+            .await?;
+            ahead.truncate(read);
+            *peeked = ahead;
+        }
+        let read = buf.len().min(peeked.len());
+        buf[..read].copy_from_slice(&peeked[..read]);
+        Ok(read)
+    }
+
+    /// Whether nothing can be read from the pipe yet, without waiting or consuming anything.
+    ///
+    /// EOF, pending data and errors all count as not idle. Data mio already read ahead isn't
+    /// seen by `PeekNamedPipe`, but a closed pipe is always reported.
+    pub(crate) fn is_idle(&self) -> bool {
+        if !self.peeked().is_empty() {
+            return false;
+        }
+        let handle = match self.inner {
+            NamedPipe::Client(ref c) => c.as_raw_handle(),
+            NamedPipe::Server(ref s) => s.as_raw_handle(),
+        };
+        let mut available = 0;
+        let ok = unsafe {
+            PeekNamedPipe(
+                handle as HANDLE,
+                ptr::null_mut(),
+                0,
+                ptr::null_mut(),
+                &mut available,
+                ptr::null_mut(),
+            )
+        };
+        ok != 0 && available == 0
+    }
+
+    pub(crate) fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.try_write(buf)
+    }
+
+    pub(crate) fn into_split(self) -> (OwnedReadHalf, OwnedWriteHalf) {
+        let pipe = Arc::new(self.inner);
+        (
+            OwnedReadHalf {
+                pipe: pipe.clone(),
+                peeked: self
+                    .peeked
+                    .into_inner()
+                    .unwrap_or_else(PoisonError::into_inner),
+            },
+            OwnedWriteHalf { pipe },
+        )
+    }
+
+    pub(crate) fn impersonate_client(&self) -> io::Result<Impersonation<'_>> {
+        let NamedPipe::Server(ref server) = self.inner else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Only the server end of a pipe can impersonate its client",
+            ));
+        };
+        if unsafe { ImpersonateNamedPipeClient(server.as_raw_handle() as HANDLE) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Impersonation {
+            _marker: marker::PhantomData,
+        })
+    }
+
+    pub(crate) fn pipe_info(&self) -> io::Result<PipeInfo> {
+        let (info, handle) = match self.inner {
+            NamedPipe::Client(ref c) => (c.info()?, c.as_raw_handle()),
+            NamedPipe::Server(ref s) => (s.info()?, s.as_raw_handle()),
+        };
+
+        let mut state = 0;
+        let mut current_instances = 0;
+        let ok = unsafe {
+            GetNamedPipeHandleStateW(
+                handle as HANDLE,
+                &mut state,
+                &mut current_instances,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                0,
+            )
+        };
+        if ok == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(PipeInfo {
+            pipe_mode: info.mode,
+            read_mode: if state & PIPE_READMODE_MESSAGE != 0 {
+                PipeMode::Message
+            } else {
+                PipeMode::Byte
+            },
+            max_instances: info.max_instances,
+            current_instances,
+            in_buffer_size: info.in_buffer_size,
+            out_buffer_size: info.out_buffer_size,
+        })
+    }
+}
+
+/// Diagnostic information about the named pipe behind a connection
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PipeInfo {
+    /// The mode the pipe was created with.
+    pub pipe_mode: PipeMode,
+    /// The mode data is read in on this end of the pipe.
+    pub read_mode: PipeMode,
+    /// The maximum number of pipe instances that can be created.
+    pub max_instances: u32,
+    /// The number of pipe instances that currently exist.
+    pub current_instances: u32,
+    /// The size of the buffer for incoming data, in bytes.
+    pub in_buffer_size: u32,
+    /// The size of the buffer for outgoing data, in bytes.
+    pub out_buffer_size: u32,
+}
+
+/// The current thread acting with the token of a named pipe client, created by
+/// [`Connection::impersonate_client`](crate::Connection::impersonate_client).
+///
+/// Dropping the guard reverts the thread to the server's own token. Impersonation applies to the
+/// calling thread only, so the guard can't be sent to other threads and shouldn't be held across
+/// `.await` points.
+#[derive(Debug)]
+#[must_use = "impersonation ends as soon as the guard is dropped"]
+pub struct Impersonation<'a> {
+    // Bound to the connection and to the thread that impersonates
+    _marker: marker::PhantomData<(&'a Connection, *const ())>,
+}
+
+impl Impersonation<'_> {
+    /// Reverts the thread to the server's own token, reporting any error.
+    pub fn revert(self) -> io::Result<()> {
+        let result = revert_to_self();
+        mem::forget(self);
+        result
+    }
+}
+
+impl Drop for Impersonation<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = revert_to_self() {
+            // Carrying on with the client's token would run server code with the wrong identity
+            tracing::error!("Failed to revert client impersonation: {e}");
+            std::process::abort();
+        }
+    }
+}
+
+fn revert_to_self() -> io::Result<()> {
+    if unsafe { RevertToSelf() } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl AsHandle for Connection {
+    fn as_handle(&self) -> BorrowedHandle<'_> {
+        self.inner.as_handle()
+    }
+}
+
+impl AsRawHandle for Connection {
+    fn as_raw_handle(&self) -> RawHandle {
+        self.inner.as_raw_handle()
     }
 }
 
@@ -196,6 +849,13 @@ impl AsyncRead for Connection {
         buf: &mut tokio::io::ReadBuf<'_>,
     ) -> Poll<io::Result<()>> {
         let this = Pin::into_inner(self);
+        let peeked = this
+            .peeked
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        if read_peeked(peeked, buf) {
+            return Poll::Ready(Ok(()));
+        }
         match this.inner {
             NamedPipe::Client(ref mut c) => Pin::new(c).poll_read(ctx, buf),
             NamedPipe::Server(ref mut s) => Pin::new(s).poll_read(ctx, buf),
@@ -203,6 +863,17 @@ impl AsyncRead for Connection {
     }
 }
 
+/// Moves bytes read ahead by `peek` into `buf`, returning whether there were any.
+fn read_peeked(peeked: &mut Vec<u8>, buf: &mut tokio::io::ReadBuf<'_>) -> bool {
+    if peeked.is_empty() {
+        return false;
+    }
+    let read = buf.remaining().min(peeked.len());
+    buf.put_slice(&peeked[..read]);
+    peeked.drain(..read);
+    true
+}
+
 impl AsyncWrite for Connection {
     fn poll_write(
         self: Pin<&mut Self>,
@@ -216,6 +887,25 @@ impl AsyncWrite for Connection {
         }
     }
 
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        ctx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<Result<usize, io::Error>> {
+        let this = Pin::into_inner(self);
+        match this.inner {
+            NamedPipe::Client(ref mut c) => Pin::new(c).poll_write_vectored(ctx, bufs),
+            NamedPipe::Server(ref mut s) => Pin::new(s).poll_write_vectored(ctx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match self.inner {
+            NamedPipe::Client(ref c) => c.is_write_vectored(),
+            NamedPipe::Server(ref s) => s.is_write_vectored(),
+        }
+    }
+
     fn poll_flush(self: Pin<&mut Self>, ctx: &mut Context<'_>) -> Poll<Result<(), io::Error>> {
         let this = Pin::into_inner(self);
         match this.inner {
@@ -233,8 +923,99 @@ impl AsyncWrite for Connection {
     }
 }
 
+impl NamedPipe {
+    fn poll_read_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self {
+            Self::Client(c) => c.poll_read_ready(cx),
+            Self::Server(s) => s.poll_read_ready(cx),
+        }
+    }
+
+    fn poll_write_ready(&self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match self {
+            Self::Client(c) => c.poll_write_ready(cx),
+            Self::Server(s) => s.poll_write_ready(cx),
+        }
+    }
+
+    fn try_read(&self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Client(c) => c.try_read(buf),
+            Self::Server(s) => s.try_read(buf),
+        }
+    }
+
+    fn try_write(&self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Client(c) => c.try_write(buf),
+            Self::Server(s) => s.try_write(buf),
+        }
+    }
+}
+
+// Named pipes don't have owned halves in tokio, so both halves share the pipe and go through the
+// readiness-based `&self` methods, which don't need exclusive access
+pub(crate) struct OwnedReadHalf {
+    pipe: Arc<NamedPipe>,
+    peeked: Vec<u8>,
+}
+
+pub(crate) struct OwnedWriteHalf {
+    pipe: Arc<NamedPipe>,
+}
+
+impl AsyncRead for OwnedReadHalf {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = Pin::into_inner(self);
+        if read_peeked(&mut this.peeked, buf) {
+            return Poll::Ready(Ok(()));
+        }
+        loop {
+            ready!(this.pipe.poll_read_ready(cx))?;
+            match this.pipe.try_read(buf.initialize_unfilled()) {
+                Ok(read) => {
+                    buf.advance(read);
+                    return Poll::Ready(Ok(()));
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                Err(e) => return Poll::Ready(Err(e)),
+            }
+        }
+    }
+}
+
+impl AsyncWrite for OwnedWriteHalf {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        loop {
+            ready!(self.pipe.poll_write_ready(cx))?;
+            match self.pipe.try_write(buf) {
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
 pub(crate) struct SecurityAttributes {
     attributes: Option<InnerAttributes>,
+    // reject clients that don't run as the same user as the server
+    same_user_only: bool,
 }
 
 const DEFAULT_SECURITY_ATTRIBUTES: SecurityAttributes = SecurityAttributes {
@@ -251,6 +1032,7 @@ const DEFAULT_SECURITY_ATTRIBUTES: SecurityAttributes = SecurityAttributes {
             bInheritHandle: 0,
         },
     }),
+    same_user_only: false,
 };
 
 impl SecurityAttributes {
@@ -269,7 +1051,10 @@ impl SecurityAttributes {
         let attributes = Some(InnerAttributes::allow_everyone(
             GENERIC_READ | FILE_WRITE_DATA,
         )?);
-        Ok(Self { attributes })
+        Ok(Self {
+            attributes,
+            same_user_only: self.same_user_only,
+        })
     }
 
     pub(crate) fn set_mode(self, _mode: u16) -> io::Result<Self> {
@@ -281,7 +1066,42 @@ impl SecurityAttributes {
         let attributes = Some(InnerAttributes::allow_everyone(
             GENERIC_READ | GENERIC_WRITE,
         )?);
-        Ok(Self { attributes })
+        Ok(Self {
+            attributes,
+            same_user_only: false,
+        })
+    }
+
+    pub(crate) fn same_user_only() -> io::Result<Self> {
+        Ok(Self {
+            same_user_only: true,
+            ..DEFAULT_SECURITY_ATTRIBUTES
+        })
+    }
+
+    pub(crate) fn from_sddl(sddl: &str) -> io::Result<Self> {
+        Ok(Self {
+            attributes: Some(InnerAttributes::from_sddl(sddl)?),
+            same_user_only: false,
+        })
+    }
+
+    pub(crate) fn allow_sids<'a>(
+        entries: impl IntoIterator<Item = (&'a str, u32)>,
+    ) -> io::Result<Self> {
+        // A protected DACL, so nothing is inherited besides the given entries
+        let mut sddl = String::from("D:P");
+        for (sid, access_mask) in entries {
+            // Anything else could inject further entries into the SDDL string
+            if sid.is_empty() || !sid.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Invalid SID {sid:?}"),
+                ));
+            }
+            sddl.push_str(&format!("(A;;{access_mask:#x};;;{sid})"));
+        }
+        Self::from_sddl(&sddl)
     }
 }
 
@@ -335,6 +1155,111 @@ impl Drop for Sid {
     }
 }
 
+/// The user a process runs as, read from its access token.
+struct ProcessUser {
+    // Holds a TOKEN_USER followed by the SID it points to
+    token_user: Vec<u8>,
+}
+
+impl ProcessUser {
+    fn current() -> io::Result<Self> {
+        // The pseudo handle returned by GetCurrentProcess doesn't need to be closed
+        Self::from_process_handle(unsafe { GetCurrentProcess() })
+    }
+
+    fn for_process(pid: u32) -> io::Result<Self> {
+        let process = unsafe { OpenProcess(PROCESS_QUERY_LIMITED_INFORMATION, 0, pid) };
+        if process == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let process = unsafe { OwnedHandle::from_raw_handle(process as RawHandle) };
+        Self::from_process_handle(process.as_raw_handle() as HANDLE)
+    }
+
+    fn from_process_handle(process: HANDLE) -> io::Result<Self> {
+        let mut token = 0;
+        if unsafe { OpenProcessToken(process, TOKEN_QUERY, &mut token) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let token = unsafe { OwnedHandle::from_raw_handle(token as RawHandle) };
+        Self::from_token(token.as_raw_handle() as HANDLE)
+    }
+
+    /// The user of the client on the other end of a server pipe, as authenticated by the pipe.
+    fn for_pipe_client(pipe: HANDLE) -> io::Result<Self> {
+        if unsafe { ImpersonateNamedPipeClient(pipe) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut token = 0;
+        // Opened with the server's own rights, the client's may not allow querying its token
+        let opened = unsafe { OpenThreadToken(GetCurrentThread(), TOKEN_QUERY, 1, &mut token) };
+        let opened = if opened == 0 {
+            Err(io::Error::last_os_error())
+        } else {
+            Ok(unsafe { OwnedHandle::from_raw_handle(token as RawHandle) })
+        };
+        if let Err(e) = revert_to_self() {
+            // Carrying on with the client's token would run server code with the wrong identity
+            tracing::error!("Failed to revert client impersonation: {e}");
+            std::process::abort();
+        }
+        Self::from_token(opened?.as_raw_handle() as HANDLE)
+    }
+
+    fn from_token(token: HANDLE) -> io::Result<Self> {
+        let mut len = 0;
+        if unsafe { GetTokenInformation(token, TokenUser, ptr::null_mut(), 0, &mut len) } == 0 {
+            let e = io::Error::last_os_error();
+            if e.raw_os_error() != Some(ERROR_INSUFFICIENT_BUFFER as i32) {
+                return Err(e);
+            }
+        }
+        let mut token_user = vec![0u8; len as usize];
+        if unsafe {
+            GetTokenInformation(
+                token,
+                TokenUser,
+                token_user.as_mut_ptr().cast(),
+                len,
+                &mut len,
+            )
+        } == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok(Self { token_user })
+    }
+
+    // Unsafe - the returned pointer is only valid for the lifetime of self.
+    unsafe fn sid(&self) -> PSID {
+        ptr::read_unaligned(self.token_user.as_ptr().cast::<TOKEN_USER>())
+            .User
+            .Sid
+    }
+
+    fn same_as(&self, other: &Self) -> bool {
+        unsafe { EqualSid(self.sid(), other.sid()) != 0 }
+    }
+
+    fn is_local_system(&self) -> bool {
+        unsafe { IsWellKnownSid(self.sid(), WinLocalSystemSid) != 0 }
+    }
+
+    fn sid_string(&self) -> io::Result<String> {
+        let mut string_sid: PWSTR = ptr::null_mut();
+        if unsafe { ConvertSidToStringSidW(self.sid(), &mut string_sid) } == 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let sid = unsafe {
+            let len = (0..).take_while(|&i| *string_sid.add(i) != 0).count();
+            String::from_utf16_lossy(std::slice::from_raw_parts(string_sid, len))
+        };
+        unsafe { LocalFree(string_sid as HLOCAL) };
+        Ok(sid)
+    }
+}
+
 struct AceWithSid<'a> {
     explicit_access: EXPLICIT_ACCESS_W,
     _marker: marker::PhantomData<&'a Sid>,
@@ -433,6 +1358,24 @@ impl SecurityDescriptor {
         Ok(Self { descriptor_ptr })
     }
 
+    fn from_sddl(sddl: &str) -> io::Result<Self> {
+        let sddl: Vec<u16> = sddl.encode_utf16().chain(Some(0)).collect();
+        let mut descriptor_ptr = ptr::null_mut();
+        if unsafe {
+            ConvertStringSecurityDescriptorToSecurityDescriptorW(
+                sddl.as_ptr(),
+                SDDL_REVISION_1,
+                &mut descriptor_ptr,
+                ptr::null_mut(),
+            )
+        } == 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+        // Allocated with LocalAlloc, so it's freed like the descriptors created by `new`
+        Ok(Self { descriptor_ptr })
+    }
+
     fn set_dacl(&mut self, acl: &Acl) -> io::Result<()> {
         if unsafe {
             SetSecurityDescriptorDacl(self.descriptor_ptr, true as i32, acl.as_ptr(), false as i32)
@@ -497,6 +1440,23 @@ impl InnerAttributes {
         Ok(attributes)
     }
 
+    fn from_sddl(sddl: &str) -> io::Result<Self> {
+        let descriptor = SecurityDescriptor::from_sddl(sddl)?;
+        let mut attrs = unsafe { mem::zeroed::<SECURITY_ATTRIBUTES>() };
+        attrs.nLength = mem::size_of::<SECURITY_ATTRIBUTES>() as u32;
+        attrs.lpSecurityDescriptor = unsafe { descriptor.as_ptr() };
+        attrs.bInheritHandle = false as i32;
+
+        Ok(Self {
+            // The DACL is part of the self-relative descriptor
+            acl: Acl {
+                acl_ptr: ptr::null(),
+            },
+            descriptor,
+            attrs,
+        })
+    }
+
     unsafe fn as_ptr(&mut self) -> *const SECURITY_ATTRIBUTES {
         &mut self.attrs
     }
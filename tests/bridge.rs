@@ -0,0 +1,40 @@
+#![cfg(feature = "bridge")]
+
+use std::time::Duration;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::oneshot;
+use tokio_ipc::bridge::{self, BridgeTarget};
+use tokio_ipc::{Endpoint, ServerId};
+
+#[tokio::test]
+async fn bridges_ipc_connections_to_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let target = BridgeTarget::Tcp(listener.local_addr().unwrap().to_string());
+    tokio::spawn(async move {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        stream.write_all(&buf).await.unwrap();
+    });
+
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let endpoint = Endpoint::new(ServerId::new(format!("bridge-{num}")), None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let server = tokio::spawn(bridge::serve(endpoint, target, async {
+        let _ = stop_rx.await;
+    }));
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let mut client = Endpoint::connect(path, None).await.unwrap();
+    client.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"ping", &buf);
+
+    drop(client);
+    stop_tx.send(()).unwrap();
+    server.await.unwrap().unwrap();
+}
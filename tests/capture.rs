@@ -0,0 +1,68 @@
+#![cfg(feature = "capture")]
+
+use std::io::{self, Cursor};
+
+use bytes::Bytes;
+use tokio_ipc::capture::{self, CaptureReader, Direction, TappedConnection};
+use tokio_ipc::framing::FramedConnection;
+use tokio_ipc::Endpoint;
+
+#[tokio::test]
+async fn tap_records_frames_in_both_directions() {
+    let (first, second) = Endpoint::pair().await.unwrap();
+    let mut tapped = TappedConnection::new(FramedConnection::new(first), Vec::new()).unwrap();
+    let mut peer = FramedConnection::new(second);
+
+    tapped.send(Bytes::from_static(b"request")).await.unwrap();
+    assert_eq!(&b"request"[..], peer.recv().await.unwrap().unwrap());
+    peer.send(Bytes::from_static(b"response")).await.unwrap();
+    assert_eq!(&b"response"[..], tapped.recv().await.unwrap().unwrap());
+
+    let (_, capture) = tapped.into_parts();
+    let records: Vec<_> = CaptureReader::new(Cursor::new(capture.unwrap()))
+        .unwrap()
+        .collect::<Result<_, _>>()
+        .unwrap();
+    assert_eq!(2, records.len());
+    assert_eq!(Direction::Sent, records[0].direction);
+    assert_eq!(&b"request"[..], records[0].frame);
+    assert_eq!(Direction::Received, records[1].direction);
+    assert_eq!(&b"response"[..], records[1].frame);
+    assert!(records[0].timestamp <= records[1].timestamp);
+}
+
+#[tokio::test]
+async fn replay_feeds_received_frames_to_handler() {
+    let path = std::env::temp_dir().join(format!(
+        "tokio-ipc-capture-{}",
+        rand::Rng::gen::<u64>(&mut rand::thread_rng())
+    ));
+    let (first, second) = Endpoint::pair().await.unwrap();
+    let mut tapped = TappedConnection::create(FramedConnection::new(first), &path).unwrap();
+    let mut peer = FramedConnection::new(second);
+    for frame in [&b"one"[..], b"two"] {
+        peer.send(Bytes::from_static(frame)).await.unwrap();
+        tapped.recv().await.unwrap().unwrap();
+    }
+    tapped.send(Bytes::from_static(b"ignored")).await.unwrap();
+    drop(tapped);
+
+    let sent = capture::replay(&path, |mut conn| async move {
+        for _ in 0..2 {
+            let frame = conn.recv().await.unwrap().unwrap();
+            let mut echoed = frame.to_vec();
+            echoed.reverse();
+            conn.send(echoed.into()).await.unwrap();
+        }
+    })
+    .await
+    .unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(vec![&b"eno"[..], b"owt"], sent);
+}
+
+#[test]
+fn reader_rejects_other_files() {
+    let err = CaptureReader::new(Cursor::new(b"not a capture".to_vec())).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+}
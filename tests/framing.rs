@@ -0,0 +1,140 @@
+#![cfg(feature = "framing")]
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::StreamExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_ipc::framing::FramedConnection;
+use tokio_ipc::{Endpoint, ExpiryReason, ServerId};
+
+#[tokio::test]
+async fn framed_roundtrip_preserves_boundaries() {
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let endpoint = Endpoint::new(ServerId::new(format!("framing-{num}")), None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path, None).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+    let mut client = FramedConnection::new(client);
+    let mut server = FramedConnection::new(server);
+
+    client.send(Bytes::from_static(b"hello")).await.unwrap();
+    client.send(Bytes::new()).await.unwrap();
+    client.send(Bytes::from_static(b"world")).await.unwrap();
+    assert_eq!(&b"hello"[..], server.recv().await.unwrap().unwrap());
+    assert_eq!(&b""[..], server.recv().await.unwrap().unwrap());
+    assert_eq!(&b"world"[..], server.recv().await.unwrap().unwrap());
+
+    drop(client);
+    assert!(server.recv().await.is_none());
+}
+
+#[tokio::test]
+async fn framed_rejects_oversized_frames() {
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let endpoint = Endpoint::new(ServerId::new(format!("framing-{num}")), None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path, None).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+    let mut client = FramedConnection::with_max_frame_length(client, 4);
+    let mut server = FramedConnection::with_max_frame_length(server, 4);
+
+    assert!(client.send(Bytes::from_static(b"too long")).await.is_err());
+
+    let header = 8u32.to_be_bytes();
+    client.get_mut().write_all(&header).await.unwrap();
+    let err = server.recv().await.unwrap().unwrap_err();
+    assert_eq!(std::io::ErrorKind::InvalidData, err.kind());
+}
+
+#[tokio::test]
+async fn max_buffered_caps_frame_length() {
+    let (client, mut server) = Endpoint::pair().await.unwrap();
+    server.set_max_buffered(Some(4));
+    let mut client = FramedConnection::new(client);
+    let mut server = FramedConnection::new(server);
+    assert_eq!(4, server.max_frame_length());
+
+    client.send(Bytes::from_static(b"ping")).await.unwrap();
+    assert_eq!(&b"ping"[..], server.recv().await.unwrap().unwrap());
+    client.send(Bytes::from_static(b"too long")).await.unwrap();
+    let err = server.recv().await.unwrap().unwrap_err();
+    assert_eq!(std::io::ErrorKind::InvalidData, err.kind());
+}
+
+#[tokio::test]
+async fn expired_connection_sends_close_notice() {
+    let (client, mut server) = Endpoint::pair().await.unwrap();
+    server.set_max_lifetime(Some(Duration::from_millis(50)));
+    let mut client = FramedConnection::new(client);
+    let mut server = FramedConnection::new(server);
+
+    server.send(Bytes::from_static(b"hello")).await.unwrap();
+    assert_eq!(&b"hello"[..], client.recv().await.unwrap().unwrap());
+    assert_eq!(None, client.peer_expiry());
+
+    // The server notices the end of the lifetime while waiting for a frame
+    assert!(server.recv().await.is_none());
+    assert!(client.recv().await.is_none());
+    assert_eq!(Some(ExpiryReason::MaxLifetime), client.peer_expiry());
+}
+
+#[tokio::test]
+async fn incoming_framed_yields_framed_connections() {
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let endpoint = Endpoint::new(ServerId::new(format!("framing-{num}")), None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming_framed().unwrap();
+    incoming.set_max_frame_length(4);
+
+    let client = Endpoint::connect(path, None).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+    assert_eq!(4, server.max_frame_length());
+    let mut client = FramedConnection::new(client);
+
+    client.send(Bytes::from_static(b"ping")).await.unwrap();
+    assert_eq!(&b"ping"[..], server.recv().await.unwrap().unwrap());
+    client.send(Bytes::from_static(b"too long")).await.unwrap();
+    let err = server.recv().await.unwrap().unwrap_err();
+    assert_eq!(std::io::ErrorKind::InvalidData, err.kind());
+}
+
+#[tokio::test]
+async fn upgrade_switches_protocol_after_negotiation() {
+    let (client, server) = Endpoint::pair().await.unwrap();
+    let mut client = FramedConnection::new(client);
+    let mut server = FramedConnection::new(server);
+
+    let server = tokio::spawn(async move {
+        assert_eq!(&b"hello"[..], server.recv().await.unwrap().unwrap());
+        assert!(server.recv().await.is_none());
+        assert_eq!(Some("zstd"), server.peer_upgrade());
+        server.reject_upgrade().await.unwrap();
+        assert_eq!(&b"plain"[..], server.recv().await.unwrap().unwrap());
+
+        assert!(server.recv().await.is_none());
+        assert_eq!(Some("raw"), server.peer_upgrade());
+        let mut upgraded = server.accept_upgrade().await.unwrap();
+        let mut buf = [0; 3];
+        upgraded.read_exact(&mut buf).await.unwrap();
+        upgraded.write_all(&buf).await.unwrap();
+    });
+
+    client.send(Bytes::from_static(b"hello")).await.unwrap();
+    let Err(mut client) = client.upgrade("zstd").await.unwrap() else {
+        panic!("upgrade wasn't rejected");
+    };
+    client.send(Bytes::from_static(b"plain")).await.unwrap();
+    let Ok(mut upgraded) = client.upgrade("raw").await.unwrap() else {
+        panic!("upgrade wasn't accepted");
+    };
+    upgraded.write_all(b"abc").await.unwrap();
+    let mut buf = [0; 3];
+    upgraded.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"abc", &buf);
+    server.await.unwrap();
+}
@@ -0,0 +1,20 @@
+#![cfg(feature = "futures-io")]
+
+use futures::{AsyncReadExt, AsyncWriteExt, StreamExt};
+use tokio_ipc::{Endpoint, ServerId};
+
+#[tokio::test]
+async fn futures_io_roundtrip() {
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let endpoint = Endpoint::new(ServerId::new(format!("futures-io-{num}")), None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut client = Endpoint::connect(path, None).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+
+    client.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"ping", &buf);
+}
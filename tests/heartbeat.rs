@@ -0,0 +1,146 @@
+#![cfg(feature = "heartbeat")]
+
+use std::io;
+use std::time::Duration;
+
+use bytes::Bytes;
+use futures::StreamExt;
+use tokio_ipc::framing::FramedConnection;
+use tokio_ipc::heartbeat::{HeartbeatConnection, HeartbeatOptions};
+use tokio_ipc::{Connection, Endpoint, ServerId};
+
+fn options() -> Option<HeartbeatOptions> {
+    Some(HeartbeatOptions {
+        interval: Duration::from_millis(20),
+        timeout: Duration::from_millis(100),
+    })
+}
+
+async fn pair() -> (Connection, Connection) {
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let endpoint = Endpoint::new(ServerId::new(format!("heartbeat-{num}")), None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path, None).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+    (client, server)
+}
+
+#[tokio::test]
+async fn heartbeat_keeps_idle_connection_alive() {
+    let (client, server) = pair().await;
+    let mut client = HeartbeatConnection::new(FramedConnection::new(client), options());
+    let mut server = HeartbeatConnection::new(FramedConnection::new(server), options());
+
+    let echo = tokio::spawn(async move {
+        while let Some(frame) = server.recv().await {
+            server.send(frame.unwrap()).await.unwrap();
+        }
+    });
+
+    // Several timeouts pass without application traffic
+    let idle = tokio::time::timeout(Duration::from_millis(300), client.recv()).await;
+    assert!(idle.is_err(), "idle connection should stay open");
+
+    client.send(Bytes::from_static(b"ping")).await.unwrap();
+    assert_eq!(&b"ping"[..], client.recv().await.unwrap().unwrap());
+
+    drop(client);
+    echo.await.unwrap();
+}
+
+#[tokio::test]
+async fn heartbeat_detects_unresponsive_peer() {
+    let (client, server) = pair().await;
+    let mut client = HeartbeatConnection::new(FramedConnection::new(client), options());
+    // The peer keeps the connection open but never answers pings
+    let _server = FramedConnection::new(server);
+
+    let err = tokio::time::timeout(Duration::from_secs(5), client.recv())
+        .await
+        .expect("missing heartbeats should be detected")
+        .unwrap()
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+}
+
+#[tokio::test]
+async fn ping_measures_round_trip_time() {
+    let (client, server) = pair().await;
+    let mut client = HeartbeatConnection::new(FramedConnection::new(client), options());
+    let mut server = HeartbeatConnection::new(FramedConnection::new(server), options());
+
+    let echo = tokio::spawn(async move {
+        server.send(Bytes::from_static(b"early")).await.unwrap();
+        while let Some(frame) = server.recv().await {
+            server.send(frame.unwrap()).await.unwrap();
+        }
+    });
+
+    let rtt = client.ping().await.unwrap();
+    assert!(rtt < Duration::from_secs(1));
+    // Frames that arrived while waiting for the pong aren't lost
+    assert_eq!(&b"early"[..], client.recv().await.unwrap().unwrap());
+
+    drop(client);
+    echo.await.unwrap();
+}
+
+#[tokio::test]
+async fn ping_fails_if_peer_does_not_answer() {
+    let (client, server) = pair().await;
+    let mut client = HeartbeatConnection::new(FramedConnection::new(client), options());
+    let _server = FramedConnection::new(server);
+
+    let err = tokio::time::timeout(Duration::from_secs(5), client.ping())
+        .await
+        .expect("missing pong should be detected")
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+}
+
+#[tokio::test]
+async fn round_trip_times_are_reported() {
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let endpoint = Endpoint::new(ServerId::new(format!("heartbeat-{num}")), None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    let stats = incoming.stats();
+
+    let client = Endpoint::connect(path, None).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+    // Long enough that no pings are sent in the background
+    let options = HeartbeatOptions {
+        interval: Duration::from_secs(60),
+        timeout: Duration::from_secs(120),
+    };
+    let mut client = HeartbeatConnection::new(FramedConnection::new(client), Some(options.clone()));
+    let mut server = HeartbeatConnection::new(FramedConnection::new(server), Some(options));
+    assert!(server.round_trip_time().is_none());
+
+    let echo = tokio::spawn(async move {
+        while let Some(frame) = client.recv().await {
+            client.send(frame.unwrap()).await.unwrap();
+        }
+    });
+
+    let first = server.ping().await.unwrap();
+    let second = server.ping().await.unwrap();
+    let round_trip_time = server.round_trip_time().unwrap();
+    assert_eq!(2, round_trip_time.samples);
+    assert_eq!(second, round_trip_time.last);
+    assert_eq!(first.min(second), round_trip_time.min);
+    assert_eq!(first.max(second), round_trip_time.max);
+
+    // Pings of accepted connections count towards the endpoint
+    let snapshot = stats.snapshot();
+    assert_eq!(2, snapshot.round_trips);
+    assert_eq!(
+        round_trip_time.max.as_micros(),
+        snapshot.max_round_trip_time.as_micros()
+    );
+
+    drop(server);
+    echo.await.unwrap();
+}
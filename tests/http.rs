@@ -0,0 +1,56 @@
+#![cfg(feature = "hyper")]
+
+use std::convert::Infallible;
+use std::future::poll_fn;
+use std::io;
+use std::pin::Pin;
+
+use hyper::body::{Body, Incoming};
+use hyper::service::service_fn;
+use hyper::{Request, Response};
+use tokio::sync::oneshot;
+use tokio_ipc::http::{Acceptor, Connector};
+use tokio_ipc::{Endpoint, ServerId};
+
+async fn read_body(mut body: Incoming) -> String {
+    let mut data = Vec::new();
+    while let Some(frame) = poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await {
+        if let Ok(chunk) = frame.unwrap().into_data() {
+            data.extend_from_slice(&chunk);
+        }
+    }
+    String::from_utf8(data).unwrap()
+}
+
+#[tokio::test]
+async fn requests_are_routed_by_authority() {
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let endpoint = Endpoint::new(ServerId::new(format!("http-{num}")), None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let acceptor = Acceptor::new(endpoint.incoming().unwrap());
+    let service = service_fn(|req: Request<Incoming>| async move {
+        let body = format!("{} {}", req.method(), req.uri().path());
+        Ok::<_, Infallible>(Response::new(body))
+    });
+    let (stop, stopped) = oneshot::channel::<()>();
+    let server = tokio::spawn(acceptor.serve(service, async {
+        let _ = stopped.await;
+    }));
+
+    let connector = Connector::new(None).route("daemon", path).unwrap();
+    let req = Request::get("http://daemon:2375/version")
+        .body(String::new())
+        .unwrap();
+    let resp = connector.send(req).await.unwrap();
+    assert_eq!(200, resp.status());
+    assert_eq!("GET /version", read_body(resp.into_body()).await);
+
+    let error = connector
+        .connect(&"http://elsewhere/".parse().unwrap())
+        .await
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::NotFound, error.kind());
+
+    stop.send(()).unwrap();
+    server.await.unwrap().unwrap();
+}
@@ -0,0 +1,89 @@
+#![cfg(feature = "instrument")]
+
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_ipc::{Endpoint, ServerId};
+use tracing::field::{Field, Visit};
+use tracing::span::{Attributes, Id, Record};
+use tracing::{Event, Metadata, Subscriber};
+
+#[derive(Default)]
+struct Recorded {
+    roles: Mutex<Vec<String>>,
+    events: Mutex<Vec<String>>,
+    next_id: AtomicU64,
+}
+
+struct Recorder(Arc<Recorded>);
+
+struct FieldValue<'a>(&'a str, Option<String>);
+
+impl Visit for FieldValue<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        if field.name() == self.0 {
+            self.1 = Some(format!("{value:?}"));
+        }
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == self.0 {
+            self.1 = Some(value.to_owned());
+        }
+    }
+}
+
+impl Subscriber for Recorder {
+    fn enabled(&self, _: &Metadata<'_>) -> bool {
+        true
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        if span.metadata().name() == "ipc_connection" {
+            let mut role = FieldValue("role", None);
+            span.record(&mut role);
+            self.0.roles.lock().unwrap().extend(role.1);
+        }
+        Id::from_u64(self.0.next_id.fetch_add(1, Ordering::Relaxed) + 1)
+    }
+
+    fn record(&self, _: &Id, _: &Record<'_>) {}
+
+    fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let mut message = FieldValue("message", None);
+        event.record(&mut message);
+        self.0.events.lock().unwrap().extend(message.1);
+    }
+
+    fn enter(&self, _: &Id) {}
+
+    fn exit(&self, _: &Id) {}
+}
+
+#[tokio::test]
+async fn connections_are_instrumented() {
+    let recorded = Arc::new(Recorded::default());
+    let _guard = tracing::subscriber::set_default(Recorder(recorded.clone()));
+
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let endpoint = Endpoint::new(ServerId::new(format!("instrument-{num}")), None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    let mut client = Endpoint::connect(path, None).await.unwrap();
+    let mut server = incoming.accept().await.unwrap();
+
+    client.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).await.unwrap();
+
+    let roles = recorded.roles.lock().unwrap().clone();
+    assert_eq!(vec!["client", "server"], roles);
+    let events = recorded.events.lock().unwrap().clone();
+    assert!(events.iter().any(|event| event == "Connection opened"));
+    assert!(events.iter().any(|event| event == "Wrote"));
+    assert!(events.iter().any(|event| event == "Read"));
+}
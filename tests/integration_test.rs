@@ -84,6 +84,7 @@ async fn single_id() {
     #[cfg(not(windows))]
     let options = Some(tokio_ipc::EndpointOptions {
         on_conflict: tokio_ipc::OnConflict::Overwrite,
+        ..Default::default()
     });
     #[cfg(windows)]
     let options = None;
@@ -97,6 +98,7 @@ async fn nested_path() {
     #[cfg(not(windows))]
     let options = Some(tokio_ipc::EndpointOptions {
         on_conflict: tokio_ipc::OnConflict::Overwrite,
+        ..Default::default()
     });
     #[cfg(windows)]
     let options = None;
@@ -112,6 +114,7 @@ async fn error_on_path_exists() {
     let path = dummy_endpoint("test");
     let options = tokio_ipc::EndpointOptions {
         on_conflict: tokio_ipc::OnConflict::Error,
+        ..Default::default()
     };
     let mut incoming = Endpoint::new(path.clone(), Some(options))
         .unwrap()
@@ -124,12 +127,100 @@ async fn error_on_path_exists() {
     assert!(Endpoint::new(path, Some(options)).is_err());
 }
 
+#[cfg(unix)]
+#[tokio::test]
+async fn single_instance_refuses_second_server() {
+    let path = dummy_endpoint("test");
+    let options = tokio_ipc::EndpointOptions {
+        on_conflict: tokio_ipc::OnConflict::Overwrite,
+        single_instance: true,
+        ..Default::default()
+    };
+    let incoming = Endpoint::new(path.clone(), Some(options))
+        .unwrap()
+        .incoming()
+        .unwrap();
+
+    let err = Endpoint::new(path.clone(), Some(options)).err().unwrap();
+    assert_eq!(io::ErrorKind::AddrInUse, err.kind());
+
+    drop(incoming);
+    let _incoming = Endpoint::new(path, Some(options))
+        .unwrap()
+        .incoming()
+        .unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn atomic_bind_applies_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let folder = std::env::temp_dir().join(format!(
+        "tokio-ipc-atomic-{}",
+        rand::Rng::gen::<u64>(&mut rand::thread_rng())
+    ));
+    std::fs::create_dir(&folder).unwrap();
+    let options = tokio_ipc::EndpointOptions {
+        atomic_bind: true,
+        ..Default::default()
+    };
+    let endpoint = Endpoint::new(ServerId::new("test").parent_folder(&folder), Some(options))
+        .unwrap()
+        .security_attributes(SecurityAttributes::empty().set_mode(0o640).unwrap());
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(0o640, mode & 0o777);
+    // The staging folder is gone
+    let entries: Vec<_> = std::fs::read_dir(&folder)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .collect();
+    assert_eq!(vec![path.clone()], entries);
+
+    let mut client = Endpoint::connect(path, None).await.unwrap();
+    let mut server_conn = incoming.next().await.unwrap().unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    server_conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"hello", &buf);
+
+    drop(incoming);
+    std::fs::remove_dir_all(folder).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn overwrite_if_stale_keeps_live_sockets() {
+    let path = dummy_endpoint("test");
+    let options = tokio_ipc::EndpointOptions {
+        on_conflict: tokio_ipc::OnConflict::OverwriteIfStale,
+        ..Default::default()
+    };
+    let endpoint = Endpoint::new(path.clone(), Some(options)).unwrap();
+    let socket_path = endpoint.path().to_path_buf();
+    let incoming = endpoint.incoming().unwrap();
+
+    let err = Endpoint::new(path.clone(), Some(options)).err().unwrap();
+    assert_eq!(io::ErrorKind::AddrInUse, err.kind());
+    assert!(socket_path.exists());
+
+    // A listener that went away without removing its socket file leaves a stale file behind
+    drop(incoming.into_inner());
+    assert!(socket_path.exists());
+    let endpoint = Endpoint::new(path, Some(options)).unwrap();
+    let _incoming = endpoint.incoming().unwrap();
+}
+
 #[tokio::test]
 async fn ok_on_path_overwrite() {
     let path = dummy_endpoint("test");
     #[cfg(not(windows))]
     let options = Some(tokio_ipc::EndpointOptions {
         on_conflict: tokio_ipc::OnConflict::Overwrite,
+        ..Default::default()
     });
     #[cfg(windows)]
     let options = None;
@@ -197,6 +288,7 @@ async fn incoming_stream_is_static() {
     #[cfg(not(windows))]
     let options = Some(tokio_ipc::EndpointOptions {
         on_conflict: tokio_ipc::OnConflict::Overwrite,
+        ..Default::default()
     });
     #[cfg(windows)]
     let options = None;
@@ -210,6 +302,7 @@ fn create_endpoint_with_permissions(attr: SecurityAttributes) -> ::std::io::Resu
     #[cfg(not(windows))]
     let options = Some(tokio_ipc::EndpointOptions {
         on_conflict: tokio_ipc::OnConflict::Overwrite,
+        ..Default::default()
     });
     #[cfg(windows)]
     let options = None;
@@ -232,6 +325,44 @@ async fn test_endpoint_permissions() {
             .unwrap(),
     )
     .expect("failed with attributes for connecting");
+    create_endpoint_with_permissions(SecurityAttributes::same_user_only().unwrap())
+        .expect("failed with same user only attributes");
+}
+
+#[tokio::test]
+async fn same_user_only_accepts_own_user() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), None)
+        .unwrap()
+        .security_attributes(SecurityAttributes::same_user_only().unwrap());
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut client = Endpoint::connect(path, None).await.unwrap();
+    let mut server_conn = incoming.next().await.unwrap().unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    server_conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"hello", &buf);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn set_group_changes_socket_group() {
+    use std::os::unix::fs::MetadataExt;
+
+    let gid = unsafe { libc::getegid() };
+    let endpoint = Endpoint::new(dummy_endpoint("test"), None)
+        .unwrap()
+        .security_attributes(SecurityAttributes::empty().set_group(gid).unwrap());
+    let path = endpoint.path().to_path_buf();
+    let _incoming = endpoint.incoming().unwrap();
+    assert_eq!(gid, std::fs::metadata(&path).unwrap().gid());
+
+    let err = SecurityAttributes::empty()
+        .set_group_name("tokio-ipc-no-such-group")
+        .err()
+        .expect("looking up a missing group should fail");
+    assert_eq!(io::ErrorKind::NotFound, err.kind());
 }
 
 #[cfg(unix)]
@@ -243,3 +374,1326 @@ fn set_server_id_directory() {
         .unwrap();
     assert_eq!("/tmp/test.sock", path.to_string_lossy());
 }
+
+#[tokio::test]
+async fn read_timeout_elapses() {
+    let path = dummy_endpoint("test");
+    #[cfg(not(windows))]
+    let options = Some(tokio_ipc::EndpointOptions {
+        on_conflict: tokio_ipc::OnConflict::Overwrite,
+        ..Default::default()
+    });
+    #[cfg(windows)]
+    let options = None;
+
+    let endpoint = Endpoint::new(path, options).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    let server = tokio::spawn(async move {
+        // Hold the connection open without ever writing to it
+        let conn = incoming.next().await;
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        drop(conn);
+    });
+
+    let mut client = Endpoint::connect(path, None).await.unwrap();
+    client.set_read_timeout(Some(Duration::from_millis(100)));
+    assert_eq!(Some(Duration::from_millis(100)), client.read_timeout());
+
+    let mut buf = [0u8; 1];
+    let err = client.read(&mut buf).await.unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+    server.abort();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn wait_for_path_resolves_on_bind() {
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let endpoint = Endpoint::new(path.clone(), None).unwrap();
+
+    let waiter = tokio::spawn(tokio_ipc::wait_for_path(path.clone()));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let _incoming = endpoint.incoming().unwrap();
+
+    tokio::time::timeout(Duration::from_secs(5), waiter)
+        .await
+        .expect("timed out waiting for socket")
+        .unwrap()
+        .unwrap();
+    assert!(path.exists());
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn wait_for_pipe_resolves_on_create() {
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let endpoint = Endpoint::new(path.clone(), None).unwrap();
+
+    let waiter = tokio::spawn(tokio_ipc::wait_for_pipe(
+        path.clone(),
+        Duration::from_secs(5),
+    ));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let _incoming = endpoint.incoming().unwrap();
+
+    waiter.await.unwrap().unwrap();
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn wait_for_pipe_times_out() {
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let err = tokio_ipc::wait_for_pipe(path, Duration::from_millis(100))
+        .await
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+}
+
+#[tokio::test(start_paused = true)]
+async fn read_timeout_with_paused_clock() {
+    let path = dummy_endpoint("test");
+    #[cfg(not(windows))]
+    let options = Some(tokio_ipc::EndpointOptions {
+        on_conflict: tokio_ipc::OnConflict::Overwrite,
+        ..Default::default()
+    });
+    #[cfg(windows)]
+    let options = None;
+
+    let endpoint = Endpoint::new(path, options).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    let server = tokio::spawn(async move {
+        let conn = incoming.next().await;
+        std::future::pending::<()>().await;
+        drop(conn);
+    });
+
+    let mut client = Endpoint::connect(path, None).await.unwrap();
+    // An hour-long timeout resolves instantly because the runtime auto-advances the paused clock
+    client.set_read_timeout(Some(Duration::from_secs(3600)));
+    let start = tokio::time::Instant::now();
+    let mut buf = [0u8; 1];
+    let err = client.read(&mut buf).await.unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+    assert!(start.elapsed() >= Duration::from_secs(3600));
+    server.abort();
+}
+
+#[tokio::test(start_paused = true)]
+async fn read_timeout_restarts_after_cancelled_read() {
+    let (mut client, _server) = Endpoint::pair().await.unwrap();
+    client.set_read_timeout(Some(Duration::from_secs(10)));
+
+    let mut buf = [0u8; 1];
+    let cancelled = tokio::time::timeout(Duration::from_secs(9), client.read(&mut buf)).await;
+    assert!(cancelled.is_err());
+
+    // The next read gets the full timeout rather than the second left over from the dropped one
+    let start = tokio::time::Instant::now();
+    let err = client.read(&mut buf).await.unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+    assert!(start.elapsed() >= Duration::from_secs(10));
+}
+
+#[tokio::test]
+async fn endpoint_stats_track_connections() {
+    let path = dummy_endpoint("test");
+    #[cfg(not(windows))]
+    let options = Some(tokio_ipc::EndpointOptions {
+        on_conflict: tokio_ipc::OnConflict::Overwrite,
+        ..Default::default()
+    });
+    #[cfg(windows)]
+    let options = None;
+
+    let endpoint = Endpoint::new(path, options).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    let stats = incoming.stats();
+
+    let mut client = Endpoint::connect(path, None).await.unwrap();
+    let mut server_conn = incoming.next().await.unwrap().unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    server_conn.read_exact(&mut buf).await.unwrap();
+    server_conn.write_all(b"hi").await.unwrap();
+
+    let snapshot = stats.snapshot();
+    assert_eq!(1, snapshot.active_connections);
+    assert_eq!(1, snapshot.accepted_connections);
+    assert_eq!(5, snapshot.bytes_read);
+    assert_eq!(2, snapshot.bytes_written);
+
+    drop(server_conn);
+    assert_eq!(0, stats.snapshot().active_connections);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn refuse_insecure_parent_folder() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let folder = std::env::temp_dir().join(format!(
+        "tokio-ipc-insecure-{}",
+        rand::Rng::gen::<u64>(&mut rand::thread_rng())
+    ));
+    std::fs::create_dir(&folder).unwrap();
+    std::fs::set_permissions(&folder, std::fs::Permissions::from_mode(0o777)).unwrap();
+    let path = ServerId::new("test").parent_folder(&folder);
+
+    let err = Endpoint::new(path.clone(), None)
+        .unwrap()
+        .incoming()
+        .err()
+        .expect("binding in a world-writable folder should fail");
+    assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+
+    let options = tokio_ipc::EndpointOptions {
+        allow_insecure_folder: true,
+        ..Default::default()
+    };
+    let incoming = Endpoint::new(path, Some(options)).unwrap().incoming();
+    assert!(incoming.is_ok());
+    drop(incoming);
+    std::fs::remove_dir_all(folder).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn refuse_folder_owned_by_another_user() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let folder = std::env::temp_dir().join(format!(
+        "tokio-ipc-foreign-{}",
+        rand::Rng::gen::<u64>(&mut rand::thread_rng())
+    ));
+    std::fs::create_dir(&folder).unwrap();
+    std::fs::set_permissions(&folder, std::fs::Permissions::from_mode(0o755)).unwrap();
+    // Handing the folder to another user takes root
+    if std::os::unix::fs::chown(&folder, Some(65534), None).is_err() {
+        std::fs::remove_dir_all(folder).unwrap();
+        return;
+    }
+
+    let err = Endpoint::new(ServerId::new("test").parent_folder(&folder), None)
+        .unwrap()
+        .incoming()
+        .err()
+        .expect("binding in a folder owned by another user should fail");
+    assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+    std::fs::remove_dir_all(folder).unwrap();
+}
+
+#[tokio::test]
+async fn verify_server_identity() {
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let _incoming = endpoint.incoming().unwrap();
+
+    let options = tokio_ipc::EndpointOptions {
+        server_identity: Some(tokio_ipc::ServerIdentity::CurrentUser),
+        ..Default::default()
+    };
+    assert!(Endpoint::connect(path.clone(), Some(options)).await.is_ok());
+
+    #[cfg(unix)]
+    {
+        let options = tokio_ipc::EndpointOptions {
+            server_identity: Some(tokio_ipc::ServerIdentity::Uid(u32::MAX - 1)),
+            ..Default::default()
+        };
+        let err = Endpoint::connect(path, Some(options))
+            .await
+            .err()
+            .expect("connecting to a socket with the wrong owner should fail");
+        assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+    }
+}
+
+#[tokio::test]
+async fn named_lock_and_event() {
+    use tokio_ipc::sync::{NamedEvent, NamedLock};
+
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let name = format!("sync-{num}");
+
+    let lock = NamedLock::try_acquire(&name).unwrap();
+    assert!(lock.is_some());
+    assert!(NamedLock::try_acquire(&name).unwrap().is_none());
+    drop(lock);
+    assert!(NamedLock::try_acquire(&name).unwrap().is_some());
+
+    // Signaling an event nobody waits on is a no-op
+    NamedEvent::signal(&name).unwrap();
+
+    let mut event = NamedEvent::create(&name).unwrap();
+    NamedEvent::signal(&name).unwrap();
+    tokio::time::timeout(Duration::from_secs(5), event.wait())
+        .await
+        .expect("event should be signaled")
+        .unwrap();
+}
+
+#[tokio::test]
+async fn broadcast_reaches_all_receivers() {
+    use tokio_ipc::broadcast::{broadcast, BroadcastReceiver};
+
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let group = format!("broadcast-{num}");
+
+    let mut first = BroadcastReceiver::join(&group).unwrap();
+    let mut second = BroadcastReceiver::join(&group).unwrap();
+    assert_eq!(2, broadcast(&group, b"update available").unwrap());
+
+    for receiver in [&mut first, &mut second] {
+        let mut buf = [0; 64];
+        let len = tokio::time::timeout(Duration::from_secs(5), receiver.recv(&mut buf))
+            .await
+            .expect("message should arrive")
+            .unwrap();
+        assert_eq!(b"update available", &buf[..len]);
+    }
+
+    drop(second);
+    assert_eq!(1, broadcast(&group, b"again").unwrap());
+}
+
+#[test]
+fn broadcast_rejects_paths_as_group_names() {
+    use tokio_ipc::broadcast::{broadcast, BroadcastReceiver};
+
+    for group in ["", "../escape", "nested/group", r"nested\group"] {
+        let err = BroadcastReceiver::join(group).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        let err = broadcast(group, b"hello").unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    }
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn pipe_info_reports_pipe_state() {
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path, None).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+
+    for conn in [&client, &server] {
+        let info = conn.pipe_info().unwrap();
+        assert_eq!(tokio_ipc::PipeMode::Byte, info.pipe_mode);
+        assert_eq!(tokio_ipc::PipeMode::Byte, info.read_mode);
+        assert!(info.current_instances >= 1);
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn accept_batch_hands_out_every_connection() {
+    let path = dummy_endpoint("test");
+    let options = tokio_ipc::EndpointOptions {
+        accept_batch_size: std::num::NonZeroUsize::new(4),
+        ..Default::default()
+    };
+    let endpoint = Endpoint::new(path, Some(options)).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut clients = Vec::new();
+    for _ in 0..6 {
+        clients.push(Endpoint::connect(path.clone(), None).await.unwrap());
+    }
+    for _ in 0..6 {
+        tokio::time::timeout(Duration::from_secs(5), incoming.next())
+            .await
+            .expect("connection should be accepted")
+            .unwrap()
+            .unwrap();
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn into_inner_keeps_listener_bound() {
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let listener = endpoint.incoming().unwrap().into_inner();
+    assert!(path.exists());
+
+    let client = Endpoint::connect(path.clone(), None).await.unwrap();
+    let (mut server, _addr) = listener.accept().await.unwrap();
+    let mut client = client.into_inner();
+    client.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"ping", &buf);
+
+    drop(listener);
+    std::fs::remove_file(path).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn raw_parts_hand_over_listener() {
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let (listener, socket_file) = endpoint.incoming().unwrap().into_raw_parts().unwrap();
+    assert_eq!(Some(&path), socket_file.as_ref());
+    assert!(path.exists());
+
+    // Clients can connect while nobody is accepting
+    let mut client = Endpoint::connect(path.clone(), None).await.unwrap();
+    let mut incoming = tokio_ipc::IpcStream::from_raw_parts(listener, socket_file).unwrap();
+    let mut server = incoming.accept().await.unwrap();
+    client.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"ping", &buf);
+
+    drop(incoming);
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn max_connection_lifetime_closes_connection() {
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    incoming.set_max_connection_lifetime(Some(Duration::from_millis(50)));
+
+    let client = Endpoint::connect(path, None).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+    assert_eq!(Some(Duration::from_millis(50)), server.max_lifetime());
+
+    // The read resolves once the lifetime is over instead of waiting for data
+    let mut buf = [0; 4];
+    assert_eq!(0, server.read(&mut buf).await.unwrap());
+    let err = server.write_all(b"ping").await.unwrap_err();
+    assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+
+    // Named pipes have no half-close, so only Unix peers observe the end of the stream
+    #[cfg(unix)]
+    {
+        let mut client = client;
+        assert_eq!(0, client.read(&mut buf).await.unwrap());
+    }
+    #[cfg(windows)]
+    drop(client);
+}
+
+#[tokio::test]
+async fn connection_idle_timeout_closes_idle_connection() {
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    incoming.set_connection_idle_timeout(Some(Duration::from_millis(100)));
+
+    let mut client = Endpoint::connect(path, None).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+    assert_eq!(Some(Duration::from_millis(100)), server.idle_timeout());
+
+    // Activity keeps the connection open past the idle timeout
+    let mut buf = [0; 4];
+    for _ in 0..4 {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        client.write_all(b"ping").await.unwrap();
+        server.read_exact(&mut buf).await.unwrap();
+    }
+
+    // The read resolves once the connection was idle for too long
+    assert_eq!(0, server.read(&mut buf).await.unwrap());
+    let err = server.write_all(b"ping").await.unwrap_err();
+    assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+}
+
+#[tokio::test]
+async fn expiry_is_noticed_without_polling_the_connection() {
+    use tokio_ipc::ExpiryReason;
+
+    let (_client, mut server) = Endpoint::pair().await.unwrap();
+    server.set_idle_timeout(Some(Duration::from_millis(50)));
+    assert_eq!(None, server.expiry());
+
+    let reason = tokio::time::timeout(Duration::from_secs(5), server.expired())
+        .await
+        .expect("the idle timeout should run out");
+    assert_eq!(ExpiryReason::Idle, reason);
+    assert_eq!(Some(ExpiryReason::Idle), server.expiry());
+
+    let mut buf = [0; 4];
+    assert_eq!(0, server.read(&mut buf).await.unwrap());
+}
+
+#[tokio::test]
+async fn connection_extensions() {
+    #[derive(Debug, PartialEq)]
+    struct UserId(u32);
+
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let _client = Endpoint::connect(path, None).await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+    assert!(server.extensions().is_empty());
+
+    assert_eq!(None, server.extensions_mut().insert(UserId(1)));
+    assert_eq!(Some(UserId(1)), server.extensions_mut().insert(UserId(2)));
+    server.extensions_mut().get_mut::<UserId>().unwrap().0 += 1;
+    assert_eq!(Some(&UserId(3)), server.extensions().get::<UserId>());
+    assert_eq!(None, server.extensions().get::<String>());
+    assert_eq!(Some(UserId(3)), server.extensions_mut().remove::<UserId>());
+    assert!(server.extensions().is_empty());
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn remote_pipes_require_opt_in() {
+    let path = ServerId::new("test").remote_host("other-host");
+    let err = Endpoint::connect(path, None)
+        .await
+        .err()
+        .expect("connecting to a remote pipe should require opt-in");
+    assert_eq!(io::ErrorKind::PermissionDenied, err.kind());
+}
+
+#[tokio::test]
+async fn randomized_endpoint_discovery() {
+    let first = ServerId::randomized("test").unwrap();
+    assert_ne!(first, ServerId::randomized("test").unwrap());
+
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let name = format!("discovery-{num}");
+    let endpoint = Endpoint::new(first, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let advertisement = tokio_ipc::discovery::advertise(&name, path).unwrap();
+    let _incoming = endpoint.incoming().unwrap();
+
+    let path = tokio_ipc::discovery::discover(&name).unwrap();
+    assert_eq!(advertisement.endpoint(), path);
+    assert!(Endpoint::connect(path, None).await.is_ok());
+
+    drop(advertisement);
+    let err = tokio_ipc::discovery::discover(&name).unwrap_err();
+    assert_eq!(io::ErrorKind::NotFound, err.kind());
+}
+
+#[test]
+fn discovery_rejects_paths_as_names() {
+    for name in ["", "../escape", "nested/name", r"nested\name"] {
+        let err = tokio_ipc::discovery::advertise(name, dummy_endpoint("test")).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+        let err = tokio_ipc::discovery::discover(name).unwrap_err();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    }
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn rebind_removed_socket_file() {
+    let path = dummy_endpoint("test");
+    let options = tokio_ipc::EndpointOptions {
+        on_socket_removed: tokio_ipc::OnSocketRemoved::Rebind,
+        ..Default::default()
+    };
+    let endpoint = Endpoint::new(path, Some(options)).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    let server = tokio::spawn(async move { incoming.next().await.unwrap() });
+
+    std::fs::remove_file(&path).unwrap();
+    let rebound = tokio_ipc::wait_for_path(path.clone());
+    tokio::time::timeout(Duration::from_secs(5), rebound)
+        .await
+        .expect("socket file should be rebound")
+        .unwrap();
+
+    let _client = Endpoint::connect(path, None).await.unwrap();
+    server.await.unwrap().unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn removed_socket_file_keeps_failing_accepts() {
+    let path = dummy_endpoint("test");
+    let options = tokio_ipc::EndpointOptions {
+        on_socket_removed: tokio_ipc::OnSocketRemoved::Error,
+        ..Default::default()
+    };
+    let endpoint = Endpoint::new(path, Some(options)).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    std::fs::remove_file(&path).unwrap();
+    for _ in 0..2 {
+        let err = tokio::time::timeout(Duration::from_secs(5), incoming.accept())
+            .await
+            .expect("the removal should be noticed")
+            .unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, err.kind());
+        let removed = err
+            .get_ref()
+            .and_then(|e| e.downcast_ref::<tokio_ipc::SocketRemoved>())
+            .expect("the error should say the socket was removed");
+        assert_eq!(path, removed.path());
+    }
+}
+
+#[tokio::test]
+async fn owned_split_halves() {
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path, None).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+
+    let (mut server_read, mut server_write) = server.into_split();
+    let echo = tokio::spawn(async move {
+        let mut buf = [0; 4];
+        server_read.read_exact(&mut buf).await.unwrap();
+        buf
+    });
+    let (mut client_read, mut client_write) = client.into_split();
+    client_write.write_all(b"ping").await.unwrap();
+    let received = echo.await.unwrap();
+    server_write.write_all(&received).await.unwrap();
+
+    let mut buf = [0; 4];
+    client_read.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"ping", &buf);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn peer_credentials_identify_client() {
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let _client = Endpoint::connect(path, None).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+    let credentials = server.peer_credentials().unwrap();
+    assert_eq!(unsafe { libc::geteuid() }, credentials.uid());
+    assert_eq!(unsafe { libc::getegid() }, credentials.gid());
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    assert_eq!(Some(std::process::id()), credentials.pid());
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn peer_credentials_identify_pipe_process() {
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path, None).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+    for conn in [&client, &server] {
+        let credentials = conn.peer_credentials().unwrap();
+        assert_eq!(Some(std::process::id()), credentials.pid());
+        assert!(credentials.sid().starts_with("S-1-"));
+        assert_eq!(None, credentials.remote_computer());
+    }
+    assert_eq!(
+        client.peer_credentials().unwrap(),
+        server.peer_credentials().unwrap()
+    );
+}
+
+#[tokio::test]
+async fn incoming_filtered_drops_rejected_peers() {
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming_filtered(|_| false).unwrap();
+
+    let mut client = Endpoint::connect(path, None).await.unwrap();
+    let accepted = tokio::time::timeout(Duration::from_millis(200), incoming.next()).await;
+    assert!(accepted.is_err(), "rejected peer shouldn't be yielded");
+    let mut buf = [0u8; 1];
+    assert!(matches!(client.read(&mut buf).await, Ok(0) | Err(_)));
+
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let pid = std::process::id();
+    let mut incoming = endpoint
+        .incoming_filtered(move |credentials| credentials.pid().map_or(true, |p| p == pid))
+        .unwrap();
+
+    let _client = Endpoint::connect(path, None).await.unwrap();
+    let _server = incoming.next().await.unwrap().unwrap();
+    assert_eq!(1, incoming.stats().snapshot().accepted_connections);
+}
+
+#[tokio::test]
+async fn reconnecting_connection_recovers() {
+    use tokio_ipc::{Backoff, ConnectionState, ReconnectingConnection};
+
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut client = ReconnectingConnection::connect(path, None, Backoff::default())
+        .await
+        .unwrap();
+    let states = client.state_changes();
+    drop(incoming.next().await.unwrap().unwrap());
+
+    let server = tokio::spawn(async move {
+        let mut conn = incoming.next().await.unwrap().unwrap();
+        conn.write_all(b"back").await.unwrap();
+        conn
+    });
+    let mut buf = [0; 4];
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"back", &buf);
+    assert_eq!(ConnectionState::Connected, client.state());
+    assert!(states.has_changed().unwrap());
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn pool_reuses_live_connections() {
+    use tokio_ipc::Pool;
+
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    let pool = Pool::new(path, None, 2).unwrap();
+
+    let mut first = pool.get().await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+    first.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    drop(first);
+    assert_eq!(1, pool.idle_count());
+
+    // The idle connection is handed out again without connecting.
+    let reused = pool.get().await.unwrap();
+    assert_eq!(0, pool.idle_count());
+    drop(reused);
+
+    // Once the server closes it, the next checkout opens a fresh connection.
+    drop(server);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let mut fresh = pool.get().await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+    fresh.write_all(b"pong").await.unwrap();
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"pong", &buf);
+}
+
+#[test]
+fn pool_rejects_zero_size() {
+    let err = tokio_ipc::Pool::new(dummy_endpoint("test"), None, 0)
+        .err()
+        .expect("a pool without room for connections should be rejected");
+    assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+}
+
+#[tokio::test]
+async fn pool_discards_connections_with_unexpected_data() {
+    use tokio_ipc::Pool;
+
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    let pool = Pool::new(path, None, 1).unwrap();
+
+    let first = pool.get().await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+    drop(first);
+    server.write_all(b"stray").await.unwrap();
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    let mut fresh = pool.get().await.unwrap();
+    let mut server = incoming.next().await.unwrap().unwrap();
+    fresh.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"ping", &buf);
+}
+
+#[tokio::test]
+async fn connect_with_retries_until_server_binds() {
+    use tokio_ipc::{Backoff, ConnectOptions};
+
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let err = Endpoint::connect_with(
+        path.clone(),
+        None,
+        ConnectOptions {
+            timeout: Some(Duration::from_millis(100)),
+            retry: Some(Backoff::default()),
+        },
+    )
+    .await
+    .unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+
+    let connect_options = ConnectOptions {
+        timeout: Some(Duration::from_secs(5)),
+        retry: Some(Backoff {
+            initial_delay: Duration::from_millis(10),
+            ..Default::default()
+        }),
+    };
+    let client = tokio::spawn(Endpoint::connect_with(path.clone(), None, connect_options));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    let mut incoming = Endpoint::new(path, None).unwrap().incoming().unwrap();
+    let _server = incoming.next().await.unwrap().unwrap();
+    client.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn connect_wait_waits_for_server() {
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let client = tokio::spawn(Endpoint::connect_wait(
+        path.clone(),
+        None,
+        Duration::from_secs(5),
+    ));
+    tokio::time::sleep(Duration::from_millis(100)).await;
+    assert!(!client.is_finished());
+
+    let mut incoming = Endpoint::new(path, None).unwrap().incoming().unwrap();
+    let _server = incoming.next().await.unwrap().unwrap();
+    client.await.unwrap().unwrap();
+
+    let missing = dummy_endpoint("test");
+    let err = Endpoint::connect_wait(missing, None, Duration::from_millis(50))
+        .await
+        .unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+}
+
+#[tokio::test]
+async fn shutdown_stops_accepting_and_drains() {
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    let shutdown = incoming.shutdown_handle();
+
+    let _client = Endpoint::connect(path, None).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+
+    // The connection is still open, so draining times out.
+    let drained = shutdown
+        .clone()
+        .shutdown(Some(Duration::from_millis(50)))
+        .await;
+    assert!(!drained);
+    assert!(incoming.next().await.is_none());
+
+    let draining = tokio::spawn(shutdown.shutdown(Some(Duration::from_secs(5))));
+    drop(server);
+    assert!(draining.await.unwrap());
+}
+
+#[tokio::test]
+async fn serve_runs_handlers_until_shutdown() {
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let server = tokio::spawn(endpoint.serve(
+        |mut conn| async move {
+            let mut buf = [0; 4];
+            conn.read_exact(&mut buf).await.unwrap();
+            conn.write_all(&buf).await.unwrap();
+        },
+        async {
+            let _ = stop_rx.await;
+        },
+    ));
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let mut client = Endpoint::connect(path.clone(), None).await.unwrap();
+    client.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"ping", &buf);
+
+    stop_tx.send(()).unwrap();
+    server.await.unwrap().unwrap();
+    assert!(Endpoint::connect(path, None).await.is_err());
+}
+
+#[tokio::test]
+async fn serve_limits_batch_handlers() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    incoming.set_qos_classifier(|_| tokio_ipc::QosClass::Batch);
+    incoming.set_max_batch_handlers(std::num::NonZeroUsize::new(1));
+    let server = tokio::spawn(incoming.serve(
+        |mut conn| async move {
+            conn.write_all(b"hi").await.unwrap();
+            // Hold on to the slot until the client hangs up
+            let mut buf = [0; 1];
+            let _ = conn.read(&mut buf).await;
+        },
+        std::future::pending(),
+    ));
+
+    let mut buf = [0; 2];
+    let mut first = Endpoint::connect(path.clone(), None).await.unwrap();
+    first.read_exact(&mut buf).await.unwrap();
+
+    let mut second = Endpoint::connect(path, None).await.unwrap();
+    let waiting = tokio::time::timeout(Duration::from_millis(200), second.read_exact(&mut buf));
+    assert!(waiting.await.is_err(), "batch handler shouldn't start yet");
+
+    drop(first);
+    tokio::time::timeout(Duration::from_secs(5), second.read_exact(&mut buf))
+        .await
+        .expect("second batch handler should start once the first is done")
+        .unwrap();
+    assert_eq!(b"hi", &buf);
+    server.abort();
+}
+
+#[tokio::test]
+async fn middleware_chain_wraps_handler() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    use tokio_ipc::middleware::{from_fn, Chain};
+    use tokio_ipc::PeerCredentials;
+
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+
+    let finished = Arc::new(AtomicUsize::new(0));
+    let counter = finished.clone();
+    let handler = Chain::new()
+        .layer(from_fn(move |conn, next| {
+            let counter = counter.clone();
+            async move {
+                next.run(conn).await;
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        }))
+        .layer(from_fn(|mut conn, next| async move {
+            let credentials = conn.peer_credentials().unwrap();
+            conn.extensions_mut().insert(credentials);
+            next.run(conn).await;
+        }))
+        .handler(|mut conn| async move {
+            let credentials = conn.extensions().get::<PeerCredentials>().cloned();
+            assert_eq!(Some(std::process::id()), credentials.unwrap().pid());
+            conn.write_all(b"done").await.unwrap();
+        });
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let server = tokio::spawn(endpoint.serve(handler, async {
+        let _ = stop_rx.await;
+    }));
+
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    let mut client = Endpoint::connect(path, None).await.unwrap();
+    let mut buf = [0; 4];
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"done", &buf);
+
+    stop_tx.send(()).unwrap();
+    server.await.unwrap().unwrap();
+    assert_eq!(1, finished.load(Ordering::SeqCst));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn max_connections_pauses_accepting() {
+    let path = dummy_endpoint("test");
+    let options = tokio_ipc::EndpointOptions {
+        max_connections: std::num::NonZeroUsize::new(1),
+        ..Default::default()
+    };
+    let endpoint = Endpoint::new(path, Some(options)).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let _first_client = Endpoint::connect(path.clone(), None).await.unwrap();
+    let _second_client = Endpoint::connect(path, None).await.unwrap();
+    let first = incoming.accept().await.unwrap();
+
+    let pending = tokio::time::timeout(Duration::from_millis(100), incoming.accept()).await;
+    assert!(pending.is_err(), "accepted beyond the connection limit");
+
+    drop(first);
+    tokio::time::timeout(Duration::from_secs(5), incoming.accept())
+        .await
+        .expect("connection should be accepted once below the limit")
+        .unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn connection_passes_file_descriptors() {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path, None).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+
+    let (mut local, remote) = UnixStream::pair().unwrap();
+    let sent = client.send_with_fds(b"fd", &[remote]).await.unwrap();
+    assert_eq!(2, sent);
+
+    let mut buf = [0; 8];
+    let (received, fds, truncated) = server.recv_with_fds(&mut buf).await.unwrap();
+    assert_eq!(b"fd", &buf[..received]);
+    assert_eq!(1, fds.len());
+    assert!(!truncated);
+
+    // The received descriptor refers to the same socket the client sent
+    let mut remote = UnixStream::from(fds.into_iter().next().unwrap());
+    remote.write_all(b"hello").unwrap();
+    let mut hello = [0; 5];
+    local.read_exact(&mut hello).unwrap();
+    assert_eq!(b"hello", &hello);
+
+    let err = client.send_with_fds(&[], &[local]).await.unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn peer_security_context_matches_own_process() {
+    let (a, b) = Endpoint::pair().await.unwrap();
+    match (a.peer_security_context(), b.peer_security_context()) {
+        (Ok(a_label), Ok(b_label)) => assert_eq!(a_label, b_label),
+        // No security module labels sockets on this system
+        (Err(a_err), Err(b_err)) => {
+            assert_eq!(Some(libc::ENOPROTOOPT), a_err.raw_os_error());
+            assert_eq!(a_err.raw_os_error(), b_err.raw_os_error());
+        }
+        results => panic!("Ends of the same pair disagree: {results:?}"),
+    }
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn connection_passes_credentials() {
+    let path = dummy_endpoint("test");
+    let endpoint = Endpoint::new(path, None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut client = Endpoint::connect(path, None).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+    server.set_pass_credentials(true).unwrap();
+
+    client.send_with_credentials(b"one").await.unwrap();
+    let mut buf = [0; 3];
+    let (received, credentials) = server.recv_with_credentials(&mut buf).await.unwrap();
+    assert_eq!(b"one", &buf[..received]);
+    let credentials = credentials.unwrap();
+    let peer = server.peer_credentials().unwrap();
+    assert_eq!(std::process::id(), credentials.pid());
+    assert_eq!(peer.uid(), credentials.uid());
+    assert_eq!(peer.gid(), credentials.gid());
+
+    // With SO_PASSCRED enabled, plain writes carry credentials as well
+    client.write_all(b"two").await.unwrap();
+    let (received, credentials) = server.recv_with_credentials(&mut buf).await.unwrap();
+    assert_eq!(b"two", &buf[..received]);
+    assert_eq!(Some(std::process::id()), credentials.map(|c| c.pid()));
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn datagram_sockets_exchange_messages() {
+    use tokio_ipc::datagram::DatagramSocket;
+
+    let receiver = DatagramSocket::bind(dummy_endpoint("dgram"), None).unwrap();
+    let path = receiver.path().unwrap().to_path_buf();
+    let reply = DatagramSocket::bind(dummy_endpoint("dgram"), None).unwrap();
+    let anonymous = DatagramSocket::unbound().unwrap();
+
+    anonymous.send_to(b"first", path.clone()).await.unwrap();
+    reply.send_to(b"second", path.clone()).await.unwrap();
+
+    let mut buf = [0; 16];
+    let (len, sender) = receiver.recv_from(&mut buf).await.unwrap();
+    assert_eq!(b"first", &buf[..len]);
+    assert_eq!(None, sender);
+    let (len, sender) = receiver.recv_from(&mut buf).await.unwrap();
+    assert_eq!(b"second", &buf[..len]);
+    assert_eq!(reply.path(), sender.as_deref());
+
+    drop(receiver);
+    assert!(!path.exists());
+}
+
+#[tokio::test]
+async fn pair_connects_both_ends() {
+    let (mut first, mut second) = Endpoint::pair().await.unwrap();
+
+    first.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    second.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"ping", &buf);
+
+    second.write_all(b"pong").await.unwrap();
+    first.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"pong", &buf);
+
+    drop(first);
+    assert_eq!(0, second.read(&mut buf).await.unwrap());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn buffer_sizes_apply_to_connections() {
+    let options = tokio_ipc::EndpointOptions {
+        send_buffer_size: Some(65536),
+        recv_buffer_size: Some(32768),
+        ..Default::default()
+    };
+    let endpoint = Endpoint::new(dummy_endpoint("test"), Some(options)).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path, Some(options)).await.unwrap();
+    let server_conn = incoming.next().await.unwrap().unwrap();
+    for conn in [&client, &server_conn] {
+        // Linux doubles the requested sizes
+        assert!(conn.send_buffer_size().unwrap() >= 65536);
+        assert!(conn.recv_buffer_size().unwrap() >= 32768);
+    }
+
+    client.set_send_buffer_size(16384).unwrap();
+    let size = client.send_buffer_size().unwrap();
+    assert!((16384..65536).contains(&size));
+}
+
+#[tokio::test]
+async fn try_read_and_write_without_waiting() {
+    use tokio::io::Interest;
+
+    let (first, second) = Endpoint::pair().await.unwrap();
+    let mut buf = [0; 4];
+    let err = second.try_read(&mut buf).unwrap_err();
+    assert_eq!(io::ErrorKind::WouldBlock, err.kind());
+
+    first.ready(Interest::WRITABLE).await.unwrap();
+    assert_eq!(4, first.try_write(b"ping").unwrap());
+
+    let mut read = 0;
+    while read < buf.len() {
+        let ready = second.ready(Interest::READABLE).await.unwrap();
+        assert!(ready.is_readable());
+        match second.try_read(&mut buf[read..]) {
+            Ok(n) => read += n,
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            Err(e) => panic!("{e}"),
+        }
+    }
+    assert_eq!(b"ping", &buf);
+}
+
+#[tokio::test]
+async fn peek_leaves_bytes_for_reads() {
+    let (mut first, mut second) = Endpoint::pair().await.unwrap();
+    first.write_all(b"magic-rest").await.unwrap();
+
+    let mut magic = [0; 5];
+    let peeked = second.peek(&mut magic).await.unwrap();
+    assert!(peeked > 0);
+    assert_eq!(&b"magic"[..peeked], &magic[..peeked]);
+
+    let mut buf = [0; 10];
+    second.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"magic-rest", &buf);
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn connection_converts_to_and_from_fd() {
+    use std::os::fd::{AsFd, AsRawFd};
+
+    let (mut first, second) = Endpoint::pair().await.unwrap();
+    let raw_fd = second.as_raw_fd();
+    assert_eq!(raw_fd, second.as_fd().as_raw_fd());
+
+    let fd = second.into_fd().unwrap();
+    assert_eq!(raw_fd, fd.as_raw_fd());
+    let mut second = Connection::from_fd(fd).await.unwrap();
+    first.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    second.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"ping", &buf);
+
+    let incoming = Endpoint::new(dummy_endpoint("test"), None)
+        .unwrap()
+        .incoming()
+        .unwrap();
+    assert_eq!(incoming.as_raw_fd(), incoming.as_fd().as_raw_fd());
+}
+
+#[tokio::test]
+async fn connection_writes_vectored() {
+    let (mut first, mut second) = Endpoint::pair().await.unwrap();
+    #[cfg(unix)]
+    assert!(tokio::io::AsyncWrite::is_write_vectored(&first));
+
+    let bufs = [io::IoSlice::new(b"head"), io::IoSlice::new(b"payload")];
+    let written = first.write_vectored(&bufs).await.unwrap();
+    let mut buf = vec![0; written];
+    second.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&b"headpayload"[..written], &buf[..]);
+}
+
+#[tokio::test]
+async fn connections_report_socket_paths() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    assert_eq!(Some(path.clone()), incoming.local_path());
+
+    let client = Endpoint::connect(path.clone(), None).await.unwrap();
+    let server_conn = incoming.next().await.unwrap().unwrap();
+    assert_eq!(Some(path.clone()), server_conn.local_addr().unwrap());
+    assert_eq!(Some(path.clone()), client.peer_addr().unwrap());
+    #[cfg(unix)]
+    {
+        assert_eq!(None, client.local_addr().unwrap());
+        assert_eq!(None, server_conn.peer_addr().unwrap());
+    }
+    #[cfg(windows)]
+    assert_eq!(Some(path), client.local_addr().unwrap());
+}
+
+#[tokio::test]
+async fn connections_share_endpoint_path() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path.clone(), None).await.unwrap();
+    let server_conn = incoming.next().await.unwrap().unwrap();
+    assert_eq!(Some(path.as_path()), server_conn.endpoint_path());
+    assert_eq!(Some(path.as_path()), client.endpoint_path());
+
+    let (first, _second) = Endpoint::pair().await.unwrap();
+    assert_eq!(None, first.endpoint_path());
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn second_listener_on_existing_pipe_fails() {
+    let path = dummy_endpoint("test");
+    let _incoming = Endpoint::new(path.clone(), None)
+        .unwrap()
+        .incoming()
+        .unwrap();
+
+    let err = Endpoint::new(path, None).unwrap().incoming().err().unwrap();
+    assert_eq!(io::ErrorKind::AddrInUse, err.kind());
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn allow_sids_restricts_pipe_access() {
+    // GENERIC_ALL
+    let attributes = SecurityAttributes::allow_sids([("WD", 0x1000_0000)]).unwrap();
+    let endpoint = Endpoint::new(dummy_endpoint("test"), None)
+        .unwrap()
+        .security_attributes(attributes);
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let mut client = Endpoint::connect(path, None).await.unwrap();
+    let mut server_conn = incoming.next().await.unwrap().unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    server_conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"hello", &buf);
+
+    let err = SecurityAttributes::allow_sids([("WD)(A;;GA;;;AN", 0x1000_0000)])
+        .err()
+        .expect("SIDs with SDDL syntax should be rejected");
+    assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    assert!(SecurityAttributes::from_sddl("not sddl").is_err());
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn server_impersonates_client() {
+    let endpoint = Endpoint::new(dummy_endpoint("test"), None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let options = tokio_ipc::EndpointOptions {
+        allow_impersonation: true,
+        ..Default::default()
+    };
+    let mut client = Endpoint::connect(path, Some(options)).await.unwrap();
+    let mut server_conn = incoming.next().await.unwrap().unwrap();
+    client.write_all(b"hello").await.unwrap();
+    let mut buf = [0u8; 5];
+    server_conn.read_exact(&mut buf).await.unwrap();
+
+    let impersonation = server_conn.impersonate_client().unwrap();
+    impersonation.revert().unwrap();
+
+    let err = client.impersonate_client().err().unwrap();
+    assert_eq!(io::ErrorKind::Unsupported, err.kind());
+}
+
+#[cfg(windows)]
+#[tokio::test]
+async fn connection_from_owned_handle() {
+    use std::os::windows::fs::OpenOptionsExt;
+    use std::os::windows::io::OwnedHandle;
+
+    // FILE_FLAG_OVERLAPPED
+    const OVERLAPPED: u32 = 0x4000_0000;
+
+    let path = dummy_endpoint("test").into_ipc_path().unwrap();
+    let mut server = tokio::net::windows::named_pipe::ServerOptions::new()
+        .create(&path)
+        .unwrap();
+    let client_end = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(OVERLAPPED)
+        .open(&path)
+        .unwrap();
+    server.connect().await.unwrap();
+
+    let mut client = Connection::from_owned_handle(OwnedHandle::from(client_end)).unwrap();
+    client.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"ping", &buf);
+
+    let file = std::fs::File::open(std::env::current_exe().unwrap()).unwrap();
+    assert!(Connection::from_owned_handle(OwnedHandle::from(file)).is_err());
+}
@@ -0,0 +1,121 @@
+#![cfg(feature = "metrics")]
+
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+use metrics::{
+    Counter, Gauge, Histogram, HistogramFn, Key, KeyName, Metadata, Recorder, SharedString, Unit,
+};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_ipc::{Endpoint, ServerId};
+
+struct Samples(AtomicU64);
+
+impl HistogramFn for Samples {
+    fn record(&self, _: f64) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+#[derive(Default)]
+struct Registered {
+    counters: Mutex<HashMap<String, Arc<AtomicU64>>>,
+    gauges: Mutex<HashMap<String, Arc<AtomicU64>>>,
+    histograms: Mutex<HashMap<String, Arc<Samples>>>,
+}
+
+impl Registered {
+    fn counter(&self, name: &str) -> u64 {
+        let counters = self.counters.lock().unwrap();
+        counters
+            .get(name)
+            .map_or(0, |value| value.load(Ordering::Relaxed))
+    }
+
+    fn gauge(&self, name: &str) -> f64 {
+        let gauges = self.gauges.lock().unwrap();
+        gauges
+            .get(name)
+            .map_or(0.0, |value| f64::from_bits(value.load(Ordering::Relaxed)))
+    }
+
+    fn samples(&self, name: &str) -> u64 {
+        let histograms = self.histograms.lock().unwrap();
+        histograms
+            .get(name)
+            .map_or(0, |samples| samples.0.load(Ordering::Relaxed))
+    }
+}
+
+struct TestRecorder(Arc<Registered>);
+
+fn has_endpoint_label(key: &Key) -> bool {
+    key.labels().any(|label| label.key() == "endpoint")
+}
+
+impl Recorder for TestRecorder {
+    fn describe_counter(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_gauge(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn describe_histogram(&self, _: KeyName, _: Option<Unit>, _: SharedString) {}
+
+    fn register_counter(&self, key: &Key, _: &Metadata<'_>) -> Counter {
+        assert!(has_endpoint_label(key));
+        let mut counters = self.0.counters.lock().unwrap();
+        Counter::from_arc(counters.entry(key.name().to_owned()).or_default().clone())
+    }
+
+    fn register_gauge(&self, key: &Key, _: &Metadata<'_>) -> Gauge {
+        assert!(has_endpoint_label(key));
+        let mut gauges = self.0.gauges.lock().unwrap();
+        Gauge::from_arc(gauges.entry(key.name().to_owned()).or_default().clone())
+    }
+
+    fn register_histogram(&self, key: &Key, _: &Metadata<'_>) -> Histogram {
+        assert!(has_endpoint_label(key));
+        let mut histograms = self.0.histograms.lock().unwrap();
+        let samples = histograms
+            .entry(key.name().to_owned())
+            .or_insert_with(|| Arc::new(Samples(AtomicU64::new(0))));
+        Histogram::from_arc(samples.clone())
+    }
+}
+
+#[tokio::test]
+async fn endpoints_report_metrics() {
+    let registered = Arc::new(Registered::default());
+    metrics::set_global_recorder(TestRecorder(registered.clone())).unwrap();
+
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let endpoint = Endpoint::new(ServerId::new(format!("metrics-{num}")), None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    let mut client = Endpoint::connect(path, None).await.unwrap();
+    let mut server = incoming.accept().await.unwrap();
+
+    client.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    server.read_exact(&mut buf).await.unwrap();
+    server.write_all(b"pong!").await.unwrap();
+    client.read_exact(&mut buf[..1]).await.unwrap();
+
+    assert_eq!(
+        1,
+        registered.counter("tokio_ipc_accepted_connections_total")
+    );
+    assert_eq!(1.0, registered.gauge("tokio_ipc_active_connections"));
+    assert_eq!(4, registered.counter("tokio_ipc_bytes_read_total"));
+    assert_eq!(5, registered.counter("tokio_ipc_bytes_written_total"));
+    assert_eq!(1, registered.samples("tokio_ipc_connect_duration_seconds"));
+
+    drop(server);
+    assert_eq!(0.0, registered.gauge("tokio_ipc_active_connections"));
+
+    let missing = Endpoint::new(ServerId::new(format!("metrics-missing-{num}")), None).unwrap();
+    assert!(Endpoint::connect(missing.path().to_path_buf(), None)
+        .await
+        .is_err());
+    assert_eq!(1, registered.counter("tokio_ipc_connect_errors_total"));
+}
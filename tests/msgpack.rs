@@ -0,0 +1,27 @@
+#![cfg(feature = "msgpack")]
+
+use serde::{Deserialize, Serialize};
+use tokio_ipc::framing::FramedConnection;
+use tokio_ipc::typed::{MessagePack, TypedConnection};
+use tokio_ipc::Endpoint;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Status {
+    code: u8,
+}
+
+#[tokio::test]
+async fn msgpack_encodes_structs_as_maps() {
+    let (client, server) = Endpoint::pair().await.unwrap();
+    let mut client = TypedConnection::new(client, MessagePack);
+    let mut server = FramedConnection::new(server);
+
+    client.send(&Status { code: 7 }).await.unwrap();
+    let frame = server.recv().await.unwrap().unwrap();
+    // fixmap with one entry, fixstr "code", positive fixint 7
+    assert_eq!(&b"\x81\xa4code\x07"[..], frame);
+
+    let mut server = TypedConnection::<Status, _>::from_framed(server, MessagePack);
+    server.send(&Status { code: 9 }).await.unwrap();
+    assert_eq!(Status { code: 9 }, client.recv().await.unwrap().unwrap());
+}
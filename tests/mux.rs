@@ -0,0 +1,199 @@
+#![cfg(feature = "mux")]
+
+use std::time::Duration;
+
+use futures::StreamExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_ipc::memory::{BudgetPolicy, MemoryBudget};
+use tokio_ipc::mux::{Multiplexer, Priority};
+use tokio_ipc::{Endpoint, ServerId};
+
+#[tokio::test]
+async fn mux_streams_are_independent() {
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let endpoint = Endpoint::new(ServerId::new(format!("mux-{num}")), None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path, None).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+    let client = Multiplexer::client(client);
+    let mut server = Multiplexer::server(server);
+
+    let mut first = client.open().unwrap();
+    let mut second = client.open().unwrap();
+    second.write_all(b"second").await.unwrap();
+    first.write_all(b"first").await.unwrap();
+    first.shutdown().await.unwrap();
+
+    let mut accepted_first = server.accept().await.unwrap();
+    let mut accepted_second = server.accept().await.unwrap();
+    assert_eq!(first.id(), accepted_first.id());
+    assert_eq!(second.id(), accepted_second.id());
+
+    let mut buf = Vec::new();
+    accepted_first.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(b"first", &buf[..]);
+    let mut buf = [0; 6];
+    accepted_second.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"second", &buf);
+
+    // The half-closed stream still carries data the other way.
+    accepted_first.write_all(b"reply").await.unwrap();
+    let mut buf = [0; 5];
+    first.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"reply", &buf);
+
+    drop((client, first, second));
+    assert!(server.accept().await.is_none());
+}
+
+#[tokio::test]
+async fn writes_wait_for_the_reader() {
+    let (client, server) = Endpoint::pair().await.unwrap();
+    let client = Multiplexer::client(client);
+    let mut server = Multiplexer::server(server);
+
+    let mut stream = client.open().unwrap();
+    let payload = vec![7; 1024 * 1024];
+    let write = stream.write_all(&payload);
+    // Nobody reads on the other side, so the write runs out of window
+    assert!(tokio::time::timeout(Duration::from_millis(200), write)
+        .await
+        .is_err());
+
+    let mut accepted = server.accept().await.unwrap();
+    let writer = tokio::spawn(async move {
+        stream.write_all(&payload).await.unwrap();
+        stream.shutdown().await.unwrap();
+        stream
+    });
+    let mut buf = Vec::new();
+    accepted.read_to_end(&mut buf).await.unwrap();
+    // The timed out write got part of the payload out before it was cancelled
+    assert!(buf.len() > 1024 * 1024);
+    assert!(buf.iter().all(|&byte| byte == 7));
+    writer.await.unwrap();
+}
+
+#[tokio::test]
+async fn priority_changes_keep_stream_order() {
+    let (client, server) = Endpoint::pair().await.unwrap();
+    let client = Multiplexer::client(client);
+    let mut server = Multiplexer::server(server);
+
+    let mut bulk = client.open().unwrap();
+    let mut control = client.open().unwrap();
+    control.set_priority(Priority::High);
+    assert_eq!(Priority::High, control.priority());
+
+    bulk.write_all(&vec![1; 128 * 1024]).await.unwrap();
+    control.write_all(b"cancel").await.unwrap();
+    // Switching lanes mid-stream must not let later frames overtake earlier ones
+    control.set_priority(Priority::Normal);
+    control.write_all(b" done").await.unwrap();
+    control.shutdown().await.unwrap();
+
+    let mut accepted_bulk = server.accept().await.unwrap();
+    let mut accepted_control = server.accept().await.unwrap();
+    let mut buf = Vec::new();
+    accepted_control.read_to_end(&mut buf).await.unwrap();
+    assert_eq!(b"cancel done", &buf[..]);
+    let mut buf = vec![0; 128 * 1024];
+    accepted_bulk.read_exact(&mut buf).await.unwrap();
+    assert!(buf.iter().all(|&byte| byte == 1));
+}
+
+#[tokio::test]
+async fn writes_continue_after_peer_shuts_down() {
+    let (client, server) = Endpoint::pair().await.unwrap();
+    let client = Multiplexer::client(client);
+    let mut server = Multiplexer::server(server);
+
+    let mut stream = client.open().unwrap();
+    stream.write_all(b"hello").await.unwrap();
+    let mut accepted = server.accept().await.unwrap();
+    accepted.shutdown().await.unwrap();
+    let mut buf = Vec::new();
+    stream.read_to_end(&mut buf).await.unwrap();
+    assert!(buf.is_empty());
+
+    // More than the initial window, so it only goes through if window updates still arrive
+    let payload = vec![3; 1024 * 1024];
+    let reader = tokio::spawn(async move {
+        let mut buf = vec![0; 5 + 1024 * 1024];
+        accepted.read_exact(&mut buf).await.unwrap();
+        buf
+    });
+    tokio::time::timeout(Duration::from_secs(5), stream.write_all(&payload))
+        .await
+        .expect("writes should not stall after the peer shut down")
+        .unwrap();
+    stream.flush().await.unwrap();
+    let buf = reader.await.unwrap();
+    assert_eq!(b"hello", &buf[..5]);
+    assert!(buf[5..].iter().all(|&byte| byte == 3));
+}
+
+#[tokio::test]
+async fn max_buffered_caps_all_streams_together() {
+    let (client, mut server) = Endpoint::pair().await.unwrap();
+    server.set_max_buffered(Some(100 * 1024));
+    let client = Multiplexer::client(client);
+    let mut server = Multiplexer::server(server);
+
+    let mut bulk = client.open().unwrap();
+    let mut urgent = client.open().unwrap();
+    let payload = vec![7; 192 * 1024];
+    bulk.write_all(&payload).await.unwrap();
+    urgent.write_all(b"ping").await.unwrap();
+
+    let mut accepted_bulk = server.accept().await.unwrap();
+    let mut accepted_urgent = server.accept().await.unwrap();
+    // The unread bulk data fills the cap, so nothing more is read from the connection
+    let mut buf = [0; 4];
+    let read = tokio::time::timeout(
+        Duration::from_millis(200),
+        accepted_urgent.read_exact(&mut buf),
+    );
+    assert!(read.await.is_err());
+
+    let mut received = vec![0; payload.len()];
+    accepted_bulk.read_exact(&mut received).await.unwrap();
+    assert_eq!(payload, received);
+    accepted_urgent.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"ping", &buf);
+}
+
+#[tokio::test]
+async fn memory_budget_sheds_newest_connection() {
+    let budget = MemoryBudget::new(100 * 1024, BudgetPolicy::ShedNewest);
+    let (old_client, mut old_server) = Endpoint::pair().await.unwrap();
+    let (new_client, mut new_server) = Endpoint::pair().await.unwrap();
+    old_server.set_memory_budget(Some(budget.clone()));
+    new_server.set_memory_budget(Some(budget.clone()));
+    let old_client = Multiplexer::client(old_client);
+    let mut old_server = Multiplexer::server(old_server);
+    let new_client = Multiplexer::client(new_client);
+    let mut new_server = Multiplexer::server(new_server);
+
+    let mut old_stream = old_client.open().unwrap();
+    let mut new_stream = new_client.open().unwrap();
+    let mut accepted_old = old_server.accept().await.unwrap();
+    let mut accepted_new = new_server.accept().await.unwrap();
+    new_stream.write_all(&[1; 16 * 1024]).await.unwrap();
+    let mut received = vec![0; 16 * 1024];
+    accepted_new.read_exact(&mut received[..1]).await.unwrap();
+
+    // The older connection needs more than what's left, so the newer one is shed
+    let payload = vec![7; 128 * 1024];
+    old_stream.write_all(&payload).await.unwrap();
+    accepted_new.read_exact(&mut received[1..]).await.unwrap();
+    assert_eq!(0, accepted_new.read(&mut [0; 1]).await.unwrap());
+    drop(accepted_new);
+
+    let mut received = vec![0; payload.len()];
+    accepted_old.read_exact(&mut received).await.unwrap();
+    assert_eq!(payload, received);
+    assert_eq!(budget.limit(), budget.available());
+}
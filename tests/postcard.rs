@@ -0,0 +1,47 @@
+#![cfg(feature = "postcard")]
+
+use serde::{Deserialize, Serialize};
+use tokio_ipc::framing::FramedConnection;
+use tokio_ipc::typed::{Postcard, TypedConnection};
+use tokio_ipc::Endpoint;
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct Reading {
+    sensor: u8,
+    value: u32,
+}
+
+#[tokio::test]
+async fn postcard_uses_the_compact_wire_format() {
+    let (client, server) = Endpoint::pair().await.unwrap();
+    let mut client = TypedConnection::new(client, Postcard);
+    let mut server = FramedConnection::new(server);
+
+    client
+        .send(&Reading {
+            sensor: 2,
+            value: 300,
+        })
+        .await
+        .unwrap();
+    let frame = server.recv().await.unwrap().unwrap();
+    // the sensor byte, then 300 as a varint
+    assert_eq!(&[2, 0xac, 0x02][..], frame);
+
+    let mut server = TypedConnection::<Reading, _>::from_framed(server, Postcard);
+    server
+        .send(&Reading {
+            sensor: 1,
+            value: 5,
+        })
+        .await
+        .unwrap();
+    let reading = client.recv().await.unwrap().unwrap();
+    assert_eq!(
+        Reading {
+            sensor: 1,
+            value: 5
+        },
+        reading
+    );
+}
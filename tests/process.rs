@@ -0,0 +1,60 @@
+#![cfg(feature = "process")]
+
+use std::io;
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+use tokio_ipc::process::{connection_from_parent, spawn_with_connection, CONNECTION_ENV_VAR};
+
+/// Runs as the child of `spawned_child_talks_to_parent`, does nothing otherwise.
+#[tokio::test]
+async fn child_echo() {
+    if std::env::var_os(CONNECTION_ENV_VAR).is_none() {
+        return;
+    }
+    let mut conn = connection_from_parent().unwrap();
+    // Processes spawned by the child don't inherit the variable
+    assert!(std::env::var_os(CONNECTION_ENV_VAR).is_none());
+    let mut buf = [0; 4];
+    conn.read_exact(&mut buf).await.unwrap();
+    conn.write_all(&buf).await.unwrap();
+}
+
+/// Runs as the child of `other_descriptors_are_rejected`, does nothing otherwise.
+#[tokio::test]
+async fn child_rejects_stdin() {
+    if std::env::var_os(CONNECTION_ENV_VAR).is_none() {
+        return;
+    }
+    let error = connection_from_parent().unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidInput, error.kind());
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn other_descriptors_are_rejected() {
+    let status = Command::new(std::env::current_exe().unwrap())
+        .args(["--exact", "child_rejects_stdin", "--test-threads=1"])
+        .env(CONNECTION_ENV_VAR, "0")
+        .stdin(std::process::Stdio::null())
+        .status()
+        .await
+        .unwrap();
+    assert!(status.success());
+}
+
+#[tokio::test]
+async fn spawned_child_talks_to_parent() {
+    let mut command = Command::new(std::env::current_exe().unwrap());
+    command.args(["--exact", "child_echo", "--test-threads=1"]);
+    let (mut child, mut conn) = spawn_with_connection(&mut command).await.unwrap();
+
+    conn.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    conn.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"ping", &buf);
+
+    assert!(child.wait().await.unwrap().success());
+    // The child end was closed along with the child
+    assert_eq!(0, conn.read(&mut buf).await.unwrap());
+}
@@ -0,0 +1,53 @@
+#![cfg(feature = "proxy")]
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_ipc::{proxy, Endpoint};
+
+#[tokio::test]
+async fn relays_between_connections() {
+    let (mut client, mut relay_client) = Endpoint::pair().await.unwrap();
+    let (mut relay_server, mut server) = Endpoint::pair().await.unwrap();
+    let relay =
+        tokio::spawn(
+            async move { proxy::bidirectional(&mut relay_client, &mut relay_server).await },
+        );
+
+    client.write_all(b"request").await.unwrap();
+    let mut buf = [0; 7];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"request", &buf);
+
+    server.write_all(b"response!").await.unwrap();
+    let mut buf = [0; 9];
+    client.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"response!", &buf);
+
+    drop(client);
+    drop(server);
+    assert_eq!((7, 9), relay.await.unwrap().unwrap());
+}
+
+#[tokio::test]
+async fn relays_between_connection_and_tcp() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (mut client, mut relay_client) = Endpoint::pair().await.unwrap();
+    let relay = tokio::spawn(async move {
+        let mut upstream = TcpStream::connect(addr).await.unwrap();
+        proxy::bidirectional(&mut relay_client, &mut upstream).await
+    });
+    let (mut server, _) = listener.accept().await.unwrap();
+
+    let payload = vec![42; 1024 * 1024];
+    let (write, received) = tokio::join!(client.write_all(&payload), async {
+        let mut received = vec![0; payload.len()];
+        server.read_exact(&mut received).await.map(|_| received)
+    });
+    write.unwrap();
+    assert_eq!(payload, received.unwrap());
+
+    drop(client);
+    drop(server);
+    assert_eq!((1024 * 1024, 0), relay.await.unwrap().unwrap());
+}
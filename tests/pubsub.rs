@@ -0,0 +1,67 @@
+#![cfg(feature = "pubsub")]
+
+use std::time::Duration;
+
+use futures::StreamExt;
+use tokio_ipc::pubsub::{Publisher, Subscriber};
+use tokio_ipc::{Endpoint, ServerId};
+
+#[tokio::test]
+async fn publish_reaches_subscribers_only() {
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let endpoint = Endpoint::new(ServerId::new(format!("pubsub-{num}")), None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+    let publisher = Publisher::new(None);
+
+    let mut subscribers = Vec::new();
+    for _ in 0..2 {
+        let client = Endpoint::connect(path.clone(), None).await.unwrap();
+        publisher.add(incoming.next().await.unwrap().unwrap());
+        subscribers.push(Subscriber::new(client));
+    }
+    let mut news = subscribers[0].subscribe("news").unwrap();
+    let mut weather = subscribers[1].subscribe("weather").unwrap();
+
+    // Subscriptions are processed asynchronously by the server.
+    while publisher.publish("news", b"hello") == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(&b"hello"[..], news.next().await.unwrap());
+    while publisher.publish("weather", b"sunny") == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(&b"sunny"[..], weather.next().await.unwrap());
+    assert_eq!(0, publisher.publish("sports", b"goal"));
+
+    drop((news, weather, subscribers));
+    while publisher.client_count() > 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+}
+
+#[tokio::test]
+async fn resumed_subscriber_keeps_subscriptions() {
+    let (client, server) = Endpoint::pair().await.unwrap();
+    let publisher = Publisher::new(None);
+    publisher.add(server);
+    let subscriber = Subscriber::resumable(client);
+    let mut news = subscriber.subscribe("news").unwrap();
+    while publisher.publish("news", b"before") == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(&b"before"[..], news.next().await.unwrap());
+
+    // The server restarts with a publisher that knows nothing about the client.
+    drop(publisher);
+    subscriber.disconnected().await;
+    let (client, server) = Endpoint::pair().await.unwrap();
+    let publisher = Publisher::new(None);
+    publisher.add(server);
+    subscriber.resume(client).unwrap();
+
+    while publisher.publish("news", b"after") == 0 {
+        tokio::time::sleep(Duration::from_millis(10)).await;
+    }
+    assert_eq!(&b"after"[..], news.next().await.unwrap());
+}
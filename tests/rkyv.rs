@@ -0,0 +1,38 @@
+#![cfg(feature = "rkyv")]
+
+use bytes::Bytes;
+use tokio_ipc::framing::FramedConnection;
+use tokio_ipc::typed::ArchivedConnection;
+use tokio_ipc::Endpoint;
+
+#[derive(rkyv::Archive, rkyv::Serialize)]
+#[archive(check_bytes)]
+struct Sample {
+    sensor: u8,
+    value: u32,
+    label: String,
+}
+
+#[tokio::test]
+async fn archived_messages_are_read_in_place() {
+    let (client, server) = Endpoint::pair().await.unwrap();
+    let mut client = ArchivedConnection::<Sample>::new(client);
+    let mut server = ArchivedConnection::<Sample>::new(server);
+
+    let sample = Sample {
+        sensor: 2,
+        value: 300,
+        label: "temperature".into(),
+    };
+    client.send(&sample).await.unwrap();
+    let received = server.recv().await.unwrap().unwrap();
+    assert_eq!(2, received.sensor);
+    assert_eq!(300, received.value);
+    assert_eq!("temperature", received.label.as_str());
+
+    // Frames that aren't valid archives are reported without tearing down the connection.
+    let mut server = FramedConnection::new(server.into_inner().into_inner());
+    server.send(Bytes::from_static(b"x")).await.unwrap();
+    let err = client.recv().await.unwrap().unwrap_err();
+    assert_eq!(std::io::ErrorKind::InvalidData, err.kind());
+}
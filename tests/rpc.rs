@@ -0,0 +1,87 @@
+#![cfg(all(feature = "rpc", feature = "json"))]
+
+use std::time::Duration;
+
+use futures::StreamExt;
+use tokio_ipc::framing::FramedConnection;
+use tokio_ipc::rpc::{RpcClient, RpcServer};
+use tokio_ipc::typed::Json;
+use tokio_ipc::{Endpoint, ServerId};
+
+#[tokio::test]
+async fn rpc_matches_out_of_order_responses() {
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let endpoint = Endpoint::new(ServerId::new(format!("rpc-{num}")), None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path, None).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+    let client = RpcClient::<u64, u64, _>::new(FramedConnection::new(client), Json);
+    let server = RpcServer::<u64, u64, _>::new(FramedConnection::new(server), Json);
+
+    // Larger requests are answered sooner, so responses come back in reverse order.
+    let server = tokio::spawn(server.serve(|req, responder| async move {
+        tokio::time::sleep(Duration::from_millis(50 * (4 - req))).await;
+        responder.respond(&(req * 2)).await.unwrap();
+    }));
+
+    let calls = (1..=3).map(|req| {
+        let client = client.clone();
+        async move { client.call(&req).await.unwrap() }
+    });
+    assert_eq!(vec![2, 4, 6], futures::future::join_all(calls).await);
+
+    drop(client);
+    server.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn dropped_responder_fails_the_call() {
+    let (client, server) = Endpoint::pair().await.unwrap();
+    let client = RpcClient::<u64, u64, _>::new(FramedConnection::new(client), Json);
+    let server = RpcServer::<u64, u64, _>::new(FramedConnection::new(server), Json);
+
+    tokio::spawn(server.serve(|req, responder| async move {
+        if req == 0 {
+            drop(responder);
+        } else {
+            responder.respond(&req).await.unwrap();
+        }
+    }));
+
+    let error = client.call(&0).await.unwrap_err();
+    assert_eq!(std::io::ErrorKind::Other, error.kind());
+    assert_eq!(1, client.call(&1).await.unwrap());
+}
+
+#[tokio::test]
+async fn trace_context_is_sent_with_requests() {
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    let (client, server) = Endpoint::pair().await.unwrap();
+    let client = RpcClient::<u64, u64, _>::new(FramedConnection::new(client), Json)
+        .with_trace_context(|| Some(TRACEPARENT.into()));
+    let mut server = RpcServer::<u64, u64, _>::new(FramedConnection::new(server), Json);
+
+    let call = tokio::spawn(async move { client.call(&1).await.unwrap() });
+    let (req, responder) = server.next().await.unwrap().unwrap();
+    assert_eq!(Some(TRACEPARENT), responder.trace_context());
+    responder.respond(&req).await.unwrap();
+    assert_eq!(1, call.await.unwrap());
+
+    // Without a hook, requests don't carry a trace context
+    let (client, server) = Endpoint::pair().await.unwrap();
+    let client = RpcClient::<u64, u64, _>::new(FramedConnection::new(client), Json);
+    let (contexts, mut received) = tokio::sync::mpsc::unbounded_channel();
+    let server = RpcServer::<u64, u64, _>::new(FramedConnection::new(server), Json)
+        .with_trace_context(move |trace_context| {
+            let _ = contexts.send(trace_context.map(String::from));
+            tracing::Span::none()
+        });
+    tokio::spawn(server.serve(|req, responder| async move {
+        responder.respond(&req).await.unwrap();
+    }));
+    assert_eq!(2, client.call(&2).await.unwrap());
+    assert_eq!(None, received.recv().await.unwrap());
+}
@@ -0,0 +1,84 @@
+#![cfg(feature = "shm")]
+
+use tokio_ipc::shm::{ShmConnection, ShmOptions};
+use tokio_ipc::Endpoint;
+
+async fn shm_pair(options: Option<ShmOptions>) -> (ShmConnection, ShmConnection) {
+    let (first, second) = Endpoint::pair().await.unwrap();
+    let (first, second) = tokio::join!(
+        ShmConnection::negotiate(first, options),
+        ShmConnection::negotiate(second, options)
+    );
+    (first.unwrap(), second.unwrap())
+}
+
+#[tokio::test]
+async fn payloads_roundtrip_through_shared_memory() {
+    let (mut first, mut second) = shm_pair(None).await;
+    #[cfg(any(target_os = "linux", windows))]
+    assert!(first.is_shared());
+
+    let large: Vec<u8> = (0..1024 * 1024).map(|i| i as u8).collect();
+    first.send(b"small").await.unwrap();
+    first.send(&large).await.unwrap();
+    second.send(&large).await.unwrap();
+
+    assert_eq!(&b"small"[..], second.recv().await.unwrap().unwrap());
+    assert_eq!(large, second.recv().await.unwrap().unwrap());
+    assert_eq!(large, first.recv().await.unwrap().unwrap());
+
+    drop(first);
+    assert!(second.recv().await.is_none());
+}
+
+#[tokio::test]
+async fn full_ring_falls_back_and_wraps_around() {
+    let options = ShmOptions {
+        capacity: 1024,
+        threshold: 16,
+    };
+    let (mut first, mut second) = shm_pair(Some(options)).await;
+
+    for round in 0..5u8 {
+        let payload = vec![round; 600];
+        // Doesn't fit next to the first one until it's read
+        first.send(&payload).await.unwrap();
+        first.send(&payload).await.unwrap();
+        assert_eq!(payload, second.recv().await.unwrap().unwrap());
+        assert_eq!(payload, second.recv().await.unwrap().unwrap());
+    }
+}
+
+#[tokio::test]
+async fn zero_capacity_sends_over_the_connection() {
+    let options = ShmOptions {
+        capacity: 0,
+        ..Default::default()
+    };
+    let (mut first, mut second) = shm_pair(Some(options)).await;
+    assert!(!first.is_shared());
+
+    let payload = vec![7; 256 * 1024];
+    first.send(&payload).await.unwrap();
+    assert_eq!(payload, second.recv().await.unwrap().unwrap());
+}
+
+#[cfg(target_os = "linux")]
+#[tokio::test]
+async fn sealed_memfd_roundtrip() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio_ipc::shm::{recv_memfd, send_memfd};
+
+    let (mut first, mut second) = Endpoint::pair().await.unwrap();
+    let blob: Vec<u8> = (0..4 * 1024 * 1024).map(|i| (i % 251) as u8).collect();
+    send_memfd(&mut first, &blob).await.unwrap();
+    send_memfd(&mut first, &[]).await.unwrap();
+    first.write_all(b"after").await.unwrap();
+
+    let received = recv_memfd(&mut second).await.unwrap();
+    assert_eq!(blob, received);
+    assert!(recv_memfd(&mut second).await.unwrap().is_empty());
+    let mut buf = [0; 5];
+    second.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"after", &buf);
+}
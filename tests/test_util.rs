@@ -0,0 +1,168 @@
+#![cfg(feature = "test-util")]
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_ipc::test::{
+    assert_roundtrip, expire, pair, spawn_echo_server, temp_endpoint, Fault, FaultPolicy,
+    FaultyConnection, MockEndpoint,
+};
+use tokio_ipc::ExpiryReason;
+
+#[tokio::test]
+async fn echo_server_roundtrip() {
+    let server = spawn_echo_server().unwrap();
+    let mut client = server.connect().await.unwrap();
+    assert_roundtrip(&mut client, b"hello").await;
+    assert_roundtrip(&mut client, b"world").await;
+}
+
+#[test]
+fn temp_endpoints_are_unique() {
+    let first = temp_endpoint().unwrap();
+    let second = temp_endpoint().unwrap();
+    assert_ne!(first.path(), second.path());
+
+    #[cfg(unix)]
+    {
+        let folder = first.path().parent().unwrap().to_path_buf();
+        assert!(folder.exists());
+        drop(first);
+        assert!(!folder.exists());
+    }
+}
+
+#[tokio::test]
+async fn pair_is_connected() {
+    let (mut first, mut second) = pair().await.unwrap();
+    first.write_all(b"ping").await.unwrap();
+    let mut buf = [0; 4];
+    second.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"ping", &buf);
+}
+
+#[tokio::test]
+async fn testing_is_an_alias_of_test() {
+    let (mut first, mut second) = tokio_ipc::testing::pair().await.unwrap();
+    second.write_all(b"pong").await.unwrap();
+    let mut buf = [0; 4];
+    first.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"pong", &buf);
+}
+
+#[tokio::test]
+async fn mock_endpoint_yields_connected_clients() {
+    let endpoint = MockEndpoint::new();
+    let connector = endpoint.connector();
+    let mut incoming = endpoint.incoming();
+
+    let server = tokio::spawn(async move {
+        while let Ok(mut conn) = incoming.accept().await {
+            let mut buf = [0; 5];
+            conn.read_exact(&mut buf).await.unwrap();
+            conn.write_all(&buf).await.unwrap();
+        }
+    });
+
+    for _ in 0..2 {
+        let mut client = connector.connect().await.unwrap();
+        assert_roundtrip(&mut client, b"hello").await;
+    }
+    drop(connector);
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn faulty_connection_shortens_reads_and_writes() {
+    let (first, second) = pair().await.unwrap();
+    let mut first = FaultyConnection::new(
+        first,
+        FaultPolicy {
+            max_write_len: Some(3),
+            ..Default::default()
+        },
+    );
+    let mut second = FaultyConnection::new(
+        second,
+        FaultPolicy {
+            max_read_len: Some(2),
+            ..Default::default()
+        },
+    );
+
+    assert_eq!(3, first.write(b"hello").await.unwrap());
+    first.write_all(b"lo").await.unwrap();
+    let mut buf = [0; 5];
+    assert_eq!(2, second.read(&mut buf).await.unwrap());
+    second.read_exact(&mut buf[2..]).await.unwrap();
+    assert_eq!(b"hello", &buf);
+}
+
+#[tokio::test]
+async fn faulty_connection_disconnects_after_limit() {
+    let (mut first, second) = pair().await.unwrap();
+    let policy = FaultPolicy {
+        fault_after: Some(4),
+        ..Default::default()
+    };
+    let mut second = FaultyConnection::new(second, policy);
+
+    first.write_all(b"pingpong").await.unwrap();
+    let mut received = Vec::new();
+    second.read_to_end(&mut received).await.unwrap();
+    assert_eq!(b"ping", &received[..]);
+    let err = second.write_all(b"x").await.unwrap_err();
+    assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+}
+
+#[tokio::test]
+async fn faulty_connection_injects_errors_and_latency() {
+    let (first, mut second) = pair().await.unwrap();
+    let policy = FaultPolicy {
+        latency: Some(Duration::from_millis(20)),
+        fault_after: Some(4),
+        fault: Fault::Error(io::ErrorKind::ConnectionReset),
+        ..Default::default()
+    };
+    let mut first = FaultyConnection::new(first, policy);
+
+    let start = Instant::now();
+    first.write_all(b"ping").await.unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    let mut buf = [0; 4];
+    second.read_exact(&mut buf).await.unwrap();
+    assert_eq!(b"ping", &buf);
+
+    let err = first.write_all(b"pong").await.unwrap_err();
+    assert_eq!(io::ErrorKind::ConnectionReset, err.kind());
+}
+
+#[tokio::test]
+async fn expire_ends_the_lifetime_right_away() {
+    let (mut client, mut server) = pair().await.unwrap();
+
+    // No limit needs to be set for the hook to work
+    let (reason, ()) = tokio::join!(client.expired(), async {
+        tokio::task::yield_now().await;
+        expire(&client, ExpiryReason::Idle);
+    });
+    assert_eq!(ExpiryReason::Idle, reason);
+    let err = client.write_all(b"late").await.unwrap_err();
+    assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+    let mut buf = [0u8; 4];
+    assert_eq!(0, server.read(&mut buf).await.unwrap());
+
+    // The first expiry sticks
+    expire(&server, ExpiryReason::MaxLifetime);
+    expire(&server, ExpiryReason::Idle);
+    assert_eq!(Some(ExpiryReason::MaxLifetime), server.expiry());
+}
+
+#[tokio::test(start_paused = true)]
+async fn idle_timeout_with_paused_clock() {
+    let (mut client, _server) = pair().await.unwrap();
+    client.set_idle_timeout(Some(Duration::from_secs(3600)));
+    tokio::time::advance(Duration::from_secs(3600)).await;
+    assert_eq!(ExpiryReason::Idle, client.expired().await);
+}
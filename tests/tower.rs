@@ -0,0 +1,71 @@
+#![cfg(feature = "tower")]
+
+use std::io;
+
+use bytes::Bytes;
+use futures::future::{self, poll_fn};
+use tokio_ipc::framing::FramedConnection;
+use tokio_ipc::tower::{Client, Connector};
+use tokio_ipc::{Endpoint, ServerId};
+use tower_service::Service;
+
+async fn ready<S: Service<R>, R>(service: &mut S) -> Result<(), S::Error> {
+    poll_fn(|cx| service.poll_ready(cx)).await
+}
+
+#[tokio::test]
+async fn client_pipelines_calls() {
+    let (client, server) = Endpoint::pair().await.unwrap();
+    let mut client = Client::new(FramedConnection::new(client));
+    tokio::spawn(async move {
+        let mut server = FramedConnection::new(server);
+        while let Some(Ok(frame)) = server.recv().await {
+            let mut resp = b"re: ".to_vec();
+            resp.extend_from_slice(&frame);
+            server.send(resp.into()).await.unwrap();
+        }
+    });
+
+    ready(&mut client).await.unwrap();
+    let first = client.call(Bytes::from_static(b"first"));
+    ready(&mut client).await.unwrap();
+    let second = client.call(Bytes::from_static(b"second"));
+    let (first, second) = future::join(first, second).await;
+    assert_eq!(&b"re: first"[..], first.unwrap());
+    assert_eq!(&b"re: second"[..], second.unwrap());
+}
+
+#[tokio::test]
+async fn calls_fail_once_the_server_hangs_up() {
+    let (client, server) = Endpoint::pair().await.unwrap();
+    let mut client = Client::new(FramedConnection::new(client));
+    tokio::spawn(async move {
+        let mut server = FramedConnection::new(server);
+        // Reads the request and hangs up without answering
+        let _ = server.recv().await;
+    });
+
+    ready(&mut client).await.unwrap();
+    let error = client.call(Bytes::from_static(b"ping")).await.unwrap_err();
+    assert_eq!(io::ErrorKind::BrokenPipe, error.kind());
+    let error = ready(&mut client).await.unwrap_err();
+    assert_eq!(io::ErrorKind::BrokenPipe, error.kind());
+}
+
+#[tokio::test]
+async fn connector_connects_to_the_endpoint() {
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let endpoint = Endpoint::new(ServerId::new(format!("tower-{num}")), None).unwrap();
+    let mut incoming = endpoint.incoming().unwrap();
+    let mut connector = Connector::new(endpoint.path().to_path_buf(), None).unwrap();
+
+    ready::<_, ()>(&mut connector).await.unwrap();
+    let (client, server) = tokio::join!(connector.call(()), incoming.accept());
+    let mut client = Client::new(FramedConnection::new(client.unwrap()));
+    let mut server = FramedConnection::new(server.unwrap());
+
+    let call = tokio::spawn(client.call(Bytes::from_static(b"ping")));
+    assert_eq!(&b"ping"[..], server.recv().await.unwrap().unwrap());
+    server.send(Bytes::from_static(b"pong")).await.unwrap();
+    assert_eq!(&b"pong"[..], call.await.unwrap().unwrap());
+}
@@ -0,0 +1,65 @@
+#![cfg(feature = "json")]
+
+use bytes::Bytes;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use tokio_ipc::framing::FramedConnection;
+use tokio_ipc::typed::{Json, TypedConnection};
+use tokio_ipc::{Endpoint, ServerId};
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+enum Message {
+    Ping(u32),
+    Text(String),
+}
+
+#[tokio::test]
+async fn typed_json_roundtrip() {
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let endpoint = Endpoint::new(ServerId::new(format!("typed-{num}")), None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming().unwrap();
+
+    let client = Endpoint::connect(path, None).await.unwrap();
+    let server = incoming.next().await.unwrap().unwrap();
+    let mut client = TypedConnection::new(client, Json);
+    let mut server = FramedConnection::new(server);
+
+    client.send(&Message::Ping(7)).await.unwrap();
+    let frame = server.recv().await.unwrap().unwrap();
+    assert_eq!(&br#"{"Ping":7}"#[..], frame);
+
+    // Frames that don't decode are reported without tearing down the connection.
+    let garbage = Bytes::from_static(b"not json");
+    server.send(garbage).await.unwrap();
+    let err = client.recv().await.unwrap().unwrap_err();
+    assert_eq!(std::io::ErrorKind::InvalidData, err.kind());
+
+    let mut server = TypedConnection::<Message, _>::from_framed(server, Json);
+    server.send(&Message::Text("hi".into())).await.unwrap();
+    let reply = client.recv().await.unwrap().unwrap();
+    assert_eq!(Message::Text("hi".into()), reply);
+
+    drop(client);
+    assert!(server.recv().await.is_none());
+}
+
+#[tokio::test]
+async fn incoming_typed_yields_typed_connections() {
+    let num: u64 = rand::Rng::gen(&mut rand::thread_rng());
+    let endpoint = Endpoint::new(ServerId::new(format!("typed-{num}")), None).unwrap();
+    let path = endpoint.path().to_path_buf();
+    let mut incoming = endpoint.incoming_typed::<Message, _>(Json).unwrap();
+
+    let client = Endpoint::connect(path, None).await.unwrap();
+    let mut client = TypedConnection::new(client, Json);
+    let mut server = incoming.accept().await.unwrap();
+
+    client.send(&Message::Ping(1)).await.unwrap();
+    assert_eq!(Message::Ping(1), server.recv().await.unwrap().unwrap());
+    server.send(&Message::Text("pong".into())).await.unwrap();
+    assert_eq!(
+        Message::Text("pong".into()),
+        client.recv().await.unwrap().unwrap()
+    );
+}